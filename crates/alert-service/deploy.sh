@@ -0,0 +1,19 @@
+#!/usr/bin/env bash
+set -e
+
+BUILD_ARG=--release
+SERVER="sgc"  # ssh config name or full address
+NAME=alert-service
+
+cargo build --target=aarch64-unknown-linux-musl $BUILD_ARG
+rsync -vh --progress \
+  ../../target/aarch64-unknown-linux-musl/release/$NAME \
+  $SERVER:/tmp/
+
+cmds="
+sudo mv /tmp/$NAME /home/ha/$NAME
+sudo chown ha:ha /home/ha/$NAME
+sudo systemctl restart $NAME.service
+"
+
+ssh -t sgc "$cmds"
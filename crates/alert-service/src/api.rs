@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+pub mod client;
+pub use client::{Client, Subscribed};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Request {
+    /// Every alert that is currently firing
+    ListFiring,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+pub enum ServerError {
+    #[error(
+        "Subscription fell behind and missed {0} alerts, it has been ended. \
+        Subscribe again and use ListFiring to catch up."
+    )]
+    SubscriberLagged(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// Name of the rule in the config
+    pub rule: String,
+    pub message: String,
+    /// When the rule started firing
+    pub since: jiff::Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AlertEvent {
+    Firing(Alert),
+    Resolved {
+        alert: Alert,
+        at: jiff::Timestamp,
+    },
+}
+
+impl AlertEvent {
+    #[must_use]
+    pub fn alert(&self) -> &Alert {
+        match self {
+            AlertEvent::Firing(alert) | AlertEvent::Resolved { alert, .. } => alert,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertEvent::Firing(Alert { rule, message, .. }) => {
+                write!(f, "FIRING {rule}: {message}")
+            }
+            AlertEvent::Resolved {
+                alert: Alert { rule, message, .. },
+                ..
+            } => write!(f, "RESOLVED {rule}: {message}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Response {
+    ListFiring(Vec<Alert>),
+    Error(ServerError),
+    /// Only send to subscribers
+    Alert(AlertEvent),
+}
@@ -0,0 +1,87 @@
+use rpc::client::RpcClient;
+use tokio::net::ToSocketAddrs;
+
+use super::{Alert, AlertEvent, Request, Response, ServerError};
+
+#[derive(Clone)]
+pub struct Client(RpcClient<Request, Response>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Got unexpected response response to request {request:?}")]
+    IncorrectResponse { request: String, response: String },
+    #[error("Server ran into an specific error with our request: {0}")]
+    Server(ServerError),
+    #[error("Error while communicating with server: {0}")]
+    Comms(#[from] rpc::client::RpcError),
+}
+
+impl Client {
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        name: String,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect(addr, name).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// Needed if the alert-service was started with a client key file
+    pub async fn connect_with_key(
+        addr: impl ToSocketAddrs,
+        name: String,
+        key: &rpc::auth::Key,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_with_key(addr, name, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// Needed if the alert-service uses TLS, pass a `key` if it was also
+    /// started with a client key file
+    pub async fn connect_tls(
+        addr: impl ToSocketAddrs,
+        name: String,
+        tls: &rpc::tls::ClientConfig,
+        key: Option<&rpc::auth::Key>,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_tls(addr, name, tls, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    pub async fn list_firing(&mut self) -> Result<Vec<Alert>, Error> {
+        let request = Request::ListFiring;
+        match self.0.send_receive(request.clone()).await? {
+            Response::ListFiring(alerts) => Ok(alerts),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Get every alert that starts firing or resolves from now on
+    pub async fn subscribe(&self) -> Result<Subscribed, Error> {
+        let subscription = self.0.subscribe(()).await?;
+        Ok(Subscribed(subscription))
+    }
+}
+
+pub struct Subscribed(rpc::client::Subscription<Request, Response>);
+
+impl Subscribed {
+    pub async fn next(&mut self) -> Result<AlertEvent, Error> {
+        match self.0.next().await? {
+            Response::Alert(event) => Ok(event),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: "none, we are subscribed".to_string(),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Stops the alert events, the client this came from is not affected
+    pub async fn unsubscribe(self) -> Result<(), Error> {
+        Ok(self.0.unsubscribe().await?)
+    }
+}
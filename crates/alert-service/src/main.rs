@@ -0,0 +1,95 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use alert_service::server::Config;
+use clap::Parser;
+use color_eyre::eyre::Result;
+use data_server::api::{ConnectOptions, ReconnectingClient};
+
+#[derive(Parser, Debug)]
+#[command(name = "alert service")]
+#[command(version = "1.0")]
+#[command(about = "Watches sensor events and errors, fires alerts when rules are broken")]
+struct Cli {
+    /// data server
+    #[arg(short, long)]
+    data_server: SocketAddr,
+
+    #[command(flatten)]
+    data_server_options: ConnectOptions,
+
+    /// log-store, used at startup to find errors that began earlier
+    #[arg(short, long)]
+    log_store: SocketAddr,
+
+    #[arg(short, long)]
+    client_port: u16,
+
+    /// file with a pre-shared key per client, if given clients must prove
+    /// they have the key for the name they connect with. See the docs of
+    /// `rpc::auth` for the format.
+    #[arg(long)]
+    client_key_file: Option<PathBuf>,
+
+    /// PEM file with the certificate (chain) of this server, if given
+    /// clients must connect using TLS
+    #[arg(long, requires = "client_tls_key")]
+    client_tls_certificate: Option<PathBuf>,
+
+    /// PEM file with the private key belonging to the certificate
+    #[arg(long, requires = "client_tls_certificate")]
+    client_tls_key: Option<PathBuf>,
+
+    /// RON file with the rules and sinks, see the config module docs for
+    /// an example
+    #[arg(long, default_value = "alerts.ron")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    setup_tracing().unwrap();
+    let cli = Cli::parse();
+
+    tracing::info!("started alert-service, args: {cli:?}");
+    let config = Config::load(&cli.config)?;
+    let data_server = ReconnectingClient::new(cli.data_server, "ha-alert-service".to_string());
+    let data_server = cli.data_server_options.apply(data_server)?;
+    let keys = cli.client_key_file
+        .as_deref()
+        .map(rpc::auth::Keys::load)
+        .transpose()?;
+    let tls = cli.client_tls_certificate
+        .zip(cli.client_tls_key)
+        .map(|(certificate, key)| rpc::tls::ServerConfig::load(&certificate, &key))
+        .transpose()?;
+    alert_service::server::run(
+        data_server,
+        cli.log_store,
+        cli.client_port,
+        keys,
+        tls,
+        config,
+    )
+    .await
+}
+
+fn setup_tracing() -> Result<()> {
+    use tracing_error::ErrorLayer;
+    use tracing_subscriber::filter;
+    use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt};
+
+    color_eyre::install().unwrap();
+
+    let filter = filter::EnvFilter::builder().from_env().unwrap();
+    let fmt = tracing_subscriber::fmt::layer()
+        .pretty()
+        .with_line_number(true);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(ErrorLayer::default())
+        .init();
+    Ok(())
+}
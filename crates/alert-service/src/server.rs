@@ -0,0 +1,134 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::Result;
+use data_server::api::ReconnectingClient;
+use futures_concurrency::future::Race;
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+use crate::api::{Alert, AlertEvent};
+
+mod clients;
+pub mod config;
+mod rules;
+mod sinks;
+
+pub use config::Config;
+pub use sinks::Sink;
+
+const TICK: Duration = Duration::from_secs(5);
+/// Error history is requested in pages of this size
+const LOG_PAGE: usize = 500;
+
+// used from main and tests, with `keys` clients must authenticate and with
+// `tls` they must connect using TLS
+pub async fn run(
+    data_server: ReconnectingClient,
+    log_store: SocketAddr,
+    client_port: u16,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
+    config: Config,
+) -> Result<()> {
+    let firing = Arc::new(Mutex::new(Vec::new()));
+    let (events, _) = broadcast::channel(100);
+
+    let error = (
+        evaluate(data_server, log_store, config, firing.clone(), events.clone()),
+        clients::handle(client_port, keys, tls, firing, events),
+    )
+        .race()
+        .await;
+    assert!(
+        error.is_err(),
+        "evaluate and client::handle never return unless an error happens"
+    );
+    error
+}
+
+async fn evaluate(
+    data_server: ReconnectingClient,
+    log_store: SocketAddr,
+    Config { rules, sinks }: Config,
+    firing: Arc<Mutex<Vec<Alert>>>,
+    events: broadcast::Sender<AlertEvent>,
+) -> Result<()> {
+    let mut engine = rules::Engine::new(rules, jiff::Timestamp::now());
+    let deliveries = sinks::Deliveries::start(sinks);
+    if let Err(e) = seed_from_log_store(&mut engine, log_store).await {
+        tracing::warn!(
+            "Could not get error history from log-store, errors that started \
+            before now are not taken into account: {e:?}"
+        );
+    }
+
+    let (tx, mut messages) = mpsc::channel(100);
+    tokio::spawn(async move {
+        let mut sub = data_server.subscribe();
+        loop {
+            let msg = sub.next().await;
+            if tx.send(msg).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        let alert_events = tokio::select! {
+            msg = messages.recv() => {
+                let msg = msg.expect("forwarding task only stops if we are dropped");
+                engine.on_message(&msg, jiff::Timestamp::now())
+            }
+            _ = tick.tick() => engine.on_tick(jiff::Timestamp::now()),
+        };
+
+        for event in alert_events {
+            tracing::info!("{event}");
+            {
+                let mut firing = firing.lock().await;
+                match &event {
+                    AlertEvent::Firing(alert) => firing.push(alert.clone()),
+                    AlertEvent::Resolved { alert, .. } => firing.retain(|a| a != alert),
+                }
+            }
+            deliveries.queue(&event);
+            // no subscribers is fine
+            let _ = events.send(event);
+        }
+    }
+}
+
+async fn seed_from_log_store(engine: &mut rules::Engine, log_store: SocketAddr) -> Result<()> {
+    use color_eyre::eyre::WrapErr;
+    use color_eyre::Section;
+
+    let mut client = log_store::api::Client::connect(log_store, "ha-alert-service".to_string())
+        .await
+        .wrap_err("Could not connect to log-store")?;
+
+    let now = jiff::Timestamp::now();
+    for (device, window) in engine.history_needed() {
+        let window = i64::try_from(window.as_secs()).unwrap_or(i64::MAX);
+        let start = jiff::Timestamp::from_second(now.as_second().saturating_sub(window))
+            .unwrap_or(jiff::Timestamp::MIN);
+
+        let mut events = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = client
+                .get_logs(device.clone(), start, now, LOG_PAGE, cursor)
+                .await
+                .wrap_err("Could not get error log")
+                .with_note(|| format!("device: {device:?}"))?;
+            events.extend(page.events);
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        engine.seed(&device, &events);
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Mutex;
+
+use crate::api::{self, Alert, AlertEvent, ServerError};
+
+#[derive(Debug, Clone)]
+struct SubHandler {
+    events: broadcast::Sender<AlertEvent>,
+}
+
+impl rpc::SubscriberHandler for SubHandler {
+    type Update = api::Response;
+    type Request = ();
+
+    fn setup(
+        &mut self,
+        (): Self::Request,
+    ) -> impl std::future::Future<Output = impl Stream<Item = Self::Update> + Send + 'static>
+           + Send
+           + 'static {
+        let rx = self.events.subscribe();
+        async move { alerts(rx) }
+    }
+}
+
+fn alerts(rx: broadcast::Receiver<AlertEvent>) -> BoxStream<'static, api::Response> {
+    stream::unfold(Some(rx), |rx| async move {
+        let mut rx = rx?;
+        match rx.recv().await {
+            Ok(event) => Some((api::Response::Alert(event), Some(rx))),
+            Err(RecvError::Lagged(n)) => {
+                // the subscriber would miss alerts, end the subscription
+                let error = api::Response::Error(ServerError::SubscriberLagged(n));
+                Some((error, None))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    })
+    .boxed()
+}
+
+pub(crate) async fn handle(
+    port: u16,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
+    firing: Arc<Mutex<Vec<Alert>>>,
+    events: broadcast::Sender<AlertEvent>,
+) -> color_eyre::Result<()> {
+    rpc::server::run(
+        port,
+        keys,
+        tls,
+        move |req, _| {
+            let firing = firing.clone();
+            perform_request(req, firing)
+        },
+        Some(SubHandler { events }),
+    )
+    .await
+}
+
+async fn perform_request(request: api::Request, firing: Arc<Mutex<Vec<Alert>>>) -> api::Response {
+    match request {
+        api::Request::ListFiring => api::Response::ListFiring(firing.lock().await.clone()),
+    }
+}
@@ -0,0 +1,243 @@
+//! Rules and sinks loaded from a [RON](https://docs.rs/ron) file, for example:
+//!
+//! ```ron
+//! (
+//!     rules: [
+//!         (
+//!             name: "bed sht31 silent",
+//!             condition: Silence(device: "largebedroom/bed/sht31", factor: 3.0),
+//!         ),
+//!         (
+//!             name: "co2 high",
+//!             condition: Threshold(
+//!                 reading: "largebedroom/bed/mhz14/Co2",
+//!                 above: Some(1500.0),
+//!                 hysteresis: 100.0,
+//!                 for_at_least: "30m",
+//!             ),
+//!         ),
+//!         (
+//!             name: "sps30 broken",
+//!             condition: ErrorLasting(device: "largebedroom/bed/sps30", longer_than: "10m"),
+//!         ),
+//!         (
+//!             name: "sps30 flapping",
+//!             condition: Flapping(device: "largebedroom/bed/sps30", errors: 5, within: "1h"),
+//!         ),
+//!     ],
+//!     sinks: [
+//!         File(path: "/var/log/ha-alerts.log"),
+//!         Command(program: "notify-send", args: ["home automation"]),
+//!         Webhook(url: "https://example.org/alerts"),
+//!     ],
+//! )
+//! ```
+//!
+//! Devices are named by their path, readings by the path of their device
+//! followed by the name of the reading. Durations are a number followed by
+//! s, m, h or d.
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::{Result, Section};
+use serde::{Deserialize, Deserializer};
+
+use super::sinks::Sink;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub sinks: Vec<Sink>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum Condition {
+    /// No reading from the device for longer then `factor` times its
+    /// max sample interval
+    Silence { device: Device, factor: f32 },
+    /// The device has been reporting errors, without a reading in between,
+    /// for longer then `longer_than`
+    ErrorLasting {
+        device: Device,
+        #[serde(deserialize_with = "duration")]
+        longer_than: Duration,
+    },
+    /// The value went above `above` or below `below` for at least
+    /// `for_at_least`. Resolves once the value is `hysteresis` back within
+    /// bounds.
+    Threshold {
+        reading: Reading,
+        #[serde(default)]
+        above: Option<f32>,
+        #[serde(default)]
+        below: Option<f32>,
+        #[serde(default)]
+        hysteresis: f32,
+        #[serde(default, deserialize_with = "duration")]
+        for_at_least: Duration,
+    },
+    /// The device started reporting errors `errors` or more times within
+    /// `within`
+    Flapping {
+        device: Device,
+        errors: usize,
+        #[serde(deserialize_with = "duration")]
+        within: Duration,
+    },
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err("Could not read config")
+            .with_note(|| format!("path: {}", path.display()))?;
+        let config: Self = ron::from_str(&text)
+            .wrap_err("Could not parse config")
+            .with_note(|| format!("path: {}", path.display()))?;
+        for rule in &config.rules {
+            rule.check().with_note(|| format!("rule: {}", rule.name))?;
+        }
+        Ok(config)
+    }
+}
+
+impl Rule {
+    fn check(&self) -> Result<()> {
+        match &self.condition {
+            Condition::Silence { factor, .. } if *factor <= 0.0 => {
+                bail!("factor must be larger then zero")
+            }
+            Condition::Threshold {
+                above: None,
+                below: None,
+                ..
+            } => bail!("a threshold needs an `above` or `below` limit"),
+            Condition::Threshold { hysteresis, .. } if *hysteresis < 0.0 => {
+                bail!("hysteresis may not be negative")
+            }
+            Condition::Flapping { errors: 0, .. } => bail!("errors must be at least one"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A device given by its path, for example: `largebedroom/bed/sht31`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Device {
+    pub path: String,
+    pub device: protocol::Device,
+}
+
+impl TryFrom<String> for Device {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        let device = protocol::Device::all()
+            .into_iter()
+            .find(|device| device.path().eq_ignore_ascii_case(&path))
+            .ok_or_else(|| format!("no device at `{path}`"))?;
+        Ok(Self { path, device })
+    }
+}
+
+/// A reading given by its path, for example: `largebedroom/bed/sht31/Temperature`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Reading {
+    pub path: String,
+    pub reading: protocol::Reading,
+}
+
+impl TryFrom<String> for Reading {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        let (device, name) = path
+            .rsplit_once('/')
+            .ok_or_else(|| format!("expected `<device path>/<reading>` got: `{path}`"))?;
+        let Device { device, .. } = Device::try_from(device.to_owned())?;
+        let reading = device
+            .info()
+            .affects_readings
+            .iter()
+            .find(|reading| reading.leaf_name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("device `{device:?}` has no reading `{name}`"))?
+            .clone();
+        Ok(Self { path, reading })
+    }
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_duration(&text).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "invalid duration: `{text}`, expected for example: 90s, 15m, 2h or 1d"
+        ))
+    })
+}
+
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let unit = match text.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let value: u64 = text[..text.len() - 1].trim().parse().ok()?;
+    Some(Duration::from_secs(value.checked_mul(unit)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let docs = include_str!("config.rs");
+        let example: String = docs
+            .lines()
+            .take_while(|line| line.starts_with("//!"))
+            .skip_while(|line| !line.contains("```ron"))
+            .skip(1)
+            .take_while(|line| !line.contains("```"))
+            .map(|line| line.trim_start_matches("//!"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let config: Config = ron::from_str(&example).unwrap();
+        assert_eq!(config.rules.len(), 4);
+        assert_eq!(config.sinks.len(), 3);
+        for rule in &config.rules {
+            rule.check().unwrap();
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("300000000000000d"), None);
+    }
+
+    #[test]
+    fn unknown_device_is_rejected() {
+        let config = r#"(rules: [
+            (name: "x", condition: Silence(device: "attic/none", factor: 2.0))
+        ])"#;
+        let error = ron::from_str::<Config>(config).unwrap_err();
+        assert!(error.to_string().contains("attic/none"), "{error}");
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use data_server::api::SubMessage;
+use jiff::Timestamp;
+use log_store::api::ErrorEvent;
+
+use super::config::{Condition, Rule};
+use crate::api::{Alert, AlertEvent};
+
+/// Evaluates all rules. Feed it every message from the data-server and call
+/// [`Engine::on_tick`] regularly so that rules can fire on the absence of
+/// messages.
+pub(crate) struct Engine {
+    rules: Vec<Tracked>,
+}
+
+struct Tracked {
+    rule: Rule,
+    state: State,
+    firing: Option<Alert>,
+}
+
+enum State {
+    Silence {
+        last_seen: Timestamp,
+    },
+    ErrorLasting {
+        since: Option<Timestamp>,
+    },
+    Threshold {
+        breached_since: Option<Timestamp>,
+        last_value: f32,
+    },
+    Flapping {
+        in_error: bool,
+        /// start of every error episode within the window
+        starts: VecDeque<Timestamp>,
+    },
+}
+
+impl Engine {
+    pub(crate) fn new(rules: Vec<Rule>, now: Timestamp) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|rule| Tracked {
+                state: match rule.condition {
+                    Condition::Silence { .. } => State::Silence { last_seen: now },
+                    Condition::ErrorLasting { .. } => State::ErrorLasting { since: None },
+                    Condition::Threshold { .. } => State::Threshold {
+                        breached_since: None,
+                        last_value: f32::NAN,
+                    },
+                    Condition::Flapping { .. } => State::Flapping {
+                        in_error: false,
+                        starts: VecDeque::new(),
+                    },
+                },
+                rule,
+                firing: None,
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Devices for which the error history is needed and how far back it
+    /// should go. See [`Engine::seed`].
+    pub(crate) fn history_needed(&self) -> Vec<(protocol::Device, Duration)> {
+        let mut needed: Vec<(protocol::Device, Duration)> = Vec::new();
+        for Tracked { rule, .. } in &self.rules {
+            let (device, window) = match &rule.condition {
+                Condition::ErrorLasting { device, .. } => (&device.device, Duration::ZERO),
+                Condition::Flapping { device, within, .. } => (&device.device, *within),
+                Condition::Silence { .. } | Condition::Threshold { .. } => continue,
+            };
+            match needed.iter_mut().find(|(d, _)| d == device) {
+                Some((_, longest)) => *longest = (*longest).max(window),
+                None => needed.push((device.clone(), window)),
+            }
+        }
+        needed
+    }
+
+    /// Take errors that happened before we started into account. The events
+    /// are those the log-store has for `device`, oldest first.
+    pub(crate) fn seed(&mut self, device: &protocol::Device, events: &[ErrorEvent]) {
+        let ongoing = events.iter().find(|event| event.end.is_none());
+        for Tracked { rule, state, .. } in &mut self.rules {
+            match (&rule.condition, state) {
+                (Condition::ErrorLasting { device: d, .. }, State::ErrorLasting { since })
+                    if d.device == *device =>
+                {
+                    *since = ongoing.map(|event| event.start);
+                }
+                (Condition::Flapping { device: d, .. }, State::Flapping { in_error, starts })
+                    if d.device == *device =>
+                {
+                    *in_error = ongoing.is_some();
+                    *starts = events.iter().map(|event| event.start).collect();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub(crate) fn on_message(&mut self, msg: &SubMessage, now: Timestamp) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        for tracked in &mut self.rules {
+            let event = match msg {
+                SubMessage::Reading(reading) => tracked.on_reading(reading, now),
+                SubMessage::ErrorReport(error) => tracked.on_error(&error.device(), now),
+                SubMessage::NodeDisconnected { devices } => devices
+                    .iter()
+                    .find_map(|device| tracked.on_disconnect(device, now)),
+                SubMessage::AffectorControlled { .. } => None,
+            };
+            events.extend(event);
+        }
+        events
+    }
+
+    pub(crate) fn on_tick(&mut self, now: Timestamp) -> Vec<AlertEvent> {
+        self.rules
+            .iter_mut()
+            .filter_map(|tracked| tracked.on_tick(now))
+            .collect()
+    }
+}
+
+impl Tracked {
+    fn on_reading(&mut self, reading: &protocol::Reading, now: Timestamp) -> Option<AlertEvent> {
+        let from_device = |device: &super::config::Device| device.device == reading.device();
+        match (&self.rule.condition, &mut self.state) {
+            (Condition::Silence { device, .. }, State::Silence { last_seen })
+                if from_device(device) =>
+            {
+                *last_seen = now;
+                self.resolve(now)
+            }
+            (Condition::ErrorLasting { device, .. }, State::ErrorLasting { since })
+                if from_device(device) =>
+            {
+                *since = None;
+                self.resolve(now)
+            }
+            (Condition::Flapping { device, .. }, State::Flapping { in_error, .. })
+                if from_device(device) =>
+            {
+                *in_error = false;
+                None
+            }
+            (
+                Condition::Threshold {
+                    reading: watched,
+                    above,
+                    below,
+                    hysteresis,
+                    ..
+                },
+                State::Threshold {
+                    breached_since,
+                    last_value,
+                },
+            ) if watched.reading.is_same_as(reading) => {
+                let value = reading.leaf().val;
+                *last_value = value;
+                let breached = above.is_some_and(|limit| value > limit)
+                    || below.is_some_and(|limit| value < limit);
+                let cleared = above.map_or(true, |limit| value <= limit - hysteresis)
+                    && below.map_or(true, |limit| value >= limit + hysteresis);
+
+                if breached {
+                    breached_since.get_or_insert(now);
+                    self.on_tick(now)
+                } else if cleared || self.firing.is_none() {
+                    *breached_since = None;
+                    self.resolve(now)
+                } else {
+                    None // within the hysteresis band, keep firing
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn on_error(&mut self, errored: &protocol::Device, now: Timestamp) -> Option<AlertEvent> {
+        match (&self.rule.condition, &mut self.state) {
+            (Condition::ErrorLasting { device, .. }, State::ErrorLasting { since })
+                if device.device == *errored =>
+            {
+                since.get_or_insert(now);
+                self.on_tick(now)
+            }
+            (Condition::Flapping { device, .. }, State::Flapping { in_error, starts })
+                if device.device == *errored =>
+            {
+                if !*in_error {
+                    *in_error = true;
+                    starts.push_back(now);
+                }
+                self.on_tick(now)
+            }
+            _ => None,
+        }
+    }
+
+    fn on_disconnect(
+        &mut self,
+        disconnected: &protocol::Device,
+        now: Timestamp,
+    ) -> Option<AlertEvent> {
+        match (&self.rule.condition, &mut self.state) {
+            // the error episode ended, if the device stays away the silence
+            // rules will notice
+            (Condition::ErrorLasting { device, .. }, State::ErrorLasting { since })
+                if device.device == *disconnected =>
+            {
+                *since = None;
+                self.resolve(now)
+            }
+            (Condition::Flapping { device, .. }, State::Flapping { in_error, .. })
+                if device.device == *disconnected =>
+            {
+                *in_error = false;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn on_tick(&mut self, now: Timestamp) -> Option<AlertEvent> {
+        match (&self.rule.condition, &mut self.state) {
+            (Condition::Silence { device, factor }, State::Silence { last_seen }) => {
+                let max_interval = device.device.info().max_sample_interval;
+                let limit =
+                    Duration::try_from_secs_f64(max_interval.as_secs_f64() * f64::from(*factor))
+                        .unwrap_or(Duration::MAX);
+                let silent = elapsed(*last_seen, now);
+                if silent > limit {
+                    let message = format!(
+                        "no reading from {} for {}",
+                        device.path,
+                        fmt_duration(silent)
+                    );
+                    self.fire(message, now)
+                } else {
+                    None
+                }
+            }
+            (
+                Condition::ErrorLasting {
+                    device,
+                    longer_than,
+                },
+                State::ErrorLasting { since: Some(since) },
+            ) => {
+                let lasted = elapsed(*since, now);
+                if lasted > *longer_than {
+                    let message = format!(
+                        "{} has been reporting errors for {}",
+                        device.path,
+                        fmt_duration(lasted)
+                    );
+                    self.fire(message, now)
+                } else {
+                    None
+                }
+            }
+            (
+                Condition::Threshold {
+                    reading,
+                    above,
+                    below,
+                    for_at_least,
+                    ..
+                },
+                State::Threshold {
+                    breached_since: Some(since),
+                    last_value,
+                },
+            ) => {
+                if elapsed(*since, now) >= *for_at_least {
+                    let limit = match (above, below) {
+                        (Some(above), _) if *last_value > *above => format!("above {above}"),
+                        (_, Some(below)) => format!("below {below}"),
+                        (Some(above), None) => format!("above {above}"),
+                        (None, None) => unreachable!("checked while loading config"),
+                    };
+                    let message = format!("{} is {last_value}, {limit}", reading.path);
+                    self.fire(message, now)
+                } else {
+                    None
+                }
+            }
+            (
+                Condition::Flapping {
+                    device,
+                    errors,
+                    within,
+                },
+                State::Flapping { starts, .. },
+            ) => {
+                while starts
+                    .front()
+                    .is_some_and(|start| elapsed(*start, now) > *within)
+                {
+                    starts.pop_front();
+                }
+                if starts.len() >= *errors {
+                    let message = format!(
+                        "{} started erroring {} times in the last {}",
+                        device.path,
+                        starts.len(),
+                        fmt_duration(*within)
+                    );
+                    self.fire(message, now)
+                } else {
+                    self.resolve(now)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn fire(&mut self, message: String, now: Timestamp) -> Option<AlertEvent> {
+        if self.firing.is_some() {
+            return None;
+        }
+        let alert = Alert {
+            rule: self.rule.name.clone(),
+            message,
+            since: now,
+        };
+        self.firing = Some(alert.clone());
+        Some(AlertEvent::Firing(alert))
+    }
+
+    fn resolve(&mut self, now: Timestamp) -> Option<AlertEvent> {
+        self.firing
+            .take()
+            .map(|alert| AlertEvent::Resolved { alert, at: now })
+    }
+}
+
+fn elapsed(since: Timestamp, now: Timestamp) -> Duration {
+    let millis = now.as_millisecond().saturating_sub(since.as_millisecond());
+    Duration::from_millis(u64::try_from(millis).unwrap_or(0))
+}
+
+fn fmt_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::config;
+    use protocol::large_bedroom::{self, bed};
+
+    fn at(secs: i64) -> Timestamp {
+        Timestamp::from_second(secs).unwrap()
+    }
+
+    fn co2(val: u16) -> SubMessage {
+        SubMessage::Reading(protocol::Reading::LargeBedroom(
+            large_bedroom::Reading::Bed(bed::Reading::Co2(val)),
+        ))
+    }
+
+    fn sht_error() -> SubMessage {
+        SubMessage::ErrorReport(Box::new(protocol::Error::LargeBedroom(
+            large_bedroom::Error::Bed(bed::Error::Running(bed::SensorError::Sht31(
+                heapless::String::new(),
+            ))),
+        )))
+    }
+
+    fn rule(condition: &str) -> Rule {
+        ron::from_str(&format!("(name: \"test\", condition: {condition})")).unwrap()
+    }
+
+    #[test]
+    fn threshold_with_hysteresis() {
+        let rule = rule(
+            r#"Threshold(reading: "largebedroom/bed/mhz14/Co2",
+                above: Some(1000.0), hysteresis: 100.0, for_at_least: "60s")"#,
+        );
+        let mut engine = Engine::new(vec![rule], at(0));
+
+        assert!(engine.on_message(&co2(1200), at(0)).is_empty());
+        assert!(engine.on_tick(at(30)).is_empty());
+        let fired = engine.on_tick(at(61));
+        assert!(matches!(fired.as_slice(), [AlertEvent::Firing(_)]));
+
+        // inside the hysteresis band
+        assert!(engine.on_message(&co2(950), at(70)).is_empty());
+        let resolved = engine.on_message(&co2(850), at(80));
+        assert!(matches!(resolved.as_slice(), [AlertEvent::Resolved { .. }]));
+    }
+
+    #[test]
+    fn short_breach_does_not_fire() {
+        let rule = rule(
+            r#"Threshold(reading: "largebedroom/bed/mhz14/Co2",
+                above: Some(1000.0), hysteresis: 100.0, for_at_least: "60s")"#,
+        );
+        let mut engine = Engine::new(vec![rule], at(0));
+
+        engine.on_message(&co2(1200), at(0));
+        engine.on_message(&co2(950), at(30));
+        engine.on_message(&co2(1200), at(40));
+        assert!(engine.on_tick(at(70)).is_empty());
+    }
+
+    #[test]
+    fn error_lasting() {
+        let rule = rule(r#"ErrorLasting(device: "largebedroom/bed/sht31", longer_than: "10m")"#);
+        let mut engine = Engine::new(vec![rule], at(0));
+
+        engine.on_message(&sht_error(), at(0));
+        engine.on_message(&sht_error(), at(300));
+        assert!(engine.on_tick(at(599)).is_empty());
+        assert_eq!(engine.on_tick(at(601)).len(), 1);
+
+        let disconnected = SubMessage::NodeDisconnected {
+            devices: vec![config::Device::try_from("largebedroom/bed/sht31".to_string())
+                .unwrap()
+                .device],
+        };
+        let resolved = engine.on_message(&disconnected, at(700));
+        assert!(matches!(resolved.as_slice(), [AlertEvent::Resolved { .. }]));
+    }
+
+    #[test]
+    fn flapping() {
+        let rule =
+            rule(r#"Flapping(device: "largebedroom/bed/sht31", errors: 3, within: "10m")"#);
+        let temperature = SubMessage::Reading(protocol::Reading::LargeBedroom(
+            large_bedroom::Reading::Bed(bed::Reading::Temperature(20.0)),
+        ));
+        let mut engine = Engine::new(vec![rule], at(0));
+
+        let mut fired = Vec::new();
+        for start in [0, 60, 120] {
+            fired.extend(engine.on_message(&sht_error(), at(start)));
+            // repeated reports are part of the same episode
+            fired.extend(engine.on_message(&sht_error(), at(start + 1)));
+            fired.extend(engine.on_message(&temperature, at(start + 30)));
+        }
+        assert!(matches!(fired.as_slice(), [AlertEvent::Firing(_)]));
+
+        let resolved = engine.on_tick(at(661));
+        assert!(matches!(resolved.as_slice(), [AlertEvent::Resolved { .. }]));
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::{Result, Section};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::api::AlertEvent;
+
+/// Alerts waiting to be delivered to a sink. Once a sink has this many
+/// new alerts are dropped for that sink.
+const QUEUE: usize = 100;
+
+/// Where alerts are delivered to when they start firing or resolve
+#[derive(Debug, Clone, Deserialize)]
+pub enum Sink {
+    /// Run a program. The alert is passed through the environment variables
+    /// `ALERT_STATE` (`firing` or `resolved`), `ALERT_RULE` and
+    /// `ALERT_MESSAGE`.
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// POST the alert as JSON
+    Webhook { url: String },
+    /// Append a line per alert
+    File { path: PathBuf },
+}
+
+/// Delivers alerts to every sink. Each sink gets its alerts one at a time
+/// in the order they happened, a slow sink does not hold up the others.
+#[derive(Debug)]
+pub(crate) struct Deliveries {
+    queues: Vec<(Sink, mpsc::Sender<AlertEvent>)>,
+}
+
+impl Deliveries {
+    pub(crate) fn start(sinks: Vec<Sink>) -> Self {
+        let client = reqwest::Client::new();
+        let queues = sinks
+            .into_iter()
+            .map(|sink| {
+                let (tx, rx) = mpsc::channel(QUEUE);
+                tokio::spawn(deliver_in_order(sink.clone(), client.clone(), rx));
+                (sink, tx)
+            })
+            .collect();
+        Self { queues }
+    }
+
+    pub(crate) fn queue(&self, event: &AlertEvent) {
+        for (sink, queue) in &self.queues {
+            match queue.try_send(event.clone()) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!("Sink is too far behind, dropping alert for it: {sink:?}");
+                }
+                Err(TrySendError::Closed(_)) => {
+                    unreachable!("delivery tasks only stop if their queue is dropped")
+                }
+            }
+        }
+    }
+}
+
+async fn deliver_in_order(
+    sink: Sink,
+    client: reqwest::Client,
+    mut queue: mpsc::Receiver<AlertEvent>,
+) {
+    while let Some(event) = queue.recv().await {
+        if let Err(e) = sink.deliver(&client, &event).await {
+            tracing::warn!("Could not deliver alert: {e:?}");
+        }
+    }
+}
+
+impl Sink {
+    pub(crate) async fn deliver(&self, client: &reqwest::Client, event: &AlertEvent) -> Result<()> {
+        match self {
+            Sink::Command { program, args } => {
+                let state = match event {
+                    AlertEvent::Firing(_) => "firing",
+                    AlertEvent::Resolved { .. } => "resolved",
+                };
+                let status = tokio::process::Command::new(program)
+                    .args(args)
+                    .env("ALERT_STATE", state)
+                    .env("ALERT_RULE", &event.alert().rule)
+                    .env("ALERT_MESSAGE", &event.alert().message)
+                    .status()
+                    .await
+                    .wrap_err("Could not run command")
+                    .with_note(|| format!("program: {program}"))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(eyre!("Command failed, {status}"))
+                        .with_note(|| format!("program: {program}"))
+                }
+            }
+            Sink::Webhook { url } => {
+                client
+                    .post(url)
+                    .json(event)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .wrap_err("Could not post alert")
+                    .with_note(|| format!("url: {url}"))?;
+                Ok(())
+            }
+            Sink::File { path } => {
+                let at = match event {
+                    AlertEvent::Firing(alert) => alert.since,
+                    AlertEvent::Resolved { at, .. } => *at,
+                };
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .wrap_err("Could not open alert file")
+                    .with_note(|| format!("path: {}", path.display()))?;
+                file.write_all(format!("{at} {event}\n").as_bytes())
+                    .await
+                    .wrap_err("Could not write to alert file")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::Alert;
+
+    #[tokio::test]
+    async fn file_sink_appends() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.child("alerts.log");
+        let sink = Sink::File { path: path.clone() };
+        let alert = Alert {
+            rule: "co2 high".to_string(),
+            message: "co2 is 1600".to_string(),
+            since: jiff::Timestamp::from_second(0).unwrap(),
+        };
+
+        let client = reqwest::Client::new();
+        sink.deliver(&client, &AlertEvent::Firing(alert.clone()))
+            .await
+            .unwrap();
+        let resolved = AlertEvent::Resolved {
+            alert,
+            at: jiff::Timestamp::from_second(60).unwrap(),
+        };
+        sink.deliver(&client, &resolved).await.unwrap();
+
+        let written = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("FIRING co2 high: co2 is 1600"));
+        assert!(lines[1].ends_with("RESOLVED co2 high: co2 is 1600"));
+    }
+
+    #[tokio::test]
+    async fn alerts_are_delivered_in_order() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.child("alerts.log");
+        let deliveries = Deliveries::start(vec![Sink::File { path: path.clone() }]);
+        for i in 0..10 {
+            deliveries.queue(&AlertEvent::Firing(Alert {
+                rule: format!("rule {i}"),
+                message: "broken".to_string(),
+                since: jiff::Timestamp::from_second(0).unwrap(),
+            }));
+        }
+
+        let lines = loop {
+            let written = std::fs::read_to_string(&path).unwrap_or_default();
+            let lines: Vec<_> = written.lines().map(str::to_owned).collect();
+            if lines.len() == 10 {
+                break lines;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        for (i, line) in lines.iter().enumerate() {
+            assert!(line.ends_with(&format!("rule {i}: broken")), "{line}");
+        }
+    }
+}
@@ -1,18 +1,18 @@
-use std::net::SocketAddr;
-
 use data_server::api::{ReconnectingClient, SubMessage};
 use tokio::sync::broadcast;
 
 use crate::controller::Event;
 
-pub async fn subscribe(event_tx: broadcast::Sender<Event>, data_server: SocketAddr) {
-    let mut sub = ReconnectingClient::new(data_server, "ha-brain".to_owned()).subscribe();
+pub async fn subscribe(event_tx: broadcast::Sender<Event>, data_server: ReconnectingClient) {
+    let mut sub = data_server.subscribe();
     loop {
         match sub.next().await {
             SubMessage::Reading(reading) => {
                 event_tx.send(Event::Sensor(reading)).unwrap();
             }
-            SubMessage::ErrorReport(_) | SubMessage::AffectorControlled { .. } => continue,
+            SubMessage::ErrorReport(_)
+            | SubMessage::AffectorControlled { .. }
+            | SubMessage::NodeDisconnected { .. } => continue,
         }
     }
 }
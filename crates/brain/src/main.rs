@@ -2,6 +2,7 @@ use std::array;
 use std::net::{IpAddr, SocketAddr};
 
 use clap::Parser;
+use data_server::api::{ConnectOptions, ReconnectingClient};
 use sled;
 use tokio::sync::broadcast;
 use tracing::warn;
@@ -19,6 +20,9 @@ struct Opt {
     #[clap(short, long)]
     data_server: SocketAddr,
 
+    #[clap(flatten)]
+    data_server_options: ConnectOptions,
+
     /// ip address for mpd server
     #[clap(short, long)]
     mpd_ip: IpAddr,
@@ -57,7 +61,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let system = system::System::init(jobs, opt.hue_bridge_ip);
     let _tasks = controller::start(subscribed_rxs, event_tx.clone(), system);
 
-    tokio::task::spawn(input::sensors::subscribe(event_tx, opt.data_server));
+    let data_server = ReconnectingClient::new(opt.data_server, "ha-brain".to_owned());
+    let data_server = opt.data_server_options.apply(data_server)?;
+    tokio::task::spawn(input::sensors::subscribe(event_tx, data_server));
     input::api::setup(wakeup.clone(), opt.port).await?;
 
     unreachable!();
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 pub mod client;
+pub use client::options::ConnectOptions;
 pub use client::reconnecting::Client as ReconnectingClient;
 pub use client::reconnecting::SubscribedClient as ReconnectingSubscribedClient;
 pub use client::Client;
@@ -32,6 +33,9 @@ pub enum SubMessage {
         controlled_by: String,
     },
     ErrorReport(Box<protocol::Error>),
+    /// The connection to a node closed. Lists every device the node sent a
+    /// reading or error for while it was connected.
+    NodeDisconnected { devices: Vec<protocol::Device> },
 }
 
 #[derive(Clone, Debug, thiserror::Error, Serialize, Deserialize)]
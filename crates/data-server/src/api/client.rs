@@ -8,9 +8,10 @@ use super::ServerError;
 use super::SubMessage;
 use super::SubscribeError;
 
+pub(crate) mod options;
 pub(crate) mod reconnecting;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Client(rpc::client::RpcClient<super::Request, super::Response>);
 
 impl Client {
@@ -22,6 +23,28 @@ impl Client {
         Ok(Self(rpc_client))
     }
 
+    /// Needed if the data-server was started with a key file
+    pub async fn connect_with_key(
+        addr: impl ToSocketAddrs,
+        name: String,
+        key: &rpc::auth::Key,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_with_key(addr, name, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// Needed if the data-server uses TLS, pass a `key` if it was also
+    /// started with a key file
+    pub async fn connect_tls(
+        addr: impl ToSocketAddrs,
+        name: String,
+        tls: &rpc::tls::ClientConfig,
+        key: Option<&rpc::auth::Key>,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_tls(addr, name, tls, key).await?;
+        Ok(Self(rpc_client))
+    }
+
     pub async fn actuate_affector(
         &mut self,
         affector: protocol::Affector,
@@ -49,18 +72,19 @@ impl Client {
         }
     }
 
-    pub async fn subscribe(mut self) -> Result<Subscribed, Error<SubscribeError>> {
-        self.0.subscribe().await?;
-        Ok(Subscribed(self))
+    /// The client stays usable while subscribed
+    pub async fn subscribe(&self) -> Result<Subscribed, Error<SubscribeError>> {
+        let subscription = self.0.subscribe(()).await?;
+        Ok(Subscribed(subscription))
     }
 }
 
 #[derive(Debug)]
-pub struct Subscribed(Client);
+pub struct Subscribed(rpc::client::Subscription<super::Request, super::Response>);
 
 impl Subscribed {
     pub async fn next(&mut self) -> Result<SubMessage, Error<SubscribeError>> {
-        let received = self.0 .0.next().await?;
+        let received = self.0.next().await?;
 
         if let Response::SubUpdate(update) = received {
             Ok(update)
@@ -71,6 +95,11 @@ impl Subscribed {
             })
         }
     }
+
+    /// Stops the updates, the client this came from is not affected
+    pub async fn unsubscribe(self) -> Result<(), Error<SubscribeError>> {
+        Ok(self.0.unsubscribe().await?)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
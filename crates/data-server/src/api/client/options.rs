@@ -0,0 +1,86 @@
+//! Command line options for binaries that connect to a data-server or a
+//! store
+use std::path::PathBuf;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use tokio::net::ToSocketAddrs;
+
+use super::reconnecting;
+
+/// How to connect to a server that requires authentication or TLS.
+/// Flatten this into the cli of a binary.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ConnectOptions {
+    /// Pre-shared key proving our name to the server, needed if it was
+    /// started with a key file. Formatted as 64 hex characters.
+    #[arg(long, conflicts_with = "key_file")]
+    key: Option<rpc::auth::Key>,
+
+    /// File containing only our pre-shared key, unlike `--key` this keeps
+    /// the key out of the process list
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+
+    /// PEM file with CA certificates, connect using TLS and trust the
+    /// server if one of these signed its certificate
+    #[arg(
+        long,
+        requires = "tls_server_name",
+        conflicts_with = "tls_pinned_certificate"
+    )]
+    tls_ca: Option<PathBuf>,
+
+    /// Name the certificate of the server must be valid for, a DNS name or
+    /// IP address
+    #[arg(long, requires = "tls_ca")]
+    tls_server_name: Option<String>,
+
+    /// PEM file with a certificate of the server, connect using TLS and trust
+    /// only a server presenting one of these. May be given multiple times.
+    #[arg(long)]
+    tls_pinned_certificate: Vec<PathBuf>,
+}
+
+impl ConnectOptions {
+    /// The key to authenticate with, if any
+    pub fn key(&self) -> Result<Option<rpc::auth::Key>> {
+        match &self.key_file {
+            Some(path) => rpc::auth::Key::load(path).map(Some),
+            None => Ok(self.key.clone()),
+        }
+    }
+
+    /// How to connect using TLS, None to connect without
+    pub fn tls(&self) -> Result<Option<rpc::tls::ClientConfig>> {
+        let ca = self.tls_ca.as_deref().zip(self.tls_server_name.as_deref());
+        if let Some((ca, server_name)) = ca {
+            rpc::tls::ClientConfig::with_ca(ca, server_name).map(Some)
+        } else if !self.tls_pinned_certificate.is_empty() {
+            rpc::tls::ClientConfig::pinned(&self.tls_pinned_certificate).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Loads the key and TLS certificates, if any, and has `client` use them
+    pub fn apply(&self, mut client: reconnecting::Client) -> Result<reconnecting::Client> {
+        if let Some(key) = self.key()? {
+            client = client.with_key(key);
+        }
+        if let Some(tls) = self.tls()? {
+            client = client.with_tls(tls);
+        }
+        Ok(client)
+    }
+
+    pub async fn connect(&self, addr: impl ToSocketAddrs, name: String) -> Result<super::Client> {
+        let key = self.key()?;
+        let client = match (self.tls()?, &key) {
+            (Some(tls), key) => super::Client::connect_tls(addr, name, &tls, key.as_ref()).await,
+            (None, Some(key)) => super::Client::connect_with_key(addr, name, key).await,
+            (None, None) => super::Client::connect(addr, name).await,
+        };
+        client.wrap_err("Could not connect to data-server")
+    }
+}
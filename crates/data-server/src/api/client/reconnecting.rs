@@ -11,6 +11,8 @@ pub struct Client {
     connection: Option<super::Client>,
     addr: SocketAddr,
     name: String,
+    key: Option<rpc::auth::Key>,
+    tls: Option<rpc::tls::ClientConfig>,
 }
 
 impl Client {
@@ -21,9 +23,26 @@ impl Client {
             connection: None,
             addr,
             name,
+            key: None,
+            tls: None,
         }
     }
 
+    /// Authenticate using this key, needed if the data-server was started
+    /// with a key file
+    #[must_use]
+    pub fn with_key(mut self, key: rpc::auth::Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Connect using TLS, needed if the data-server uses it
+    #[must_use]
+    pub fn with_tls(mut self, tls: rpc::tls::ClientConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
     #[must_use]
     pub fn subscribe(self) -> SubscribedClient {
         SubscribedClient {
@@ -31,6 +50,8 @@ impl Client {
             connection: self.connection.map(ConnState::Connected),
             addr: self.addr,
             name: self.name,
+            key: self.key,
+            tls: self.tls,
         }
     }
 
@@ -43,7 +64,14 @@ impl Client {
             let mut conn = if let Some(conn) = self.connection.take() {
                 conn
             } else {
-                get_connection_or_reconnect(self.addr, &self.name, &mut self.retry_period).await
+                get_connection_or_reconnect(
+                    self.addr,
+                    &self.name,
+                    self.key.as_ref(),
+                    self.tls.as_ref(),
+                    &mut self.retry_period,
+                )
+                .await
             };
 
             match conn.actuate_affector(affector).await {
@@ -69,7 +97,14 @@ impl Client {
             let mut conn = if let Some(conn) = self.connection.take() {
                 conn
             } else {
-                get_connection_or_reconnect(self.addr, &self.name, &mut self.retry_period).await
+                get_connection_or_reconnect(
+                    self.addr,
+                    &self.name,
+                    self.key.as_ref(),
+                    self.tls.as_ref(),
+                    &mut self.retry_period,
+                )
+                .await
             };
 
             match conn.list_affectors().await {
@@ -99,6 +134,8 @@ pub struct SubscribedClient {
     connection: Option<ConnState>,
     addr: SocketAddr,
     name: String,
+    key: Option<rpc::auth::Key>,
+    tls: Option<rpc::tls::ClientConfig>,
 }
 
 impl SubscribedClient {
@@ -108,8 +145,14 @@ impl SubscribedClient {
                 conn
             } else {
                 ConnState::Connected(
-                    get_connection_or_reconnect(self.addr, &self.name, &mut self.retry_period)
-                        .await,
+                    get_connection_or_reconnect(
+                        self.addr,
+                        &self.name,
+                        self.key.as_ref(),
+                        self.tls.as_ref(),
+                        &mut self.retry_period,
+                    )
+                    .await,
                 )
             };
 
@@ -139,15 +182,23 @@ impl SubscribedClient {
 async fn get_connection_or_reconnect(
     addr: SocketAddr,
     name: &str,
+    key: Option<&rpc::auth::Key>,
+    tls: Option<&rpc::tls::ClientConfig>,
     retry_period: &mut Duration,
 ) -> super::Client {
     loop {
-        match timeout(
-            Duration::from_millis(500),
-            super::Client::connect(addr, name.to_owned()),
-        )
-        .await
-        {
+        let connect = async {
+            match (tls, key) {
+                (Some(tls), key) => {
+                    super::Client::connect_tls(addr, name.to_owned(), tls, key).await
+                }
+                (None, Some(key)) => {
+                    super::Client::connect_with_key(addr, name.to_owned(), key).await
+                }
+                (None, None) => super::Client::connect(addr, name.to_owned()).await,
+            }
+        };
+        match timeout(Duration::from_millis(500), connect).await {
             Ok(Ok(conn)) => {
                 info!("Successfully (re)connected to data-server");
                 return conn;
@@ -1,4 +1,5 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 use tokio::select;
 use tokio::sync::mpsc;
@@ -21,6 +22,21 @@ struct Cli {
     /// addr to which data-source can supply msg's
     #[arg(short, long)]
     update_addr: SocketAddr,
+
+    /// file with a pre-shared key per client, if given subscribers must
+    /// prove they have the key for the name they connect with. See the
+    /// docs of `rpc::auth` for the format.
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+
+    /// PEM file with the certificate (chain) of this server, if given
+    /// subscribers must connect using TLS
+    #[arg(long, requires = "tls_key")]
+    tls_certificate: Option<PathBuf>,
+
+    /// PEM file with the private key belonging to the certificate
+    #[arg(long, requires = "tls_certificate")]
+    tls_key: Option<PathBuf>,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -30,8 +46,19 @@ async fn main() -> Result<()> {
     let Cli {
         subscribe_addr,
         update_addr,
+        key_file,
+        tls_certificate,
+        tls_key,
     } = Cli::parse();
     assert_ne!(subscribe_addr, update_addr);
+    let keys = key_file
+        .as_deref()
+        .map(rpc::auth::Keys::load)
+        .transpose()?;
+    let tls = tls_certificate
+        .zip(tls_key)
+        .map(|(certificate, key)| rpc::tls::ServerConfig::load(&certificate, &key))
+        .transpose()?;
 
     info!("listening for updates on: {update_addr}");
     info!("serving subscribers on: {subscribe_addr}");
@@ -39,7 +66,7 @@ async fn main() -> Result<()> {
     let affectors = server::AffectorRegistar::default();
     let (tx, rx) = mpsc::channel(2000);
     select! {
-        e = server::client::handle(subscribe_addr, tx.clone(), affectors.clone()) => e,
+        e = server::client::handle(subscribe_addr, tx.clone(), affectors.clone(), keys, tls) => e,
         e = server::handle_nodes(update_addr, &tx, affectors) => e,
         e = server::spread_updates(rx) => e,
     }
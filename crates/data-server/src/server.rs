@@ -34,4 +34,7 @@ pub enum Event {
         affector: protocol::Affector,
         controlled_by: String,
     },
+    NodeDisconnected {
+        devices: Vec<protocol::Device>,
+    },
 }
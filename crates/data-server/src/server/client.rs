@@ -32,9 +32,11 @@ async fn do_setup(
 
 impl rpc::SubscriberHandler for SubHandler {
     type Update = crate::api::Response;
+    type Request = ();
 
     fn setup(
         &mut self,
+        _: Self::Request,
     ) -> impl std::future::Future<
         Output = impl futures::prelude::Stream<Item = Self::Update> + Send + 'static,
     > + Send
@@ -43,10 +45,14 @@ impl rpc::SubscriberHandler for SubHandler {
     }
 }
 
+/// With `keys` clients must authenticate, the name they proved is recorded
+/// as who controlled an affector. With `tls` clients must connect using TLS.
 pub async fn handle(
     addr: SocketAddr,
     tx: mpsc::Sender<Event>,
     affectors: Registar,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
 ) -> color_eyre::Result<()> {
     let port = addr.port();
     let handler = SubHandler {
@@ -54,6 +60,8 @@ pub async fn handle(
     };
     rpc::server::run(
         port,
+        keys,
+        tls,
         move |req, name| {
             let tx = tx.clone();
             let affectors = affectors.clone();
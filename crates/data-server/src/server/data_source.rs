@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::net::SocketAddr;
 
 use color_eyre::eyre::{eyre, Context};
@@ -82,14 +83,22 @@ async fn handle_node(
         registar.update_affectors(key, affector);
     }
 
+    let mut devices = HashSet::new();
     (
-        receive_and_spread_updates(reader, queue).in_current_span(),
+        receive_and_spread_updates(reader, &queue, &mut devices).in_current_span(),
         control_affectors(writer, rx).in_current_span(),
     )
         .race()
         .await;
 
     registar.remove(key);
+    if !devices.is_empty() {
+        let devices = devices.into_iter().collect();
+        queue
+            .send(Event::NodeDisconnected { devices })
+            .await
+            .expect("fn spread_updates should stay running");
+    }
 }
 
 async fn handshake(reader: &mut BufReader<OwnedReadHalf>) -> Result<Vec<Affector>, String> {
@@ -108,7 +117,11 @@ async fn handshake(reader: &mut BufReader<OwnedReadHalf>) -> Result<Vec<Affector
 }
 
 #[instrument(skip_all)]
-async fn receive_and_spread_updates(mut reader: BufReader<OwnedReadHalf>, queue: Sender<Event>) {
+async fn receive_and_spread_updates(
+    mut reader: BufReader<OwnedReadHalf>,
+    queue: &Sender<Event>,
+    devices: &mut HashSet<protocol::Device>,
+) {
     let mut buf = Vec::new();
     loop {
         let msg = match read_and_decode_packet(&mut reader, &mut buf).await {
@@ -122,6 +135,7 @@ async fn receive_and_spread_updates(mut reader: BufReader<OwnedReadHalf>, queue:
         match msg {
             protocol::Msg::Readings(list) => {
                 for value in list.values {
+                    devices.insert(value.device());
                     queue
                         .send(Event::NewReading(Ok(value)))
                         .await
@@ -129,6 +143,7 @@ async fn receive_and_spread_updates(mut reader: BufReader<OwnedReadHalf>, queue:
                 }
             }
             protocol::Msg::ErrorReport(report) => {
+                devices.insert(report.error.device());
                 let boxed = Box::new(report.error);
                 queue
                     .send(Event::NewReading(Err(boxed)))
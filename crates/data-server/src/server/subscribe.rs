@@ -31,6 +31,7 @@ pub async fn spread_updates(mut events: mpsc::Receiver<Event>) -> Result<()> {
                 affector,
                 controlled_by,
             },
+            Event::NodeDisconnected { devices } => SubMessage::NodeDisconnected { devices },
         };
 
         let subs = mem::take(&mut subscribers);
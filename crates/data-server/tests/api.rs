@@ -7,6 +7,8 @@ use data_server::server::{self, AffectorRegistar};
 use protocol::large_bedroom;
 use protocol::large_bedroom::bed;
 use protocol::Reading;
+use rpc::auth::{Key, Keys};
+use rpc::client::ConnectError;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::select;
@@ -23,11 +25,19 @@ enum Done {
 async fn run_server(
     client_addr: impl Into<SocketAddr>,
     data_port: impl Into<SocketAddr>,
+    keys: Option<Keys>,
 ) -> Result<Done> {
     let (tx, rx) = mpsc::channel(2000);
     let affectors = AffectorRegistar::default();
+    let clients = server::client::handle(
+        client_addr.into(),
+        tx.clone(),
+        affectors.clone(),
+        keys,
+        None,
+    );
     select! {
-        e = server::client::handle(client_addr.into(), tx.clone(), affectors.clone()) => e.unwrap(),
+        e = clients => e.unwrap(),
         e = server::handle_nodes(data_port.into(), &tx, affectors) => e.unwrap(),
         e = server::spread_updates(rx) => e?,
     };
@@ -63,19 +73,22 @@ async fn send_sensor_value(data_port: u16) -> Result<Done> {
 
 async fn subscribe_and_receive_inner(sub_port: u16) -> Result<Done> {
     tokio::time::sleep(Duration::from_millis(100)).await;
-    let mut sub = Client::connect(
+    let mut client = Client::connect(
         (Ipv4Addr::LOCALHOST, sub_port),
         "api_integration_tests".to_owned(),
     )
     .await
-    .unwrap()
-    .subscribe()
-    .await
     .unwrap();
+    let mut sub = client.subscribe().await.unwrap();
 
     let received = sub.next().await.unwrap();
     assert!(matches!(received, SubMessage::Reading(TEST_READING)));
 
+    // the client still serves calls while subscribed
+    let list = client.list_affectors().await.unwrap();
+    assert_eq!(list.len(), 1);
+    sub.unsubscribe().await.unwrap();
+
     Ok(Done::Test)
 }
 
@@ -101,6 +114,34 @@ async fn list_affectors_inner(sub_port: u16) -> Result<Done> {
     Ok(Done::Test)
 }
 
+const TEST_KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+async fn authenticate_inner(sub_port: u16) -> Result<Done> {
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let addr = (Ipv4Addr::LOCALHOST, sub_port);
+    let name = "api_integration_tests".to_owned();
+
+    let err = Client::connect(addr, name.clone()).await.unwrap_err();
+    assert!(matches!(err, ConnectError::KeyNeeded), "got: {err}");
+
+    let wrong: Key = "ff".repeat(32).parse().unwrap();
+    let err = Client::connect_with_key(addr, name.clone(), &wrong)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ConnectError::AuthFailed), "got: {err}");
+
+    let key: Key = TEST_KEY.parse().unwrap();
+    let list = Client::connect_with_key(addr, name, &key)
+        .await
+        .unwrap()
+        .list_affectors()
+        .await
+        .unwrap();
+    assert_eq!(list.len(), 1);
+
+    Ok(Done::Test)
+}
+
 #[tokio::test]
 async fn subscribe_and_receive() {
     setup_tracing();
@@ -108,7 +149,7 @@ async fn subscribe_and_receive() {
     let sub_port = reserve_port::ReservedPort::random().unwrap();
     let data_port = reserve_port::ReservedPort::random().unwrap();
     let res = select! {
-        e = run_server(([127,0,0,1], sub_port.port()), ([127,0,0,1], data_port.port())) => e,
+        e = run_server(([127,0,0,1], sub_port.port()), ([127,0,0,1], data_port.port()), None) => e,
         e = send_sensor_value(data_port.port()) => e,
         e = subscribe_and_receive_inner(sub_port.port()) => e,
     };
@@ -122,13 +163,33 @@ async fn list_affectors() {
     let sub_port = reserve_port::ReservedPort::random().unwrap();
     let data_port = reserve_port::ReservedPort::random().unwrap();
     let res = select! {
-        e = run_server(([127,0,0,1], sub_port.port()), ([127,0,0,1], data_port.port())) => e,
+        e = run_server(([127,0,0,1], sub_port.port()), ([127,0,0,1], data_port.port()), None) => e,
         e = send_sensor_value(data_port.port()) => e,
         e = list_affectors_inner(sub_port.port()) => e,
     };
     assert_eq!(res.unwrap(), Done::Test);
 }
 
+#[tokio::test]
+async fn authenticate() {
+    setup_tracing();
+
+    let keys: Keys = format!("api_integration_tests {TEST_KEY}").parse().unwrap();
+    let sub_port = reserve_port::ReservedPort::random().unwrap();
+    let data_port = reserve_port::ReservedPort::random().unwrap();
+    let server = run_server(
+        ([127, 0, 0, 1], sub_port.port()),
+        ([127, 0, 0, 1], data_port.port()),
+        Some(keys),
+    );
+    let res = select! {
+        e = server => e,
+        e = send_sensor_value(data_port.port()) => e,
+        e = authenticate_inner(sub_port.port()) => e,
+    };
+    assert_eq!(res.unwrap(), Done::Test);
+}
+
 fn setup_tracing() {
     use std::sync::Once;
     use tracing_error::ErrorLayer;
@@ -1,6 +1,7 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
-use protocol::Reading;
+use protocol::{Device, Reading};
 
 use serde::{Deserialize, Serialize};
 
@@ -14,32 +15,245 @@ pub(crate) enum Request {
     },
     ListData,
     GetData {
-        reading: Reading,
+        reading: AnyReading,
         start: jiff::Timestamp,
         end: jiff::Timestamp,
         n: usize,
     },
+    /// Like GetData but averages the data into buckets of a fixed duration
+    /// aligned to the unix epoch. The same bucket gets the same value no
+    /// matter the range it is requested in. Answered with GetData, the time
+    /// of each point is the start of its bucket. Empty buckets are left out.
+    GetDataAligned {
+        reading: AnyReading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        bucket: Duration,
+    },
+    /// Aggregates the data in buckets whose edges fall on wall-clock
+    /// boundaries in `time_zone`
+    GetBuckets {
+        reading: Reading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        size: BucketSize,
+        /// IANA name, for example: Europe/Amsterdam
+        time_zone: String,
+    },
+    GetStats {
+        readings: Vec<Reading>,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    },
+    ListGaps {
+        device: Device,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        /// defaults to the devices max sample interval, if smaller then
+        /// that the max sample interval is used
+        min_gap: Option<Duration>,
+    },
+    /// File sizes, encoding and write rate of every stored series
+    StorageStats,
+    /// Write a tarball of all data to the snapshot dir of the data-store.
+    /// The data-store picks the name and returns where it put it.
+    Snapshot,
+}
+
+/// Send when subscribing. The subscription first returns all data
+/// since `start` then every newly stored value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SubscribeRequest {
+    pub(crate) readings: Vec<Reading>,
+    pub(crate) start: jiff::Timestamp,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
 pub enum ServerError {
     #[error("We do not have any data for this reading: {reading:?}")]
     NotInStore { reading: Reading },
+    #[error("We do not have any data for this device: {device:?}")]
+    DeviceNotInStore { device: Device },
     #[error("Internal error while reading data, error: {0}")]
     ReadingFromStore(String),
     #[error("Connect request should only be send once")]
     AlreadyConnected,
     #[error("Too many requests, rate limited, next requested allowed in: {0:?}")]
     TooManyRequests(Duration),
+    #[error("Subscriber could not keep up, missed {0} newly stored lines")]
+    SubscriberLagged(u64),
+    #[error("Could not create snapshot: {0}")]
+    Snapshot(String),
+    #[error("Can not split the range into buckets: {0}")]
+    InvalidBuckets(String),
+    #[error("There is no virtual reading called: {name}")]
+    UnknownVirtualReading { name: String },
+}
+
+/// A reading to get data for, stored or virtual
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AnyReading {
+    Stored(Reading),
+    /// Named by [`VirtualReading::name`]
+    Virtual(String),
+}
+
+impl From<Reading> for AnyReading {
+    fn from(reading: Reading) -> Self {
+        Self::Stored(reading)
+    }
+}
+
+/// A reading that is not stored but computed from stored readings each time
+/// it is requested. Only GetData and GetDataAligned serve virtual readings,
+/// the other requests take stored readings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VirtualReading {
+    pub name: String,
+    /// For example: `largebedroom/bed/sht31/Temperature - outside/sht31/Temperature`
+    pub expression: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Percentile {
+    /// between 0 and 100
+    pub percentile: f32,
+    pub value: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValueStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub std_dev: f32,
+    pub percentiles: Vec<Percentile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stats {
+    pub reading: Reading,
+    /// number of stored values in the range
+    pub count: u64,
+    /// false if computed from the downsampled cache, min, max and the
+    /// percentiles are then those of the averaged buckets.
+    pub exact: bool,
+    pub first: Option<jiff::Timestamp>,
+    pub last: Option<jiff::Timestamp>,
+    /// sum of all stretches in the range without data longer then the
+    /// devices max sample interval.
+    pub total_gap: Duration,
+    /// None if there was no data in the range
+    pub values: Option<ValueStats>,
+}
+
+/// A stretch of time without any data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gap {
+    /// time of the last line before the gap (or the start of the range)
+    pub start: jiff::Timestamp,
+    /// time of the first line after the gap (or the end of the range)
+    pub end: jiff::Timestamp,
+}
+
+/// How to split a range into buckets. Buckets are aligned to local time,
+/// a day is 23 or 25 hours long when the clocks change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BucketSize {
+    /// Buckets start at local midnight and then every `Duration`, the last
+    /// bucket of the day is cut short at the next midnight. Must be between
+    /// a minute and a day.
+    Duration(Duration),
+    Hour,
+    Day,
+    /// Starting on monday
+    Week,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketValues {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bucket {
+    pub start: jiff::Timestamp,
+    /// exclusive, the start of the next bucket
+    pub end: jiff::Timestamp,
+    /// false if computed from the downsampled cache, min and max
+    /// are then those of averaged lines.
+    pub exact: bool,
+    /// None if there was no data in the bucket
+    pub values: Option<BucketValues>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredFile {
+    /// File name, the series name followed by what the file holds
+    pub name: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldStorage {
+    pub reading: Reading,
+    /// Bits a value takes up in a line
+    pub bits: u8,
+}
+
+/// How a device's series is stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesStorage {
+    pub device: Device,
+    /// Relative to the data dir without extension
+    pub path: PathBuf,
+    /// The raw data and each downsampled cache
+    pub files: Vec<StoredFile>,
+    /// Raw lines in the series, not counting those removed by retention
+    pub lines: u64,
+    pub first: Option<jiff::Timestamp>,
+    pub last: Option<jiff::Timestamp>,
+    pub fields: Vec<FieldStorage>,
+    /// Size of a line without its timestamp
+    pub payload_bytes: usize,
+    /// Averaged over the last 24 hours
+    pub lines_per_hour: f32,
+}
+
+/// A tarball with a point in time copy of the data dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// On the machine running the data-store
+    pub path: PathBuf,
+    pub files: usize,
+    /// Size of the files in the snapshot before archiving
+    pub bytes: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum Response {
-    ListData(Vec<Reading>),
+    ListData {
+        stored: Vec<Reading>,
+        /// Only those for which all inputs are stored
+        virtual_readings: Vec<VirtualReading>,
+    },
     GetData {
         time: Vec<jiff::Timestamp>,
         data: Vec<f32>,
     },
+    GetBuckets(Vec<Bucket>),
+    GetStats(Vec<Stats>),
+    ListGaps(Vec<Gap>),
+    StorageStats(Vec<SeriesStorage>),
+    Snapshot(Snapshot),
     Error(ServerError),
     Handshake,
+    /// Only send to subscribers
+    SubUpdate {
+        reading: Reading,
+        time: Vec<jiff::Timestamp>,
+        data: Vec<f32>,
+    },
 }
@@ -1,14 +1,21 @@
+use std::time::Duration;
+
 use rpc::client::RpcClient;
 use tokio::net::ToSocketAddrs;
 
-use super::Response;
+use super::{AnyReading, Response, ServerError, SubscribeRequest};
 
-pub struct Client(rpc::client::RpcClient<super::Request, super::Response>);
+#[derive(Clone)]
+pub struct Client(
+    rpc::client::RpcClient<super::Request, super::Response, super::SubscribeRequest>,
+);
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Got unexpected response response to request {request:?}")]
     IncorrectResponse { request: String, response: String },
+    #[error("Server ran into an error: {0}")]
+    Server(ServerError),
     #[error("Error while communicating with server: {0}")]
     Comms(#[from] rpc::client::RpcError),
 }
@@ -22,10 +29,54 @@ impl Client {
         Ok(Self(rpc_client))
     }
 
+    /// Needed if the data-store was started with a client key file
+    pub async fn connect_with_key(
+        addr: impl ToSocketAddrs,
+        name: String,
+        key: &rpc::auth::Key,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_with_key(addr, name, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// Needed if the data-store uses TLS, pass a `key` if it was also
+    /// started with a client key file
+    pub async fn connect_tls(
+        addr: impl ToSocketAddrs,
+        name: String,
+        tls: &rpc::tls::ClientConfig,
+        key: Option<&rpc::auth::Key>,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_tls(addr, name, tls, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// The connection failed, make a new one
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
     pub async fn list_data(&mut self) -> Result<Vec<protocol::Reading>, Error> {
         let request = super::Request::ListData;
         match self.0.send_receive(request.clone()).await? {
-            Response::ListData(list) => Ok(list),
+            Response::ListData { stored, .. } => Ok(stored),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// The virtual readings that can be requested with
+    /// [`get_data`](Self::get_data) and
+    /// [`get_data_aligned`](Self::get_data_aligned)
+    pub async fn list_virtual(&mut self) -> Result<Vec<super::VirtualReading>, Error> {
+        let request = super::Request::ListData;
+        match self.0.send_receive(request.clone()).await? {
+            Response::ListData {
+                virtual_readings, ..
+            } => Ok(virtual_readings),
             response => Err(Error::IncorrectResponse {
                 request: format!("{request:?}"),
                 response: format!("{response:?}"),
@@ -37,21 +88,204 @@ impl Client {
         &mut self,
         start: jiff::Timestamp,
         end: jiff::Timestamp,
-        reading: protocol::Reading,
+        reading: impl Into<AnyReading>,
         n: usize,
     ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>), Error> {
         let request = super::Request::GetData {
-            reading,
+            reading: reading.into(),
             start,
             end,
             n,
         };
         match self.0.send_receive(request.clone()).await? {
             Response::GetData { time, data } => Ok((time, data)),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Mean of `reading` in buckets of duration `bucket`. Buckets start at
+    /// multiples of `bucket` since the unix epoch so panning does not change
+    /// their values. Time is the start of each bucket, empty buckets are
+    /// left out.
+    pub async fn get_data_aligned(
+        &mut self,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        reading: impl Into<AnyReading>,
+        bucket: Duration,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>), Error> {
+        let request = super::Request::GetDataAligned {
+            reading: reading.into(),
+            start,
+            end,
+            bucket,
+        };
+        match self.0.send_receive(request.clone()).await? {
+            Response::GetData { time, data } => Ok((time, data)),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Min, max and mean of `reading` per bucket. Bucket edges fall on
+    /// wall-clock boundaries in `time_zone`, an IANA name such as
+    /// `Europe/Amsterdam`.
+    pub async fn get_buckets(
+        &mut self,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        reading: protocol::Reading,
+        size: super::BucketSize,
+        time_zone: String,
+    ) -> Result<Vec<super::Bucket>, Error> {
+        let request = super::Request::GetBuckets {
+            reading,
+            start,
+            end,
+            size,
+            time_zone,
+        };
+        match self.0.send_receive(request.clone()).await? {
+            Response::GetBuckets(buckets) => Ok(buckets),
+            Response::Error(e) => Err(Error::Server(e)),
             response => Err(Error::IncorrectResponse {
                 request: format!("{request:?}"),
                 response: format!("{response:?}"),
             }),
         }
     }
+
+    pub async fn get_stats(
+        &mut self,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        readings: Vec<protocol::Reading>,
+    ) -> Result<Vec<super::Stats>, Error> {
+        let request = super::Request::GetStats {
+            readings,
+            start,
+            end,
+        };
+        match self.0.send_receive(request.clone()).await? {
+            Response::GetStats(stats) => Ok(stats),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Every stretch without data longer then `min_gap`, or the devices
+    /// max sample interval if that is larger or `min_gap` is None.
+    pub async fn list_gaps(
+        &mut self,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        device: protocol::Device,
+        min_gap: Option<std::time::Duration>,
+    ) -> Result<Vec<super::Gap>, Error> {
+        let request = super::Request::ListGaps {
+            device,
+            start,
+            end,
+            min_gap,
+        };
+        match self.0.send_receive(request.clone()).await? {
+            Response::ListGaps(gaps) => Ok(gaps),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// How every series is stored and how fast it grows
+    pub async fn storage_stats(&mut self) -> Result<Vec<super::SeriesStorage>, Error> {
+        let request = super::Request::StorageStats;
+        match self.0.send_receive(request.clone()).await? {
+            Response::StorageStats(stats) => Ok(stats),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Makes the data-store write a snapshot of all its data to its
+    /// snapshot dir. Returns where, on the machine running the data-store.
+    pub async fn snapshot(&mut self) -> Result<super::Snapshot, Error> {
+        let request = super::Request::Snapshot;
+        match self.0.send_receive(request.clone()).await? {
+            Response::Snapshot(snapshot) => Ok(snapshot),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Subscribe to the data for `readings`. First returns everything stored
+    /// since `start` then every new value as it is stored.
+    pub async fn subscribe(
+        &self,
+        readings: Vec<protocol::Reading>,
+        start: jiff::Timestamp,
+    ) -> Result<Subscribed, Error> {
+        let subscription = self
+            .0
+            .subscribe(SubscribeRequest { readings, start })
+            .await?;
+        Ok(Subscribed(subscription))
+    }
+}
+
+pub struct Subscribed(
+    rpc::client::Subscription<super::Request, super::Response, super::SubscribeRequest>,
+);
+
+impl Subscribed {
+    /// The history is returned in one or more batches, after that
+    /// every update contains a single value.
+    pub async fn next(
+        &mut self,
+    ) -> Result<(protocol::Reading, Vec<jiff::Timestamp>, Vec<f32>), Error> {
+        match self.0.next().await? {
+            Response::SubUpdate {
+                reading,
+                time,
+                data,
+            } => Ok((reading, time, data)),
+            Response::Error(e) => Err(Error::Server(e)),
+            response => Err(Error::IncorrectResponse {
+                request: "none, we are subscribed".to_string(),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+    /// Switch to the data for other `readings` without reconnecting. Like
+    /// [`Client::subscribe`] this first returns what was stored since
+    /// `start`.
+    pub async fn change(
+        &mut self,
+        readings: Vec<protocol::Reading>,
+        start: jiff::Timestamp,
+    ) -> Result<(), Error> {
+        Ok(self.0.change(SubscribeRequest { readings, start }).await?)
+    }
+
+    /// Stops the updates, the client this came from is not affected
+    pub async fn unsubscribe(self) -> Result<(), Error> {
+        Ok(self.0.unsubscribe().await?)
+    }
 }
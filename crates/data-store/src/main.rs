@@ -1,32 +1,277 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
-use color_eyre::eyre::Result;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{Result, WrapErr};
+use data_server::api::{ConnectOptions, ReconnectingClient};
+use data_store::api::Client;
+use data_store::server::export;
+use data_store::server::import;
+use data_store::server::retention;
+use data_store::server::snapshot;
+use data_store::server::verify;
+use data_store::server::virtual_readings;
 
 #[derive(Parser, Debug)]
 #[command(name = "data server")]
 #[command(version = "1.0")]
 #[command(about = "Receives sensor events and spreads those to subscribed services")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
     /// data server
-    #[arg(short, long)]
-    data_server: SocketAddr,
+    #[arg(short, long, required = true)]
+    data_server: Option<SocketAddr>,
 
-    #[arg(short, long)]
-    client_port: u16,
+    #[command(flatten)]
+    data_server_options: ConnectOptions,
+
+    #[arg(short, long, required = true)]
+    client_port: Option<u16>,
+
+    /// file with a pre-shared key per client, if given clients must prove
+    /// they have the key for the name they connect with. See the docs of
+    /// `rpc::auth` for the format.
+    #[arg(long)]
+    client_key_file: Option<PathBuf>,
+
+    /// PEM file with the certificate (chain) of this server, if given
+    /// clients must connect using TLS
+    #[arg(long, requires = "client_tls_key")]
+    client_tls_certificate: Option<PathBuf>,
+
+    /// PEM file with the private key belonging to the certificate
+    #[arg(long, requires = "client_tls_certificate")]
+    client_tls_key: Option<PathBuf>,
 
     #[arg(long, default_value = ".")]
     data_dir: PathBuf,
+
+    /// Where snapshots requested by clients are written to
+    #[arg(long, default_value = "snapshots")]
+    snapshot_dir: PathBuf,
+
+    /// How long to keep data at each resolution. Older data is removed,
+    /// reads fall back to the next level. Levels not mentioned are kept forever,
+    /// as is data averaged over 1000 lines.
+    /// For example: raw=90d,10x=2y
+    #[arg(long, default_value = "")]
+    retention: retention::Policy,
+
+    /// Overrides the retention policy for one device, may be given multiple
+    /// times. For example: largebedroom/bed/sht31:raw=30d
+    #[arg(long)]
+    device_retention: Vec<retention::DevicePolicy>,
+
+    /// Seconds between syncing new lines to disk. Lines that have not been
+    /// synced yet are lost on a crash or power cut. Queries return them once
+    /// synced, subscribers get them straight away.
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    fsync_interval: u64,
+
+    /// A reading computed from stored readings when it is requested, may
+    /// be given multiple times. Clients list and get it by its name, which
+    /// may not be that of a stored reading. Readings are referred to by the
+    /// path of their series and their name. For example:
+    /// dew=dew_point(largebedroom/bed/sht31/Temperature, largebedroom/bed/sht31/Humidity)
+    /// Supports + - * / ^, parentheses and the functions: abs, sqrt, ln,
+    /// exp, min, max, c_to_f, f_to_c and dew_point(temperature, humidity).
+    #[arg(long)]
+    virtual_reading: Vec<virtual_readings::Definition>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the data of one device to a csv or jsonl file.
+    Export {
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+        /// Path of the series relative to the data dir,
+        /// for example: largebedroom/bed/sht31
+        #[arg(short, long)]
+        reading: PathBuf,
+        /// For example: 2024-06-01T00:00:00Z
+        #[arg(long)]
+        from: jiff::Timestamp,
+        /// Defaults to now
+        #[arg(long)]
+        to: Option<jiff::Timestamp>,
+        #[arg(long, value_enum, default_value_t = export::Format::Csv)]
+        format: export::Format,
+        /// Defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Append the data in a csv file (as made by export) to the series of a
+    /// device. Stop the data-store before importing.
+    Import {
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+        /// Path of the series relative to the data dir,
+        /// for example: largebedroom/bed/sht31
+        #[arg(short, long)]
+        reading: PathBuf,
+        file: PathBuf,
+        /// Only check the file, do not write anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Make the running data-store write a snapshot of all its data to its
+    /// snapshot dir. New data keeps being stored while the snapshot is made.
+    Snapshot {
+        /// Address of the data-store's client port
+        #[arg(short, long)]
+        server: SocketAddr,
+        #[command(flatten)]
+        options: ConnectOptions,
+    },
+    /// Replace the data dir with the content of a snapshot. Stop the
+    /// data-store first. The current data dir is moved aside, not removed.
+    Restore {
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+        snapshot: PathBuf,
+    },
+    /// Check every series in the data dir for corruption. Stop the
+    /// data-store first.
+    Verify {
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+        /// Drop torn journal blocks and replay lines missing from a series
+        #[arg(long)]
+        repair: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     setup_tracing().unwrap();
     let cli = Cli::parse();
-    tracing::info!("started data-server, args: {cli:?}");
 
-    data_store::server::run(cli.data_server, cli.client_port, &cli.data_dir).await
+    match cli.command {
+        None => {
+            tracing::info!("started data-server, args: {cli:?}");
+            let data_server = cli.data_server.expect("required by clap");
+            let data_server = ReconnectingClient::new(data_server, "ha-data-store".to_string());
+            let data_server = cli.data_server_options.apply(data_server)?;
+            let client_port = cli.client_port.expect("required by clap");
+            let keys = cli.client_key_file
+                .as_deref()
+                .map(rpc::auth::Keys::load)
+                .transpose()?;
+            let tls = cli.client_tls_certificate
+                .zip(cli.client_tls_key)
+                .map(|(certificate, key)| rpc::tls::ServerConfig::load(&certificate, &key))
+                .transpose()?;
+            let retention = retention::Config::new(cli.retention, cli.device_retention);
+            let fsync_interval = Duration::from_secs(cli.fsync_interval);
+            data_store::server::run(
+                data_server,
+                client_port,
+                keys,
+                tls,
+                &cli.data_dir,
+                &cli.snapshot_dir,
+                retention,
+                fsync_interval,
+                cli.virtual_reading,
+            )
+            .await
+        }
+        Some(Command::Export {
+            data_dir,
+            reading,
+            from,
+            to,
+            format,
+            output,
+        }) => {
+            let to = to.unwrap_or_else(jiff::Timestamp::now);
+            let exported = if let Some(path) = output {
+                let file = File::create(&path).wrap_err("Could not create output file")?;
+                export::export(&data_dir, &reading, from, to, format, BufWriter::new(file))?
+            } else {
+                export::export(&data_dir, &reading, from, to, format, io::stdout().lock())?
+            };
+            eprintln!("exported {exported} lines");
+            Ok(())
+        }
+        Some(Command::Import {
+            data_dir,
+            reading,
+            file,
+            dry_run,
+        }) => {
+            let input = File::open(&file).wrap_err("Could not open input file")?;
+            let report = import::import(&data_dir, &reading, BufReader::new(input), dry_run)?;
+            for (line, rejection) in &report.rejected {
+                println!("skipped line {line}: {rejection}");
+            }
+            let verb = if dry_run { "would import" } else { "imported" };
+            println!(
+                "{verb} {} lines, skipped {} lines",
+                report.imported,
+                report.rejected.len()
+            );
+            Ok(())
+        }
+        Some(Command::Snapshot { server, options }) => {
+            let name = "snapshot cli".to_string();
+            let key = options.key()?;
+            let client = match (options.tls()?, &key) {
+                (Some(tls), key) => Client::connect_tls(server, name, &tls, key.as_ref()).await,
+                (None, Some(key)) => Client::connect_with_key(server, name, key).await,
+                (None, None) => Client::connect(server, name).await,
+            };
+            let mut client = client.wrap_err("Could not connect to data-store")?;
+            let snapshot = client
+                .snapshot()
+                .await
+                .wrap_err("Could not make snapshot")?;
+            println!(
+                "wrote {} files ({:.1} MiB) to {}",
+                snapshot.files,
+                snapshot.bytes as f64 / 1024. / 1024.,
+                snapshot.path.display()
+            );
+            Ok(())
+        }
+        Some(Command::Restore { data_dir, snapshot }) => {
+            let restored = snapshot::restore(&snapshot, &data_dir)?;
+            println!("restored {} series", restored.series);
+            if let Some(previous) = restored.previous {
+                println!("previous data dir moved to: {}", previous.display());
+            }
+            Ok(())
+        }
+        Some(Command::Verify { data_dir, repair }) => {
+            let reports = verify::verify(&data_dir, repair)?;
+            let mut unrepaired = 0;
+            for report in &reports {
+                let path = report.path.display();
+                if report.problems.is_empty() {
+                    println!("{path}: ok, {} lines", report.lines);
+                    continue;
+                }
+                println!("{path}: {} lines, problems:", report.lines);
+                for problem in &report.problems {
+                    let fixed = report.repaired && problem.repairable();
+                    let status = if fixed { " (repaired)" } else { "" };
+                    println!("\t- {problem}{status}");
+                    unrepaired += usize::from(!fixed);
+                }
+            }
+            if unrepaired > 0 {
+                color_eyre::eyre::bail!("{unrepaired} problems remain");
+            }
+            Ok(())
+        }
+    }
 }
 
 fn setup_tracing() -> Result<()> {
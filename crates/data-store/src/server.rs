@@ -1,31 +1,49 @@
 use color_eyre::Result;
+use data_server::api::ReconnectingClient;
 use futures_concurrency::future::Race;
-use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 
 mod clients;
 mod db;
+pub mod export;
+pub mod import;
+pub mod retention;
+pub mod snapshot;
+pub mod verify;
+pub mod virtual_readings;
 
-// used from main and tests
-pub async fn run(data_server: SocketAddr, client_port: u16, data_dir: &Path) -> Result<()> {
-    let data = db::Data(Arc::new(Mutex::new(HashMap::new())));
+// used from main and tests, with `keys` clients must authenticate and with
+// `tls` they must connect using TLS
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    data_server: ReconnectingClient,
+    client_port: u16,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
+    data_dir: &Path,
+    snapshot_dir: &Path,
+    retention: retention::Config,
+    fsync_interval: Duration,
+    virtual_readings: Vec<virtual_readings::Definition>,
+) -> Result<()> {
+    let data = db::Data::new(
+        data_dir.to_path_buf(),
+        snapshot_dir.to_path_buf(),
+        virtual_readings,
+    );
 
     let error = (
-        db::run(
-            data_server,
-            data.clone(),
-            data_dir,
-        ),
-        clients::handle(client_port, data),
+        db::run(data_server, data.clone()),
+        clients::handle(client_port, keys, tls, data.clone()),
+        retention::run(data.clone(), retention),
+        db::commit_periodically(data, fsync_interval),
     )
         .race()
         .await;
     assert!(
         error.is_err(),
-        "db::run and client::handle never return unless an error happens"
+        "the tasks never return unless an error happens"
     );
     error
 }
@@ -1,14 +1,174 @@
-use super::db::Data;
+use futures::future;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use futures_concurrency::future::Race;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+
+use super::db::{Appended, Data, Seam};
 use crate::api::{self, ServerError};
 
-pub(crate) async fn handle(port: u16, data: Data) -> color_eyre::Result<()> {
+/// About the maximum number of points in a single update while sending
+/// history
+const HISTORY_CHUNK: u32 = 10_000;
+/// Live updates kept for a subscriber while it takes in the history
+const LIVE_BUFFER: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct SubHandler {
+    data: Data,
+}
+
+impl rpc::SubscriberHandler for SubHandler {
+    type Update = api::Response;
+    type Request = api::SubscribeRequest;
+
+    fn setup(
+        &mut self,
+        request: Self::Request,
+    ) -> impl std::future::Future<Output = impl Stream<Item = Self::Update> + Send + 'static>
+           + Send
+           + 'static {
+        do_setup(self.data.clone(), request)
+    }
+}
+
+/// Only notes where the history ends, the history itself is read a chunk
+/// at a time as the client takes it in.
+async fn do_setup(
+    data: Data,
+    api::SubscribeRequest { readings, start }: api::SubscribeRequest,
+) -> BoxStream<'static, api::Response> {
+    let (seams, appended) = match data.subscribe_appended(&readings).await {
+        Ok(res) => res,
+        Err(e) => return stream::iter([api::Response::Error(e)]).boxed(),
+    };
+
+    // lines appended after the seams are not in the history
+    let in_history: Vec<_> = seams
+        .iter()
+        .map(|seam| (seam.reading.clone(), seam.seq))
+        .collect();
+    let (tx, live) = mpsc::channel(LIVE_BUFFER);
+    tokio::spawn(forward_live(appended, readings, in_history, tx));
+    let live = stream::unfold(live, |mut rx| async move {
+        rx.recv().await.map(|update| (update, rx))
+    });
+
+    stream::iter(seams)
+        .flat_map(move |seam| history(data.clone(), seam, start))
+        .chain(live)
+        // we can no longer promise no gaps, end the subscription
+        .scan(false, |ended, update| {
+            if *ended {
+                return future::ready(None);
+            }
+            *ended = matches!(update, api::Response::Error(_));
+            future::ready(Some(update))
+        })
+        .boxed()
+}
+
+/// The history of one reading from `start` up to the seam. The next chunk
+/// is only read once the previous one is sent.
+fn history(
+    data: Data,
+    seam: Seam,
+    start: jiff::Timestamp,
+) -> impl Stream<Item = api::Response> + Send + 'static {
+    stream::unfold(Some(start), move |from| {
+        let data = data.clone();
+        let seam = seam.clone();
+        async move {
+            let (update, next) = match data.history_chunk(&seam, from?, HISTORY_CHUNK).await {
+                Ok((time, _, next)) if time.is_empty() => (None, next),
+                Ok((time, data, next)) => {
+                    let update = api::Response::SubUpdate {
+                        reading: seam.reading,
+                        time,
+                        data,
+                    };
+                    (Some(update), next)
+                }
+                Err(e) => (Some(api::Response::Error(e)), None),
+            };
+            Some((update, next))
+        }
+    })
+    .filter_map(future::ready)
+}
+
+/// Passes lines for `readings` on to `tx` until the subscription ends. A
+/// subscriber that falls too far behind gets an error instead.
+async fn forward_live(
+    mut appended: broadcast::Receiver<Appended>,
+    readings: Vec<protocol::Reading>,
+    in_history: Vec<(protocol::Reading, u64)>,
+    tx: mpsc::Sender<api::Response>,
+) {
+    loop {
+        let recv = async { Some(appended.recv().await) };
+        let ended = async {
+            tx.closed().await;
+            None
+        };
+        let Some(received) = (recv, ended).race().await else {
+            return;
+        };
+        let appended = match received {
+            Ok(appended) => appended,
+            Err(RecvError::Lagged(n)) => {
+                let error = api::Response::Error(ServerError::SubscriberLagged(n));
+                let _ = tx.send(error).await;
+                return;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        for update in live_updates(appended, &readings, &in_history) {
+            if tx.send(update).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn live_updates(
+    appended: Appended,
+    readings: &[protocol::Reading],
+    in_history: &[(protocol::Reading, u64)],
+) -> Vec<api::Response> {
+    appended
+        .values
+        .into_iter()
+        .filter(|(stored, _)| readings.iter().any(|r| r.is_same_as(stored)))
+        .filter(|(stored, _)| {
+            !in_history
+                .iter()
+                .any(|(read, seq)| read.is_same_as(stored) && appended.seq <= *seq)
+        })
+        .map(|(reading, value)| api::Response::SubUpdate {
+            reading,
+            time: vec![appended.time],
+            data: vec![value],
+        })
+        .collect()
+}
+
+pub(crate) async fn handle(
+    port: u16,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
+    data: Data,
+) -> color_eyre::Result<()> {
+    let handler = SubHandler { data: data.clone() };
     rpc::server::run(
         port,
+        keys,
+        tls,
         move |req, _| {
             let data = data.clone();
             perform_request(req, data)
         },
-        Option::<rpc::SubscribersUnsupported<api::Response>>::None,
+        Some(handler),
     )
     .await
 }
@@ -25,7 +185,10 @@ async fn perform_request_inner(
 ) -> Result<api::Response, ServerError> {
     Ok(match request {
         api::Request::Handshake { .. } => return Err(ServerError::AlreadyConnected),
-        api::Request::ListData => api::Response::ListData(data.list().await),
+        api::Request::ListData => api::Response::ListData {
+            stored: data.list().await,
+            virtual_readings: data.list_virtual().await,
+        },
         api::Request::GetData {
             reading,
             start,
@@ -35,5 +198,37 @@ async fn perform_request_inner(
             let (time, data) = data.get(reading, start, end, n).await?;
             api::Response::GetData { time, data }
         }
+        api::Request::GetDataAligned {
+            reading,
+            start,
+            end,
+            bucket,
+        } => {
+            let (time, data) = data.get_aligned(reading, start, end, bucket).await?;
+            api::Response::GetData { time, data }
+        }
+        api::Request::GetBuckets {
+            reading,
+            start,
+            end,
+            size,
+            time_zone,
+        } => {
+            let buckets = data.buckets(reading, start, end, size, time_zone).await?;
+            api::Response::GetBuckets(buckets)
+        }
+        api::Request::GetStats {
+            readings,
+            start,
+            end,
+        } => api::Response::GetStats(data.stats(readings, start, end).await?),
+        api::Request::ListGaps {
+            device,
+            start,
+            end,
+            min_gap,
+        } => api::Response::ListGaps(data.gaps(device, start, end, min_gap).await?),
+        api::Request::StorageStats => api::Response::StorageStats(data.storage_stats().await?),
+        api::Request::Snapshot => api::Response::Snapshot(data.snapshot().await?),
     })
 }
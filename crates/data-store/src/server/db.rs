@@ -1,23 +1,28 @@
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use data_server::api::ReconnectingClient;
-use tokio::sync::Mutex;
+use tokio::sync::broadcast;
 
+use color_eyre::eyre::WrapErr;
 use color_eyre::{Result, Section};
 use data_server::api::SubMessage;
 
 mod series;
-use series::Series;
+mod shared;
+pub(crate) use series::{
+    bucket_edges, device_from_path, device_path, drop_uncommitted, reading_name, uncommitted,
+    Aligned, Appended, Recovered, Series,
+};
+use shared::SharedSeries;
 
 use crate::api;
+use crate::server::virtual_readings;
 
-pub(crate) async fn run(data_server_addr: SocketAddr, data: Data, data_dir: &Path) -> Result<()> {
-    let mut sub =
-        ReconnectingClient::new(data_server_addr, "ha-data-store".to_string()).subscribe();
+pub(crate) async fn run(data_server: ReconnectingClient, data: Data) -> Result<()> {
+    let mut sub = data_server.subscribe();
 
     let mut recently_logged = (Instant::now(), String::new());
     loop {
@@ -26,7 +31,8 @@ pub(crate) async fn run(data_server_addr: SocketAddr, data: Data, data_dir: &Pat
             continue;
         };
 
-        let res = series::store(&data, &reading, data_dir)
+        let res = data
+            .store(reading.clone())
             .await
             .with_note(|| format!("reading: {reading:?}"));
 
@@ -44,40 +50,442 @@ pub(crate) async fn run(data_server_addr: SocketAddr, data: Data, data_dir: &Pat
     }
 }
 
+/// Commits the lines appended to every series every `interval`. Lines
+/// appended less than `interval` ago can get lost in a crash.
+pub(crate) async fn commit_periodically(data: Data, interval: Duration) -> Result<()> {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let all_series: Vec<_> = data.lock_series().values().cloned().collect();
+        for series in all_series {
+            if let Err(report) = blocking(move || series.with(Series::commit)).await {
+                tracing::error!("Could not commit lines: {report:?}");
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub(crate) struct Data(pub(crate) Arc<Mutex<HashMap<protocol::Device, Series>>>);
+pub(crate) struct Data {
+    /// Only locked to look up or add a series, never while doing IO
+    series: Arc<Mutex<HashMap<protocol::Device, Arc<SharedSeries>>>>,
+    /// Makes sure a series is only opened once
+    opening: Arc<tokio::sync::Mutex<()>>,
+    data_dir: PathBuf,
+    /// Where snapshots are written to
+    snapshot_dir: PathBuf,
+    virtual_readings: Arc<Vec<virtual_readings::Definition>>,
+    /// Every line written to disk
+    appended: broadcast::Sender<Appended>,
+}
+
+/// Runs blocking disk IO on a thread where blocking is fine
+pub(crate) async fn blocking<T: Send + 'static>(op: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(op).await {
+        Ok(res) => res,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
 
 impl Data {
-    pub(crate) async fn list(&self) -> Vec<protocol::Reading> {
-        self.0
-            .lock()
+    pub(crate) fn new(
+        data_dir: PathBuf,
+        snapshot_dir: PathBuf,
+        virtual_readings: Vec<virtual_readings::Definition>,
+    ) -> Self {
+        let (appended, _) = broadcast::channel(1024);
+        Self {
+            series: Arc::new(Mutex::new(HashMap::new())),
+            opening: Arc::new(tokio::sync::Mutex::new(())),
+            data_dir,
+            snapshot_dir,
+            virtual_readings: Arc::new(virtual_readings),
+            appended,
+        }
+    }
+
+    fn lock_series(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<protocol::Device, Arc<SharedSeries>>> {
+        self.series.lock().expect("no panics while holding the lock")
+    }
+
+    fn existing(&self, device: &protocol::Device) -> Option<Arc<SharedSeries>> {
+        self.lock_series().get(device).cloned()
+    }
+
+    /// Returns the series for `device`, opening it if needed. Returns None
+    /// if it does not exist and `create_missing` is false.
+    pub(crate) async fn open(
+        &self,
+        device: &protocol::Device,
+        create_missing: bool,
+    ) -> Result<Option<Arc<SharedSeries>>> {
+        if let Some(series) = self.existing(device) {
+            return Ok(Some(series));
+        }
+
+        let _opening = self.opening.lock().await;
+        // could have been opened while we waited
+        if let Some(series) = self.existing(device) {
+            return Ok(Some(series));
+        }
+
+        let data_dir = self.data_dir.clone();
+        let to_open = device.clone();
+        let series = blocking(move || {
+            let torn_bytes = drop_uncommitted(&to_open, &data_dir)
+                .wrap_err("Could not drop lines written after the last commit")?;
+            if torn_bytes > 0 {
+                tracing::warn!(
+                    "Series for {to_open:?} was not closed cleanly, dropped {torn_bytes} \
+                    bytes written after its last commit"
+                );
+            }
+            let series = if create_missing {
+                let reading = to_open
+                    .info()
+                    .affects_readings
+                    .first()
+                    .expect("a device has at least one reading it affects");
+                Series::open_or_create(reading, &data_dir).map(Some)
+            } else {
+                Series::open_existing(&to_open, &data_dir)
+            };
+            let Some(mut series) = series? else {
+                return Ok(None);
+            };
+
+            let recovered = series
+                .recover()
+                .wrap_err("Could not recover lines from journal")?;
+            if recovered.torn_bytes > 0 || recovered.replayed > 0 {
+                tracing::warn!(
+                    "Series for {to_open:?} was not closed cleanly, dropped \
+                    {} torn bytes and replayed {} lines from its journal",
+                    recovered.torn_bytes,
+                    recovered.replayed
+                );
+            }
+            Ok(Some(series))
+        })
+        .await
+        .wrap_err("Could not open series")?;
+
+        let Some(series) = series else {
+            return Ok(None);
+        };
+        let series = Arc::new(SharedSeries::new(series, self.appended.clone()));
+        self.lock_series().insert(device.clone(), series.clone());
+        Ok(Some(series))
+    }
+
+    async fn store(&self, reading: protocol::Reading) -> Result<()> {
+        let received = Instant::now();
+        let time = jiff::Timestamp::now();
+        let series = self
+            .open(&reading.device(), true)
+            .await?
+            .expect("missing series are created");
+        blocking(move || series.append(reading, received, time))
             .await
+            .wrap_err("Could not append to series")
+    }
+
+    pub(crate) async fn list(&self) -> Vec<protocol::Reading> {
+        self.lock_series()
             .keys()
             .flat_map(|dev| dev.info().affects_readings)
             .cloned()
             .collect()
     }
+
+    /// The virtual readings whose inputs are all stored
+    pub(crate) async fn list_virtual(&self) -> Vec<api::VirtualReading> {
+        let stored = self.list().await;
+        self.virtual_readings
+            .iter()
+            .filter(|definition| {
+                definition
+                    .inputs()
+                    .iter()
+                    .all(|input| stored.iter().any(|r| r.is_same_as(input)))
+            })
+            .map(virtual_readings::Definition::info)
+            .collect()
+    }
+
+    fn virtual_reading(
+        &self,
+        name: &str,
+    ) -> Result<&virtual_readings::Definition, api::ServerError> {
+        self.virtual_readings
+            .iter()
+            .find(|definition| definition.name() == name)
+            .ok_or_else(|| api::ServerError::UnknownVirtualReading {
+                name: name.to_owned(),
+            })
+    }
+
+    /// A virtual reading is computed from `n` points of each of its inputs
     pub(crate) async fn get(
+        &self,
+        reading: api::AnyReading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        n: usize,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>), api::ServerError> {
+        let name = match reading {
+            api::AnyReading::Stored(reading) => {
+                return self.get_stored(reading, start, end, n).await
+            }
+            api::AnyReading::Virtual(name) => name,
+        };
+        let definition = self.virtual_reading(&name)?;
+
+        let mut inputs = Vec::new();
+        for input in definition.inputs() {
+            inputs.push(self.get_stored(input.clone(), start, end, n).await?);
+        }
+        let range = end.as_millisecond().saturating_sub(start.as_millisecond()).max(0);
+        let spacing = Duration::from_millis(range as u64 / n.max(1) as u64);
+        Ok(definition.evaluate(&inputs, spacing))
+    }
+
+    async fn get_stored(
         &self,
         reading: protocol::Reading,
         start: jiff::Timestamp,
         end: jiff::Timestamp,
         n: usize,
     ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>), api::ServerError> {
-        let key = reading.device();
-        let mut all_series = self.0.lock().await;
-        let series = all_series
-            .get_mut(&key)
-            .ok_or_else(|| api::ServerError::NotInStore {
+        let series = self.existing(&reading.device()).ok_or_else(|| {
+            api::ServerError::NotInStore {
                 reading: reading.clone(),
-            })?;
+            }
+        })?;
 
-        let (time, mut data) = series
-            .read(&[reading], start, end, n)
-            .map_err(|e| api::ServerError::ReadingFromStore(e.to_string()))?;
+        let (time, mut data) = blocking(move || {
+            series.with(|series| series.read(&[reading], start, end, n))
+        })
+        .await
+        .map_err(|e| api::ServerError::ReadingFromStore(e.to_string()))?;
         Ok((
             time,
             data.pop().expect("one reading is put in so one comes out"),
         ))
     }
+
+    pub(crate) async fn buckets(
+        &self,
+        reading: protocol::Reading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        size: api::BucketSize,
+        time_zone: String,
+    ) -> Result<Vec<api::Bucket>, api::ServerError> {
+        let edges = bucket_edges(start, end, &size, &time_zone)
+            .map_err(|e| api::ServerError::InvalidBuckets(e.to_string()))?;
+        let series = self.existing(&reading.device()).ok_or_else(|| {
+            api::ServerError::NotInStore {
+                reading: reading.clone(),
+            }
+        })?;
+        blocking(move || series.with(|series| series.buckets(&reading, &edges)))
+            .await
+            .map_err(|e| api::ServerError::ReadingFromStore(format!("{e:#}")))
+    }
+
+    /// A virtual reading is computed from the aligned means of its inputs
+    pub(crate) async fn get_aligned(
+        &self,
+        reading: api::AnyReading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        bucket: Duration,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>), api::ServerError> {
+        let aligned = Aligned::new(start, end, bucket)
+            .map_err(|e| api::ServerError::InvalidBuckets(e.to_string()))?;
+        let name = match reading {
+            api::AnyReading::Stored(reading) => {
+                return self.get_stored_aligned(reading, aligned).await
+            }
+            api::AnyReading::Virtual(name) => name,
+        };
+        let definition = self.virtual_reading(&name)?;
+
+        let mut inputs = Vec::new();
+        for input in definition.inputs() {
+            inputs.push(self.get_stored_aligned(input.clone(), aligned).await?);
+        }
+        Ok(definition.evaluate(&inputs, bucket))
+    }
+
+    async fn get_stored_aligned(
+        &self,
+        reading: protocol::Reading,
+        aligned: Aligned,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>), api::ServerError> {
+        let series = self.existing(&reading.device()).ok_or_else(|| {
+            api::ServerError::NotInStore {
+                reading: reading.clone(),
+            }
+        })?;
+        blocking(move || series.with(|series| series.read_aligned(&reading, aligned)))
+            .await
+            .map_err(|e| api::ServerError::ReadingFromStore(format!("{e:#}")))
+    }
+
+    pub(crate) async fn stats(
+        &self,
+        readings: Vec<protocol::Reading>,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    ) -> Result<Vec<api::Stats>, api::ServerError> {
+        let mut stats = Vec::new();
+        for reading in readings {
+            let series = self.existing(&reading.device()).ok_or_else(|| {
+                api::ServerError::NotInStore {
+                    reading: reading.clone(),
+                }
+            })?;
+            let reading_stats =
+                blocking(move || series.with(|series| series.stats(reading, start, end)))
+                    .await
+                    .map_err(|e| api::ServerError::ReadingFromStore(format!("{e:#}")))?;
+            stats.push(reading_stats);
+        }
+        Ok(stats)
+    }
+
+    pub(crate) async fn gaps(
+        &self,
+        device: protocol::Device,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        min_gap: Option<Duration>,
+    ) -> Result<Vec<api::Gap>, api::ServerError> {
+        let series = self
+            .existing(&device)
+            .ok_or_else(|| api::ServerError::DeviceNotInStore {
+                device: device.clone(),
+            })?;
+        blocking(move || series.with(|series| series.gaps(start, end, min_gap)))
+            .await
+            .map_err(|e| api::ServerError::ReadingFromStore(format!("{e:#}")))
+    }
+
+    pub(crate) async fn storage_stats(&self) -> Result<Vec<api::SeriesStorage>, api::ServerError> {
+        let to_server_error =
+            |e: color_eyre::Report| api::ServerError::ReadingFromStore(format!("{e:#}"));
+        let mut stats = Vec::new();
+        for device in protocol::Device::all() {
+            let Some(series) = self.open(&device, false).await.map_err(to_server_error)? else {
+                continue;
+            };
+            let series_stats = blocking(move || series.with(|series| series.storage_stats()))
+                .await
+                .map_err(to_server_error)?;
+            stats.push(series_stats);
+        }
+        Ok(stats)
+    }
+
+    /// Writes a tarball of the data dir to the snapshot dir. Every series is
+    /// locked while the tarball is written, ingestion queues up new lines
+    /// meanwhile.
+    pub(crate) async fn snapshot(&self) -> Result<api::Snapshot, api::ServerError> {
+        let to_server_error = |e: color_eyre::Report| api::ServerError::Snapshot(format!("{e:#}"));
+        // only series that are open can be locked
+        for device in protocol::Device::all() {
+            self.open(&device, false).await.map_err(to_server_error)?;
+        }
+
+        // no new series are created while we hold this
+        let _opening = self.opening.lock().await;
+        let all_series: Vec<_> = self.lock_series().values().cloned().collect();
+        let data_dir = self.data_dir.clone();
+        let snapshot_dir = self.snapshot_dir.clone();
+        blocking(move || {
+            let mut locked: Vec<_> = all_series.iter().map(|series| series.lock()).collect();
+            for series in &mut locked {
+                series
+                    .commit()
+                    .wrap_err("Could not commit lines before the snapshot")?;
+            }
+            super::snapshot::write(&data_dir, &snapshot_dir)
+        })
+        .await
+        .map_err(to_server_error)
+    }
+
+    /// Subscribes to newly appended lines then notes per reading the last
+    /// line already stored. History up to there is read with
+    /// [`Data::history_chunk`], lines with a higher [`Appended::seq`] come
+    /// from the subscription.
+    pub(crate) async fn subscribe_appended(
+        &self,
+        readings: &[protocol::Reading],
+    ) -> Result<(Vec<Seam>, broadcast::Receiver<Appended>), api::ServerError> {
+        let appended = self.appended.subscribe();
+
+        let mut seams = Vec::new();
+        for reading in readings {
+            // not stored yet, the subscription will pick it up once it is
+            let Some(series) = self.existing(&reading.device()) else {
+                continue;
+            };
+            let (seq, last) = blocking(move || {
+                series.with(|series| {
+                    // lines are only read once committed
+                    series.commit()?;
+                    let seq = series.appended_lines();
+                    series.last_timestamp().map(|last| (seq, last))
+                })
+            })
+            .await
+            .map_err(|e| api::ServerError::ReadingFromStore(format!("{e:#}")))?;
+            seams.push(Seam {
+                reading: reading.clone(),
+                seq,
+                last,
+            });
+        }
+        Ok((seams, appended))
+    }
+
+    /// Reads the history of `seam.reading` from `start`, about `lines` lines
+    /// at most. Returns where the next chunk starts, None once the seam is
+    /// reached.
+    pub(crate) async fn history_chunk(
+        &self,
+        seam: &Seam,
+        start: jiff::Timestamp,
+        lines: u32,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>, Option<jiff::Timestamp>), api::ServerError>
+    {
+        let Some(end) = seam.last else {
+            return Ok((Vec::new(), Vec::new(), None));
+        };
+        let device = seam.reading.device();
+        let series = self
+            .existing(&device)
+            .expect("a series is never closed once open");
+        // lines are at least this far apart
+        let span = device.info().min_sample_interval * lines;
+        let reading = seam.reading.clone();
+        blocking(move || series.with(|series| series.read_chunk(&reading, start, end, span)))
+            .await
+            .map_err(|e| api::ServerError::ReadingFromStore(format!("{e:#}")))
+    }
+}
+
+/// Where the history of a subscription ends and the live lines begin
+#[derive(Debug, Clone)]
+pub(crate) struct Seam {
+    pub(crate) reading: protocol::Reading,
+    /// [`Appended::seq`] of the last stored line
+    pub(crate) seq: u64,
+    /// Time of the last stored line, None if nothing is stored
+    pub(crate) last: Option<jiff::Timestamp>,
 }
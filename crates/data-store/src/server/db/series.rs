@@ -1,13 +1,12 @@
 use std::fs::create_dir_all;
-use std::io;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use std::{io, iter};
 
 use byteseries::{downsample, series, ByteSeries};
 use color_eyre::eyre::WrapErr;
 use color_eyre::{Result, Section};
-use protocol::reading;
-use protocol::reading::tree::{Item, Tree};
+use protocol::reading::tree::Tree;
 use serde::{Deserialize, Serialize};
 use tracing::{instrument, trace};
 
@@ -16,11 +15,30 @@ use series::data::OpenError as DataOpenError;
 use series::Error::Open;
 
 mod bitspec;
+mod buckets;
+mod committed;
+mod expired;
+mod gaps;
+mod journal;
 mod resampler;
+mod stats;
 
 use self::resampler::Resampler;
+pub(crate) use buckets::{edges as bucket_edges, Aligned};
 
-use super::Data;
+use crate::api;
+
+/// Above this many lines statistics are calculated from the downsampled cache
+const MAX_LINES_FOR_EXACT_STATS: u64 = 50_000;
+/// Number of points to read from the downsampled cache for statistics
+const RESAMPLED_POINTS_FOR_STATS: usize = 5_000;
+/// Gaps are searched for in windows that contain at most this many lines
+const LINES_PER_GAP_WINDOW: u64 = 50_000;
+/// Lines are committed once this many are waiting even if it is not time yet
+const MAX_BATCH: usize = 1_000;
+/// Number of lines averaged into one point by each downsampled cache level,
+/// must match the configs in [`resample_setup`]
+const CACHE_BUCKET_SIZES: [u64; 3] = [10, 100, 1000];
 
 #[derive(Debug)]
 struct Meta {
@@ -35,6 +53,34 @@ pub(crate) struct Series {
     meta_list: Vec<Meta>,
     last_timestamp_pushed: Option<u64>,
     byteseries: ByteSeries,
+    /// Full path without extension
+    path: PathBuf,
+    /// Where the data still kept starts, see [`expired`]
+    expired: expired::Expired,
+    /// Number of lines appended since the series was opened
+    appended_lines: u64,
+    journal: journal::Journal,
+    /// Lines waiting to be committed, see [`Series::commit`]
+    batch: Vec<(u64, Vec<u8>)>,
+}
+
+/// A line that was just written to disk. Decoded again from the encoded line
+/// so it is exactly what a later read of the history returns.
+#[derive(Debug, Clone)]
+pub(crate) struct Appended {
+    /// Value of [`Series::appended_lines`] after this line was appended
+    pub(crate) seq: u64,
+    pub(crate) time: jiff::Timestamp,
+    pub(crate) values: Vec<(protocol::Reading, f32)>,
+}
+
+/// What [`Series::recover`] did or would do
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Recovered {
+    /// Bytes at the end of the journal that are not part of an intact block
+    pub(crate) torn_bytes: u64,
+    /// Lines from the journal missing from the series
+    pub(crate) replayed: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -44,8 +90,26 @@ struct Header {
 }
 
 impl Series {
+    pub(crate) fn open_or_create(reading: &protocol::Reading, dir: &Path) -> Result<Self> {
+        Ok(Self::open(reading, dir, true)?.expect("missing series are created"))
+    }
+
+    /// Returns None if there is no series for this device in `dir`
+    pub(crate) fn open_existing(device: &protocol::Device, dir: &Path) -> Result<Option<Self>> {
+        let reading = device
+            .info()
+            .affects_readings
+            .first()
+            .expect("a device has at least one reading it affects");
+        Self::open(reading, dir, false)
+    }
+
     #[instrument]
-    fn open_or_create(reading: &protocol::Reading, dir: &Path) -> Result<Self> {
+    fn open(
+        reading: &protocol::Reading,
+        dir: &Path,
+        create_missing: bool,
+    ) -> Result<Option<Self>> {
         let readings = reading.device().info().affects_readings;
         let specs = to_speclist(readings);
         let fields = bitspec::speclist_to_fields(specs);
@@ -56,50 +120,360 @@ impl Series {
             encoding: fields.clone(),
         };
 
-        let (resampler, configs) = resample_setup(&fields, payload_size);
-
         let path = base_path(reading);
         let path = dir.join(path);
-        let res = ByteSeries::builder()
-            .payload_size(payload_size)
-            .with_downsampled_cache(resampler.clone(), configs.clone())
-            .with_header(expected_header.clone())
-            .open(&path);
+        let res = open_byteseries(&path, expected_header.clone(), payload_size, false);
 
         let byteseries = match res {
-            Ok((byteseries, _)) => byteseries,
+            Ok(byteseries) => byteseries,
             Err(Open(DataOpenError::File(FileOpenError::Io(e))))
                 if e.kind() == io::ErrorKind::NotFound =>
             {
+                if !create_missing {
+                    return Ok(None);
+                }
                 if let Some(dirs) = path.parent() {
                     create_dir_all(dirs)
                         .wrap_err("Could not create dirs structure for reading")
                         .with_note(|| format!("dirs: {}", dirs.display()))?;
                 }
 
-                ByteSeries::builder()
-                    .payload_size(payload_size)
-                    .with_downsampled_cache(resampler, configs)
-                    .with_header(expected_header)
-                    .create_new(true)
-                    .open(&path)
+                open_byteseries(&path, expected_header.clone(), payload_size, true)
                     .wrap_err("Could not create new byteseries")
-                    .with_note(|| format!("path: {}", path.display()))
-                    .map(|(db, _)| db)?
+                    .with_note(|| format!("path: {}", path.display()))?
             }
             Err(e) => return Err(e).wrap_err("Could not open existing byteseries")?,
         };
+        let expired = expired::Expired::load(&path)?;
+        let journal = journal::Journal::open(&path)?;
 
-        Ok(Self {
+        Ok(Some(Self {
             line: vec![0; payload_size],
             meta_list,
             last_timestamp_pushed: None,
             byteseries,
+            path,
+            expired,
+            appended_lines: 0,
+            journal,
+            batch: Vec::new(),
+        }))
+    }
+
+    /// The readings stored in this series, in the order they are stored
+    pub(crate) fn readings(&self) -> Vec<protocol::Reading> {
+        self.meta_list.iter().map(|meta| meta.reading.clone()).collect()
+    }
+
+    /// The time as it will be stored, rounded down to the resolution of
+    /// the series. Distinct times can end up the same once stored.
+    pub(crate) fn stored_time(&self, time: jiff::Timestamp) -> jiff::Timestamp {
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        to_jiff(time.as_millisecond() as u64 / scale_factor, scale_factor)
+    }
+
+    /// Push a line with values for all readings in this series at a time of
+    /// our choosing, used when importing.
+    ///
+    /// # Panics
+    /// If values does not contain a value for every reading in this series
+    pub(crate) fn push_values(&mut self, time: jiff::Timestamp, values: &[f32]) -> Result<()> {
+        assert_eq!(values.len(), self.meta_list.len());
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        let ts = time.as_millisecond() as u64 / scale_factor;
+
+        let mut line = vec![0; self.line.len()];
+        for (meta, value) in self.meta_list.iter().zip(values) {
+            meta.field.encode(*value, &mut line);
+        }
+        self.push_line(ts, line)?;
+        self.last_timestamp_pushed = Some(ts);
+        Ok(())
+    }
+
+    fn push_line(&mut self, ts: u64, line: Vec<u8>) -> Result<()> {
+        self.batch.push((ts, line));
+        if self.batch.len() >= MAX_BATCH {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Makes the lines appended since the last commit durable. They are
+    /// written to the journal, then pushed to the byteseries whose files
+    /// are then synced and their lengths recorded. Only then is the
+    /// journal cleared. Lines that could not be pushed stay in the batch
+    /// for the next commit.
+    #[instrument(skip(self), fields(path = %self.path.display(), lines = self.batch.len()))]
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        self.journal
+            .write_block(&self.batch)
+            .wrap_err("Could not write batch to journal")?;
+        let mut pushed = 0;
+        let mut res = Ok(());
+        for (ts, line) in &self.batch {
+            if let Err(e) = self.byteseries.push_line(*ts, line) {
+                res = Err(e);
+                break;
+            }
+            pushed += 1;
+        }
+        // the journal is only cleared once a commit succeeds, until then
+        // it has every line pushed since the last one
+        self.batch.drain(..pushed);
+        res.wrap_err("Could not write to timeseries on disk")?;
+
+        committed::sync_files(&self.path).wrap_err("Could not sync series to disk")?;
+        committed::record(&self.path)?;
+        self.journal.clear()
+    }
+
+    /// Pushes lines that are in the journal but did not make it into the
+    /// byteseries before a crash or power cut. A torn block at the end of
+    /// the journal is dropped. Call [`drop_uncommitted`] before opening the
+    /// series so no torn line is left in the byteseries.
+    #[instrument(skip(self), fields(path = %self.path.display()))]
+    pub(crate) fn recover(&mut self) -> Result<Recovered> {
+        let scan = journal::scan(&self.path, self.line.len())?;
+        let mut last = self
+            .last_scaled_timestamp()
+            .wrap_err("Could not get time of last line in series")?;
+
+        let mut recovered = Recovered {
+            torn_bytes: scan.file_len - scan.valid_len,
+            replayed: 0,
+        };
+        for (ts, line) in scan.lines {
+            if last.is_some_and(|last| ts <= last) {
+                continue; // made it into the byteseries
+            }
+            self.byteseries
+                .push_line(ts, &line)
+                .wrap_err("Could not replay line from journal")?;
+            // a failed commit leaves lines in the journal twice
+            last = Some(ts);
+            recovered.replayed += 1;
+        }
+        committed::sync_files(&self.path).wrap_err("Could not sync series to disk")?;
+        committed::record(&self.path)?;
+        self.journal.clear()?;
+        Ok(recovered)
+    }
+
+    /// Checks the journal without changing anything
+    pub(crate) fn check_journal(&mut self) -> Result<Recovered> {
+        let scan = journal::scan(&self.path, self.line.len())?;
+        let last = self
+            .last_scaled_timestamp()
+            .wrap_err("Could not get time of last line in series")?;
+        Ok(Recovered {
+            torn_bytes: scan.file_len - scan.valid_len,
+            replayed: scan
+                .lines
+                .iter()
+                .filter(|(ts, _)| last.map_or(true, |last| *ts > last))
+                .count(),
         })
     }
 
-    #[instrument]
-    fn append(&mut self, reading: &protocol::Reading) -> Result<()> {
+    /// Time of the last line in the series, None if it is empty.
+    pub(crate) fn last_timestamp(&mut self) -> Result<Option<jiff::Timestamp>> {
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        if let Some((ts, _)) = self.batch.last() {
+            return Ok(Some(to_jiff(*ts, scale_factor)));
+        }
+        Ok(self
+            .last_scaled_timestamp()?
+            .map(|ts| to_jiff(ts, scale_factor)))
+    }
+
+    /// Timestamp of the last line in the byteseries
+    fn last_scaled_timestamp(&mut self) -> Result<Option<u64>> {
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        let now = jiff::Timestamp::now().as_millisecond() as u64 / scale_factor;
+        let everything = 0..=u64::MAX;
+        if self
+            .byteseries
+            .n_lines_between(everything)
+            .wrap_err("Could not check if series is empty")?
+            == 0
+        {
+            return Ok(None);
+        }
+
+        // look back from now in growing windows, only read the last few lines
+        let mut look_back = 1024;
+        loop {
+            let window = now.saturating_sub(look_back)..=u64::MAX;
+            if self
+                .byteseries
+                .n_lines_between(window.clone())
+                .wrap_err("Could not check if there is data in window")?
+                > 0
+            {
+                let mut timestamps = Vec::new();
+                self.byteseries
+                    .read_all(
+                        window,
+                        &mut gaps::TimestampsOnly,
+                        &mut timestamps,
+                        &mut Vec::new(),
+                    )
+                    .wrap_err("Could not read timestamps from disk")?;
+                return Ok(timestamps.last().copied());
+            }
+            look_back *= 2;
+        }
+    }
+
+    /// Timestamp of the first line in the byteseries given that of the last
+    fn first_scaled_timestamp(&mut self, last: u64) -> Result<u64> {
+        // binary search for the first line so we do not scan from 1970
+        let (mut before_first, mut first) = (0, last);
+        while before_first < first {
+            let mid = before_first + (first - before_first) / 2;
+            if self
+                .byteseries
+                .n_lines_between(0..=mid)
+                .wrap_err("Could not count lines")?
+                > 0
+            {
+                first = mid;
+            } else {
+                before_first = mid + 1;
+            }
+        }
+        Ok(first)
+    }
+
+    /// Sizes of the files, the range and how fast lines are being written.
+    /// Lines that were removed by the retention policy are not counted.
+    pub(crate) fn storage_stats(&mut self) -> Result<api::SeriesStorage> {
+        let device = self
+            .meta_list
+            .first()
+            .expect("a series has at least one reading")
+            .reading
+            .device();
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+
+        let files = self
+            .files_on_disk()
+            .wrap_err("Could not get size of files")?
+            .into_iter()
+            .map(|(path, bytes)| api::StoredFile {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                bytes,
+            })
+            .collect();
+        let lines = self
+            .byteseries
+            .n_lines_between(0..=u64::MAX)
+            .wrap_err("Could not count lines")?;
+        let last = self.last_scaled_timestamp()?;
+        let first = last
+            .map(|last| self.first_scaled_timestamp(last))
+            .transpose()?;
+
+        const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+        let now = jiff::Timestamp::now().as_millisecond() as u64 / scale_factor;
+        let lines_last_day = self
+            .byteseries
+            .n_lines_between(now.saturating_sub(DAY_MS / scale_factor)..=now)
+            .wrap_err("Could not count lines written in the last day")?;
+
+        Ok(api::SeriesStorage {
+            path: device_path(&device),
+            device,
+            files,
+            lines,
+            first: first.map(|ts| to_jiff(ts, scale_factor)),
+            last: last.map(|ts| to_jiff(ts, scale_factor)),
+            fields: self
+                .meta_list
+                .iter()
+                .map(|meta| api::FieldStorage {
+                    reading: meta.reading.clone(),
+                    bits: meta.field.length,
+                })
+                .collect(),
+            payload_bytes: self.line.len(),
+            lines_per_hour: lines_last_day as f32 / 24.0,
+        })
+    }
+
+    /// Reads the time of every line checking they can be read and are in
+    /// order. Returns the number of lines and how many of those are not
+    /// after the line before them.
+    #[instrument(skip(self), fields(path = %self.path.display()))]
+    pub(crate) fn check_lines(&mut self) -> Result<(u64, u64)> {
+        let min_sample_interval = self.device_info().min_sample_interval;
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        let window = LINES_PER_GAP_WINDOW * min_sample_interval.as_millis() as u64 / scale_factor;
+        let window = window.max(1);
+        let Some(last) = self.last_scaled_timestamp()? else {
+            return Ok((0, 0));
+        };
+
+        let first = self.first_scaled_timestamp(last)?;
+
+        let mut lines = 0;
+        let mut out_of_order = 0;
+        let mut prev = None;
+        let mut window_start = first;
+        while window_start <= last {
+            let window_end = window_start.saturating_add(window).min(last);
+            let range = window_start..=window_end;
+            if self
+                .byteseries
+                .n_lines_between(range.clone())
+                .wrap_err("Could not check if there is data in window")?
+                > 0
+            {
+                let mut timestamps = Vec::new();
+                self.byteseries
+                    .read_all(
+                        range,
+                        &mut gaps::TimestampsOnly,
+                        &mut timestamps,
+                        &mut Vec::new(),
+                    )
+                    .wrap_err("Could not read timestamps from disk")
+                    .with_note(|| format!("window: {window_start}..={window_end}"))?;
+                for ts in timestamps {
+                    if prev.is_some_and(|prev| ts <= prev) {
+                        out_of_order += 1;
+                    }
+                    prev = Some(ts);
+                    lines += 1;
+                }
+            }
+            window_start = window_end + 1;
+        }
+        Ok((lines, out_of_order))
+    }
+
+    /// Number of lines appended since the series was opened
+    pub(crate) fn appended_lines(&self) -> u64 {
+        self.appended_lines
+    }
+
+    /// Adds the reading to the line, once all readings in the line are set
+    /// it is written. The line gets the time the last reading was received.
+    #[instrument(skip(received))]
+    pub(crate) fn append(
+        &mut self,
+        reading: &protocol::Reading,
+        received: Instant,
+        time: jiff::Timestamp,
+    ) -> Result<Option<Appended>> {
         let index = reading
             .device()
             .info()
@@ -114,7 +488,7 @@ impl Series {
 
         let meta = &mut self.meta_list[index];
         meta.field.encode(reading.leaf().val, &mut self.line);
-        meta.set_at = Some(Instant::now());
+        meta.set_at = Some(received);
 
         let max_interval = reading.device().info().max_sample_interval;
 
@@ -123,11 +497,9 @@ impl Series {
             .iter()
             .map(|Meta { set_at, .. }| set_at)
             .all(|set| {
-                set.is_some_and(|set| set.elapsed() < max_interval)
+                set.is_some_and(|set| received.saturating_duration_since(set) < max_interval)
             })
         {
-            let time = jiff::Timestamp::now();
-
             let device_info = reading.leaf().device.info();
             let scale_factor = millis_to_minimal_representation(device_info);
             let scaled_time = time.as_millisecond() as u64 / scale_factor;
@@ -136,17 +508,134 @@ impl Series {
             if self.last_timestamp_pushed.is_some_and(|ts| ts == new_ts) {
                 tracing::warn!("Skipping datapoint with same timestamp");
             }
-            self.byteseries
-                .push_line(new_ts, &self.line)
-                .wrap_err("Could not write to timeseries on disk")?;
+            self.push_line(new_ts, self.line.clone())?;
+            self.appended_lines += 1;
+            let appended = Appended {
+                seq: self.appended_lines,
+                time: to_jiff(new_ts, scale_factor),
+                values: self
+                    .meta_list
+                    .iter()
+                    .map(|meta| (meta.reading.clone(), meta.field.decode(&self.line)))
+                    .collect(),
+            };
             self.line.fill(0);
 
             for meta in &mut self.meta_list {
                 meta.set_at = None;
             }
+            return Ok(Some(appended));
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    /// # Panics
+    /// If any of the requested readings are not part of this series.
+    fn fields(&self, readings: &[protocol::Reading]) -> Vec<bitspec::Field<f32>> {
+        readings
+            .iter()
+            .map(|requested| {
+                self.meta_list
+                    .iter()
+                    .find(|meta| requested.is_same_as(&meta.reading))
+                    .inspect(|meta| trace!("meta used for decoding: {meta:?}"))
+                    .map(|meta| meta.field.clone())
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "caller of read makes sure all readings are part of this \
+                        series.\n\tseries: {:?},\n\trequested: {:?}",
+                            self.meta_list, readings
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Reads every line between start and end without resampling. Where
+    /// the raw lines expired the points of the downsampled cache are
+    /// returned instead.
+    ///
+    /// # Panics
+    /// If any of the requested readings are not part of this series.
+    #[instrument(skip(self))]
+    pub(crate) fn read_all(
+        &mut self,
+        readings: &[protocol::Reading],
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<Vec<f32>>)> {
+        let device_info = readings
+            .first()
+            .expect("There is at least one reading to read")
+            .leaf()
+            .device
+            .info();
+        let scale_factor = millis_to_minimal_representation(device_info);
+        let start = start.as_millisecond() as u64 / scale_factor;
+        let end = end.as_millisecond() as u64 / scale_factor;
+        let mut decoder = Resampler::from_fields(self.fields(readings), self.line.len());
+        let mut timestamps = Vec::new();
+        let mut interleaved_data = Vec::new();
+        // the raw lines there were removed, the caches still have that time
+        let kept = self
+            .read_expired(
+                start..=end,
+                &mut decoder,
+                &mut timestamps,
+                &mut interleaved_data,
+            )
+            .wrap_err("Could not read expired part from the downsampled cache")?;
+        if let Some(range) = kept {
+            let n_lines = self
+                .byteseries
+                .n_lines_between(range.clone())
+                .wrap_err("Could not check if there is data between start and end")?;
+            if n_lines > 0 {
+                self.byteseries
+                    .read_all(range, &mut decoder, &mut timestamps, &mut interleaved_data)
+                    .wrap_err("Could not read lines from disk")?;
+            }
+        }
+
+        let time = timestamps
+            .into_iter()
+            .map(|ts| to_jiff(ts, scale_factor))
+            .collect();
+        Ok((time, deinterleave(interleaved_data, readings.len())))
+    }
+
+    /// Like [`Series::read_all`] but reads no further than `span` past
+    /// `start`. Also returns where the next chunk starts, None once `end`
+    /// is reached. Consecutive chunks neither overlap nor leave gaps.
+    ///
+    /// # Panics
+    /// If the reading is not part of this series.
+    #[instrument(skip(self))]
+    pub(crate) fn read_chunk(
+        &mut self,
+        reading: &protocol::Reading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        span: Duration,
+    ) -> Result<(Vec<jiff::Timestamp>, Vec<f32>, Option<jiff::Timestamp>)> {
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        let start_scaled = start.as_millisecond() as u64 / scale_factor;
+        let end_scaled = end.as_millisecond() as u64 / scale_factor;
+        if start_scaled > end_scaled {
+            return Ok((Vec::new(), Vec::new(), None));
+        }
+
+        let span = (span.as_millis() as u64 / scale_factor).max(1);
+        let chunk_end = end_scaled.min(start_scaled + span - 1);
+        let (time, mut data) = self.read_all(
+            std::slice::from_ref(reading),
+            start,
+            to_jiff(chunk_end, scale_factor),
+        )?;
+        let next = (chunk_end < end_scaled).then(|| to_jiff(chunk_end + 1, scale_factor));
+        let data = data.pop().expect("one reading is put in so one comes out");
+        Ok((time, data, next))
     }
 
     /// # Panics
@@ -169,23 +658,7 @@ impl Series {
         let start = start.as_millisecond() as u64 / scale_factor;
         let end = end.as_millisecond() as u64 / scale_factor;
         let range = start..=end;
-        let fields = readings
-            .iter()
-            .map(|requested| {
-                self.meta_list
-                    .iter()
-                    .find(|meta| requested.is_same_as(&meta.reading))
-                    .inspect(|meta| trace!("meta used for decoding: {meta:?}"))
-                    .map(|meta| meta.field.clone())
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "caller of read makes sure all readings are part of this \
-                        series.\n\tseries: {:?},\n\trequested: {:?}",
-                            self.meta_list, readings
-                        )
-                    })
-            })
-            .collect();
+        let fields = self.fields(readings);
         let mut resampler = Resampler::from_fields(fields, self.line.len());
 
         let mut timestamps = Vec::with_capacity(n * 2);
@@ -201,25 +674,188 @@ impl Series {
 
         let time = timestamps
             .into_iter()
-            .map(|ts| {
-                let millis = ts * scale_factor;
-                jiff::Timestamp::from_millisecond(millis as i64)
-                    .expect("timestamps are between MIN and MAX times of Timestamp type")
-            })
+            .map(|ts| to_jiff(ts, scale_factor))
             .collect();
+        Ok((time, deinterleave(interleaved_data, readings.len())))
+    }
 
-        let len = interleaved_data
-            .first()
-            .expect("read returns an error if there is not data")
-            .len();
-        let mut data = vec![Vec::new(); len];
-        for interleaved in interleaved_data {
-            for (interleaved, data) in interleaved.into_iter().zip(data.iter_mut()) {
-                data.push(interleaved);
+    /// Uses all the data if there is not too much, otherwise falls back
+    /// to the downsampled cache.
+    #[instrument(skip(self))]
+    pub(crate) fn stats(
+        &mut self,
+        reading: protocol::Reading,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    ) -> Result<api::Stats> {
+        let device_info = reading.device().info();
+        let max_sample_interval = device_info.max_sample_interval;
+        let end = end.min(jiff::Timestamp::now());
+        let scale_factor = millis_to_minimal_representation(device_info);
+        let range = start.as_millisecond() as u64 / scale_factor
+            ..=end.as_millisecond() as u64 / scale_factor;
+        let expired_per_point = self.expired.lines_per_point(*range.start());
+        let count = self
+            .byteseries
+            .n_lines_between(range)
+            .wrap_err("Could not count lines between start and end")?;
+
+        let readings = [reading];
+        let (exact, time, mut data) = if expired_per_point > 1 {
+            // the raw lines for the start of the range were removed
+            let (time, data) = self
+                .read(&readings, start, end, RESAMPLED_POINTS_FOR_STATS)
+                .wrap_err("Could not read from downsampled cache")?;
+            (false, time, data)
+        } else if count == 0 {
+            (true, Vec::new(), vec![Vec::new()])
+        } else if count <= MAX_LINES_FOR_EXACT_STATS {
+            let (time, data) = self.read_all(&readings, start, end)?;
+            (true, time, data)
+        } else {
+            let (time, data) = self
+                .read(&readings, start, end, RESAMPLED_POINTS_FOR_STATS)
+                .wrap_err("Could not read from downsampled cache")?;
+            (false, time, data)
+        };
+        let data = data.pop().expect("one reading is put in so one comes out");
+
+        // a point from the cache stands for multiple lines
+        let lines_per_point = (count / time.len().max(1) as u64).max(expired_per_point);
+        let max_interval = max_sample_interval * lines_per_point as u32;
+        let [reading] = readings;
+        Ok(stats::compute(
+            reading,
+            count,
+            exact,
+            &time,
+            &data,
+            (start, end),
+            max_interval,
+        ))
+    }
+
+    /// Every stretch without data longer then `min_gap` and the devices
+    /// max sample interval, including those at the start and end of the
+    /// range. Sampling at the max interval is normal and not a gap.
+    #[instrument(skip(self))]
+    pub(crate) fn gaps(
+        &mut self,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        min_gap: Option<Duration>,
+    ) -> Result<Vec<api::Gap>> {
+        let device_info = self.device_info();
+        let threshold = min_gap
+            .unwrap_or_default()
+            .max(device_info.max_sample_interval);
+        let min_sample_interval = device_info.min_sample_interval;
+        let end = end.min(jiff::Timestamp::now());
+        let scale_factor = millis_to_minimal_representation(device_info);
+        let window =
+            LINES_PER_GAP_WINDOW * min_sample_interval.as_millis() as u64 / scale_factor;
+
+        let mut gaps = Vec::new();
+        let mut prev = start;
+        let mut window_start = start.as_millisecond() as u64 / scale_factor;
+        let last = end.as_millisecond() as u64 / scale_factor;
+
+        // where the raw lines were removed only the points of the
+        // downsampled cache are left, those are further apart
+        let expired_per_point = self.expired.lines_per_point(window_start);
+        if expired_per_point > 1 {
+            let mut resampler = Resampler::from_fields(self.header().encoding, self.line.len());
+            let mut timestamps = Vec::new();
+            let kept = self
+                .read_expired(
+                    window_start..=last,
+                    &mut resampler,
+                    &mut timestamps,
+                    &mut Vec::new(),
+                )
+                .wrap_err("Could not read expired part from the downsampled cache")?;
+            let raw_from = to_jiff(self.expired.raw_from(), scale_factor).min(end);
+            let time = timestamps.into_iter().map(|ts| to_jiff(ts, scale_factor));
+            gaps.extend(gaps::between(
+                iter::once(prev).chain(time).chain([raw_from]),
+                threshold * expired_per_point as u32,
+            ));
+            let Some(kept) = kept else {
+                return Ok(gaps);
+            };
+            prev = raw_from;
+            window_start = *kept.start();
+        }
+
+        // reading everything at once could take a lot of memory
+        while window_start <= last {
+            let window_end = (window_start + window).min(last);
+            let mut timestamps = Vec::new();
+            if self
+                .byteseries
+                .n_lines_between(window_start..=window_end)
+                .wrap_err("Could not check if there is data in window")?
+                > 0
+            {
+                self.byteseries
+                    .read_all(
+                        window_start..=window_end,
+                        &mut gaps::TimestampsOnly,
+                        &mut timestamps,
+                        &mut Vec::new(),
+                    )
+                    .wrap_err("Could not read timestamps from disk")?;
             }
+
+            let time: Vec<_> = timestamps
+                .into_iter()
+                .map(|ts| to_jiff(ts, scale_factor))
+                .collect();
+            gaps.extend(gaps::between(
+                iter::once(prev).chain(time.iter().copied()),
+                threshold,
+            ));
+            prev = time.last().copied().unwrap_or(prev);
+            window_start = window_end + 1;
+        }
+        gaps.extend(gaps::between([prev, end], threshold));
+        Ok(gaps)
+    }
+
+    fn header(&self) -> Header {
+        Header {
+            readings: self.readings(),
+            encoding: self.meta_list.iter().map(|meta| meta.field.clone()).collect(),
+        }
+    }
+
+    fn device_info(&self) -> protocol::DeviceInfo {
+        self.meta_list
+            .first()
+            .expect("a series has at least one reading")
+            .reading
+            .device()
+            .info()
+    }
+}
+
+fn to_jiff(ts: u64, scale_factor: u64) -> jiff::Timestamp {
+    let millis = ts * scale_factor;
+    jiff::Timestamp::from_millisecond(millis as i64)
+        .expect("timestamps are between MIN and MAX times of Timestamp type")
+}
+
+fn deinterleave(
+    interleaved_data: Vec<smallvec::SmallVec<f32, 8>>,
+    n_readings: usize,
+) -> Vec<Vec<f32>> {
+    let mut data = vec![Vec::with_capacity(interleaved_data.len()); n_readings];
+    for interleaved in interleaved_data {
+        for (interleaved, data) in interleaved.into_iter().zip(data.iter_mut()) {
+            data.push(interleaved);
         }
-        Ok((time, data))
     }
+    data
 }
 
 fn meta_list_and_payload_size(
@@ -257,29 +893,6 @@ pub fn millis_to_minimal_representation(device_info: protocol::DeviceInfo) -> u6
     div_factor.round() as u64
 }
 
-#[instrument(level = "debug", skip(data))]
-pub(crate) async fn store(data: &Data, reading: &protocol::Reading, data_dir: &Path) -> Result<()> {
-    let mut data = data.0.lock().await;
-
-    let key = reading.device();
-    if let Some(series) = data.get_mut(&key) {
-        series
-            .append(reading)
-            .wrap_err("failed to append to existing timeseries")?;
-    } else {
-        let mut series = Series::open_or_create(reading, data_dir)
-            .wrap_err("Could not open new series")
-            .with_note(|| format!("reading was: {reading:?}"))?;
-        series
-            .append(reading)
-            .wrap_err("failed to newly created timeseries")?;
-        let existing = data.insert(key, series);
-        assert!(existing.is_none(), "should not race we still hold the lock");
-    }
-
-    Ok(())
-}
-
 fn to_speclist(readings: &[protocol::Reading]) -> Vec<bitspec::LengthWithOps> {
     readings
         .iter()
@@ -293,22 +906,59 @@ fn to_speclist(readings: &[protocol::Reading]) -> Vec<bitspec::LengthWithOps> {
 
 /// relative path without extension
 fn base_path(reading: &protocol::Reading) -> PathBuf {
-    let mut parts = Vec::new();
-    let mut current = reading as &dyn Tree;
-    loop {
-        match current.inner() {
-            Item::Leaf(reading::Info { device, .. }) => {
-                parts.push(device.info().name.to_lowercase());
-                break;
-            }
-            Item::Node(inner) => {
-                parts.push(current.name().to_lowercase());
-                current = inner;
-                continue;
-            }
-        }
-    }
-    parts.into_iter().collect()
+    PathBuf::from(reading.device_path())
+}
+
+/// Cuts off what was written to the files of the series after its last
+/// commit, see [`committed`]. Call before opening the series, the lines
+/// are replayed from the journal by [`Series::recover`]. Returns the number
+/// of bytes removed.
+pub(crate) fn drop_uncommitted(device: &protocol::Device, dir: &Path) -> Result<u64> {
+    committed::truncate(&dir.join(device_path(device)))
+}
+
+/// Number of bytes [`drop_uncommitted`] would remove
+pub(crate) fn uncommitted(device: &protocol::Device, dir: &Path) -> Result<u64> {
+    committed::uncommitted(&dir.join(device_path(device)))
+}
+
+/// Path of the series for `device` relative to the data dir, without
+/// extension.
+pub(crate) fn device_path(device: &protocol::Device) -> PathBuf {
+    PathBuf::from(device.path())
+}
+
+/// Finds the device whose series is stored at `path`. The path is relative
+/// to the data dir and without extension, for example `largebedroom/bed/sht31`.
+pub(crate) fn device_from_path(path: &Path) -> Option<protocol::Device> {
+    protocol::Device::all().into_iter().find(|device| {
+        device
+            .info()
+            .affects_readings
+            .first()
+            .is_some_and(|reading| base_path(reading) == path)
+    })
+}
+
+/// Last part of the readings path, for example `Temperature`
+pub(crate) fn reading_name(reading: &protocol::Reading) -> String {
+    reading.leaf_name()
+}
+
+fn open_byteseries(
+    path: &Path,
+    header: Header,
+    payload_size: usize,
+    create_new: bool,
+) -> Result<ByteSeries, series::Error> {
+    let (resampler, configs) = resample_setup(&header.encoding, payload_size);
+    ByteSeries::builder()
+        .payload_size(payload_size)
+        .with_downsampled_cache(resampler, configs)
+        .with_header(header)
+        .create_new(create_new)
+        .open(path)
+        .map(|(byteseries, _)| byteseries)
 }
 
 fn resample_setup(
@@ -384,6 +1034,15 @@ mod test {
         assert_ne!(base_path(&reading_a), base_path(&reading_b));
     }
 
+    #[test]
+    fn device_found_from_path() {
+        let reading =
+            Reading::LargeBedroom(large_bedroom::Reading::Bed(bed::Reading::Humidity(0.0)));
+        let device = device_from_path(Path::new("largebedroom/bed/sht31"));
+        assert_eq!(device, Some(reading.device()));
+        assert_eq!(reading_name(&reading), "Humidity");
+    }
+
     #[test]
     fn reading_path_is_expected() {
         let reading =
@@ -0,0 +1,415 @@
+//! Splitting a range into buckets that line up with the local wall clock or
+//! with multiples of a fixed duration and aggregating the data in each of
+//! them.
+use std::time::Duration;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use jiff::tz::TimeZone;
+use jiff::{Span, Timestamp, ToSpan, Zoned};
+
+use super::Series;
+use crate::api::{self, BucketSize, BucketValues};
+
+/// A request may not split its range into more buckets than this
+const MAX_BUCKETS: usize = 10_000;
+/// Above this many lines in a bucket it is computed from the downsampled cache
+const MAX_LINES_FOR_EXACT_BUCKET: u64 = 10_000;
+/// Number of points read from the downsampled cache per bucket
+const RESAMPLED_POINTS_PER_BUCKET: usize = 500;
+const MIN_DURATION: Duration = Duration::from_secs(60);
+const MAX_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("unknown time zone `{name}`: {error}")]
+    UnknownTimeZone { name: String, error: String },
+    #[error("end ({end}) must come after start ({start})")]
+    EndBeforeStart { start: Timestamp, end: Timestamp },
+    #[error("bucket duration must be between a minute and a day, got: {0:?}")]
+    DurationOutOfRange(Duration),
+    #[error("range would be split into more then {MAX_BUCKETS} buckets")]
+    TooMany,
+    #[error("bucket duration must be at least a millisecond")]
+    ZeroDuration,
+    #[error("could not compute bucket edge: {0}")]
+    Time(#[from] jiff::Error),
+}
+
+/// The edges of the buckets covering `start` till `end`. The first edge is
+/// at or before `start`, the last at or after `end`. Bucket `i` runs from
+/// edge `i` up to but not including edge `i + 1`.
+pub(crate) fn edges(
+    start: Timestamp,
+    end: Timestamp,
+    size: &BucketSize,
+    time_zone: &str,
+) -> Result<Vec<Timestamp>, Error> {
+    let tz = TimeZone::get(time_zone).map_err(|e| Error::UnknownTimeZone {
+        name: time_zone.to_owned(),
+        error: e.to_string(),
+    })?;
+    if end <= start {
+        return Err(Error::EndBeforeStart { start, end });
+    }
+    if let BucketSize::Duration(duration) = size {
+        if !(MIN_DURATION..=MAX_DURATION).contains(duration) {
+            return Err(Error::DurationOutOfRange(*duration));
+        }
+    }
+
+    let start = start.to_zoned(tz);
+    let mut edge = floor(&start, size)?;
+    // after the clocks were turned back flooring can end up more then one
+    // bucket before start
+    loop {
+        let next = next(&edge, size)?;
+        if next.timestamp() > start.timestamp() {
+            break;
+        }
+        edge = next;
+    }
+
+    let mut edges = vec![edge.timestamp()];
+    while edge.timestamp() < end {
+        if edges.len() > MAX_BUCKETS {
+            return Err(Error::TooMany);
+        }
+        edge = next(&edge, size)?;
+        edges.push(edge.timestamp());
+    }
+    Ok(edges)
+}
+
+/// Start of the local hour, day or week containing `zoned`. For durations
+/// this is the start of the day, the first bucket is found from there.
+fn floor(zoned: &Zoned, size: &BucketSize) -> Result<Zoned, jiff::Error> {
+    let datetime = zoned.datetime();
+    let date = datetime.date();
+    let floored = match size {
+        BucketSize::Hour => date.at(datetime.hour(), 0, 0, 0),
+        BucketSize::Day | BucketSize::Duration(_) => date.at(0, 0, 0, 0),
+        BucketSize::Week => {
+            let since_monday = date.weekday().to_monday_zero_offset();
+            date.checked_sub(i64::from(since_monday).days())?.at(0, 0, 0, 0)
+        }
+    };
+    floored.to_zoned(zoned.time_zone().clone())
+}
+
+fn next(edge: &Zoned, size: &BucketSize) -> Result<Zoned, jiff::Error> {
+    match size {
+        // an hour is always an hour, even when the clocks change
+        BucketSize::Hour => edge.checked_add(1.hour()),
+        // days and weeks follow the calendar, they are not always 24*7 hours
+        BucketSize::Day => edge.checked_add(1.day()),
+        BucketSize::Week => edge.checked_add(1.week()),
+        BucketSize::Duration(duration) => {
+            let next_day = floor(edge, &BucketSize::Day)?.checked_add(1.day())?;
+            let millis = i64::try_from(duration.as_millis()).expect("at most a day");
+            let next = edge.checked_add(Span::new().milliseconds(millis))?;
+            Ok(if next.timestamp() < next_day.timestamp() {
+                next
+            } else {
+                next_day
+            })
+        }
+    }
+}
+
+/// Buckets aligned to multiples of `bucket` since the unix epoch covering
+/// `start` till `end`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Aligned {
+    first: Timestamp,
+    bucket_ms: i64,
+    buckets: usize,
+}
+
+impl Aligned {
+    pub(crate) fn new(start: Timestamp, end: Timestamp, bucket: Duration) -> Result<Self, Error> {
+        if end <= start {
+            return Err(Error::EndBeforeStart { start, end });
+        }
+        let bucket_ms = i64::try_from(bucket.as_millis()).unwrap_or(i64::MAX);
+        if bucket_ms == 0 {
+            return Err(Error::ZeroDuration);
+        }
+
+        let first = start.as_millisecond().div_euclid(bucket_ms) * bucket_ms;
+        let covered = end.as_millisecond().saturating_sub(first);
+        let buckets = (covered / bucket_ms + i64::from(covered % bucket_ms != 0)) as u64;
+        if buckets > MAX_BUCKETS as u64 {
+            return Err(Error::TooMany);
+        }
+        // every bucket edge must be a valid timestamp
+        let end = first.saturating_add(bucket_ms.saturating_mul(buckets as i64));
+        Timestamp::from_millisecond(end)?;
+        Ok(Self {
+            first: Timestamp::from_millisecond(first)?,
+            bucket_ms,
+            buckets: buckets as usize,
+        })
+    }
+
+    fn edge(&self, idx: usize) -> Timestamp {
+        let edge = self.first.as_millisecond() + idx as i64 * self.bucket_ms;
+        Timestamp::from_millisecond(edge).expect("checked in new")
+    }
+
+    /// Start and (exclusive) end of each bucket
+    fn ranges(&self) -> impl Iterator<Item = (Timestamp, Timestamp)> + '_ {
+        (0..self.buckets).map(|idx| (self.edge(idx), self.edge(idx + 1)))
+    }
+
+    /// Exclusive
+    fn end(&self) -> Timestamp {
+        self.edge(self.buckets)
+    }
+}
+
+impl Series {
+    /// Mean of `reading` in each of the aligned buckets. Every bucket is
+    /// read on its own so its value does not depend on the range it is
+    /// requested in. Time is the start of the bucket, empty buckets are left
+    /// out.
+    pub(crate) fn read_aligned(
+        &mut self,
+        reading: &protocol::Reading,
+        aligned: Aligned,
+    ) -> Result<(Vec<Timestamp>, Vec<f32>)> {
+        let scale_factor = super::millis_to_minimal_representation(reading.device().info());
+        let last = aligned.end() - 1.millisecond();
+        let range = aligned.first.as_millisecond() as u64 / scale_factor
+            ..=last.as_millisecond() as u64 / scale_factor;
+        let expired = self.expired.lines_per_point(*range.start()) > 1;
+        let lines = self
+            .byteseries
+            .n_lines_between(range)
+            .wrap_err("Could not count lines in range")?;
+        if lines == 0 && !expired {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        let mut time = Vec::new();
+        let mut means = Vec::new();
+        for (start, end) in aligned.ranges() {
+            let Some((_, data)) = self.bucket_data(reading, start, end)? else {
+                continue;
+            };
+            if let Some(values) = values(&data) {
+                time.push(start);
+                means.push(values.mean);
+            }
+        }
+        Ok((time, means))
+    }
+
+    /// Aggregates `reading` in the buckets between `edges`, see [`edges`].
+    pub(crate) fn buckets(
+        &mut self,
+        reading: &protocol::Reading,
+        edges: &[Timestamp],
+    ) -> Result<Vec<api::Bucket>> {
+        let mut buckets = Vec::with_capacity(edges.len().saturating_sub(1));
+        for edge in edges.windows(2) {
+            let (start, end) = (edge[0], edge[1]);
+            let (exact, values) = match self.bucket_data(reading, start, end)? {
+                Some((exact, data)) => (exact, values(&data)),
+                None => (true, None),
+            };
+            buckets.push(api::Bucket {
+                start,
+                end,
+                exact,
+                values,
+            });
+        }
+        Ok(buckets)
+    }
+
+    /// The data of `reading` from `start` up to but not including `end`.
+    /// Exact if every line was read, otherwise the points the downsampled
+    /// cache has. None if there are no lines.
+    fn bucket_data(
+        &mut self,
+        reading: &protocol::Reading,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Option<(bool, Vec<f32>)>> {
+        let scale_factor = super::millis_to_minimal_representation(reading.device().info());
+        let readings = [reading.clone()];
+        let last = end - 1.millisecond();
+        let range = start.as_millisecond() as u64 / scale_factor
+            ..=last.as_millisecond() as u64 / scale_factor;
+        // raw lines removed by retention are not counted, the downsampled
+        // cache still has them
+        let expired = self.expired.lines_per_point(*range.start()) > 1;
+        let count = self
+            .byteseries
+            .n_lines_between(range)
+            .wrap_err("Could not count lines in bucket")?;
+
+        // reading a range without lines is an error
+        if count == 0 && !expired {
+            return Ok(None);
+        }
+
+        let (exact, mut data) = if !expired && count <= MAX_LINES_FOR_EXACT_BUCKET {
+            let (_, data) = self.read_all(&readings, start, last)?;
+            (true, data)
+        } else {
+            let (_, data) = self
+                .read(&readings, start, last, RESAMPLED_POINTS_PER_BUCKET)
+                .wrap_err("Could not read from downsampled cache")?;
+            (false, data)
+        };
+        let data = data.pop().expect("one reading is put in so one comes out");
+        Ok(Some((exact, data)))
+    }
+}
+
+fn values(data: &[f32]) -> Option<BucketValues> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let mean = data.iter().copied().map(f64::from).sum::<f64>() / data.len() as f64;
+    Some(BucketValues {
+        min: data.iter().copied().fold(f32::INFINITY, f32::min),
+        max: data.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        mean: mean as f32,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn local(edges: &[Timestamp]) -> Vec<String> {
+        let tz = TimeZone::get("Europe/Amsterdam").unwrap();
+        edges
+            .iter()
+            .map(|edge| edge.to_zoned(tz.clone()).datetime().to_string())
+            .collect()
+    }
+
+    fn ts(zoned: &str) -> Timestamp {
+        zoned.parse::<Zoned>().unwrap().timestamp()
+    }
+
+    #[test]
+    fn days_follow_dst() {
+        // clocks went forward on the 31st
+        let start = ts("2024-03-30T12:00[Europe/Amsterdam]");
+        let end = ts("2024-04-01T12:00[Europe/Amsterdam]");
+        let edges = edges(start, end, &BucketSize::Day, "Europe/Amsterdam").unwrap();
+        assert_eq!(
+            local(&edges),
+            [
+                "2024-03-30T00:00:00",
+                "2024-03-31T00:00:00",
+                "2024-04-01T00:00:00",
+                "2024-04-02T00:00:00"
+            ]
+        );
+        assert_eq!(edges[2].as_second() - edges[1].as_second(), 23 * 60 * 60);
+    }
+
+    #[test]
+    fn hours_when_clocks_go_back() {
+        let start = ts("2024-10-27T00:00[Europe/Amsterdam]");
+        let end = ts("2024-10-28T00:00[Europe/Amsterdam]");
+        let edges = edges(start, end, &BucketSize::Hour, "Europe/Amsterdam").unwrap();
+        // 25 hours in this day
+        assert_eq!(edges.len(), 26);
+        assert!(edges
+            .windows(2)
+            .all(|w| w[1].as_second() - w[0].as_second() == 60 * 60));
+    }
+
+    #[test]
+    fn durations_restart_at_midnight() {
+        let start = ts("2024-03-31T01:00[Europe/Amsterdam]");
+        let end = ts("2024-04-01T01:00[Europe/Amsterdam]");
+        let duration = BucketSize::Duration(Duration::from_secs(7 * 60 * 60));
+        let edges = edges(start, end, &duration, "Europe/Amsterdam").unwrap();
+        assert_eq!(
+            local(&edges),
+            [
+                "2024-03-31T00:00:00",
+                // 7 hours later on the wall clock is 8:00
+                "2024-03-31T08:00:00",
+                "2024-03-31T15:00:00",
+                "2024-03-31T22:00:00",
+                "2024-04-01T00:00:00",
+                "2024-04-01T07:00:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // a wednesday
+        let start = ts("2024-06-05T12:00[Europe/Amsterdam]");
+        let end = ts("2024-06-06T12:00[Europe/Amsterdam]");
+        let edges = edges(start, end, &BucketSize::Week, "Europe/Amsterdam").unwrap();
+        assert_eq!(local(&edges), ["2024-06-03T00:00:00", "2024-06-10T00:00:00"]);
+    }
+
+    #[test]
+    fn aligned_to_multiples() {
+        let start = Timestamp::from_second(95).unwrap();
+        let end = Timestamp::from_second(301).unwrap();
+        let aligned = Aligned::new(start, end, Duration::from_secs(60)).unwrap();
+        assert_eq!(aligned.first, Timestamp::from_second(60).unwrap());
+        assert_eq!(aligned.buckets, 5);
+        assert_eq!(aligned.end(), Timestamp::from_second(360).unwrap());
+
+        // shifting the window does not move the buckets
+        let shifted = Aligned::new(start + 10.seconds(), end, Duration::from_secs(60)).unwrap();
+        assert_eq!(shifted.first, aligned.first);
+    }
+
+    #[test]
+    fn aligned_bucket_starts() {
+        let aligned = Aligned::new(
+            Timestamp::from_second(5).unwrap(),
+            Timestamp::from_second(30).unwrap(),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        let ranges: Vec<_> = aligned
+            .ranges()
+            .map(|(start, end)| (start.as_second(), end.as_second()))
+            .collect();
+        assert_eq!(ranges, [(0, 10), (10, 20), (20, 30)]);
+
+        let huge = Duration::from_secs(u64::MAX);
+        assert!(Aligned::new(Timestamp::UNIX_EPOCH, Timestamp::MAX, huge).is_err());
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let start = Timestamp::UNIX_EPOCH;
+        let end = start + 1.hour();
+        assert!(matches!(
+            edges(start, end, &BucketSize::Day, "Mars/Olympus_Mons"),
+            Err(Error::UnknownTimeZone { .. })
+        ));
+        assert!(matches!(
+            edges(end, start, &BucketSize::Day, "UTC"),
+            Err(Error::EndBeforeStart { .. })
+        ));
+        let too_short = BucketSize::Duration(Duration::from_secs(1));
+        assert!(matches!(
+            edges(start, end, &too_short, "UTC"),
+            Err(Error::DurationOutOfRange(_))
+        ));
+        let year = Timestamp::from_second(365 * 24 * 60 * 60).unwrap();
+        assert!(matches!(
+            edges(start, year, &BucketSize::Duration(MIN_DURATION), "UTC"),
+            Err(Error::TooMany)
+        ));
+    }
+}
@@ -0,0 +1,206 @@
+//! The length of every byteseries file as of the last commit, stored next
+//! to the series in the `.committed` file. A line per file: its name, a
+//! space and its length in bytes.
+//!
+//! The byteseries files are only appended to. Anything past the recorded
+//! length was written after the last commit and can end in a torn line
+//! after a power cut. It is cut off before the series is opened, the lines
+//! in it are still in the journal and replayed from there.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::{Result, Section};
+
+/// Files next to the series that are not part of the byteseries
+const NOT_BYTESERIES: [&str; 5] = [
+    "journal",
+    "expired",
+    "expired_new",
+    "committed",
+    "committed_new",
+];
+
+fn path(series_path: &Path) -> PathBuf {
+    series_path.with_extension("committed")
+}
+
+/// All files that belong to the series at `path` (without extension)
+pub(super) fn files(path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut prefix = path.file_name().map(OsString::from).unwrap_or_default();
+    prefix.push(".");
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not list files in dir")
+                .with_note(|| format!("dir: {}", dir.display()))
+        }
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.wrap_err("Could not read dir entry")?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+fn byteseries_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = files(path)?;
+    files.retain(|file| {
+        file.extension()
+            .is_some_and(|ext| !NOT_BYTESERIES.iter().any(|other| ext == *other))
+    });
+    Ok(files)
+}
+
+/// Makes sure everything written to the byteseries at `path` is on disk
+pub(super) fn sync_files(path: &Path) -> Result<()> {
+    for file in byteseries_files(path)? {
+        File::open(&file)
+            .and_then(|file| file.sync_all())
+            .wrap_err("Could not sync file")
+            .with_note(|| format!("path: {}", file.display()))?;
+    }
+    Ok(())
+}
+
+/// Makes a rename in the directory of `path` durable
+pub(super) fn sync_dir(path: &Path) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .wrap_err("Could not sync dir")
+        .with_note(|| format!("dir: {}", dir.display()))
+}
+
+/// Records the current length of the byteseries files, call once they are
+/// synced.
+pub(super) fn record(series_path: &Path) -> Result<()> {
+    let mut list = String::new();
+    for file in byteseries_files(series_path)? {
+        let len = fs::metadata(&file)
+            .wrap_err("Could not get file size")
+            .with_note(|| format!("path: {}", file.display()))?
+            .len();
+        let name = file
+            .file_name()
+            .expect("files only returns paths with a file name")
+            .to_string_lossy();
+        list.push_str(&format!("{name} {len}\n"));
+    }
+
+    let path = path(series_path);
+    let tmp = path.with_extension("committed_new");
+    File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(list.as_bytes())?;
+            file.sync_all()
+        })
+        .wrap_err("Could not write committed file lengths")
+        .with_note(|| format!("path: {}", tmp.display()))?;
+    fs::rename(&tmp, &path)
+        .wrap_err("Could not move file in place")
+        .with_note(|| format!("path: {}", path.display()))?;
+    sync_dir(&path)
+}
+
+/// Files that are longer than they were at the last commit with the length
+/// they had then. Empty if nothing was ever recorded.
+fn grown(series_path: &Path) -> Result<Vec<(PathBuf, u64, u64)>> {
+    let path = path(series_path);
+    let list = match fs::read_to_string(&path) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not read committed file lengths")
+                .with_note(|| format!("path: {}", path.display()))
+        }
+    };
+
+    let dir = series_path.parent().unwrap_or(Path::new("."));
+    let mut grown = Vec::new();
+    for line in list.lines() {
+        let (name, committed) = line
+            .rsplit_once(' ')
+            .and_then(|(name, len)| Some((name, len.parse::<u64>().ok()?)))
+            .ok_or_else(|| eyre!("Invalid line in committed file lengths: {line}"))
+            .with_note(|| format!("path: {}", path.display()))?;
+        let file = dir.join(name);
+        let len = match fs::metadata(&file) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not get file size")
+                    .with_note(|| format!("path: {}", file.display()))
+            }
+        };
+        if len > committed {
+            grown.push((file, committed, len));
+        }
+    }
+    Ok(grown)
+}
+
+/// Bytes written to the byteseries files after the last commit
+pub(super) fn uncommitted(series_path: &Path) -> Result<u64> {
+    Ok(grown(series_path)?
+        .into_iter()
+        .map(|(_, committed, len)| len - committed)
+        .sum())
+}
+
+/// Cuts off everything written to the byteseries files after the last
+/// commit. The series must not be open. Returns the number of bytes removed.
+pub(super) fn truncate(series_path: &Path) -> Result<u64> {
+    let mut removed = 0;
+    for (file, committed, len) in grown(series_path)? {
+        OpenOptions::new()
+            .write(true)
+            .open(&file)
+            .and_then(|file| {
+                file.set_len(committed)?;
+                file.sync_all()
+            })
+            .wrap_err("Could not truncate file")
+            .with_note(|| format!("path: {}", file.display()))?;
+        removed += len - committed;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bytes_after_commit_are_cut_off() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let series = dir.path().join("sht31");
+        let data = series.with_extension("byteseries");
+        fs::write(&data, b"header line").unwrap();
+        fs::write(series.with_extension("journal"), b"block").unwrap();
+        assert_eq!(uncommitted(&series).unwrap(), 0);
+
+        record(&series).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&data).unwrap();
+        file.write_all(b" torn").unwrap();
+        fs::write(series.with_extension("journal"), b"longer block").unwrap();
+        assert_eq!(uncommitted(&series).unwrap(), 5);
+
+        assert_eq!(truncate(&series).unwrap(), 5);
+        assert_eq!(fs::read(&data).unwrap(), b"header line");
+        assert_eq!(uncommitted(&series).unwrap(), 0);
+    }
+}
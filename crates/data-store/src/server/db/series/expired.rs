@@ -0,0 +1,252 @@
+//! Removing data the retention policy no longer keeps. Raw lines are
+//! removed from the start of the series while the downsampled caches keep
+//! covering that time until they expire themselves. Reads of an expired
+//! stretch fall through to the finest cache level that still has it.
+//!
+//! Where the data of each level starts is stored next to the series, in the
+//! `.expired` file, as three little endian `u64` timestamps: raw, 10x and
+//! 100x. The 1000x cache is kept forever.
+use std::fs::{self, File};
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use byteseries::series;
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Result, Section};
+use tracing::instrument;
+
+use super::committed::{self, files, sync_dir, sync_files};
+use super::resampler::Resampler;
+use super::{millis_to_minimal_representation, Series, CACHE_BUCKET_SIZES};
+use crate::server::retention::Policy;
+
+/// Raw data, the 10x and the 100x cache
+const LEVELS: usize = 3;
+/// Removing lines from the start of a file rewrites it. Only do so once
+/// this fraction of the retention period expired since the last time.
+const TRUNCATE_FRACTION: u64 = 10;
+/// Never read more points than this from an expired stretch at once
+const MAX_EXPIRED_POINTS: u64 = 100_000;
+
+fn path(series_path: &Path) -> PathBuf {
+    series_path.with_extension("expired")
+}
+
+/// Where the data kept at each level starts, earlier lines were removed.
+/// Level 0 is the raw data, 1 and 2 the caches averaging 10 and 100 lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Expired {
+    kept_from: [u64; LEVELS],
+}
+
+impl Expired {
+    pub(super) fn load(series_path: &Path) -> Result<Self> {
+        let path = path(series_path);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not read where expired data ends")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        };
+        Self::decode(&bytes)
+            .ok_or_else(|| color_eyre::eyre::eyre!("File has the wrong length"))
+            .with_note(|| format!("path: {}", path.display()))
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != LEVELS * 8 {
+            return None;
+        }
+        let mut kept_from = [0; LEVELS];
+        for (kept_from, bytes) in kept_from.iter_mut().zip(bytes.chunks_exact(8)) {
+            *kept_from = u64::from_le_bytes(bytes.try_into().expect("chunks are 8 long"));
+        }
+        Some(Self { kept_from })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.kept_from
+            .iter()
+            .flat_map(|ts| ts.to_le_bytes())
+            .collect()
+    }
+
+    /// Replaces the file in one go, a crash leaves either the old or the
+    /// new version.
+    fn store(&self, series_path: &Path) -> Result<()> {
+        let path = path(series_path);
+        let tmp = path.with_extension("expired_new");
+        File::create(&tmp)
+            .and_then(|mut file| {
+                io::Write::write_all(&mut file, &self.encode())?;
+                file.sync_all()
+            })
+            .wrap_err("Could not write where expired data ends")
+            .with_note(|| format!("path: {}", tmp.display()))?;
+        fs::rename(&tmp, &path)
+            .wrap_err("Could not move file in place")
+            .with_note(|| format!("path: {}", path.display()))?;
+        sync_dir(&path)
+    }
+
+    /// The raw lines before this were removed
+    pub(super) fn raw_from(&self) -> u64 {
+        self.kept_from[0]
+    }
+
+    /// Number of lines each stored point at `ts` stands for. That is one
+    /// where the raw lines are kept, otherwise the bucket size of the finest
+    /// cache that still covers `ts`.
+    pub(super) fn lines_per_point(&self, ts: u64) -> u64 {
+        if ts >= self.raw_from() {
+            return 1;
+        }
+        CACHE_BUCKET_SIZES
+            .into_iter()
+            .zip(self.kept_from[1..].iter().copied().chain([0]))
+            .find(|(_, kept_from)| ts >= *kept_from)
+            .map(|(bucket_size, _)| bucket_size)
+            .expect("the coarsest cache is never truncated")
+    }
+}
+
+impl Series {
+    /// Removes data older than the policy allows for a level. The 1000x
+    /// cache is kept forever.
+    #[instrument(skip(self), fields(path = %self.path.display()))]
+    pub(crate) fn enforce_retention(
+        &mut self,
+        policy: &Policy,
+        now: jiff::Timestamp,
+    ) -> Result<()> {
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        let now = now.as_millisecond() as u64 / scale_factor;
+
+        let mut expired = self.expired;
+        for (level, keep) in policy.levels().into_iter().enumerate() {
+            let Some(keep) = keep else {
+                continue;
+            };
+            let keep = keep.as_millis() as u64 / scale_factor;
+            let cutoff = now.saturating_sub(keep);
+            let kept_from = &mut expired.kept_from[level];
+            if cutoff.saturating_sub(*kept_from) >= keep / TRUNCATE_FRACTION {
+                *kept_from = cutoff;
+            }
+        }
+        if expired == self.expired {
+            return Ok(());
+        }
+
+        // recorded first, after a crash reads then skip lines that might
+        // only have been partly removed
+        expired
+            .store(&self.path)
+            .wrap_err("Could not record where the data now starts")?;
+        let previous = std::mem::replace(&mut self.expired, expired);
+
+        if expired.kept_from[0] != previous.kept_from[0] {
+            self.byteseries
+                .truncate_before(expired.kept_from[0])
+                .wrap_err("Could not remove expired raw lines")?;
+        }
+        for (cache, (kept_from, before)) in expired.kept_from[1..]
+            .iter()
+            .zip(&previous.kept_from[1..])
+            .enumerate()
+        {
+            if kept_from != before {
+                self.byteseries
+                    .truncate_downsampled_before(cache, *kept_from)
+                    .wrap_err_with(|| {
+                        format!("Could not remove expired lines from cache {cache}")
+                    })?;
+            }
+        }
+        sync_files(&self.path).wrap_err("Could not sync series to disk")?;
+        committed::record(&self.path)
+    }
+
+    /// Size in bytes of the series and its downsampled caches
+    pub(crate) fn disk_usage(&self) -> Result<u64> {
+        Ok(self.files_on_disk()?.iter().map(|(_, bytes)| bytes).sum())
+    }
+
+    /// Every file of the series and its downsampled caches with their size
+    /// in bytes
+    pub(crate) fn files_on_disk(&self) -> Result<Vec<(PathBuf, u64)>> {
+        let mut on_disk = Vec::new();
+        for file in files(&self.path)? {
+            let bytes = fs::metadata(&file)
+                .wrap_err("Could not get file size")
+                .with_note(|| format!("path: {}", file.display()))?
+                .len();
+            on_disk.push((file, bytes));
+        }
+        on_disk.sort();
+        Ok(on_disk)
+    }
+
+    /// Reads the points the downsampled caches have for the part of `range`
+    /// from which the raw lines were removed. Returns what is left of the
+    /// range, None if all of it expired.
+    pub(super) fn read_expired(
+        &mut self,
+        range: RangeInclusive<u64>,
+        resampler: &mut Resampler,
+        timestamps: &mut Vec<u64>,
+        data: &mut Vec<smallvec::SmallVec<f32, 8>>,
+    ) -> Result<Option<RangeInclusive<u64>>, series::Error> {
+        let raw_from = self.expired.raw_from();
+        let (start, end) = range.into_inner();
+        if start >= raw_from {
+            return Ok(Some(start..=end));
+        }
+
+        let expired_end = end.min(raw_from - 1);
+        let scale_factor = millis_to_minimal_representation(self.device_info());
+        let interval = self.device_info().min_sample_interval.as_millis() as u64 / scale_factor;
+        let per_point = interval.max(1) * self.expired.lines_per_point(expired_end);
+        let n = ((expired_end - start) / per_point + 1).min(MAX_EXPIRED_POINTS);
+        self.byteseries
+            .read_n(n as usize, start..=expired_end, resampler, timestamps, data)?;
+
+        Ok((end >= raw_from).then_some(raw_from..=end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stored_and_loaded() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let series = dir.path().join("sht31");
+        assert_eq!(Expired::load(&series).unwrap(), Expired::default());
+
+        let expired = Expired {
+            kept_from: [300, 200, 100],
+        };
+        expired.store(&series).unwrap();
+        assert_eq!(Expired::load(&series).unwrap(), expired);
+        assert!(!path(&series).with_extension("expired_new").exists());
+    }
+
+    #[test]
+    fn expired_stretches_come_from_the_finest_cache() {
+        let expired = Expired {
+            kept_from: [300, 200, 100],
+        };
+        assert_eq!(expired.lines_per_point(300), 1);
+        assert_eq!(expired.lines_per_point(299), 10);
+        assert_eq!(expired.lines_per_point(200), 10);
+        assert_eq!(expired.lines_per_point(150), 100);
+        assert_eq!(expired.lines_per_point(0), 1000);
+        assert_eq!(Expired::default().lines_per_point(0), 1);
+    }
+}
@@ -0,0 +1,62 @@
+use std::iter;
+use std::time::Duration;
+
+use crate::api::Gap;
+
+/// Every stretch between consecutive timestamps longer then `threshold`.
+pub(super) fn between(
+    time: impl IntoIterator<Item = jiff::Timestamp>,
+    threshold: Duration,
+) -> impl Iterator<Item = Gap> {
+    let mut time = time.into_iter();
+    let mut prev = time.next();
+    iter::from_fn(move || loop {
+        let curr = time.next()?;
+        let gap_start = prev
+            .replace(curr)
+            .expect("prev is only None if there are no timestamps");
+        if duration(gap_start, curr) > threshold {
+            return Some(Gap {
+                start: gap_start,
+                end: curr,
+            });
+        }
+    })
+}
+
+pub(super) fn duration(start: jiff::Timestamp, end: jiff::Timestamp) -> Duration {
+    let millis = (end.as_millisecond() - start.as_millisecond()).max(0);
+    Duration::from_millis(millis as u64)
+}
+
+/// Decodes nothing, for when only the timestamps are needed
+#[derive(Debug)]
+pub(super) struct TimestampsOnly;
+impl byteseries::Decoder for TimestampsOnly {
+    type Item = ();
+
+    fn decode_payload(&mut self, _: &[u8]) -> Self::Item {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jiff::{Timestamp, ToSpan};
+
+    #[test]
+    fn finds_all_gaps() {
+        let start = Timestamp::UNIX_EPOCH;
+        let time = [0, 1, 2, 10, 11, 30].map(|s| start + s.seconds());
+        let gaps: Vec<_> = between(time, Duration::from_secs(5)).collect();
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0].start, start + 2.seconds());
+        assert_eq!(gaps[0].end, start + 10.seconds());
+        assert_eq!(gaps[1].start, start + 11.seconds());
+        assert_eq!(gaps[1].end, start + 30.seconds());
+    }
+
+    #[test]
+    fn no_timestamps_no_gaps() {
+        assert_eq!(between(Vec::new(), Duration::from_secs(5)).count(), 0);
+    }
+}
@@ -0,0 +1,172 @@
+//! Lines are written in checksummed blocks to a journal next to the series
+//! before they are pushed to the byteseries. Once the byteseries files are
+//! synced to disk the journal is cleared. After a power cut the journal
+//! holds any lines that might not have made it into the byteseries.
+//!
+//! A block is: `len: u32`, `crc32 of the body: u32` followed by the body,
+//! both little endian. The body is a list of lines, each an `u64` little
+//! endian timestamp followed by the payload.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Result, Section};
+
+const BLOCK_HEADER: usize = 8;
+const TIMESTAMP: usize = 8;
+
+pub(super) fn path(series_path: &Path) -> PathBuf {
+    series_path.with_extension("journal")
+}
+
+#[derive(Debug)]
+pub(super) struct Journal {
+    file: File,
+}
+
+impl Journal {
+    pub(super) fn open(series_path: &Path) -> Result<Self> {
+        let path = path(series_path);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .wrap_err("Could not open journal")
+            .with_note(|| format!("path: {}", path.display()))?;
+        Ok(Self { file })
+    }
+
+    /// Returns once the block is on disk
+    pub(super) fn write_block(&mut self, lines: &[(u64, Vec<u8>)]) -> Result<()> {
+        let block = encode_block(lines);
+        self.file
+            .write_all(&block)
+            .wrap_err("Could not append block to journal")?;
+        self.file.sync_data().wrap_err("Could not sync journal")
+    }
+
+    pub(super) fn clear(&mut self) -> Result<()> {
+        self.file.set_len(0).wrap_err("Could not clear journal")?;
+        self.file.sync_all().wrap_err("Could not sync journal")
+    }
+}
+
+fn encode_block(lines: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let body: Vec<u8> = lines
+        .iter()
+        .flat_map(|(ts, line)| ts.to_le_bytes().into_iter().chain(line.iter().copied()))
+        .collect();
+    let mut block = Vec::with_capacity(BLOCK_HEADER + body.len());
+    block.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    block.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    block.extend_from_slice(&body);
+    block
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Scan {
+    /// Lines in the blocks that are intact
+    pub(crate) lines: Vec<(u64, Vec<u8>)>,
+    /// Length of the journal up to and including the last intact block
+    pub(crate) valid_len: u64,
+    pub(crate) file_len: u64,
+}
+
+impl Scan {
+    /// The journal ends in a block that was not completely written or is
+    /// corrupt. Everything from there on can not be trusted.
+    pub(crate) fn torn(&self) -> bool {
+        self.valid_len < self.file_len
+    }
+}
+
+/// Reads every intact block in the journal, stops at the first that is not.
+pub(super) fn scan(series_path: &Path, payload_size: usize) -> Result<Scan> {
+    let path = path(series_path);
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Scan::default()),
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not read journal")
+                .with_note(|| format!("path: {}", path.display()))
+        }
+    };
+    Ok(scan_bytes(&bytes, payload_size))
+}
+
+fn scan_bytes(bytes: &[u8], payload_size: usize) -> Scan {
+    let line_size = TIMESTAMP + payload_size;
+    let mut scan = Scan {
+        file_len: bytes.len() as u64,
+        ..Scan::default()
+    };
+
+    let mut rest = bytes;
+    while rest.len() >= BLOCK_HEADER {
+        let (header, after_header) = rest.split_at(BLOCK_HEADER);
+        let len = u32::from_le_bytes(header[..4].try_into().expect("slice is 4 long")) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().expect("slice is 4 long"));
+        if after_header.len() < len || len % line_size != 0 {
+            break;
+        }
+        let (body, after_body) = after_header.split_at(len);
+        if crc32fast::hash(body) != crc {
+            break;
+        }
+
+        scan.lines.extend(body.chunks_exact(line_size).map(|line| {
+            let (ts, payload) = line.split_at(TIMESTAMP);
+            let ts = u64::from_le_bytes(ts.try_into().expect("slice is 8 long"));
+            (ts, payload.to_vec())
+        }));
+        scan.valid_len += (BLOCK_HEADER + len) as u64;
+        rest = after_body;
+    }
+    scan
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines() -> Vec<(u64, Vec<u8>)> {
+        vec![(1, vec![1, 2, 3]), (2, vec![4, 5, 6])]
+    }
+
+    #[test]
+    fn intact_blocks_are_read() {
+        let mut bytes = encode_block(&lines());
+        bytes.extend(encode_block(&[(3, vec![7, 8, 9])]));
+        let scan = scan_bytes(&bytes, 3);
+        assert!(!scan.torn());
+        assert_eq!(scan.lines.len(), 3);
+        assert_eq!(scan.lines[2], (3, vec![7, 8, 9]));
+    }
+
+    #[test]
+    fn torn_trailing_block_is_found() {
+        let first = encode_block(&lines());
+        let mut bytes = first.clone();
+        let second = encode_block(&[(3, vec![7, 8, 9])]);
+        bytes.extend_from_slice(&second[..second.len() - 1]);
+
+        let scan = scan_bytes(&bytes, 3);
+        assert!(scan.torn());
+        assert_eq!(scan.valid_len, first.len() as u64);
+        assert_eq!(scan.lines, lines());
+    }
+
+    #[test]
+    fn corrupt_block_is_not_trusted() {
+        let mut bytes = encode_block(&lines());
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let scan = scan_bytes(&bytes, 3);
+        assert!(scan.torn());
+        assert_eq!(scan.valid_len, 0);
+        assert!(scan.lines.is_empty());
+    }
+}
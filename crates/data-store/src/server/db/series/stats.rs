@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use super::gaps;
+use crate::api::{self, Percentile, ValueStats};
+
+const PERCENTILES: [f32; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+/// `start` and `end` are the requested range, stretches without data
+/// longer then `max_interval` between them count as gap.
+pub(super) fn compute(
+    reading: protocol::Reading,
+    count: u64,
+    exact: bool,
+    time: &[jiff::Timestamp],
+    data: &[f32],
+    (start, end): (jiff::Timestamp, jiff::Timestamp),
+    max_interval: Duration,
+) -> api::Stats {
+    let edges = [start]
+        .into_iter()
+        .chain(time.iter().copied())
+        .chain([end]);
+    let total_gap = gaps::between(edges, max_interval)
+        .map(|gap| gaps::duration(gap.start, gap.end))
+        .sum();
+
+    api::Stats {
+        reading,
+        count,
+        exact,
+        first: time.first().copied(),
+        last: time.last().copied(),
+        total_gap,
+        values: value_stats(data),
+    }
+}
+
+fn value_stats(data: &[f32]) -> Option<ValueStats> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let n = data.len() as f64;
+    let mean = data.iter().copied().map(f64::from).sum::<f64>() / n;
+    let variance = data
+        .iter()
+        .copied()
+        .map(f64::from)
+        .map(|v| (v - mean).powi(2))
+        .sum::<f64>()
+        / n;
+
+    let mut sorted = data.to_vec();
+    sorted.sort_unstable_by(f32::total_cmp);
+    let percentiles = PERCENTILES
+        .into_iter()
+        .map(|percentile| {
+            let idx = (percentile / 100.0 * (sorted.len() - 1) as f32).round() as usize;
+            Percentile {
+                percentile,
+                value: sorted[idx],
+            }
+        })
+        .collect();
+
+    Some(ValueStats {
+        min: sorted[0],
+        max: sorted[sorted.len() - 1],
+        mean: mean as f32,
+        std_dev: variance.sqrt() as f32,
+        percentiles,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jiff::{Timestamp, ToSpan};
+    use protocol::large_bedroom::{self, bed};
+
+    const READING: protocol::Reading = protocol::Reading::LargeBedroom(
+        large_bedroom::Reading::Bed(bed::Reading::Temperature(0.0)),
+    );
+
+    #[test]
+    fn values() {
+        let start = Timestamp::UNIX_EPOCH;
+        let time: Vec<_> = (0..5).map(|i| start + i.seconds()).collect();
+        let data = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let stats = compute(
+            READING,
+            5,
+            true,
+            &time,
+            &data,
+            (start, start + 4.seconds()),
+            Duration::from_secs(2),
+        );
+
+        let values = stats.values.unwrap();
+        assert_eq!(values.min, 1.0);
+        assert_eq!(values.max, 5.0);
+        assert_eq!(values.mean, 3.0);
+        assert!((values.std_dev - 2f32.sqrt()).abs() < 0.001);
+        let median = values
+            .percentiles
+            .iter()
+            .find(|p| p.percentile == 50.0)
+            .unwrap();
+        assert_eq!(median.value, 3.0);
+        assert_eq!(stats.total_gap, Duration::ZERO);
+    }
+
+    #[test]
+    fn gaps_include_edges() {
+        let start = Timestamp::UNIX_EPOCH;
+        let time = [start + 10.seconds(), start + 11.seconds(), start + 31.seconds()];
+        let stats = compute(
+            READING,
+            3,
+            true,
+            &time,
+            &[0.0; 3],
+            (start, start + 40.seconds()),
+            Duration::from_secs(5),
+        );
+        assert_eq!(stats.total_gap, Duration::from_secs(10 + 20 + 9));
+    }
+
+    #[test]
+    fn empty() {
+        let start = Timestamp::UNIX_EPOCH;
+        let stats = compute(
+            READING,
+            0,
+            true,
+            &[],
+            &[],
+            (start, start + 40.seconds()),
+            Duration::from_secs(5),
+        );
+        assert!(stats.values.is_none());
+        assert_eq!(stats.total_gap, Duration::from_secs(40));
+    }
+}
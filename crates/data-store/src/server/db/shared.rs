@@ -0,0 +1,173 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::Instant;
+
+use color_eyre::Result;
+use tokio::sync::broadcast;
+
+use super::{Appended, Series};
+
+#[derive(Debug)]
+struct Queued {
+    reading: protocol::Reading,
+    received: Instant,
+    time: jiff::Timestamp,
+}
+
+/// A series shared between ingestion and the clients reading from it.
+/// Appending never waits for a reader. New readings are queued and written
+/// by whoever holds the series as soon as they are done with it.
+///
+/// All methods do blocking disk IO, call them from a blocking thread.
+///
+/// A reader that panics does not take appending down with it, the locks
+/// are taken over even if they are poisoned.
+#[derive(Debug)]
+pub(crate) struct SharedSeries {
+    series: Mutex<Series>,
+    queued: Mutex<Vec<Queued>>,
+    /// Every line written to disk, only send while holding the series lock
+    appended: broadcast::Sender<Appended>,
+}
+
+impl SharedSeries {
+    pub(crate) fn new(series: Series, appended: broadcast::Sender<Appended>) -> Self {
+        Self {
+            series: Mutex::new(series),
+            queued: Mutex::new(Vec::new()),
+            appended,
+        }
+    }
+
+    /// Writes the reading unless someone else holds the series. It is then
+    /// queued and written by them, they report any error writing it. Only
+    /// errors about this reading are returned.
+    pub(crate) fn append(
+        &self,
+        reading: protocol::Reading,
+        received: Instant,
+        time: jiff::Timestamp,
+    ) -> Result<()> {
+        let mut series = match self.series.try_lock() {
+            Ok(series) => series,
+            Err(TryLockError::WouldBlock) => {
+                self.lock_queue().push(Queued {
+                    reading,
+                    received,
+                    time,
+                });
+                // the holder could have released the series before we queued
+                self.flush();
+                return Ok(());
+            }
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        };
+        // readings queued earlier go first
+        self.write_queued(&mut series);
+        let res = self.write(&mut series, &reading, received, time);
+        drop(series);
+        self.flush();
+        res
+    }
+
+    /// Gives `op` exclusive access to the series. Readings queued before
+    /// `op` runs are appended first, those queued while it runs are appended
+    /// afterwards. Appended lines are read from the series once committed.
+    pub(crate) fn with<T>(&self, op: impl FnOnce(&mut Series) -> T) -> T {
+        op(&mut self.lock())
+    }
+
+    /// Exclusive access to the series until the returned guard is dropped.
+    /// Readings queued before are appended first, those queued while the
+    /// guard is held are appended once it is dropped.
+    pub(crate) fn lock(&self) -> Locked<'_> {
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        self.write_queued(&mut series);
+        Locked {
+            shared: self,
+            series: Some(series),
+        }
+    }
+
+    /// Writes the queue unless someone holds the series, they write it once
+    /// they are done
+    fn flush(&self) {
+        loop {
+            let mut series = match self.series.try_lock() {
+                Ok(series) => series,
+                Err(TryLockError::WouldBlock) => return,
+                Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            };
+            self.write_queued(&mut series);
+            drop(series);
+
+            // a reading could have been queued after we wrote the queue but
+            // before we released the lock. It is ours to write.
+            if self.lock_queue().is_empty() {
+                return;
+            }
+        }
+    }
+
+    /// The queued readings were appended by other callers, errors writing
+    /// them are only logged
+    fn write_queued(&self, series: &mut Series) {
+        let queued = std::mem::take(&mut *self.lock_queue());
+        for Queued {
+            reading,
+            received,
+            time,
+        } in queued
+        {
+            if let Err(report) = self.write(series, &reading, received, time) {
+                tracing::error!("Could not append queued reading: {report:?}");
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        series: &mut Series,
+        reading: &protocol::Reading,
+        received: Instant,
+        time: jiff::Timestamp,
+    ) -> Result<()> {
+        // send while holding the lock so subscribers get lines in order.
+        // Errors if no one is subscribed.
+        if let Some(appended) = series.append(reading, received, time)? {
+            let _ = self.appended.send(appended);
+        }
+        Ok(())
+    }
+
+    fn lock_queue(&self) -> MutexGuard<'_, Vec<Queued>> {
+        self.queued.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) struct Locked<'a> {
+    shared: &'a SharedSeries,
+    /// Only None while dropping
+    series: Option<MutexGuard<'a, Series>>,
+}
+
+impl Deref for Locked<'_> {
+    type Target = Series;
+
+    fn deref(&self) -> &Self::Target {
+        self.series.as_ref().expect("only None while dropping")
+    }
+}
+
+impl DerefMut for Locked<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.series.as_mut().expect("only None while dropping")
+    }
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        drop(self.series.take());
+        self.shared.flush();
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::Write;
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::{Result, Section};
+use jiff::{Span, Timestamp};
+
+use super::db::{self, Series};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Comma separated, first line is a header with the reading names
+    Csv,
+    /// One json object per line
+    Jsonl,
+}
+
+/// Reading everything at once could take a lot of memory, export the
+/// series in windows this long instead.
+const WINDOW_HOURS: i64 = 24 * 7;
+
+/// Write all lines between `from` and `to` in the series stored at
+/// `series_path` to `out`. The `series_path` is relative to `data_dir` and
+/// without extension, for example `largebedroom/bed/sht31`.
+///
+/// Returns the number of lines exported.
+pub fn export(
+    data_dir: &Path,
+    series_path: &Path,
+    from: Timestamp,
+    to: Timestamp,
+    format: Format,
+    mut out: impl Write,
+) -> Result<usize> {
+    let mut series = open(data_dir, series_path)?;
+    let readings = series.readings();
+    let names: Vec<_> = readings.iter().map(db::reading_name).collect();
+    let precisions: Vec<_> = readings
+        .iter()
+        .map(|reading| {
+            use protocol::reading::tree::Tree;
+            reading.leaf().precision()
+        })
+        .collect();
+
+    if let Format::Csv = format {
+        writeln!(out, "time,{}", names.join(",")).wrap_err("Could not write header")?;
+    }
+
+    let mut exported = 0;
+    let mut window_start = from;
+    while window_start <= to {
+        let window_end = window_start
+            .checked_add(Span::new().hours(WINDOW_HOURS))
+            .unwrap_or(Timestamp::MAX)
+            .min(to);
+        let (time, data) = series
+            .read_all(&readings, window_start, window_end)
+            .wrap_err("Could not read data from series")
+            .with_note(|| format!("window: {window_start} till {window_end}"))?;
+
+        for (i, time) in time.iter().enumerate() {
+            let values = data
+                .iter()
+                .map(|reading_data| reading_data[i])
+                .zip(&precisions);
+            match format {
+                Format::Csv => write_csv_line(&mut out, *time, values),
+                Format::Jsonl => write_json_line(&mut out, *time, names.iter().zip(values)),
+            }
+            .wrap_err("Could not write line")?;
+        }
+        exported += time.len();
+
+        if window_end == to {
+            break;
+        }
+        // read_all includes the end, do not export the line there twice
+        window_start = window_end + Span::new().milliseconds(1);
+    }
+
+    out.flush().wrap_err("Could not flush output")?;
+    Ok(exported)
+}
+
+fn write_csv_line<'a>(
+    out: &mut impl Write,
+    time: Timestamp,
+    values: impl Iterator<Item = (f32, &'a usize)>,
+) -> std::io::Result<()> {
+    write!(out, "{time}")?;
+    for (value, precision) in values {
+        write!(out, ",{value:.precision$}")?;
+    }
+    writeln!(out)
+}
+
+fn write_json_line<'a>(
+    out: &mut impl Write,
+    time: Timestamp,
+    values: impl Iterator<Item = (&'a String, (f32, &'a usize))>,
+) -> std::io::Result<()> {
+    write!(out, "{{\"time\":\"{time}\"")?;
+    for (name, (value, precision)) in values {
+        write!(out, ",\"{name}\":{value:.precision$}")?;
+    }
+    writeln!(out, "}}")
+}
+
+pub(super) fn open(data_dir: &Path, series_path: &Path) -> Result<Series> {
+    let device = device(series_path)?;
+    Series::open_existing(&device, data_dir)
+        .wrap_err("Could not open series")?
+        .ok_or_else(|| eyre!("There is no data for this device in the data dir"))
+        .with_note(|| format!("data dir: {}", data_dir.display()))
+        .with_note(|| format!("device: {device:?}"))
+}
+
+pub(super) fn device(series_path: &Path) -> Result<protocol::Device> {
+    let series_path = series_path.with_extension("");
+    db::device_from_path(&series_path)
+        .ok_or_else(|| eyre!("No device is stored at this path"))
+        .with_note(|| format!("path: {}", series_path.display()))
+        .suggestion("The path looks like: largebedroom/bed/sht31")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csv_line_uses_reading_precision() {
+        let mut out = Vec::new();
+        let time: Timestamp = "2024-06-01T12:00:00Z".parse().unwrap();
+        write_csv_line(&mut out, time, [(21.456, &1), (50.0, &0)].into_iter()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2024-06-01T12:00:00Z,21.5,50\n"
+        );
+    }
+
+    #[test]
+    fn json_line_is_an_object() {
+        let mut out = Vec::new();
+        let time: Timestamp = "2024-06-01T12:00:00Z".parse().unwrap();
+        let names = ["Temperature".to_string(), "Humidity".to_string()];
+        let values = [(21.456, &2), (50.0, &0)];
+        write_json_line(&mut out, time, names.iter().zip(values)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"time\":\"2024-06-01T12:00:00Z\",\"Temperature\":21.46,\"Humidity\":50}\n"
+        );
+    }
+}
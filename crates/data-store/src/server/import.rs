@@ -0,0 +1,242 @@
+use std::io::BufRead;
+use std::path::Path;
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::{Result, Section};
+use jiff::Timestamp;
+
+use super::db::{self, Series};
+use super::export;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum Rejection {
+    #[error("could not parse line: {0}")]
+    Malformed(String),
+    #[error("expected {expected} fields got {got}")]
+    WrongFieldCount { expected: usize, got: usize },
+    #[error("time {time} is not after the previous line's time: {previous}")]
+    OutOfOrder { time: Timestamp, previous: Timestamp },
+    #[error("value {value} for {reading} is outside the allowed range: {range:?}")]
+    OutOfRange {
+        reading: String,
+        value: f32,
+        range: std::ops::Range<f32>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub imported: usize,
+    /// Line number (starting at 1) and why the line was not imported
+    pub rejected: Vec<(usize, Rejection)>,
+}
+
+/// Append the lines in a csv file (as produced by export) to the series
+/// stored at `series_path`. The series is created if it does not exist.
+/// Lines that do not come after the last line in the series or contain
+/// values that can not be stored are skipped and reported.
+///
+/// The data-store must not be running while importing as it could be
+/// writing to the same series.
+pub fn import(
+    data_dir: &Path,
+    series_path: &Path,
+    input: impl BufRead,
+    dry_run: bool,
+) -> Result<Report> {
+    let device = export::device(series_path)?;
+    let reading = device
+        .info()
+        .affects_readings
+        .first()
+        .expect("a device has at least one reading it affects");
+    if !dry_run {
+        db::drop_uncommitted(&device, data_dir)
+            .wrap_err("Could not drop lines written after the last commit")?;
+    }
+    let mut series = Series::open_or_create(reading, data_dir)
+        .wrap_err("Could not open or create series")
+        .with_note(|| format!("data dir: {}", data_dir.display()))?;
+    if !dry_run {
+        series.recover().wrap_err("Could not recover lines from journal")?;
+    }
+    let readings = series.readings();
+
+    let mut lines = input.lines().enumerate();
+    let Some((_, header)) = lines.next() else {
+        bail!("Input is empty, expected at least a header");
+    };
+    let header = header.wrap_err("Could not read header")?;
+    let columns = column_order(&header, &readings)?;
+
+    let mut report = Report::default();
+    let mut previous = series
+        .last_timestamp()
+        .wrap_err("Could not get time of last line in series")?;
+    let mut values = vec![0f32; readings.len()];
+    for (idx, line) in lines {
+        let line = line.wrap_err("Could not read line from input")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_numb = idx + 1;
+        let time = match parse_line(&line, &columns, &readings, &mut values) {
+            // compare as stored, lines that differ less then the
+            // resolution of the series would otherwise get the same time
+            Ok(time) => series.stored_time(time),
+            Err(rejection) => {
+                report.rejected.push((line_numb, rejection));
+                continue;
+            }
+        };
+        if let Some(previous) = previous.filter(|previous| time <= *previous) {
+            report
+                .rejected
+                .push((line_numb, Rejection::OutOfOrder { time, previous }));
+            continue;
+        }
+
+        if !dry_run {
+            series
+                .push_values(time, &values)
+                .wrap_err("Could not append line to series")
+                .with_note(|| format!("line: {line_numb}"))?;
+        }
+        previous = Some(time);
+        report.imported += 1;
+    }
+
+    series.commit().wrap_err("Could not commit imported lines")?;
+    Ok(report)
+}
+
+/// For each column after time the index of the reading in the series
+fn column_order(header: &str, readings: &[protocol::Reading]) -> Result<Vec<usize>> {
+    let names: Vec<_> = readings.iter().map(db::reading_name).collect();
+    let mut columns = header.split(',').map(str::trim);
+    if columns.next() != Some("time") {
+        bail!("The first column in the header must be `time`, header: {header}");
+    }
+
+    let order = columns
+        .map(|column| {
+            names
+                .iter()
+                .position(|name| name == column)
+                .ok_or_else(|| color_eyre::eyre::eyre!("Unknown column: {column}"))
+                .with_note(|| format!("the columns for this device are: {names:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (idx, name) in names.iter().enumerate() {
+        if !order.contains(&idx) {
+            bail!("Missing column for reading: {name}, header: {header}");
+        }
+    }
+    if order.len() != names.len() {
+        bail!("Header contains duplicate columns: {header}");
+    }
+    Ok(order)
+}
+
+/// Parses a csv line placing the values in the order of the series in
+/// `values`.
+fn parse_line(
+    line: &str,
+    columns: &[usize],
+    readings: &[protocol::Reading],
+    values: &mut [f32],
+) -> Result<Timestamp, Rejection> {
+    let fields: Vec<_> = line.split(',').map(str::trim).collect();
+    if fields.len() != columns.len() + 1 {
+        return Err(Rejection::WrongFieldCount {
+            expected: columns.len() + 1,
+            got: fields.len(),
+        });
+    }
+
+    let time: Timestamp = fields[0]
+        .parse()
+        .map_err(|e| Rejection::Malformed(format!("invalid time: {e}")))?;
+    for (field, idx) in fields[1..].iter().zip(columns) {
+        let reading = &readings[*idx];
+        let value: f32 = field
+            .parse()
+            .map_err(|e| Rejection::Malformed(format!("invalid value `{field}`: {e}")))?;
+        let range = reading.range();
+        if !range.contains(&value) {
+            return Err(Rejection::OutOfRange {
+                reading: db::reading_name(reading),
+                value,
+                range,
+            });
+        }
+        values[*idx] = value;
+    }
+    Ok(time)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::large_bedroom::{self, bed};
+    use protocol::Reading;
+
+    fn readings() -> Vec<Reading> {
+        Reading::LargeBedroom(large_bedroom::Reading::Bed(bed::Reading::Humidity(0.0)))
+            .device()
+            .info()
+            .affects_readings
+            .to_vec()
+    }
+
+    #[test]
+    fn columns_may_be_reordered() {
+        let readings = readings();
+        let mut names: Vec<_> = readings.iter().map(db::reading_name).collect();
+        names.reverse();
+        let header = format!("time,{}", names.join(","));
+
+        let order = column_order(&header, &readings).unwrap();
+        let expected: Vec<_> = (0..readings.len()).rev().collect();
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn header_must_contain_all_readings() {
+        let readings = readings();
+        let name = db::reading_name(&readings[0]);
+        assert!(column_order(&format!("time,{name}"), &readings).is_err());
+        assert!(column_order("time,NotAReading", &readings).is_err());
+    }
+
+    #[test]
+    fn out_of_range_value_is_rejected() {
+        let readings = readings();
+        let columns: Vec<_> = (0..readings.len()).collect();
+        let mut values = vec![0.0; readings.len()];
+        let too_large = readings[0].range().end + 1.0;
+        let mut line = format!("2024-06-01T12:00:00Z,{too_large}");
+        for reading in &readings[1..] {
+            line.push_str(&format!(",{}", reading.range().start));
+        }
+
+        let res = parse_line(&line, &columns, &readings, &mut values);
+        assert!(matches!(res, Err(Rejection::OutOfRange { .. })));
+    }
+
+    #[test]
+    fn wrong_field_count_is_rejected() {
+        let readings = readings();
+        let columns: Vec<_> = (0..readings.len()).collect();
+        let mut values = vec![0.0; readings.len()];
+        let res = parse_line("2024-06-01T12:00:00Z", &columns, &readings, &mut values);
+        assert_eq!(
+            res,
+            Err(Rejection::WrongFieldCount {
+                expected: readings.len() + 1,
+                got: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Removes old data according to a retention policy. Raw lines are removed
+//! while the downsampled caches of the series keep covering that time. That
+//! way plots of old data still work, just with less detail.
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use tracing::instrument;
+
+use super::db::{self, Data};
+
+/// How often the policy is enforced
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to keep data at each resolution, `None` keeps it forever. Data
+/// that has been averaged over 1000 lines is always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// Every line as it was stored
+    pub raw: Option<Duration>,
+    /// Lines averaged in buckets of 10
+    pub bucket_10: Option<Duration>,
+    /// Lines averaged in buckets of 100
+    pub bucket_100: Option<Duration>,
+}
+
+impl Policy {
+    /// Retention for raw, 10x and 100x averaged data in that order
+    pub(crate) fn levels(&self) -> [Option<Duration>; 3] {
+        [self.raw, self.bucket_10, self.bucket_100]
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("expected `<level>=<duration>` got: {0}")]
+    MissingEquals(String),
+    #[error("unknown level: {0}, options are: raw, 10x and 100x")]
+    UnknownLevel(String),
+    #[error("invalid duration: {0}, expected for example: 12h, 90d, 2y or forever")]
+    InvalidDuration(String),
+    #[error("expected `<device path>:<policy>` got: {0}")]
+    MissingColon(String),
+    #[error("no device is stored at: {0}")]
+    UnknownDevice(String),
+}
+
+/// Parses for example: `raw=90d,10x=2y`. Levels that are not mentioned are
+/// kept forever.
+impl FromStr for Policy {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Policy::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (level, duration) = part
+                .split_once('=')
+                .ok_or_else(|| ParseError::MissingEquals(part.to_string()))?;
+            let duration = parse_duration(duration.trim())?;
+            match level.trim() {
+                "raw" => policy.raw = duration,
+                "10x" => policy.bucket_10 = duration,
+                "100x" => policy.bucket_100 = duration,
+                other => return Err(ParseError::UnknownLevel(other.to_string())),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Option<Duration>, ParseError> {
+    if s == "forever" {
+        return Ok(None);
+    }
+
+    const HOUR: u64 = 60 * 60;
+    let invalid = || ParseError::InvalidDuration(s.to_string());
+    let unit_len = s.chars().last().ok_or_else(invalid)?.len_utf8();
+    let (number, unit) = s.split_at(s.len() - unit_len);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "h" => HOUR,
+        "d" => 24 * HOUR,
+        "w" => 7 * 24 * HOUR,
+        "y" => 365 * 24 * HOUR,
+        _ => return Err(invalid()),
+    };
+    Ok(Some(Duration::from_secs(number * unit)))
+}
+
+/// A policy for one device, parsed from: `<device path>:<policy>`, for
+/// example: `largebedroom/bed/sht31:raw=30d`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePolicy {
+    pub device: protocol::Device,
+    pub policy: Policy,
+}
+
+impl FromStr for DevicePolicy {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, policy) = s
+            .split_once(':')
+            .ok_or_else(|| ParseError::MissingColon(s.to_string()))?;
+        let device = db::device_from_path(Path::new(path))
+            .ok_or_else(|| ParseError::UnknownDevice(path.to_string()))?;
+        Ok(Self {
+            device,
+            policy: policy.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Used for devices without their own policy
+    pub global: Policy,
+    pub per_device: HashMap<protocol::Device, Policy>,
+}
+
+impl Config {
+    pub fn new(global: Policy, per_device: Vec<DevicePolicy>) -> Self {
+        Self {
+            global,
+            per_device: per_device
+                .into_iter()
+                .map(|DevicePolicy { device, policy }| (device, policy))
+                .collect(),
+        }
+    }
+
+    fn policy(&self, device: &protocol::Device) -> &Policy {
+        self.per_device.get(device).unwrap_or(&self.global)
+    }
+
+    fn keeps_everything(&self) -> bool {
+        self.global == Policy::default()
+            && self.per_device.values().all(|p| *p == Policy::default())
+    }
+}
+
+pub(crate) async fn run(data: Data, config: Config) -> Result<()> {
+    if config.keeps_everything() {
+        tracing::info!("No retention policy configured, keeping all data");
+        return std::future::pending().await;
+    }
+
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut reclaimed = 0;
+        for device in protocol::Device::all() {
+            match enforce(&data, &device, config.policy(&device)).await {
+                Ok(bytes) => reclaimed += bytes,
+                Err(report) => {
+                    tracing::error!("Could not enforce retention for {device:?}: {report:?}")
+                }
+            }
+        }
+        tracing::info!(
+            "Enforced retention policy, reclaimed {:.1} MiB",
+            reclaimed as f64 / 1024. / 1024.
+        );
+    }
+}
+
+/// Returns the number of bytes reclaimed
+#[instrument(skip(data))]
+async fn enforce(
+    data: &Data,
+    device: &protocol::Device,
+    policy: &Policy,
+) -> Result<u64> {
+    let Some(series) = data.open(device, false).await? else {
+        return Ok(0);
+    };
+
+    // new lines are queued while we hold the series
+    let policy = policy.clone();
+    db::blocking(move || {
+        series.with(|series| {
+            let before = series.disk_usage().wrap_err("Could not get disk usage")?;
+            series
+                .enforce_retention(&policy, jiff::Timestamp::now())
+                .wrap_err("Could not remove expired data")?;
+            let after = series.disk_usage().wrap_err("Could not get disk usage")?;
+            Ok(before.saturating_sub(after))
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn parse_policy() {
+        let policy: Policy = "raw=90d,10x=2y".parse().unwrap();
+        assert_eq!(
+            policy,
+            Policy {
+                raw: Some(Duration::from_secs(90 * DAY)),
+                bucket_10: Some(Duration::from_secs(2 * 365 * DAY)),
+                bucket_100: None,
+            }
+        );
+        let policy: Policy = "raw=12h, 100x=forever".parse().unwrap();
+        assert_eq!(policy.raw, Some(Duration::from_secs(DAY / 2)));
+        assert_eq!(policy.bucket_100, None);
+    }
+
+    #[test]
+    fn parse_invalid_policy() {
+        assert!(matches!(
+            "raw=90".parse::<Policy>(),
+            Err(ParseError::InvalidDuration(_))
+        ));
+        assert!(matches!(
+            "1000x=1y".parse::<Policy>(),
+            Err(ParseError::UnknownLevel(_))
+        ));
+        assert!(matches!(
+            "raw".parse::<Policy>(),
+            Err(ParseError::MissingEquals(_))
+        ));
+    }
+
+    #[test]
+    fn parse_device_policy() {
+        let parsed: DevicePolicy = "largebedroom/bed/sht31:raw=30d".parse().unwrap();
+        assert_eq!(parsed.policy.raw, Some(Duration::from_secs(30 * DAY)));
+        assert!(matches!(
+            "nothere/sht31:raw=30d".parse::<DevicePolicy>(),
+            Err(ParseError::UnknownDevice(_))
+        ));
+    }
+}
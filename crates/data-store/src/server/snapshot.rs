@@ -0,0 +1,72 @@
+//! Tarballs with a point in time copy of the data dir and restoring them.
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::{Result, Section};
+
+use super::db::{self, Series};
+use crate::api;
+
+/// Archives every file in `data_dir` into a new tarball in `snapshot_dir`.
+/// Nothing may write to `data_dir` while this runs.
+pub(crate) fn write(data_dir: &Path, snapshot_dir: &Path) -> Result<api::Snapshot> {
+    let written = dir_snapshot::write(data_dir, snapshot_dir, "data-store")?;
+    Ok(api::Snapshot {
+        path: written.path,
+        files: written.files,
+        bytes: written.bytes,
+    })
+}
+
+#[derive(Debug)]
+pub struct Restored {
+    pub series: usize,
+    /// Where the data dir that was replaced was moved to
+    pub previous: Option<PathBuf>,
+}
+
+/// Replaces `data_dir` with the content of a snapshot. It only replaces
+/// `data_dir` if every series in the snapshot opens, which checks its header
+/// matches the current format. The old data dir is kept next to it. The
+/// data-store must not be running.
+pub fn restore(snapshot: &Path, data_dir: &Path) -> Result<Restored> {
+    let restored = dir_snapshot::restore(snapshot, data_dir, |unpacked| {
+        let mut series = 0;
+        for device in protocol::Device::all() {
+            let opened = Series::open_existing(&device, unpacked)
+                .wrap_err("Series in snapshot is damaged or has an unexpected header")
+                .with_note(|| format!("series: {}", db::device_path(&device).display()))?;
+            series += usize::from(opened.is_some());
+        }
+        if series == 0 {
+            bail!("Snapshot contains no series, not restoring it");
+        }
+        Ok(series)
+    })?;
+
+    Ok(Restored {
+        series: restored.checked,
+        previous: restored.previous,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn restore_refuses_empty_snapshot() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let data_dir = dir.path().join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("unrelated"), b"keep me").unwrap();
+        let source = dir.path().join("empty");
+        fs::create_dir_all(&source).unwrap();
+        let snapshot = write(&source, &dir.path().join("snapshots")).unwrap();
+
+        assert!(restore(&snapshot.path, &data_dir).is_err());
+        assert_eq!(fs::read(data_dir.join("unrelated")).unwrap(), b"keep me");
+    }
+}
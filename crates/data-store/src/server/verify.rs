@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Result, Section};
+
+use super::db::{self, Series};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Problem {
+    #[error("could not open series: {0}")]
+    Open(String),
+    #[error("could not read lines: {0}")]
+    Read(String),
+    #[error("{0} lines are not after the line before them")]
+    OutOfOrder(u64),
+    #[error("journal ends in {0} bytes that are not part of an intact block")]
+    TornJournal(u64),
+    #[error("series files hold {0} bytes written after the last commit")]
+    Uncommitted(u64),
+    #[error("{0} lines in the journal are missing from the series")]
+    NotReplayed(usize),
+}
+
+impl Problem {
+    /// Can be repaired without losing data that was committed
+    pub fn repairable(&self) -> bool {
+        matches!(
+            self,
+            Problem::TornJournal(_) | Problem::Uncommitted(_) | Problem::NotReplayed(_)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// Relative to the data dir without extension
+    pub path: PathBuf,
+    pub lines: u64,
+    pub problems: Vec<Problem>,
+    /// The repairable problems have been repaired
+    pub repaired: bool,
+}
+
+/// Checks every series in `data_dir`. If `repair` is set torn journal
+/// blocks and whatever was written to the series after its last commit are
+/// dropped. Then lines missing from the series are replayed from the
+/// journal. The data-store must not be running.
+pub fn verify(data_dir: &Path, repair: bool) -> Result<Vec<Report>> {
+    let mut reports = Vec::new();
+    for device in protocol::Device::all() {
+        let path = db::device_path(&device);
+        // the series may only be opened once the uncommitted bytes, that
+        // could end in a torn line, are dropped
+        let uncommitted = db::uncommitted(&device, data_dir)
+            .wrap_err("Could not check for bytes written after the last commit")?;
+        if repair && uncommitted > 0 {
+            db::drop_uncommitted(&device, data_dir)
+                .wrap_err("Could not drop bytes written after the last commit")
+                .with_note(|| format!("path: {}", path.display()))?;
+        }
+        let mut problems = Vec::new();
+        if uncommitted > 0 {
+            problems.push(Problem::Uncommitted(uncommitted));
+        }
+
+        let mut series = match Series::open_existing(&device, data_dir) {
+            Ok(Some(series)) => series,
+            Ok(None) => continue,
+            Err(report) => {
+                problems.push(Problem::Open(format!("{report:#}")));
+                reports.push(Report {
+                    path,
+                    lines: 0,
+                    problems,
+                    repaired: repair && uncommitted > 0,
+                });
+                continue;
+            }
+        };
+
+        let mut report = Report {
+            path,
+            lines: 0,
+            problems,
+            repaired: false,
+        };
+        let db::Recovered {
+            torn_bytes,
+            replayed,
+        } = series.check_journal().wrap_err("Could not check journal")?;
+        if torn_bytes > 0 {
+            report.problems.push(Problem::TornJournal(torn_bytes));
+        }
+        if replayed > 0 {
+            report.problems.push(Problem::NotReplayed(replayed));
+        }
+        if repair && !report.problems.is_empty() {
+            series
+                .recover()
+                .wrap_err("Could not repair series")
+                .with_note(|| format!("path: {}", report.path.display()))?;
+            report.repaired = true;
+        }
+
+        match series.check_lines() {
+            Ok((lines, out_of_order)) => {
+                report.lines = lines;
+                if out_of_order > 0 {
+                    report.problems.push(Problem::OutOfOrder(out_of_order));
+                }
+            }
+            Err(e) => report.problems.push(Problem::Read(format!("{e:#}"))),
+        }
+        reports.push(report);
+    }
+    Ok(reports)
+}
@@ -0,0 +1,209 @@
+//! Readings that are not stored but computed from stored readings when they
+//! are requested. Each has its own name, clients list them next to the
+//! stored readings. See [`expr`] for the expressions they are defined by.
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::api;
+
+mod expr;
+pub use expr::ParseError;
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    name: String,
+    source: String,
+    parsed: expr::Parsed,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DefinitionError {
+    #[error("expected `name=expression`")]
+    MissingEquals,
+    #[error("the name may not be empty")]
+    EmptyName,
+    #[error("`{0}` is a stored reading, give the virtual reading its own name")]
+    NameIsReading(String),
+    #[error("invalid expression: {0}")]
+    Expression(#[from] ParseError),
+}
+
+impl FromStr for Definition {
+    type Err = DefinitionError;
+
+    /// For example: `bed_weight=largebedroom/bed/nau7802left/WeightLeft +
+    /// largebedroom/bed/nau7802right/WeightRight`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, source) = s.split_once('=').ok_or(DefinitionError::MissingEquals)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(DefinitionError::EmptyName);
+        }
+        if expr::resolve(name).is_some() {
+            return Err(DefinitionError::NameIsReading(name.to_owned()));
+        }
+        let source = source.trim();
+        Ok(Self {
+            name: name.to_owned(),
+            source: source.to_owned(),
+            parsed: expr::parse(source)?,
+        })
+    }
+}
+
+impl Definition {
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The stored readings this is computed from
+    pub(crate) fn inputs(&self) -> &[protocol::Reading] {
+        &self.parsed.inputs
+    }
+
+    pub(crate) fn info(&self) -> api::VirtualReading {
+        api::VirtualReading {
+            name: self.name.clone(),
+            expression: self.source.clone(),
+        }
+    }
+
+    /// Computes the value at every point in time of the input with the most
+    /// points. The other inputs are interpolated to those times. Points where
+    /// an input has no data nearby, or where the result is not a finite
+    /// number, are left out.
+    ///
+    /// `inputs` contains the time and values for each of [`Self::inputs`].
+    /// An input is considered to have no data at a time if the points
+    /// around it are further than twice `spacing` or its devices max sample
+    /// interval apart.
+    pub(crate) fn evaluate(
+        &self,
+        inputs: &[(Vec<jiff::Timestamp>, Vec<f32>)],
+        spacing: Duration,
+    ) -> (Vec<jiff::Timestamp>, Vec<f32>) {
+        let Some(base) = inputs
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (time, _))| time.len())
+            .map(|(idx, _)| idx)
+        else {
+            // an expression without readings is constant, there are no
+            // times to give it at
+            return (Vec::new(), Vec::new());
+        };
+
+        let max_gaps: Vec<_> = self
+            .inputs()
+            .iter()
+            .map(|reading| 2 * spacing.max(reading.device().info().max_sample_interval))
+            .collect();
+
+        let mut result = (Vec::new(), Vec::new());
+        let mut values = vec![0f64; inputs.len()];
+        'points: for &time in &inputs[base].0 {
+            for (idx, ((input_time, input_data), max_gap)) in
+                inputs.iter().zip(&max_gaps).enumerate()
+            {
+                let Some(value) = interpolate(input_time, input_data, time, *max_gap) else {
+                    continue 'points;
+                };
+                values[idx] = value;
+            }
+
+            let value = self.parsed.expr.eval(&values);
+            if value.is_finite() {
+                result.0.push(time);
+                result.1.push(value as f32);
+            }
+        }
+        result
+    }
+}
+
+/// Linear interpolation between the points around `at`. None if `at` is
+/// outside the data or the points around it are more than `max_gap` apart.
+fn interpolate(
+    time: &[jiff::Timestamp],
+    data: &[f32],
+    at: jiff::Timestamp,
+    max_gap: Duration,
+) -> Option<f64> {
+    let after = time.partition_point(|t| *t < at);
+    if time.get(after) == Some(&at) {
+        return Some(f64::from(data[after]));
+    }
+    if after == 0 || after == time.len() {
+        return None;
+    }
+
+    let before = after - 1;
+    let span = (time[after].as_millisecond() - time[before].as_millisecond()) as f64;
+    if span > max_gap.as_millis() as f64 {
+        return None;
+    }
+    let fraction = (at.as_millisecond() - time[before].as_millisecond()) as f64 / span;
+    let (a, b) = (f64::from(data[before]), f64::from(data[after]));
+    Some(a + (b - a) * fraction)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jiff::Timestamp;
+
+    fn time(seconds: &[i64]) -> Vec<Timestamp> {
+        seconds
+            .iter()
+            .map(|s| Timestamp::from_second(*s).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_definition() {
+        let def: Definition = "bed_weight = largebedroom/bed/nau7802left/WeightLeft + \
+            largebedroom/bed/nau7802right/WeightRight"
+            .parse()
+            .unwrap();
+        assert_eq!(def.name(), "bed_weight");
+        assert_eq!(def.inputs().len(), 2);
+        assert!(matches!(
+            "no equals".parse::<Definition>(),
+            Err(DefinitionError::MissingEquals)
+        ));
+        assert!(matches!(
+            " = largebedroom/bed/sht31/Temperature".parse::<Definition>(),
+            Err(DefinitionError::EmptyName)
+        ));
+        assert!(matches!(
+            "largebedroom/bed/sht31/Temperature=largebedroom/bed/sht31/Humidity + 1"
+                .parse::<Definition>(),
+            Err(DefinitionError::NameIsReading(_))
+        ));
+    }
+
+    #[test]
+    fn inputs_are_aligned() {
+        let def: Definition = "diff=largebedroom/bed/sht31/Temperature - \
+            largebedroom/bed/bme680/Pressure"
+            .parse()
+            .unwrap();
+        let inputs = [
+            (time(&[0, 10, 20, 30]), vec![10.0, 11.0, 12.0, 13.0]),
+            (time(&[5, 25]), vec![0.0, 2.0]),
+        ];
+        let (time, data) = def.evaluate(&inputs, Duration::from_secs(10));
+        // no data from the second input before 5 and after 25
+        assert_eq!(time, self::time(&[10, 20]));
+        assert_eq!(data, vec![10.5, 10.5]);
+    }
+
+    #[test]
+    fn gaps_are_not_interpolated() {
+        let time = time(&[0, 100]);
+        let data = [0.0, 10.0];
+        let at = Timestamp::from_second(50).unwrap();
+        assert_eq!(interpolate(&time, &data, at, Duration::from_secs(100)), Some(5.0));
+        assert_eq!(interpolate(&time, &data, at, Duration::from_secs(99)), None);
+    }
+}
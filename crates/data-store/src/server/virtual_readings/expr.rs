@@ -0,0 +1,457 @@
+//! A small expression language over stored readings, for example:
+//! `largebedroom/bed/nau7802left/WeightLeft + largebedroom/bed/nau7802right/WeightRight`
+//!
+//! Supports numbers, `+ - * / ^`, parentheses and the functions in
+//! [`Function`]. A reading is referred to by the path of its series followed
+//! by its name. Since paths contain `/` division needs a space before it.
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::CharIndices;
+
+use crate::server::db;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ParseError {
+    #[error("unexpected character `{found}` at position {at}")]
+    UnexpectedChar { found: char, at: usize },
+    #[error("unexpected `{found}` at position {at}, expected {expected}")]
+    Unexpected {
+        found: String,
+        at: usize,
+        expected: &'static str,
+    },
+    #[error("expression ended early, expected {expected}")]
+    UnexpectedEnd { expected: &'static str },
+    #[error(
+        "no stored reading is called `{0}`, use the path of the series then \
+        the name of the reading, for example: largebedroom/bed/sht31/Temperature"
+    )]
+    UnknownReading(String),
+    #[error("unknown function `{0}`, available are: {}", Function::NAMES.join(", "))]
+    UnknownFunction(String),
+    #[error("function `{name}` takes {expected} arguments, got {got}")]
+    WrongArgumentCount {
+        name: &'static str,
+        expected: usize,
+        got: usize,
+    },
+    #[error("invalid number `{0}`")]
+    InvalidNumber(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Function {
+    Abs,
+    Sqrt,
+    Ln,
+    Exp,
+    Min,
+    Max,
+    /// Celsius to Fahrenheit
+    CToF,
+    /// Fahrenheit to Celsius
+    FToC,
+    /// From temperature in Celsius and relative humidity in percent, using
+    /// the Magnus formula
+    DewPoint,
+}
+
+impl Function {
+    const ALL: [Function; 9] = [
+        Function::Abs,
+        Function::Sqrt,
+        Function::Ln,
+        Function::Exp,
+        Function::Min,
+        Function::Max,
+        Function::CToF,
+        Function::FToC,
+        Function::DewPoint,
+    ];
+    const NAMES: [&'static str; 9] = [
+        "abs",
+        "sqrt",
+        "ln",
+        "exp",
+        "min",
+        "max",
+        "c_to_f",
+        "f_to_c",
+        "dew_point",
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .position(|n| *n == name)
+            .map(|idx| Self::ALL[idx])
+    }
+
+    fn name(self) -> &'static str {
+        let idx = Self::ALL
+            .iter()
+            .position(|f| *f == self)
+            .expect("every function is in ALL");
+        Self::NAMES[idx]
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Min | Function::Max | Function::DewPoint => 2,
+            _ => 1,
+        }
+    }
+
+    fn apply(self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (Function::Abs, [x]) => x.abs(),
+            (Function::Sqrt, [x]) => x.sqrt(),
+            (Function::Ln, [x]) => x.ln(),
+            (Function::Exp, [x]) => x.exp(),
+            (Function::Min, [a, b]) => a.min(*b),
+            (Function::Max, [a, b]) => a.max(*b),
+            (Function::CToF, [c]) => c * 9.0 / 5.0 + 32.0,
+            (Function::FToC, [f]) => (f - 32.0) * 5.0 / 9.0,
+            (Function::DewPoint, [t, rh]) => {
+                const A: f64 = 17.62;
+                const B: f64 = 243.12;
+                let gamma = (rh / 100.0).ln() + A * t / (B + t);
+                B * gamma / (A - gamma)
+            }
+            _ => unreachable!("arity is checked while parsing"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Number(f64),
+    /// Index into [`Parsed::inputs`]
+    Input(usize),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    /// `inputs` are the values of the readings in [`Parsed::inputs`]
+    pub(crate) fn eval(&self, inputs: &[f64]) -> f64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Input(idx) => inputs[*idx],
+            Expr::Neg(expr) => -expr.eval(inputs),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(inputs), b.eval(inputs));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Pow => a.powf(b),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<_> = args.iter().map(|arg| arg.eval(inputs)).collect();
+                function.apply(&args)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Parsed {
+    pub(crate) expr: Expr,
+    /// Every reading used, each only once
+    pub(crate) inputs: Vec<protocol::Reading>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    /// A reading path or function name
+    Word(String),
+    Op(Op),
+    Minus,
+    Open,
+    Close,
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(n) => n.to_string(),
+            Token::Word(word) => word.clone(),
+            Token::Op(op) => format!("{op:?}"),
+            Token::Minus => "-".to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+            Token::Comma => ",".to_string(),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    fn take_while(
+        chars: &mut Peekable<CharIndices>,
+        input: &str,
+        start: usize,
+        pred: impl Fn(char) -> bool,
+    ) -> String {
+        let mut end = input.len();
+        while let Some((idx, c)) = chars.peek() {
+            if !pred(*c) {
+                end = *idx;
+                break;
+            }
+            chars.next();
+        }
+        input[start..end].to_string()
+    }
+
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '0'..='9' | '.' => {
+                let number = take_while(&mut chars, input, at, |c| c.is_ascii_digit() || c == '.');
+                let value = number
+                    .parse()
+                    .map_err(|_| ParseError::InvalidNumber(number.clone()))?;
+                tokens.push((at, Token::Number(value)));
+                continue;
+            }
+            c if c.is_alphabetic() => {
+                let word =
+                    take_while(&mut chars, input, at, |c| c.is_alphanumeric() || "_/".contains(c));
+                tokens.push((at, Token::Word(word)));
+                continue;
+            }
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Minus,
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '^' => Token::Op(Op::Pow),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            found => return Err(ParseError::UnexpectedChar { found, at }),
+        };
+        chars.next();
+        tokens.push((at, token));
+    }
+    Ok(tokens)
+}
+
+/// Parses an expression, every reading it uses must be a stored reading.
+pub(crate) fn parse(input: &str) -> Result<Parsed, ParseError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        inputs: Vec::new(),
+    };
+    let expr = parser.sum()?;
+    if let Some((at, token)) = parser.tokens.next() {
+        return Err(ParseError::Unexpected {
+            found: token.describe(),
+            at,
+            expected: "an operator or the end",
+        });
+    }
+    Ok(Parsed {
+        expr,
+        inputs: parser.inputs,
+    })
+}
+
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<(usize, Token)>>,
+    inputs: Vec<protocol::Reading>,
+}
+
+impl Parser {
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.product()?;
+        loop {
+            let op = match self.tokens.peek() {
+                Some((_, Token::Op(Op::Add))) => Op::Add,
+                Some((_, Token::Minus)) => Op::Sub,
+                _ => return Ok(expr),
+            };
+            self.tokens.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = match self.tokens.peek() {
+                Some((_, Token::Op(op @ (Op::Mul | Op::Div)))) => *op,
+                _ => return Ok(expr),
+            };
+            self.tokens.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if let Some((_, Token::Minus)) = self.tokens.peek() {
+            self.tokens.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        if let Some((_, Token::Op(Op::Pow))) = self.tokens.peek() {
+            self.tokens.next();
+            // right associative: 2^3^2 is 2^(3^2)
+            return Ok(Expr::Binary(Op::Pow, Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        const EXPECTED: &str = "a number, reading, function or `(`";
+        let Some((at, token)) = self.tokens.next() else {
+            return Err(ParseError::UnexpectedEnd { expected: EXPECTED });
+        };
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Open => {
+                let expr = self.sum()?;
+                self.expect(Token::Close, "`)`")?;
+                Ok(expr)
+            }
+            Token::Word(word) if matches!(self.tokens.peek(), Some((_, Token::Open))) => {
+                self.tokens.next();
+                self.call(word)
+            }
+            Token::Word(word) => self.input(word),
+            token => Err(ParseError::Unexpected {
+                found: token.describe(),
+                at,
+                expected: EXPECTED,
+            }),
+        }
+    }
+
+    fn call(&mut self, name: String) -> Result<Expr, ParseError> {
+        let function = Function::from_name(&name).ok_or(ParseError::UnknownFunction(name))?;
+        let mut args = vec![self.sum()?];
+        while let Some((_, Token::Comma)) = self.tokens.peek() {
+            self.tokens.next();
+            args.push(self.sum()?);
+        }
+        self.expect(Token::Close, "`,` or `)`")?;
+
+        if args.len() != function.arity() {
+            return Err(ParseError::WrongArgumentCount {
+                name: function.name(),
+                expected: function.arity(),
+                got: args.len(),
+            });
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    fn input(&mut self, path: String) -> Result<Expr, ParseError> {
+        let reading = resolve(&path).ok_or(ParseError::UnknownReading(path))?;
+        let idx = match self.inputs.iter().position(|r| r.is_same_as(&reading)) {
+            Some(idx) => idx,
+            None => {
+                self.inputs.push(reading);
+                self.inputs.len() - 1
+            }
+        };
+        Ok(Expr::Input(idx))
+    }
+
+    fn expect(&mut self, expected: Token, description: &'static str) -> Result<(), ParseError> {
+        match self.tokens.next() {
+            Some((_, token)) if token == expected => Ok(()),
+            Some((at, token)) => Err(ParseError::Unexpected {
+                found: token.describe(),
+                at,
+                expected: description,
+            }),
+            None => Err(ParseError::UnexpectedEnd { expected: description }),
+        }
+    }
+}
+
+/// Finds the reading at `path`, for example `largebedroom/bed/sht31/Temperature`
+pub(super) fn resolve(path: &str) -> Option<protocol::Reading> {
+    let (series, name) = path.rsplit_once('/')?;
+    let device = db::device_from_path(Path::new(series))?;
+    device
+        .info()
+        .affects_readings
+        .iter()
+        .find(|reading| db::reading_name(reading) == name)
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEMPERATURE: &str = "largebedroom/bed/sht31/Temperature";
+    const HUMIDITY: &str = "largebedroom/bed/sht31/Humidity";
+
+    #[test]
+    fn precedence() {
+        let parsed = parse("1 + 2 * 3 ^ 2 - -4 / 2").unwrap();
+        assert_eq!(parsed.expr.eval(&[]), 1.0 + 2.0 * 9.0 + 2.0);
+        let parsed = parse("(1 + 2) * 3").unwrap();
+        assert_eq!(parsed.expr.eval(&[]), 9.0);
+    }
+
+    #[test]
+    fn readings_are_inputs() {
+        let parsed = parse(&format!("{TEMPERATURE} - {HUMIDITY} + {TEMPERATURE}")).unwrap();
+        assert_eq!(parsed.inputs.len(), 2);
+        assert_eq!(parsed.expr.eval(&[20.0, 50.0]), -10.0);
+    }
+
+    #[test]
+    fn functions() {
+        let parsed = parse(&format!("dew_point({TEMPERATURE}, {HUMIDITY})")).unwrap();
+        let dew_point = parsed.expr.eval(&[20.0, 50.0]);
+        assert!((dew_point - 9.26).abs() < 0.01, "dew point was: {dew_point}");
+        assert_eq!(parse("c_to_f(100)").unwrap().expr.eval(&[]), 212.0);
+        assert_eq!(parse("max(1, min(3, 2))").unwrap().expr.eval(&[]), 2.0);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            parse("largebedroom/bed/sht31/Nope"),
+            Err(ParseError::UnknownReading(_))
+        ));
+        assert!(matches!(parse("nope(1)"), Err(ParseError::UnknownFunction(_))));
+        assert!(matches!(
+            parse("min(1)"),
+            Err(ParseError::WrongArgumentCount { .. })
+        ));
+        assert!(matches!(parse("1 +"), Err(ParseError::UnexpectedEnd { .. })));
+        assert!(matches!(parse("(1"), Err(ParseError::UnexpectedEnd { .. })));
+        assert!(matches!(parse("1 2"), Err(ParseError::Unexpected { .. })));
+        assert!(matches!(parse("1 $ 2"), Err(ParseError::UnexpectedChar { .. })));
+    }
+}
@@ -2,7 +2,9 @@ use std::net::SocketAddr;
 use std::sync::Once;
 use std::time::Duration;
 
+use data_server::api::ReconnectingClient;
 use data_server::server::AffectorRegistar;
+use data_store::api::AnyReading;
 use futures::FutureExt;
 use futures_concurrency::future::Race;
 use protocol::large_bedroom::bed;
@@ -28,8 +30,15 @@ async fn data_server(client_addr: impl Into<SocketAddr>, data_port: impl Into<So
 
     let (tx, rx) = mpsc::channel(2000);
     let affectors = AffectorRegistar::default();
+    let clients = server::client::handle(
+        client_addr.into(),
+        tx.clone(),
+        affectors.clone(),
+        None,
+        None,
+    );
     tokio::select! {
-        e = server::client::handle(client_addr.into(), tx.clone(), affectors.clone()) => e.unwrap(),
+        e = clients => e.unwrap(),
         e = server::handle_nodes(data_port.into(), &tx, affectors) => e.unwrap(),
         e = server::spread_updates(rx) => e.unwrap(),
     };
@@ -110,7 +119,75 @@ async fn check_client_get_data(
         .into_iter()
         .zip(sensor_values.into_iter().copied())
         .inspect(|r| println!("(got, expected): {r:?}"))
-        .all(|(a, b)| (a - b).abs() < 0.1))
+        .all(|(a, b)| (a - b).abs() < 0.1));
+
+    let virtual_readings = client.list_virtual().await.unwrap();
+    assert!(virtual_readings.iter().any(|r| r.name == "doubled"));
+    let (_, data) = client
+        .get_data(
+            jiff::Timestamp::now() - jiff::Span::default().seconds(30),
+            jiff::Timestamp::now() + jiff::Span::default().seconds(30),
+            AnyReading::Virtual("doubled".to_owned()),
+            5,
+        )
+        .await
+        .unwrap();
+    assert!(data
+        .into_iter()
+        .zip(sensor_values.into_iter().copied())
+        .all(|(a, b)| (a - 2. * b).abs() < 0.1))
+}
+
+async fn check_client_subscribe(data_store_addr: SocketAddr, sensor_values: &[f32]) {
+    let mut client =
+        data_store::api::Client::connect(data_store_addr, "data_store_example".to_owned())
+            .await
+            .unwrap();
+    let reading = test_readings(0.0)[0].clone();
+    let start = jiff::Timestamp::now() - jiff::Span::default().seconds(30);
+    let mut subscribed = client
+        .subscribe(vec![reading.clone()], start)
+        .await
+        .unwrap();
+
+    let mut time = Vec::new();
+    let mut data = Vec::new();
+    while data.len() < sensor_values.len() {
+        let (got_reading, new_time, new_data) =
+            tokio::time::timeout(Duration::from_secs(5), subscribed.next())
+                .await
+                .expect("all values should arrive")
+                .unwrap();
+        assert!(got_reading.is_same_as(&reading));
+        time.extend(new_time);
+        data.extend(new_data);
+    }
+
+    assert!(
+        time.windows(2).all(|w| w[0] < w[1]),
+        "duplicate or out of order timestamps: {time:?}"
+    );
+    assert!(data
+        .into_iter()
+        .zip(sensor_values.iter().copied())
+        .inspect(|r| println!("(got, expected): {r:?}"))
+        .all(|(a, b)| (a - b).abs() < 0.1));
+
+    // the connection still serves calls while subscribed
+    let stored = client.list_data().await.unwrap();
+    assert!(stored.iter().any(|r| r.is_same_as(&reading)));
+
+    // changing the subscription sends the history again
+    subscribed
+        .change(vec![reading.clone()], start)
+        .await
+        .unwrap();
+    let (got_reading, _, data) = tokio::time::timeout(Duration::from_secs(5), subscribed.next())
+        .await
+        .expect("history should arrive")
+        .unwrap();
+    assert!(got_reading.is_same_as(&reading));
+    assert!(!data.is_empty());
 }
 
 static SETUP_REPORTING: Once = Once::new();
@@ -148,7 +225,17 @@ async fn list_data() {
         ([127, 0, 0, 1], data_port.port()),
     );
     let run_data_store = sleep(DATA_SERVER_STARTUP).then(|()| {
-        data_store::server::run(data_server_addr, data_store_addr.port(), test_dir.path())
+        data_store::server::run(
+            ReconnectingClient::new(data_server_addr, "ha-data-store".to_string()),
+            data_store_addr.port(),
+            None,
+            None,
+            test_dir.path(),
+            &test_dir.path().join("snapshots"),
+            Default::default(),
+            Duration::from_millis(100),
+            Vec::new(),
+        )
     });
     let send_sensor_value = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP)
         .then(|()| send_sensor_values(data_port.port(), &[0.0], &data_send));
@@ -193,7 +280,19 @@ async fn read_data() {
         ([127, 0, 0, 1], data_port.port()),
     );
     let run_data_store = sleep(DATA_SERVER_STARTUP).then(|()| {
-        data_store::server::run(data_server_addr, data_store_addr.port(), test_dir.path())
+        data_store::server::run(
+            ReconnectingClient::new(data_server_addr, "ha-data-store".to_string()),
+            data_store_addr.port(),
+            None,
+            None,
+            test_dir.path(),
+            &test_dir.path().join("snapshots"),
+            Default::default(),
+            Duration::from_millis(100),
+            vec!["doubled = largebedroom/bed/sht31/Temperature * 2"
+                .parse()
+                .unwrap()],
+        )
     });
     let send_sensor_value = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP)
         .then(|()| send_sensor_values(data_port.port(), &sensor_values, &data_send));
@@ -211,3 +310,57 @@ async fn read_data() {
 
     res.unwrap();
 }
+
+#[tokio::test]
+async fn subscribe() {
+    const DATA_SERVER_STARTUP: Duration = Duration::from_millis(20);
+    const DATA_STORE_STARTUP: Duration = Duration::from_millis(20);
+    const FIRST_MSG_PROCESSED: Duration = Duration::from_millis(1000);
+
+    setup_reporting();
+
+    let test_dir = TempDir::new().unwrap();
+
+    let sub_port = reserve_port::ReservedPort::random().unwrap();
+    let data_port = reserve_port::ReservedPort::random().unwrap();
+    let store_port = reserve_port::ReservedPort::random().unwrap();
+
+    let data_server_addr = SocketAddr::from(([127, 0, 0, 1], sub_port.port()));
+    let data_store_addr = SocketAddr::from(([127, 0, 0, 1], store_port.port()));
+
+    let data_send = Notify::new();
+    // first is stored before we subscribe, the rest after
+    let sensor_values = [0.1, 0.2, 0.3];
+    let run_data_server = data_server(
+        ([127, 0, 0, 1], sub_port.port()),
+        ([127, 0, 0, 1], data_port.port()),
+    );
+    let run_data_store = sleep(DATA_SERVER_STARTUP).then(|()| {
+        data_store::server::run(
+            ReconnectingClient::new(data_server_addr, "ha-data-store".to_string()),
+            data_store_addr.port(),
+            None,
+            None,
+            test_dir.path(),
+            &test_dir.path().join("snapshots"),
+            Default::default(),
+            Duration::from_millis(100),
+            Vec::new(),
+        )
+    });
+    let send_sensor_value = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP)
+        .then(|()| send_sensor_values(data_port.port(), &sensor_values, &data_send));
+    let run_test = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP + FIRST_MSG_PROCESSED)
+        .then(|()| check_client_subscribe(data_store_addr, &sensor_values));
+
+    let res = (
+        run_test.map(Result::Ok),
+        send_sensor_value.map(Result::Ok),
+        run_data_store,
+        run_data_server.map(Result::Ok),
+    )
+        .race()
+        .await;
+
+    res.unwrap();
+}
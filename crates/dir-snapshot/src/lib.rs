@@ -0,0 +1,241 @@
+//! Tarballs with a point in time copy of a dir and restoring them. Used by
+//! the data-store and the log-store for their data and log dirs.
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::{Result, Section};
+
+#[derive(Debug)]
+pub struct Written {
+    pub path: PathBuf,
+    pub files: usize,
+    /// Size of the files in the snapshot before archiving
+    pub bytes: u64,
+}
+
+/// Archives every file in `dir` into a new tarball in `snapshot_dir`. The
+/// name is picked here: `<prefix>-<unix seconds>.tar`, existing snapshots
+/// are never overwritten. Snapshots in `dir` are not archived. Nothing may
+/// write to `dir` while this runs.
+pub fn write(dir: &Path, snapshot_dir: &Path, prefix: &str) -> Result<Written> {
+    fs::create_dir_all(snapshot_dir)
+        .wrap_err("Could not create snapshot dir")
+        .with_note(|| format!("path: {}", snapshot_dir.display()))?;
+    let snapshot_dir = snapshot_dir
+        .canonicalize()
+        .wrap_err("Could not get full path of snapshot dir")?;
+
+    // the tarball only gets its name once it is complete
+    let partial = snapshot_dir.join(format!(".{prefix}.tar.partial"));
+    let file = File::create(&partial)
+        .wrap_err("Could not create snapshot file")
+        .with_note(|| format!("path: {}", partial.display()))?;
+    let mut builder = tar::Builder::new(BufWriter::new(file));
+
+    let mut files = 0;
+    let mut bytes = 0;
+    for path in files_in(dir)? {
+        // the snapshot dir could be inside dir
+        if path
+            .canonicalize()
+            .is_ok_and(|path| path.starts_with(&snapshot_dir))
+        {
+            continue;
+        }
+        let name = path
+            .strip_prefix(dir)
+            .expect("files_in only returns paths in dir");
+        builder
+            .append_path_with_name(&path, name)
+            .wrap_err("Could not add file to snapshot")
+            .with_note(|| format!("file: {}", path.display()))?;
+        files += 1;
+        bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    }
+
+    let file = builder
+        .into_inner()
+        .wrap_err("Could not finish snapshot")?
+        .into_inner()
+        .wrap_err("Could not flush snapshot")?;
+    file.sync_all()
+        .wrap_err("Could not sync snapshot to disk")?;
+
+    let path = move_in_place(&partial, &snapshot_dir, prefix)?;
+    Ok(Written { path, files, bytes })
+}
+
+/// Gives the finished tarball a name no other snapshot has
+fn move_in_place(partial: &Path, snapshot_dir: &Path, prefix: &str) -> Result<PathBuf> {
+    let time = jiff::Timestamp::now().as_second();
+    for attempt in 0.. {
+        let name = match attempt {
+            0 => format!("{prefix}-{time}.tar"),
+            n => format!("{prefix}-{time}-{n}.tar"),
+        };
+        let path = snapshot_dir.join(name);
+        // hard_link fails if the name is taken, rename would replace it
+        match fs::hard_link(partial, &path) {
+            Ok(()) => {
+                fs::remove_file(partial).wrap_err("Could not remove partial snapshot")?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not move snapshot in place")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        }
+    }
+    unreachable!("there are more attempts than files in a dir")
+}
+
+fn files_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir)
+        .wrap_err("Could not list dir")
+        .with_note(|| format!("dir: {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.wrap_err("Could not read dir entry")?;
+        let file_type = entry.file_type().wrap_err("Could not get file type")?;
+        if file_type.is_dir() {
+            files.extend(files_in(&entry.path())?);
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+#[derive(Debug)]
+pub struct Restored<T> {
+    /// What `check` returned
+    pub checked: T,
+    /// Where the dir that was replaced was moved to
+    pub previous: Option<PathBuf>,
+}
+
+/// Replaces `dir` with the content of a snapshot. The snapshot is unpacked
+/// next to `dir` first and passed to `check`. Only if that succeeds is
+/// `dir` replaced. The old dir is kept next to it. Whatever uses `dir` must
+/// not be running.
+pub fn restore<T>(
+    snapshot: &Path,
+    dir: &Path,
+    check: impl FnOnce(&Path) -> Result<T>,
+) -> Result<Restored<T>> {
+    let staging = with_name_suffix(dir, ".restoring");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .wrap_err("Could not remove leftover from an earlier restore")
+            .with_note(|| format!("path: {}", staging.display()))?;
+    }
+    fs::create_dir_all(&staging)
+        .wrap_err("Could not create dir to unpack snapshot in")
+        .with_note(|| format!("path: {}", staging.display()))?;
+
+    let file = File::open(snapshot)
+        .wrap_err("Could not open snapshot")
+        .with_note(|| format!("path: {}", snapshot.display()))?;
+    tar::Archive::new(file)
+        .unpack(&staging)
+        .wrap_err("Could not unpack snapshot")?;
+
+    let checked = match check(&staging) {
+        Ok(checked) => checked,
+        Err(report) => {
+            return Err(report).suggestion(format!(
+                "The snapshot has not been restored, {} is unchanged",
+                dir.display()
+            ))
+        }
+    };
+
+    let previous = if dir.exists() {
+        let time = jiff::Timestamp::now().as_second();
+        let previous = with_name_suffix(dir, &format!(".before-restore-{time}"));
+        if previous.exists() {
+            bail!("Restored a snapshot less then a second ago, try again");
+        }
+        fs::rename(dir, &previous)
+            .wrap_err("Could not move current dir out of the way")
+            .with_note(|| format!("to: {}", previous.display()))?;
+        Some(previous)
+    } else {
+        None
+    };
+    fs::rename(&staging, dir)
+        .wrap_err("Could not move unpacked snapshot in place")
+        .with_note(|| format!("from: {}", staging.display()))?;
+
+    Ok(Restored { checked, previous })
+}
+
+fn with_name_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path
+        .canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf())
+        .into_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names_in(snapshot: &Path) -> Vec<PathBuf> {
+        tar::Archive::new(File::open(snapshot).unwrap())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_are_not_archived() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("largebedroom/bed")).unwrap();
+        fs::write(
+            dir.path().join("largebedroom/bed/sht31.byteseries"),
+            b"data",
+        )
+        .unwrap();
+        let snapshot_dir = dir.path().join("snapshots");
+
+        let first = write(dir.path(), &snapshot_dir, "data-store").unwrap();
+        let second = write(dir.path(), &snapshot_dir, "data-store").unwrap();
+        assert_ne!(first.path, second.path);
+        assert_eq!(second.files, 1);
+        assert_eq!(second.bytes, 4);
+        assert!(second
+            .path
+            .starts_with(snapshot_dir.canonicalize().unwrap()));
+        assert_eq!(
+            names_in(&second.path),
+            vec![PathBuf::from("largebedroom/bed/sht31.byteseries")]
+        );
+    }
+
+    #[test]
+    fn restore_only_after_check() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let data_dir = dir.path().join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("file"), b"old").unwrap();
+        let snapshots = dir.path().join("snapshots");
+        let snapshot = write(&data_dir, &snapshots, "test").unwrap();
+        fs::write(data_dir.join("file"), b"new").unwrap();
+
+        assert!(restore::<()>(&snapshot.path, &data_dir, |_| bail!("damaged")).is_err());
+        assert_eq!(fs::read(data_dir.join("file")).unwrap(), b"new");
+
+        let restored = restore(&snapshot.path, &data_dir, |_| Ok(())).unwrap();
+        assert_eq!(fs::read(data_dir.join("file")).unwrap(), b"old");
+        let previous = restored.previous.unwrap();
+        assert_eq!(fs::read(previous.join("file")).unwrap(), b"new");
+    }
+}
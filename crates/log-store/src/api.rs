@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use protocol::{Device, Reading};
@@ -5,14 +6,59 @@ use protocol::{Device, Reading};
 use serde::{Deserialize, Serialize};
 
 pub mod client;
-pub use client::Client;
+pub use client::{Client, Subscribed};
+
+mod coalesce;
+pub use coalesce::{coalesce_flapping, FLAPPING_EPISODES, FLAPPING_WINDOW};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum Request {
     Handshake { name: String },
-    GetLog(protocol::Device),
-    GetStats(protocol::Device),
+    /// Error events that were going on at some point between `start` and
+    /// `end`, oldest first. That includes an event that started before
+    /// `start` but ended after it and the ongoing error if it started
+    /// before `end`. Pass the cursor from the previous page to get the next
+    /// one.
+    GetLog {
+        device: protocol::Device,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        limit: usize,
+        cursor: Option<Cursor>,
+    },
+    /// Percentiles of the time between readings, merged over the hourly
+    /// windows that overlap `start` till `end`
+    GetStats {
+        timing: Timing,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    },
+    /// Times `affector` was controlled between `start` and `end`, oldest
+    /// first. Only the kind of affector matters, its value is ignored.
+    GetAffectorLog {
+        affector: protocol::Affector,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        limit: usize,
+        cursor: Option<Cursor>,
+    },
+    /// How much of `start` till `end` the device was healthy, in error or
+    /// silent. Combines the error log with the time between the device its
+    /// cycles.
+    GetAvailability {
+        device: protocol::Device,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    },
     ListDevices,
+    /// For every device that reported errors recently the `top` errors it
+    /// reported most. Counts start when the log-store starts.
+    GetErrorSummary {
+        top: usize,
+    },
+    /// Write a tarball of all logs to the snapshot dir of the log-store.
+    /// The log-store picks the name and returns where it put it.
+    Snapshot,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
@@ -21,6 +67,11 @@ pub enum ServerError {
     AlreadyConnected,
     #[error("Too many requests, rate limited, next requested allowed in: {0:?}")]
     TooManyRequests(Duration),
+    #[error(
+        "Subscription fell behind and missed {0} error episodes, it has been \
+        ended. Subscribe again to get the errors that are ongoing."
+    )]
+    SubscriberLagged(u64),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
@@ -33,12 +84,47 @@ pub enum GetStatsError {
 
 #[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
 pub enum GetLogError {
-    #[error("Too many error events ({found}) between requested timepoints, max is: {max}")]
-    TooMuchData { max: i32, found: u64 },
+    #[error("Limit must be between 1 and {max}, got: {requested}")]
+    InvalidLimit { requested: usize, max: usize },
     #[error("Internal error while reading data, error: {0}")]
     InternalError(String),
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+pub enum AvailabilityError {
+    #[error("The range must end after it starts and start before now")]
+    InvalidRange,
+    #[error("Internal error while reading data, error: {0}")]
+    InternalError(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Internal error while creating snapshot, error: {0}")]
+    InternalError(String),
+}
+
+/// A tarball with a point in time copy of the log dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// On the machine running the log-store
+    pub path: PathBuf,
+    pub files: usize,
+    /// Size of the files in the snapshot before archiving
+    pub bytes: u64,
+}
+
+/// Which intervals to get statistics for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Timing {
+    /// Between consecutive readings of this kind, the value is ignored
+    Reading(Reading),
+    /// Between the starts of consecutive cycles of the device. A device
+    /// sends its readings one after the other, a cycle ends when a reading
+    /// arrives again.
+    DeviceCycle(Device),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Percentile {
     pub bucket_ends: u64,
@@ -51,14 +137,169 @@ pub struct ErrorEvent {
     pub start: jiff::Timestamp,
     /// if None then the error is ongoing
     pub end: Option<jiff::Timestamp>,
+    /// Set when `end` is
+    pub end_reason: Option<EndReason>,
+    pub error: protocol::Error,
+    /// Number of episodes this event summarises. More than one once merged
+    /// by [`coalesce_flapping`], `error` is then the error most of them had.
+    pub episodes: u32,
+}
+
+/// How often a device reported an error, repeats of the current error
+/// included
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorCount {
     pub error: protocol::Error,
+    pub last_hour: u32,
+    pub last_day: u32,
+    pub last_seen: jiff::Timestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceErrors {
+    pub device: Device,
+    /// The device keeps starting new error episodes, see
+    /// [`coalesce_flapping`]
+    pub flapping: bool,
+    /// Most reported in the last day first
+    pub top: Vec<ErrorCount>,
+}
+
+/// How a device did over a time range. Parts of the range in the future
+/// are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Availability {
+    /// Percentage of the range the device was sending readings
+    pub healthy: f64,
+    /// Percentage of the range the device was reporting errors
+    pub in_error: f64,
+    /// Percentage of the range the device sent nothing at all
+    pub silent: f64,
+    /// The longest outages, longest first
+    pub longest_outages: Vec<Outage>,
+    /// Number of outages in the range
+    pub outages: usize,
+    /// Time healthy divided by the number of outages. None if there were
+    /// none.
+    pub mean_time_between_failures: Option<Duration>,
+}
+
+/// A period in which the device did not send readings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Outage {
+    /// The device reported errors, consecutive episodes are one outage
+    Error {
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        episodes: u32,
+    },
+    /// The device sent nothing for `duration`. Only the hour in which it
+    /// started sending again is known, it starts at `resumed_in`.
+    Silent {
+        resumed_in: jiff::Timestamp,
+        duration: Duration,
+    },
+}
+
+impl Outage {
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        match self {
+            Outage::Error { start, end, .. } => {
+                Duration::from_millis((end.as_millisecond() - start.as_millisecond()) as u64)
+            }
+            Outage::Silent { duration, .. } => *duration,
+        }
+    }
+}
+
+/// Where the next page of a [`LogPage`] starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Start of the first event on the next page, in seconds since the unix
+    /// epoch
+    pub(crate) start: u64,
+    /// Events starting at `start` that were on previous pages
+    pub(crate) skip: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogPage {
+    pub events: Vec<ErrorEvent>,
+    /// None if this is the last page
+    pub next: Option<Cursor>,
+}
+
+/// An affector was controlled, for example the bed led changed color
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AffectorEvent {
+    pub at: jiff::Timestamp,
+    /// With the value it was set to
+    pub affector: protocol::Affector,
+    /// Name of the client that controlled it
+    pub controlled_by: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AffectorPage {
+    pub events: Vec<AffectorEvent>,
+    /// None if this is the last page
+    pub next: Option<Cursor>,
+}
+
+/// A change to the current error of a device. These follow the stored
+/// history: an episode starts when a device reports a new error and ends
+/// once it reports another, sends a reading or its node disconnects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ErrorEpisode {
+    /// The device had no error and now reports one, `end` is None
+    Started(ErrorEvent),
+    /// The device reported a different error. The `previous` episode ended
+    /// with [`EndReason::Replaced`].
+    Changed {
+        previous: ErrorEvent,
+        current: ErrorEvent,
+    },
+    /// The error ended because of a reading or a disconnect
+    Resolved(ErrorEvent),
+}
+
+impl ErrorEpisode {
+    #[must_use]
+    pub fn device(&self) -> Device {
+        match self {
+            ErrorEpisode::Started(event)
+            | ErrorEpisode::Changed { current: event, .. }
+            | ErrorEpisode::Resolved(event) => event.error.device(),
+        }
+    }
+}
+
+/// Why an error stopped being the current error of its device
+///
+/// The variant index is stored on disk, new variants must be added at the
+/// end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EndReason {
+    /// The device reported a different error
+    Replaced,
+    /// The device sent a reading
+    Cleared,
+    /// The node the device is on disconnected from the data-server
+    Disconnected,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum Response {
-    GetLog(Result<Vec<ErrorEvent>, GetLogError>),
+    GetLog(Result<LogPage, GetLogError>),
+    GetAffectorLog(Result<AffectorPage, GetLogError>),
+    GetAvailability(Result<Availability, AvailabilityError>),
     ListDevices(Vec<Device>),
+    GetErrorSummary(Vec<DeviceErrors>),
     GetStats(Result<Vec<Percentile>, GetStatsError>),
+    Snapshot(Result<Snapshot, SnapshotError>),
     Error(ServerError),
     Handshake,
+    /// Only send to subscribers
+    ErrorEpisode(ErrorEpisode),
 }
@@ -2,13 +2,16 @@ use protocol::Device;
 use rpc::client::RpcClient;
 use tokio::net::ToSocketAddrs;
 
-use crate::api::Percentile;
+use crate::api::{Percentile, Timing};
 
-use super::ErrorEvent;
 use super::GetLogError;
 use super::GetStatsError;
 use super::Response;
+use super::{DeviceErrors, ErrorEpisode, ServerError};
+use super::{AffectorPage, Cursor, LogPage, Snapshot, SnapshotError};
+use super::{Availability, AvailabilityError};
 
+#[derive(Clone)]
 pub struct Client(rpc::client::RpcClient<super::Request, super::Response>);
 
 #[derive(Debug, thiserror::Error)]
@@ -30,11 +33,43 @@ impl Client {
         Ok(Self(rpc_client))
     }
 
+    /// Needed if the log-store was started with a client key file
+    pub async fn connect_with_key(
+        addr: impl ToSocketAddrs,
+        name: String,
+        key: &rpc::auth::Key,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_with_key(addr, name, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// Needed if the log-store uses TLS, pass a `key` if it was also
+    /// started with a client key file
+    pub async fn connect_tls(
+        addr: impl ToSocketAddrs,
+        name: String,
+        tls: &rpc::tls::ClientConfig,
+        key: Option<&rpc::auth::Key>,
+    ) -> Result<Self, rpc::client::ConnectError> {
+        let rpc_client = RpcClient::connect_tls(addr, name, tls, key).await?;
+        Ok(Self(rpc_client))
+    }
+
+    /// The connection failed, make a new one
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
+    /// Distribution of the intervals picked by `timing`. Intervals are kept
+    /// per hour, every hour overlapping `start` till `end` is included.
     pub async fn get_percentiles(
         &mut self,
-        device: protocol::Device,
+        timing: Timing,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
     ) -> Result<Vec<Percentile>, Error<GetStatsError>> {
-        let request = super::Request::GetStats(device);
+        let request = super::Request::GetStats { timing, start, end };
         match self.0.send_receive(request.clone()).await? {
             Response::GetStats(Ok(percentiles)) => Ok(percentiles),
             Response::GetStats(Err(e)) => Err(Error::Request(e)),
@@ -45,11 +80,23 @@ impl Client {
         }
     }
 
+    /// A page of at most `limit` error events that overlap `start` till
+    /// `end`. Pass [`LogPage::next`] as `cursor` to get the next page.
     pub async fn get_logs(
         &mut self,
         device: protocol::Device,
-    ) -> Result<Vec<ErrorEvent>, Error<GetLogError>> {
-        let request = super::Request::GetLog(device);
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        limit: usize,
+        cursor: Option<Cursor>,
+    ) -> Result<LogPage, Error<GetLogError>> {
+        let request = super::Request::GetLog {
+            device,
+            start,
+            end,
+            limit,
+            cursor,
+        };
         match self.0.send_receive(request.clone()).await? {
             Response::GetLog(Ok(log)) => Ok(log),
             Response::GetLog(Err(e)) => Err(Error::Request(e)),
@@ -60,6 +107,67 @@ impl Client {
         }
     }
 
+    /// A page of at most `limit` times an affector of the same kind as
+    /// `affector` was controlled between `start` and `end`. Pass
+    /// [`AffectorPage::next`] as `cursor` to get the next page.
+    pub async fn get_affector_log(
+        &mut self,
+        affector: protocol::Affector,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+        limit: usize,
+        cursor: Option<Cursor>,
+    ) -> Result<AffectorPage, Error<GetLogError>> {
+        let request = super::Request::GetAffectorLog {
+            affector,
+            start,
+            end,
+            limit,
+            cursor,
+        };
+        match self.0.send_receive(request.clone()).await? {
+            Response::GetAffectorLog(Ok(page)) => Ok(page),
+            Response::GetAffectorLog(Err(e)) => Err(Error::Request(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Makes the log-store write a snapshot of all its logs to its snapshot
+    /// dir. Returns where, on the machine running the log-store.
+    pub async fn snapshot(&mut self) -> Result<Snapshot, Error<SnapshotError>> {
+        let request = super::Request::Snapshot;
+        match self.0.send_receive(request.clone()).await? {
+            Response::Snapshot(Ok(snapshot)) => Ok(snapshot),
+            Response::Snapshot(Err(e)) => Err(Error::Request(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// How much of `start` till `end` the device was healthy, see
+    /// [`Availability`]
+    pub async fn get_availability(
+        &mut self,
+        device: Device,
+        start: jiff::Timestamp,
+        end: jiff::Timestamp,
+    ) -> Result<Availability, Error<AvailabilityError>> {
+        let request = super::Request::GetAvailability { device, start, end };
+        match self.0.send_receive(request.clone()).await? {
+            Response::GetAvailability(Ok(availability)) => Ok(availability),
+            Response::GetAvailability(Err(e)) => Err(Error::Request(e)),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
     pub async fn list_devices(&mut self) -> Result<Vec<Device>, Error<GetLogError>> {
         let request = super::Request::ListDevices;
         match self.0.send_receive(request.clone()).await? {
@@ -70,4 +178,48 @@ impl Client {
             }),
         }
     }
+
+    /// The `top` most reported errors of every device that reported any
+    /// in the last day
+    pub async fn get_error_summary(
+        &mut self,
+        top: usize,
+    ) -> Result<Vec<DeviceErrors>, Error<GetLogError>> {
+        let request = super::Request::GetErrorSummary { top };
+        match self.0.send_receive(request.clone()).await? {
+            Response::GetErrorSummary(summary) => Ok(summary),
+            response => Err(Error::IncorrectResponse {
+                request: format!("{request:?}"),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Get every error episode that starts, changes or ends from now on.
+    /// Errors that are ongoing when subscribing are send first as
+    /// [`ErrorEpisode::Started`].
+    pub async fn subscribe(&self) -> Result<Subscribed, Error<ServerError>> {
+        let subscription = self.0.subscribe(()).await?;
+        Ok(Subscribed(subscription))
+    }
+}
+
+pub struct Subscribed(rpc::client::Subscription<super::Request, super::Response>);
+
+impl Subscribed {
+    pub async fn next(&mut self) -> Result<ErrorEpisode, Error<ServerError>> {
+        match self.0.next().await? {
+            Response::ErrorEpisode(episode) => Ok(episode),
+            Response::Error(e) => Err(Error::Request(e)),
+            response => Err(Error::IncorrectResponse {
+                request: "none, we are subscribed".to_string(),
+                response: format!("{response:?}"),
+            }),
+        }
+    }
+
+    /// Stops the episodes, the client this came from is not affected
+    pub async fn unsubscribe(self) -> Result<(), Error<ServerError>> {
+        Ok(self.0.unsubscribe().await?)
+    }
 }
@@ -0,0 +1,165 @@
+//! A device that keeps starting new error episodes fills its log. The log
+//! keeps every episode, readers that want a short history merge the
+//! episodes of a flapping device here.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::{EndReason, ErrorEvent};
+
+/// A device flaps while this many episodes started within
+/// [`FLAPPING_WINDOW`]
+pub const FLAPPING_EPISODES: usize = 5;
+pub const FLAPPING_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Episodes merged while the device was flapping
+struct Run {
+    start: jiff::Timestamp,
+    end: jiff::Timestamp,
+    /// How the last episode ended
+    reason: EndReason,
+    /// Every distinct error with the number of episodes it had
+    errors: Vec<(protocol::Error, u32)>,
+}
+
+impl Run {
+    fn add(&mut self, error: protocol::Error, episodes: u32) {
+        match self.errors.iter_mut().find(|(e, _)| *e == error) {
+            Some((_, count)) => *count += episodes,
+            None => self.errors.push((error, episodes)),
+        }
+    }
+
+    fn into_event(self) -> ErrorEvent {
+        let episodes = self.errors.iter().map(|(_, episodes)| episodes).sum();
+        let (error, _) = self
+            .errors
+            .into_iter()
+            .max_by_key(|(_, episodes)| *episodes)
+            .expect("created with one episode");
+        ErrorEvent {
+            start: self.start,
+            end: Some(self.end),
+            end_reason: Some(self.reason),
+            error,
+            episodes,
+        }
+    }
+}
+
+/// Merges the episodes that ended while the device was flapping into one
+/// event, its `error` is the one most of them had. A stretch of flapping
+/// ends once fewer than [`FLAPPING_EPISODES`] started within
+/// [`FLAPPING_WINDOW`]. The ongoing error is never merged. Expects
+/// `events` oldest first, as [`Client::get_logs`](super::Client::get_logs)
+/// returns them.
+#[must_use]
+pub fn coalesce_flapping(events: Vec<ErrorEvent>) -> Vec<ErrorEvent> {
+    let mut coalesced = Vec::new();
+    let mut run: Option<Run> = None;
+    let mut recent = VecDeque::new();
+
+    for event in events {
+        forget_before(&mut recent, event.start);
+        if recent.len() < FLAPPING_EPISODES {
+            coalesced.extend(run.take().map(Run::into_event));
+        }
+        recent.push_back(event.start);
+
+        let Some(end) = event.end else {
+            coalesced.extend(run.take().map(Run::into_event));
+            coalesced.push(event);
+            continue;
+        };
+        forget_before(&mut recent, end);
+        if recent.len() < FLAPPING_EPISODES {
+            coalesced.extend(run.take().map(Run::into_event));
+            coalesced.push(event);
+            continue;
+        }
+
+        let run = run.get_or_insert_with(|| Run {
+            start: event.start,
+            end,
+            reason: EndReason::Replaced,
+            errors: Vec::new(),
+        });
+        run.end = end;
+        run.reason = event.end_reason.unwrap_or(EndReason::Replaced);
+        run.add(event.error, event.episodes.max(1));
+    }
+    coalesced.extend(run.map(Run::into_event));
+    coalesced
+}
+
+fn forget_before(recent: &mut VecDeque<jiff::Timestamp>, now: jiff::Timestamp) {
+    let window = FLAPPING_WINDOW.as_millis() as i64;
+    while recent
+        .front()
+        .is_some_and(|start| now.as_millisecond() - start.as_millisecond() > window)
+    {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use protocol::large_bedroom::{self, bed};
+
+    use super::*;
+
+    fn test_error(text: &str) -> protocol::Error {
+        protocol::Error::LargeBedroom(large_bedroom::Error::Bed(bed::Error::Setup(
+            bed::SensorError::Sht31(heapless::String::from_str(text).unwrap()),
+        )))
+    }
+
+    fn episode(start: i64, end: Option<i64>, text: &str) -> ErrorEvent {
+        let at = |second| jiff::Timestamp::from_second(second).unwrap();
+        ErrorEvent {
+            start: at(start),
+            end: end.map(at),
+            end_reason: end.map(|_| EndReason::Replaced),
+            error: test_error(text),
+            episodes: 1,
+        }
+    }
+
+    #[test]
+    fn flapping_episodes_are_merged() {
+        let mut events: Vec<_> = ["a", "b", "a", "b", "a", "b"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| episode(i as i64, Some(i as i64 + 1), text))
+            .collect();
+        events.last_mut().unwrap().end_reason = Some(EndReason::Cleared);
+        // long after the device calmed down
+        events.push(episode(3600, Some(3601), "c"));
+        events.push(episode(3602, None, "c"));
+
+        let coalesced = coalesce_flapping(events);
+        let episodes: Vec<_> = coalesced.iter().map(|event| event.episodes).collect();
+        // the fifth episode and later are merged while the device flaps
+        assert_eq!(episodes, [1, 1, 1, 1, 2, 1, 1]);
+        let summary = &coalesced[4];
+        assert_eq!(summary.start.as_second(), 4);
+        assert_eq!(summary.end.unwrap().as_second(), 6);
+        assert_eq!(summary.end_reason, Some(EndReason::Cleared));
+        assert!(coalesced[6].end.is_none());
+    }
+
+    #[test]
+    fn ongoing_error_is_not_merged() {
+        let mut events: Vec<_> = (0..6)
+            .map(|i| episode(i, Some(i + 1), &i.to_string()))
+            .collect();
+        events.push(episode(6, None, "ongoing"));
+
+        let coalesced = coalesce_flapping(events);
+        assert_eq!(coalesced.len(), 6);
+        assert_eq!(coalesced[4].episodes, 2);
+        assert_eq!(coalesced[5].error, test_error("ongoing"));
+        assert_eq!(coalesced[5].episodes, 1);
+    }
+}
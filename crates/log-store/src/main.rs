@@ -1,32 +1,134 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use clap::Parser;
-use color_eyre::eyre::Result;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{Result, WrapErr};
+use data_server::api::{ConnectOptions, ReconnectingClient};
+use log_store::api::Client;
+use log_store::server::snapshot;
 
 #[derive(Parser, Debug)]
 #[command(name = "data server")]
 #[command(version = "1.0")]
 #[command(about = "Receives sensor events then logs errors and tracks timing")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
     /// data server
-    #[arg(short, long)]
-    data_server: SocketAddr,
+    #[arg(short, long, required = true)]
+    data_server: Option<SocketAddr>,
 
-    #[arg(short, long)]
-    client_port: u16,
+    #[command(flatten)]
+    data_server_options: ConnectOptions,
+
+    #[arg(short, long, required = true)]
+    client_port: Option<u16>,
+
+    /// file with a pre-shared key per client, if given clients must prove
+    /// they have the key for the name they connect with. See the docs of
+    /// `rpc::auth` for the format.
+    #[arg(long)]
+    client_key_file: Option<PathBuf>,
+
+    /// PEM file with the certificate (chain) of this server, if given
+    /// clients must connect using TLS
+    #[arg(long, requires = "client_tls_key")]
+    client_tls_certificate: Option<PathBuf>,
+
+    /// PEM file with the private key belonging to the certificate
+    #[arg(long, requires = "client_tls_certificate")]
+    client_tls_key: Option<PathBuf>,
 
     #[arg(long, default_value = ".")]
     log_dir: PathBuf,
+
+    /// Where snapshots requested by clients are written to
+    #[arg(long, default_value = "snapshots")]
+    snapshot_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Make the running log-store write a snapshot of all its logs and
+    /// current errors to its snapshot dir.
+    Snapshot {
+        /// Address of the log-store's client port
+        #[arg(short, long)]
+        server: SocketAddr,
+        #[command(flatten)]
+        options: ConnectOptions,
+    },
+    /// Replace the log dir with the content of a snapshot. Stop the
+    /// log-store first. The current log dir is moved aside, not removed.
+    Restore {
+        #[arg(long, default_value = ".")]
+        log_dir: PathBuf,
+        snapshot: PathBuf,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     setup_tracing().unwrap();
     let cli = Cli::parse();
-    tracing::info!("started log-store, args: {cli:?}");
 
-    log_store::server::run(cli.data_server, cli.client_port, &cli.log_dir).await
+    match cli.command {
+        None => {
+            tracing::info!("started log-store, args: {cli:?}");
+            let data_server = cli.data_server.expect("required by clap");
+            let data_server = ReconnectingClient::new(data_server, "ha-log-store".to_string());
+            let data_server = cli.data_server_options.apply(data_server)?;
+            let client_port = cli.client_port.expect("required by clap");
+            let keys = cli.client_key_file
+                .as_deref()
+                .map(rpc::auth::Keys::load)
+                .transpose()?;
+            let tls = cli.client_tls_certificate
+                .zip(cli.client_tls_key)
+                .map(|(certificate, key)| rpc::tls::ServerConfig::load(&certificate, &key))
+                .transpose()?;
+            log_store::server::run(
+                data_server,
+                client_port,
+                keys,
+                tls,
+                &cli.log_dir,
+                &cli.snapshot_dir,
+            )
+            .await
+        }
+        Some(Command::Snapshot { server, options }) => {
+            let name = "snapshot cli".to_string();
+            let key = options.key()?;
+            let client = match (options.tls()?, &key) {
+                (Some(tls), key) => Client::connect_tls(server, name, &tls, key.as_ref()).await,
+                (None, Some(key)) => Client::connect_with_key(server, name, key).await,
+                (None, None) => Client::connect(server, name).await,
+            };
+            let mut client = client.wrap_err("Could not connect to log-store")?;
+            let snapshot = client
+                .snapshot()
+                .await
+                .wrap_err("Could not make snapshot")?;
+            println!(
+                "wrote {} files ({:.1} MiB) to {}",
+                snapshot.files,
+                snapshot.bytes as f64 / 1024. / 1024.,
+                snapshot.path.display()
+            );
+            Ok(())
+        }
+        Some(Command::Restore { log_dir, snapshot }) => {
+            let restored = snapshot::restore(&snapshot, &log_dir)?;
+            println!("restored {} logs", restored.logs);
+            if let Some(previous) = restored.previous {
+                println!("previous log dir moved to: {}", previous.display());
+            }
+            Ok(())
+        }
+    }
 }
 
 fn setup_tracing() -> Result<()> {
@@ -1,27 +1,38 @@
 use color_eyre::Result;
+use data_server::api::ReconnectingClient;
 use futures_concurrency::future::Race;
-use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::path::Path;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
 mod clients;
 mod db;
+pub mod snapshot;
 
-// used from main and tests
-pub async fn run(data_server: SocketAddr, client_port: u16, log_dir: &Path) -> Result<()> {
-    let stats = db::Stats(Arc::new(Mutex::new(HashMap::new())));
-    let logs = db::Logs(Arc::new(Mutex::new(HashMap::new())));
+// used from main and tests, with `keys` clients must authenticate and with
+// `tls` they must connect using TLS
+pub async fn run(
+    data_server: ReconnectingClient,
+    client_port: u16,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
+    log_dir: &Path,
+    snapshot_dir: &Path,
+) -> Result<()> {
+    let stats = db::Stats::new(log_dir);
+    let logs = db::Logs::open(log_dir);
+    let affectors = db::Affectors::new(log_dir);
 
     let error = (
-        db::run(
-            data_server,
-            stats.clone(),
-            logs.clone(),
-            log_dir,
+        db::run(data_server, stats.clone(), logs.clone(), affectors.clone()),
+        clients::handle(
+            client_port,
+            keys,
+            tls,
+            stats,
+            logs,
+            affectors,
+            log_dir.to_path_buf(),
+            snapshot_dir.to_path_buf(),
         ),
-        clients::handle(client_port, stats, logs),
     )
         .race()
         .await;
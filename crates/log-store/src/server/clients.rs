@@ -1,21 +1,89 @@
-use super::db::{Logs, Stats};
-use crate::api::{self, ServerError};
+use std::path::PathBuf;
 
-pub(crate) async fn handle(port: u16, stats: Stats, logs: Logs) -> color_eyre::Result<()> {
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::db::{availability, Affectors, Logs, Stats};
+use crate::api::{self, ErrorEpisode, ServerError};
+
+#[derive(Debug, Clone)]
+struct SubHandler {
+    logs: Logs,
+}
+
+impl rpc::SubscriberHandler for SubHandler {
+    type Update = api::Response;
+    type Request = ();
+
+    fn setup(
+        &mut self,
+        (): Self::Request,
+    ) -> impl std::future::Future<Output = impl Stream<Item = Self::Update> + Send + 'static>
+           + Send
+           + 'static {
+        do_setup(self.logs.clone())
+    }
+}
+
+async fn do_setup(logs: Logs) -> BoxStream<'static, api::Response> {
+    let (ongoing, rx) = logs.subscribe().await;
+    let ongoing = ongoing
+        .into_iter()
+        .map(|event| api::Response::ErrorEpisode(ErrorEpisode::Started(event)));
+    let live = stream::unfold(Some(rx), |rx| async move {
+        let mut rx = rx?;
+        match rx.recv().await {
+            Ok(episode) => Some((api::Response::ErrorEpisode(episode), Some(rx))),
+            Err(RecvError::Lagged(n)) => {
+                // the subscriber would miss episodes, end the subscription
+                let error = api::Response::Error(ServerError::SubscriberLagged(n));
+                Some((error, None))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+
+    stream::iter(ongoing).chain(live).boxed()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn handle(
+    port: u16,
+    keys: Option<rpc::auth::Keys>,
+    tls: Option<rpc::tls::ServerConfig>,
+    stats: Stats,
+    logs: Logs,
+    affectors: Affectors,
+    log_dir: PathBuf,
+    snapshot_dir: PathBuf,
+) -> color_eyre::Result<()> {
+    let handler = SubHandler { logs: logs.clone() };
     rpc::server::run(
         port,
+        keys,
+        tls,
         move |req, _| {
             let stats = stats.clone();
             let logs = logs.clone();
-            perform_request(req, stats, logs)
+            let affectors = affectors.clone();
+            let log_dir = log_dir.clone();
+            let snapshot_dir = snapshot_dir.clone();
+            perform_request(req, stats, logs, affectors, log_dir, snapshot_dir)
         },
-        Option::<rpc::SubscribersUnsupported<api::Response>>::None,
+        Some(handler),
     )
     .await
 }
 
-async fn perform_request(request: api::Request, stats: Stats, logs: Logs) -> api::Response {
-    match perform_request_inner(request, stats, logs).await {
+async fn perform_request(
+    request: api::Request,
+    stats: Stats,
+    logs: Logs,
+    affectors: Affectors,
+    log_dir: PathBuf,
+    snapshot_dir: PathBuf,
+) -> api::Response {
+    match perform_request_inner(request, stats, logs, affectors, log_dir, snapshot_dir).await {
         Ok(resp) => resp,
         Err(e) => api::Response::Error(e),
     }
@@ -24,11 +92,41 @@ async fn perform_request_inner(
     request: api::Request,
     stats: Stats,
     logs: Logs,
+    affectors: Affectors,
+    log_dir: PathBuf,
+    snapshot_dir: PathBuf,
 ) -> Result<api::Response, ServerError> {
     Ok(match request {
         api::Request::Handshake { .. } => return Err(ServerError::AlreadyConnected),
-        api::Request::GetLog(device) => api::Response::GetLog(logs.get(&device).await),
-        api::Request::GetStats(device) => api::Response::GetStats(stats.get(&device).await),
+        api::Request::GetLog {
+            device,
+            start,
+            end,
+            limit,
+            cursor,
+        } => api::Response::GetLog(logs.get(&device, start..=end, limit, cursor).await),
+        api::Request::GetAffectorLog {
+            affector,
+            start,
+            end,
+            limit,
+            cursor,
+        } => api::Response::GetAffectorLog(
+            affectors.get(&affector, start..=end, limit, cursor).await,
+        ),
+        api::Request::GetStats { timing, start, end } => {
+            api::Response::GetStats(stats.get(&timing, start..=end).await)
+        }
+        api::Request::GetAvailability { device, start, end } => api::Response::GetAvailability(
+            availability::report(&logs, &stats, &device, start..=end).await,
+        ),
         api::Request::ListDevices => api::Response::ListDevices(logs.list_devices().await),
+        api::Request::GetErrorSummary { top } => {
+            api::Response::GetErrorSummary(logs.summary(top).await)
+        }
+        api::Request::Snapshot => {
+            let snapshot = affectors.while_paused(logs.snapshot(log_dir, snapshot_dir));
+            api::Response::Snapshot(snapshot.await)
+        }
     })
 }
@@ -1,5 +1,3 @@
-use std::net::SocketAddr;
-use std::path::Path;
 use std::time::{Duration, Instant};
 
 use data_server::api::ReconnectingClient;
@@ -7,21 +5,25 @@ use data_server::api::ReconnectingClient;
 use color_eyre::Result;
 use data_server::api::SubMessage;
 
+mod affectors;
+pub(crate) use affectors::Affectors;
+
+pub(crate) mod availability;
+
 mod log;
-pub(crate) use log::Logs;
+pub(crate) use log::{CurrentError, Log, Logs};
 
 mod stats;
 pub(crate) use stats::Stats;
 use tracing::debug;
 
 pub(crate) async fn run(
-    data_server_addr: SocketAddr,
+    data_server: ReconnectingClient,
     stats: Stats,
     logs: Logs,
-    log_dir: &Path,
+    affectors: Affectors,
 ) -> Result<()> {
-    let mut sub =
-        ReconnectingClient::new(data_server_addr, "ha-log-store".to_string()).subscribe();
+    let mut sub = data_server.subscribe();
 
     let mut recently_logged = (Instant::now(), String::new());
     loop {
@@ -29,14 +31,18 @@ pub(crate) async fn run(
         debug!("Got msg from data-server: {msg:?}");
         let res = match msg {
             SubMessage::Reading(reading) => {
-                if let Err(e) = stats.increment(reading.device()).await {
+                if let Err(e) = stats.increment(&reading).await {
                     Err(e)
                 } else {
                     logs.clear_err(reading.device()).await
                 }
             }
-            SubMessage::ErrorReport(report) => logs.set_err(*report, log_dir).await,
-            SubMessage::AffectorControlled { .. } => continue,
+            SubMessage::ErrorReport(report) => logs.set_err(*report).await,
+            SubMessage::NodeDisconnected { devices } => logs.node_disconnected(&devices).await,
+            SubMessage::AffectorControlled {
+                affector,
+                controlled_by,
+            } => affectors.record(affector, controlled_by).await,
         };
 
         const FIVE_MIN: Duration = Duration::from_secs(60 * 5);
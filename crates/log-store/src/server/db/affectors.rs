@@ -0,0 +1,289 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use byteseries::ByteSeries;
+use color_eyre::eyre::Context;
+use color_eyre::{Result, Section};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use super::log::{encode_line, open_series, read_page, ApiResult};
+use crate::api::{self, AffectorPage, Cursor};
+
+/// Longer client names are truncated
+const MAX_NAME: usize = 64;
+/// Bincode uses four bytes for an enum tag where postcard uses one. A string
+/// is prefixed by its length as an u64. Lines that are longer anyway are
+/// refused, a test checks every affector fits.
+const PAYLOAD_SIZE: usize = 4 * protocol::Affector::max_size() + 8 + MAX_NAME;
+
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+struct AffectorLog {
+    #[derivative(Debug = "ignore")]
+    history: ByteSeries,
+}
+
+impl AffectorLog {
+    /// Returns None if there is no log for this affector in `dir` and
+    /// `create_missing` is false
+    #[instrument]
+    fn open(
+        dir: &Path,
+        affector: &protocol::Affector,
+        create_missing: bool,
+    ) -> Result<Option<Self>> {
+        let path = dir.join(base_path(affector));
+        let header = format!(
+            "Bincode encoded log of who controlled {affector:?} and how. \
+            Each line has a size: {PAYLOAD_SIZE} + 2"
+        );
+        Ok(open_series(&path, PAYLOAD_SIZE, header, create_missing)
+            .wrap_err("Could not open affector log")
+            .with_note(|| format!("affector: {affector:?}"))?
+            .map(|history| Self { history }))
+    }
+
+    fn push(
+        &mut self,
+        at: jiff::Timestamp,
+        affector: protocol::Affector,
+        mut controlled_by: String,
+    ) -> Result<()> {
+        if controlled_by.len() > MAX_NAME {
+            let end = (0..=MAX_NAME)
+                .rev()
+                .find(|i| controlled_by.is_char_boundary(*i))
+                .expect("0 is always a char boundary");
+            controlled_by.truncate(end);
+        }
+
+        let line = StoredAffectorEvent {
+            affector,
+            controlled_by,
+        };
+        let line = encode_line(&line, PAYLOAD_SIZE).wrap_err("Could not encode AffectorEvent")?;
+        self.history
+            .push_line(at.as_second() as u64, line)
+            .wrap_err("Could not push new AffectorEvent into history")
+    }
+
+    fn get(
+        &mut self,
+        range: RangeInclusive<jiff::Timestamp>,
+        limit: usize,
+        cursor: Option<Cursor>,
+    ) -> Result<ApiResult<AffectorPage>> {
+        let page = read_page(&mut self.history, &mut Decoder, range, limit, cursor)?;
+        let (lines, next) = match page {
+            Ok(page) => page,
+            Err(e) => return Ok(Err(e)),
+        };
+        let events = lines
+            .into_iter()
+            .map(|(at, event)| {
+                let event = event.wrap_err("Could not decode stored AffectorEvent")?;
+                Ok(api::AffectorEvent {
+                    at: jiff::Timestamp::from_second(at as i64)
+                        .expect("was a jiff::Timestamp before it became a u64"),
+                    affector: event.affector,
+                    controlled_by: event.controlled_by,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Ok(AffectorPage { events, next }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredAffectorEvent {
+    affector: protocol::Affector,
+    controlled_by: String,
+}
+
+#[derive(Debug)]
+struct Decoder;
+impl byteseries::Decoder for Decoder {
+    type Item = bincode::Result<StoredAffectorEvent>;
+
+    fn decode_payload(&mut self, payload: &[u8]) -> Self::Item {
+        bincode::deserialize(payload)
+    }
+}
+
+/// Who controlled which affector when, one log per kind of affector
+#[derive(Debug, Clone)]
+pub(crate) struct Affectors {
+    dir: PathBuf,
+    logs: Arc<Mutex<HashMap<PathBuf, AffectorLog>>>,
+}
+
+impl Affectors {
+    pub(crate) fn new(log_dir: &Path) -> Self {
+        Self {
+            dir: log_dir.to_path_buf(),
+            logs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Runs `task` while no events are recorded, they wait until it is done
+    pub(crate) async fn while_paused<T>(&self, task: impl Future<Output = T>) -> T {
+        let _logs = self.logs.lock().await;
+        task.await
+    }
+
+    pub(crate) async fn record(
+        &self,
+        affector: protocol::Affector,
+        controlled_by: String,
+    ) -> Result<()> {
+        let mut logs = self.logs.lock().await;
+        let log = match logs.entry(base_path(&affector)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let log = AffectorLog::open(&self.dir, &affector, true)?
+                    .expect("missing logs are created");
+                entry.insert(log)
+            }
+        };
+        log.push(jiff::Timestamp::now(), affector, controlled_by)
+    }
+
+    pub(crate) async fn get(
+        &self,
+        affector: &protocol::Affector,
+        range: RangeInclusive<jiff::Timestamp>,
+        limit: usize,
+        cursor: Option<Cursor>,
+    ) -> ApiResult<AffectorPage> {
+        let mut logs = self.logs.lock().await;
+        let path = base_path(affector);
+        if !logs.contains_key(&path) {
+            match AffectorLog::open(&self.dir, affector, false) {
+                Ok(Some(log)) => {
+                    logs.insert(path.clone(), log);
+                }
+                Ok(None) => {
+                    return Ok(AffectorPage {
+                        events: Vec::new(),
+                        next: None,
+                    })
+                }
+                Err(report) => {
+                    return Err(api::GetLogError::InternalError(format!("{report:#}")))
+                }
+            }
+        }
+
+        let log = logs.get_mut(&path).expect("just inserted if missing");
+        match log.get(range, limit, cursor) {
+            Err(report) => Err(api::GetLogError::InternalError(format!("{report:#}"))),
+            Ok(res) => res,
+        }
+    }
+}
+
+/// relative path without extension, for example:
+/// `largebedroom/bed/affectors/rgbled`. The same for every value of an
+/// affector.
+fn base_path(affector: &protocol::Affector) -> PathBuf {
+    use protocol::affector::tree::{Item, Tree};
+
+    let mut parts = Vec::new();
+    let mut current = affector as &dyn Tree;
+    while let Item::Node(inner) = current.inner() {
+        parts.push(current.name().to_lowercase());
+        current = inner;
+    }
+    // the name of affectors with fields includes them: `RgbLed { red: .. }`
+    let name = current.name();
+    let name = name.split_whitespace().next().unwrap_or_default();
+    parts.push("affectors".to_string());
+    parts.push(name.to_lowercase());
+    parts.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use protocol::large_bedroom::{self, bed};
+
+    use super::*;
+
+    fn led(red: u8) -> protocol::Affector {
+        protocol::Affector::LargeBedroom(large_bedroom::Affector::Bed(bed::Affector::RgbLed {
+            red,
+            green: 0,
+            blue: 0,
+        }))
+    }
+
+    #[test]
+    fn path_ignores_value() {
+        assert_eq!(base_path(&led(1)), base_path(&led(2)));
+        assert_eq!(
+            base_path(&led(1)),
+            PathBuf::from("largebedroom/bed/affectors/rgbled")
+        );
+    }
+
+    #[test]
+    fn every_affector_fits() {
+        // the led is not part of a device
+        let affectors = protocol::Device::all()
+            .into_iter()
+            .flat_map(|device| device.info().affectors)
+            .cloned()
+            .chain([led(u8::MAX)]);
+        for affector in affectors {
+            let line = StoredAffectorEvent {
+                affector: affector.clone(),
+                controlled_by: "x".repeat(MAX_NAME),
+            };
+            let size = bincode::serialized_size(&line).unwrap();
+            assert!(
+                size <= PAYLOAD_SIZE as u64,
+                "{affector:?} needs {size} bytes"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_stored_per_kind() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let affectors = Affectors::new(dir.path());
+        let calib = protocol::Affector::LargeBedroom(large_bedroom::Affector::Bed(
+            bed::Affector::MhzZeroPointCalib,
+        ));
+
+        affectors.record(led(1), "brain".to_string()).await.unwrap();
+        affectors.record(calib, "sensor-tui".to_string()).await.unwrap();
+        affectors.record(led(2), "x".repeat(100)).await.unwrap();
+
+        let everything = jiff::Timestamp::MIN..=jiff::Timestamp::MAX;
+        let page = affectors
+            .get(&led(0), everything.clone(), 10, None)
+            .await
+            .unwrap();
+        assert!(page.next.is_none());
+        let events: Vec<_> = page
+            .events
+            .into_iter()
+            .map(|event| (event.affector, event.controlled_by))
+            .collect();
+        assert_eq!(
+            events,
+            [(led(1), "brain".to_string()), (led(2), "x".repeat(MAX_NAME))]
+        );
+
+        // reopened from disk
+        let affectors = Affectors::new(dir.path());
+        let page = affectors.get(&calib, everything, 10, None).await.unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].controlled_by, "sensor-tui");
+    }
+}
@@ -0,0 +1,264 @@
+//! How much of a time range a device was healthy. Time in error comes from
+//! the error log. Silence is estimated from the gaps between the cycles of
+//! the device, those are only known per hour.
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::api::{self, Outage};
+
+use super::log::MAX_PAGE;
+use super::stats::WINDOW;
+use super::{Logs, Stats};
+
+/// Gaps between cycles up to this many times the max sample interval of the
+/// device are normal
+const ALLOWED_GAP_FACTOR: u32 = 2;
+/// Outages listed in the report
+const LONGEST: usize = 5;
+
+pub(crate) async fn report(
+    logs: &Logs,
+    stats: &Stats,
+    device: &protocol::Device,
+    range: RangeInclusive<jiff::Timestamp>,
+) -> Result<api::Availability, api::AvailabilityError> {
+    let now = jiff::Timestamp::now();
+    let start = *range.start();
+    let end = (*range.end()).min(now);
+    if end <= start {
+        return Err(api::AvailabilityError::InvalidRange);
+    }
+
+    // includes the error that was ongoing at the start, however long ago it
+    // started
+    let mut events = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = logs
+            .get(device, start..=end, MAX_PAGE, cursor)
+            .await
+            .map_err(|e| api::AvailabilityError::InternalError(e.to_string()))?;
+        events.extend(page.events);
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    let allowed = device.info().max_sample_interval * ALLOWED_GAP_FACTOR;
+    let gaps = stats
+        .gaps(device, start..=end, allowed)
+        .await
+        .map_err(|report| api::AvailabilityError::InternalError(format!("{report:#}")))?;
+    Ok(summarize(start..=end, &events, &gaps, allowed, now))
+}
+
+struct ErrorOutage {
+    start: jiff::Timestamp,
+    end: jiff::Timestamp,
+    episodes: u32,
+    /// A gap between cycles was attributed to this outage
+    explains_gap: bool,
+}
+
+impl ErrorOutage {
+    fn duration_within(&self, range: &RangeInclusive<jiff::Timestamp>) -> Duration {
+        let start = self.start.max(*range.start());
+        let end = self.end.min(*range.end());
+        between(start, end)
+    }
+}
+
+fn between(start: jiff::Timestamp, end: jiff::Timestamp) -> Duration {
+    let ms = end.as_millisecond() - start.as_millisecond();
+    Duration::from_millis(ms.max(0) as u64)
+}
+
+/// Merges episodes that follow each other into one outage. An error that is
+/// still ongoing ends `now`.
+fn error_outages(events: &[api::ErrorEvent], now: jiff::Timestamp) -> Vec<ErrorOutage> {
+    // the history stores starts in whole seconds
+    const TOLERANCE: i64 = 1000;
+
+    let mut outages: Vec<ErrorOutage> = Vec::new();
+    for event in events {
+        let end = event.end.unwrap_or(now);
+        let follows = |last: &&mut ErrorOutage| {
+            event.start.as_millisecond() - last.end.as_millisecond() <= TOLERANCE
+        };
+        if let Some(last) = outages.last_mut().filter(follows) {
+            last.end = last.end.max(end);
+            last.episodes += event.episodes;
+        } else {
+            outages.push(ErrorOutage {
+                start: event.start,
+                end,
+                episodes: event.episodes,
+                explains_gap: false,
+            });
+        }
+    }
+    outages
+}
+
+/// A device in error sends no readings. The gap in its cycles that ends in
+/// the hour its error ended is, up to the length of the error, not counted
+/// as silence.
+fn summarize(
+    range: RangeInclusive<jiff::Timestamp>,
+    events: &[api::ErrorEvent],
+    gaps: &[(jiff::Timestamp, Duration)],
+    allowed: Duration,
+    now: jiff::Timestamp,
+) -> api::Availability {
+    let total = between(*range.start(), *range.end());
+    let mut errors = error_outages(events, now);
+    errors.retain(|outage| !outage.duration_within(&range).is_zero());
+    let in_error: Duration = errors
+        .iter()
+        .map(|outage| outage.duration_within(&range))
+        .sum();
+
+    let mut silences = Vec::new();
+    for (window, gap) in gaps {
+        let window_end = window.as_second() + WINDOW;
+        let mut silent = gap.saturating_sub(allowed);
+        if let Some(error) = errors.iter_mut().find(|error| {
+            !error.explains_gap && error.end >= *window && error.end.as_second() < window_end
+        }) {
+            error.explains_gap = true;
+            silent = silent.saturating_sub(between(error.start, error.end));
+        }
+        if !silent.is_zero() {
+            silences.push(Outage::Silent {
+                resumed_in: *window,
+                duration: silent,
+            });
+        }
+    }
+    let silent = silences
+        .iter()
+        .map(Outage::duration)
+        .sum::<Duration>()
+        .min(total.saturating_sub(in_error));
+    let healthy = total.saturating_sub(in_error).saturating_sub(silent);
+
+    let mut outages: Vec<_> = errors
+        .iter()
+        .map(|outage| Outage::Error {
+            start: outage.start.max(*range.start()),
+            end: outage.end.min(*range.end()),
+            episodes: outage.episodes,
+        })
+        .chain(silences)
+        .collect();
+    outages.sort_by_key(|outage| std::cmp::Reverse(outage.duration()));
+    let n_outages = outages.len();
+    outages.truncate(LONGEST);
+
+    let percentage = |part: Duration| 100.0 * part.as_secs_f64() / total.as_secs_f64();
+    api::Availability {
+        healthy: percentage(healthy),
+        in_error: percentage(in_error),
+        silent: percentage(silent),
+        longest_outages: outages,
+        outages: n_outages,
+        mean_time_between_failures: (n_outages > 0).then(|| healthy / n_outages as u32),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use protocol::large_bedroom::{self, bed};
+
+    use super::*;
+    use crate::api::EndReason;
+
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+
+    fn at(second: i64) -> jiff::Timestamp {
+        jiff::Timestamp::from_second(second).unwrap()
+    }
+
+    fn event(start: i64, end: Option<i64>, reason: EndReason) -> api::ErrorEvent {
+        api::ErrorEvent {
+            start: at(start),
+            end: end.map(at),
+            end_reason: end.map(|_| reason),
+            error: protocol::Error::LargeBedroom(large_bedroom::Error::Bed(bed::Error::Setup(
+                bed::SensorError::Sht31(heapless::String::from_str("test").unwrap()),
+            ))),
+            episodes: 1,
+        }
+    }
+
+    fn assert_close(percentage: f64, expected: f64) {
+        assert!(
+            (percentage - expected).abs() < 1e-9,
+            "{percentage} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn errors_and_silence_add_up() {
+        let events = [
+            // replaced by the next, one outage
+            event(HOUR, Some(HOUR + 30 * MINUTE), EndReason::Replaced),
+            event(HOUR + 30 * MINUTE, Some(2 * HOUR), EndReason::Cleared),
+            event(9 * HOUR, None, EndReason::Cleared),
+        ];
+        let allowed = Duration::from_secs(60);
+        let gaps = [
+            // caused by the first error, one minute of extra silence
+            (at(2 * HOUR), Duration::from_secs(62 * 60)),
+            (at(5 * HOUR), Duration::from_secs(31 * 60)),
+        ];
+
+        let report = summarize(
+            at(0)..=at(10 * HOUR),
+            &events,
+            &gaps,
+            allowed,
+            at(10 * HOUR),
+        );
+        assert_close(report.in_error, 20.0);
+        assert_close(report.silent, 31.0 / 600.0 * 100.0);
+        assert_close(report.healthy + report.in_error + report.silent, 100.0);
+        assert_eq!(report.outages, 4);
+        assert!(matches!(
+            report.longest_outages[0],
+            Outage::Error { episodes: 2, .. }
+        ));
+        assert_eq!(
+            report.longest_outages[2].duration(),
+            Duration::from_secs(30 * 60)
+        );
+        assert_eq!(
+            report.mean_time_between_failures,
+            Some(Duration::from_secs((10 * HOUR - 2 * HOUR - 31 * MINUTE) as u64) / 4)
+        );
+    }
+
+    #[test]
+    fn ongoing_error_before_range_is_clipped() {
+        let events = [event(0, None, EndReason::Cleared)];
+        let report = summarize(
+            at(HOUR)..=at(2 * HOUR),
+            &events,
+            &[],
+            Duration::from_secs(60),
+            at(2 * HOUR),
+        );
+        assert_close(report.in_error, 100.0);
+        assert_close(report.healthy, 0.0);
+        match report.longest_outages[..] {
+            [Outage::Error { start, end, .. }] => {
+                assert_eq!((start, end), (at(HOUR), at(2 * HOUR)));
+            }
+            ref other => panic!("expected one error outage, got: {other:?}"),
+        }
+    }
+}
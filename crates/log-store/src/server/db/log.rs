@@ -1,32 +1,47 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::iter;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use byteseries::file::OpenError as FileOpenError;
 use byteseries::{series, ByteSeries};
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{eyre, Context};
 use color_eyre::{Result, Section};
 use protocol::Device;
 use serde::{Deserialize, Serialize};
 use series::data::OpenError as DataOpenError;
 use series::Error::Open;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use tracing::{debug, info, instrument, warn};
 
-use crate::api::{self, ErrorEvent};
+use crate::api::{self, Cursor, EndReason, ErrorEpisode, LogPage};
+
+mod flapping;
+mod occurrences;
+
+/// Max number of events in one page of the log
+pub(super) const MAX_PAGE: usize = 1000;
+/// Logs created before the line size was derived from the bincode encoding
+/// used the postcard bound. The longest errors do not fit in their lines.
+const LEGACY_PAYLOAD_SIZE: usize = protocol::Error::max_size();
 
 #[derive(derivative::Derivative)]
 #[derivative(Debug)]
 pub(crate) struct Log {
     #[derivative(Debug = "ignore")]
     history: ByteSeries,
+    /// Every line is padded to this size
+    payload_size: usize,
     current: CurrentError,
+    flapping: flapping::Flapping,
+    occurrences: occurrences::Occurrences,
 }
 
 #[derive(Debug)]
-struct CurrentError {
+pub(crate) struct CurrentError {
     file: std::fs::File,
     value: Option<(jiff::Timestamp, protocol::Error)>,
 }
@@ -67,6 +82,23 @@ impl CurrentError {
         Ok(Self { file, value })
     }
 
+    /// Errors if the current error stored for `device` in `dir` can not be
+    /// read. Unlike opening it this does not reset a file that is damaged.
+    pub(crate) fn check(dir: &Path, device: &Device) -> Result<()> {
+        let path = dir.join(base_path(device)).with_extension("current_error");
+        let buf = match std::fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).wrap_err("Could not read current error"),
+        };
+        if !buf.is_empty() {
+            bincode::deserialize::<(jiff::Timestamp, protocol::Error)>(&buf)
+                .wrap_err("Current error could not be deserialized")
+                .with_note(|| format!("path: {}", path.display()))?;
+        }
+        Ok(())
+    }
+
     fn take(&mut self) -> Result<Option<(jiff::Timestamp, protocol::Error)>> {
         self.file
             .set_len(0)
@@ -97,230 +129,836 @@ impl CurrentError {
 }
 
 impl Log {
-    #[instrument]
     pub fn open_or_create(dir: &Path, device: &Device) -> Result<Self> {
+        Ok(Self::open(dir, device, true)?.expect("missing logs are created"))
+    }
+
+    /// Returns None if there is no log for this device in `dir`
+    pub fn open_existing(dir: &Path, device: &Device) -> Result<Option<Self>> {
+        Self::open(dir, device, false)
+    }
+
+    #[instrument]
+    fn open(dir: &Path, device: &Device, create_missing: bool) -> Result<Option<Self>> {
         let path = base_path(device);
         let path = dir.join(path);
 
-        let payload_size = protocol::Error::max_size();
-        let header = format!(
-            "Bincode encoded error logs for {device:?}. \
-            Each line has a size: {payload_size} + 2"
-        );
+        let header = |payload_size: usize| {
+            format!(
+                "Bincode encoded error logs for {device:?}. \
+                Each line has a size: {payload_size} + 2"
+            )
+        };
 
-        let res = ByteSeries::builder()
-            .payload_size(payload_size)
-            .with_header(header.clone())
-            .open(&path);
-
-        let history = match res {
-            Ok((byteseries, _)) => byteseries,
-            Err(Open(DataOpenError::File(FileOpenError::Io(e))))
-                if e.kind() == io::ErrorKind::NotFound =>
-            {
-                if let Some(dirs) = path.parent() {
-                    std::fs::create_dir_all(dirs)
-                        .wrap_err("Could not create dirs structure for reading")
-                        .with_note(|| format!("dirs: {}", dirs.display()))?;
-                }
-                info!("creating new byteseries");
-                ByteSeries::builder()
-                    .payload_size(payload_size)
-                    .with_header(header)
-                    .create_new(true)
-                    .open(&path)
-                    .wrap_err("Could not create new byteseries")
-                    .with_note(|| format!("path: {}", path.display()))?
-                    .0
-            }
-            Err(e) => {
-                return Err(e)
-                    .wrap_err("Could not open existing byteseries")
-                    .with_note(|| format!("path: {}", path.display()))
+        let payload_size = payload_size();
+        let opened = open_series(&path, payload_size, header(payload_size), create_missing);
+        let opened = match opened {
+            Ok(opened) => opened.map(|history| (history, payload_size)),
+            Err(report) => {
+                let legacy = header(LEGACY_PAYLOAD_SIZE);
+                let Ok(Some(history)) = open_series(&path, LEGACY_PAYLOAD_SIZE, legacy, false)
+                else {
+                    return Err(report);
+                };
+                warn!(
+                    "Log for {device:?} uses lines of {LEGACY_PAYLOAD_SIZE} bytes, errors \
+                    that do not fit can not be stored. Move the log away to start a new one."
+                );
+                Some((history, LEGACY_PAYLOAD_SIZE))
             }
         };
+        let Some((history, payload_size)) = opened else {
+            return Ok(None);
+        };
 
-        Ok(Self {
+        Ok(Some(Self {
             history,
+            payload_size,
             current: CurrentError::open_or_create(&path)
                 .wrap_err("Could not setup current error store")
                 .with_note(|| format!("device: {device:?}"))
                 .with_note(|| format!("path: {}", path.display()))?,
-        })
+            flapping: flapping::Flapping::default(),
+            occurrences: occurrences::Occurrences::default(),
+        }))
     }
 
+    /// Returns None if this error is already the current error
     #[instrument]
-    pub fn set_err(&mut self, new_report: protocol::Error) -> Result<()> {
-        if let Some((started, report)) = self.current.get() {
+    pub fn set_err(&mut self, new_report: protocol::Error) -> Result<Option<ErrorEpisode>> {
+        let now = jiff::Timestamp::now();
+        self.occurrences.record(&new_report, now);
+        if let Some((_, report)) = self.current.get() {
             if report == &new_report {
-                return Ok(());
+                return Ok(None);
             }
-            let line = StoredErrorEvent {
-                end: jiff::Timestamp::now(),
-                error: report.clone(),
-            };
-            let line = bincode::serialize(&line).wrap_err("Could not serialize ErrorEvent")?;
-            let payload_size = protocol::Error::max_size();
-            let line: Vec<_> = line
-                .into_iter()
-                .chain(iter::repeat(0))
-                .take(payload_size)
-                .collect();
-
-            self.history
-                .push_line(started.as_second() as u64, line)
-                .wrap_err("Could not push new ErrorEvent into history")?;
         }
+        let previous = self.end_current(EndReason::Replaced)?;
+        self.flapping.episode_started(now);
 
         debug!("Registered new error: {new_report}");
         self.current
             .set(new_report)
-            .wrap_err("Failed to set new error in current error store")
+            .wrap_err("Failed to set new error in current error store")?;
+        let current = self.current_event().expect("just set");
+        Ok(Some(match previous {
+            Some(previous) => ErrorEpisode::Changed { previous, current },
+            None => ErrorEpisode::Started(current),
+        }))
     }
 
+    fn current_event(&self) -> Option<api::ErrorEvent> {
+        self.current
+            .get()
+            .as_ref()
+            .map(|(start, error)| api::ErrorEvent {
+                start: *start,
+                end: None,
+                end_reason: None,
+                error: error.clone(),
+                episodes: 1,
+            })
+    }
+
+    /// Moves the current error, if any, to the history. It is only removed
+    /// from the current error store once it is in the history. Returns the
+    /// error that ended.
     #[instrument]
-    fn clear(&mut self) -> Result<()> {
+    fn end_current(&mut self, reason: EndReason) -> Result<Option<api::ErrorEvent>> {
+        let Some((started, report)) = self.current.get().clone() else {
+            return Ok(None);
+        };
+
+        let end = jiff::Timestamp::now();
+        self.push(
+            started,
+            StoredErrorEvent {
+                end,
+                error: report.clone(),
+                reason,
+                episodes: 1,
+            },
+        )?;
         self.current
             .take()
             .wrap_err("failed to set value of current error to None")?;
-        debug!("Cleared any error");
-        Ok(())
+        debug!("Ended current error, reason: {reason:?}");
+        Ok(Some(api::ErrorEvent {
+            start: started,
+            end: Some(end),
+            end_reason: Some(reason),
+            error: report,
+            episodes: 1,
+        }))
     }
 
-    fn get_all(&mut self) -> Result<ApiResult<Vec<ErrorEvent>>> {
-        let mut timestamps = Vec::new();
-        let mut data = Vec::new();
-        let n_lines = self.history.n_lines_between(..).wrap_err(
-            "Could not check if there is not too much data \
-            between the requested points",
-        )?;
-        if n_lines > 200 {
-            return Ok(Err(api::GetLogError::TooMuchData {
-                max: 200,
-                found: n_lines,
-            }));
+    fn push(&mut self, started: jiff::Timestamp, line: StoredErrorEvent) -> Result<()> {
+        let line =
+            encode_line(&line, self.payload_size).wrap_err("Could not encode ErrorEvent")?;
+        self.history
+            .push_line(started.as_second() as u64, line)
+            .wrap_err("Could not push new ErrorEvent into history")
+    }
+
+    /// A page of the events that overlap `range`, see
+    /// [`api::Request::GetLog`]
+    fn get(
+        &mut self,
+        range: RangeInclusive<jiff::Timestamp>,
+        limit: usize,
+        cursor: Option<Cursor>,
+    ) -> Result<ApiResult<LogPage>> {
+        // the first page starts with the event that was going on at the
+        // start of the range, if any
+        let mut earlier = Vec::new();
+        if cursor.is_none() && (1..=MAX_PAGE).contains(&limit) {
+            earlier = self.ongoing_at(*range.start())?;
         }
 
-        let current = self
-            .current
-            .value
-            .as_ref()
-            .map(|(ts, event)| api::ErrorEvent {
-                start: *ts,
-                end: None,
-                error: event.clone(),
+        let (lines, mut next) = if earlier.len() >= limit {
+            earlier.truncate(limit);
+            let start = range.start().as_second().max(0) as u64;
+            (Vec::new(), Some(Cursor { start, skip: 0 }))
+        } else {
+            let limit = limit - earlier.len();
+            match read_page(&mut self.history, &mut Decoder, range.clone(), limit, cursor)? {
+                Ok(page) => page,
+                Err(e) => return Ok(Err(e)),
+            }
+        };
+        let mut events = earlier
+            .into_iter()
+            .chain(lines)
+            .map(|(start, line)| {
+                let line = line.wrap_err("Could not decode stored ErrorEvent")?;
+                Ok(api::ErrorEvent {
+                    start: jiff::Timestamp::from_second(start as i64)
+                        .expect("was a jiff::Timestamp before it became a u64"),
+                    end: Some(line.end),
+                    end_reason: Some(line.reason),
+                    error: line.error,
+                    episodes: line.episodes.max(1),
+                })
             })
-            .into_iter();
+            .collect::<Result<Vec<_>>>()?;
 
-        if n_lines == 0 {
-            return Ok(Ok(current.collect()));
+        let current = self
+            .current_event()
+            .filter(|current| current.start <= *range.end());
+        if let (None, Some(current)) = (next, current) {
+            if events.len() < limit {
+                events.push(current);
+            } else {
+                // the history is done, the next page only has the current error
+                next = Some(Cursor {
+                    start: range.end().as_second().max(0) as u64 + 1,
+                    skip: 0,
+                });
+            }
         }
 
-        self.history
-            .read_all(.., &mut Decoder, &mut timestamps, &mut data)
-            .wrap_err("Could not read log events from disk")?;
+        Ok(Ok(LogPage { events, next }))
+    }
 
-        let mut res: Vec<_> = timestamps
-            .into_iter()
-            .zip(data)
-            .map(|(start, StoredErrorEvent { end, error })| api::ErrorEvent {
-                start: jiff::Timestamp::from_second(start as i64)
-                    .expect("was a jiff::Timestamp before it became a u64"),
-                end: Some(end),
-                error,
-            })
-            .collect();
-        res.extend(current);
+    /// The events in the history that started before `at` and ended at or
+    /// after it. A device has one error at a time, only the last events
+    /// before `at` can still be going on.
+    fn ongoing_at(
+        &mut self,
+        at: jiff::Timestamp,
+    ) -> Result<Vec<(u64, bincode::Result<StoredErrorEvent>)>> {
+        let Some(before) = (at.as_second().max(0) as u64).checked_sub(1) else {
+            return Ok(Vec::new());
+        };
+        let Some(last) = last_line_start(&mut self.history, before)? else {
+            return Ok(Vec::new());
+        };
+        let mut lines = read_lines(&mut self.history, &mut Decoder, last..=last)?;
+        // lines that can not be decoded are kept to report the error
+        lines.retain(|(_, line)| line.as_ref().map_or(true, |line| line.end >= at));
+        Ok(lines)
+    }
+}
+
+/// Returns None if the series does not exist and `create_missing` is false
+pub(super) fn open_series(
+    path: &Path,
+    payload_size: usize,
+    header: String,
+    create_missing: bool,
+) -> Result<Option<ByteSeries>> {
+    let res = ByteSeries::builder()
+        .payload_size(payload_size)
+        .with_header(header.clone())
+        .open(path);
+
+    match res {
+        Ok((byteseries, stored)) if stored == header => Ok(Some(byteseries)),
+        Ok((_, stored)) => Err(eyre!("Existing byteseries has a different header"))
+            .with_note(|| format!("path: {}", path.display()))
+            .with_note(|| format!("expected: {header}"))
+            .with_note(|| format!("found: {stored}")),
+        Err(Open(DataOpenError::File(FileOpenError::Io(e))))
+            if e.kind() == io::ErrorKind::NotFound =>
+        {
+            if !create_missing {
+                return Ok(None);
+            }
+            if let Some(dirs) = path.parent() {
+                std::fs::create_dir_all(dirs)
+                    .wrap_err("Could not create dirs structure for reading")
+                    .with_note(|| format!("dirs: {}", dirs.display()))?;
+            }
+            info!("creating new byteseries");
+            let (byteseries, _) = ByteSeries::builder()
+                .payload_size(payload_size)
+                .with_header(header)
+                .create_new(true)
+                .open(path)
+                .wrap_err("Could not create new byteseries")
+                .with_note(|| format!("path: {}", path.display()))?;
+            Ok(Some(byteseries))
+        }
+        Err(e) => Err(e)
+            .wrap_err("Could not open existing byteseries")
+            .with_note(|| format!("path: {}", path.display())),
+    }
+}
+
+/// At most `limit` lines with a timestamp in `range`, continuing from
+/// `cursor`. Also returns where the next page starts, None if there are no
+/// more lines in `range`.
+#[allow(clippy::type_complexity)]
+pub(super) fn read_page<D: byteseries::Decoder>(
+    series: &mut ByteSeries,
+    decoder: &mut D,
+    range: RangeInclusive<jiff::Timestamp>,
+    limit: usize,
+    cursor: Option<Cursor>,
+) -> Result<ApiResult<(Vec<(u64, D::Item)>, Option<Cursor>)>> {
+    if limit == 0 || limit > MAX_PAGE {
+        return Ok(Err(api::GetLogError::InvalidLimit {
+            requested: limit,
+            max: MAX_PAGE,
+        }));
+    }
+
+    let end = range.end().as_second().max(0) as u64;
+    let (from, skip) = match cursor {
+        Some(Cursor { start, skip }) => (start, skip),
+        None => (range.start().as_second().max(0) as u64, 0),
+    };
+    if from > end {
+        return Ok(Ok((Vec::new(), None)));
+    }
 
-        Ok(Ok(res))
+    // one more then the limit to find out if there is a next page
+    let needed = (skip + limit + 1) as u64;
+    let last = page_end(series, from, end, needed)?;
+    let mut lines: Vec<_> = read_lines(series, decoder, from..=last)?
+        .into_iter()
+        .skip(skip)
+        .collect();
+
+    let mut next = None;
+    if let Some((next_start, _)) = lines.get(limit) {
+        let mut next_skip = lines[..limit]
+            .iter()
+            .filter(|(start, _)| start == next_start)
+            .count();
+        if *next_start == from {
+            next_skip += skip;
+        }
+        next = Some(Cursor {
+            start: *next_start,
+            skip: next_skip,
+        });
     }
+    lines.truncate(limit);
+    Ok(Ok((lines, next)))
+}
+
+/// The smallest `last` in `from..=end` for which there are at least
+/// `needed` lines in `from..=last`. That is `end` if there are fewer.
+fn page_end(series: &mut ByteSeries, from: u64, end: u64, needed: u64) -> Result<u64> {
+    let (mut low, mut high) = (from, end);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let lines = series
+            .n_lines_between(from..=mid)
+            .wrap_err("Could not count log events in range")?;
+        if lines >= needed {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(high)
+}
+
+/// The timestamp of the last line at or before `before`, None if there is
+/// no such line
+fn last_line_start(series: &mut ByteSeries, before: u64) -> Result<Option<u64>> {
+    let mut count = |range: RangeInclusive<u64>| {
+        series
+            .n_lines_between(range)
+            .wrap_err("Could not count log events in range")
+    };
+    if count(0..=before)? == 0 {
+        return Ok(None);
+    }
+
+    // there is a line in `low..=before`
+    let (mut low, mut high) = (0, before);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if count(mid..=before)? > 0 {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Ok(Some(low))
+}
+
+fn read_lines<D: byteseries::Decoder>(
+    series: &mut ByteSeries,
+    decoder: &mut D,
+    range: RangeInclusive<u64>,
+) -> Result<Vec<(u64, D::Item)>> {
+    let n_lines = series
+        .n_lines_between(range.clone())
+        .wrap_err("Could not count log events in range")?;
+    if n_lines == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut timestamps = Vec::new();
+    let mut data = Vec::new();
+    series
+        .read_all(range, decoder, &mut timestamps, &mut data)
+        .wrap_err("Could not read log events from disk")?;
+    Ok(timestamps.into_iter().zip(data).collect())
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct StoredErrorEvent {
     end: jiff::Timestamp,
     error: protocol::Error,
+    /// Lines are padded with zeros. Lines from before this field existed
+    /// therefore decode as the first variant: `Replaced`. That is correct as
+    /// only replaced errors were stored back then.
+    reason: EndReason,
+    /// Number of episodes this line summarises. Every line is written as one
+    /// episode, lines from older versions may summarise a flapping device.
+    /// Lines from before this field decode as zero, they are one episode.
+    episodes: u32,
+}
+
+/// The line with the longest bincode encoding. Bincode uses four bytes for
+/// an enum tag and eight for the length of a string. All errors carrying a
+/// message are the longest. A negative year and nanoseconds make for the
+/// longest timestamp.
+fn longest_line() -> StoredErrorEvent {
+    use protocol::large_bedroom::{self, bed};
+
+    let message = iter::repeat('x').take(200).collect();
+    let error = bed::Error::Running(bed::SensorError::Sht31(message));
+    StoredErrorEvent {
+        end: jiff::Timestamp::new(jiff::Timestamp::MIN.as_second() + 1, -1)
+            .expect("just after the minimum"),
+        error: protocol::Error::LargeBedroom(large_bedroom::Error::Bed(error)),
+        reason: EndReason::Replaced,
+        episodes: u32::MAX,
+    }
+}
+
+/// Every line in the log is padded to this size
+fn payload_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(|| {
+        bincode::serialized_size(&longest_line()).expect("the longest line serializes") as usize
+    })
+}
+
+/// Serializes `line` padded with zeros to `payload_size`. Returns an error
+/// if it does not fit.
+pub(super) fn encode_line(line: &impl Serialize, payload_size: usize) -> Result<Vec<u8>> {
+    let mut line = bincode::serialize(line).wrap_err("Could not serialize line")?;
+    if line.len() > payload_size {
+        return Err(eyre!("Line is longer then the lines in the log"))
+            .with_note(|| format!("line: {} bytes, log lines: {payload_size} bytes", line.len()));
+    }
+    line.resize(payload_size, 0);
+    Ok(line)
 }
 
 #[derive(Debug)]
 struct Decoder;
 impl byteseries::Decoder for Decoder {
-    type Item = StoredErrorEvent;
+    type Item = bincode::Result<StoredErrorEvent>;
 
     fn decode_payload(&mut self, payload: &[u8]) -> Self::Item {
-        bincode::deserialize(payload).expect("if its successfully serialized it should deserialize")
+        bincode::deserialize(payload)
     }
 }
 
-type ApiResult<T> = std::result::Result<T, api::GetLogError>;
+pub(super) type ApiResult<T> = std::result::Result<T, api::GetLogError>;
+
+/// Error episodes waiting for slow subscribers
+const EPISODE_BUFFER: usize = 100;
 
 #[derive(Debug, Clone)]
-pub(crate) struct Logs(pub(crate) Arc<Mutex<HashMap<protocol::Device, Log>>>);
+pub(crate) struct Logs {
+    dir: PathBuf,
+    logs: Arc<Mutex<HashMap<protocol::Device, Log>>>,
+    episodes: broadcast::Sender<ErrorEpisode>,
+}
 
 impl Logs {
-    pub async fn set_err(&self, report: protocol::Error, log_dir: &Path) -> Result<()> {
+    /// Opens the log of every device that has one in `log_dir`, errors that
+    /// were ongoing before a restart then end like any other
+    pub(crate) fn open(log_dir: &Path) -> Self {
+        let mut logs = HashMap::new();
+        for device in Device::all() {
+            match Log::open_existing(log_dir, &device) {
+                Ok(Some(log)) => {
+                    logs.insert(device, log);
+                }
+                Ok(None) => (),
+                Err(report) => warn!("Could not open error log for {device:?}: {report:?}"),
+            }
+        }
+        Self {
+            dir: log_dir.to_path_buf(),
+            logs: Arc::new(Mutex::new(logs)),
+            episodes: broadcast::channel(EPISODE_BUFFER).0,
+        }
+    }
+
+    /// The log of `device`, opened from disk if it is not open yet. None if
+    /// there is no log for the device and `create_missing` is false.
+    fn log<'a>(
+        &self,
+        map: &'a mut HashMap<protocol::Device, Log>,
+        device: &protocol::Device,
+        create_missing: bool,
+    ) -> Result<Option<&'a mut Log>> {
+        let log = match map.entry(device.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let Some(log) = Log::open(&self.dir, entry.key(), create_missing)
+                    .wrap_err("Failed to open error Log")?
+                else {
+                    return Ok(None);
+                };
+                entry.insert(log)
+            }
+        };
+        Ok(Some(log))
+    }
+
+    /// The errors that are ongoing and every episode after them
+    pub(crate) async fn subscribe(
+        &self,
+    ) -> (Vec<api::ErrorEvent>, broadcast::Receiver<ErrorEpisode>) {
+        // while we hold the lock no episodes can be send
+        let map = self.logs.lock().await;
+        let ongoing = map.values().filter_map(Log::current_event).collect();
+        (ongoing, self.episodes.subscribe())
+    }
+
+    fn publish(&self, episode: ErrorEpisode) {
+        // fine if there are no subscribers
+        let _ = self.episodes.send(episode);
+    }
+
+    pub async fn set_err(&self, report: protocol::Error) -> Result<()> {
         let device = report.device();
-        let mut map = self.0.lock().await;
-        if let Some(log) = map.get_mut(&device) {
-            log.set_err(report)?;
-        } else {
-            let mut log = Log::open_or_create(log_dir, &device)
-                .wrap_err("Failed to open or create error Log")?;
-            log.set_err(report)?;
-            map.insert(device, log);
+        let mut map = self.logs.lock().await;
+        let log = self
+            .log(&mut map, &device, true)?
+            .expect("missing logs are created");
+        if let Some(episode) = log.set_err(report)? {
+            self.publish(episode);
         }
         Ok(())
     }
 
-    pub async fn get(&self, device: &protocol::Device) -> ApiResult<Vec<api::ErrorEvent>> {
-        let mut map = self.0.lock().await;
-        if let Some(log) = map.get_mut(device) {
-            match log.get_all() {
-                Err(report) => Err(api::GetLogError::InternalError(format!("{report:#}"))),
-                Ok(res) => res,
+    pub async fn get(
+        &self,
+        device: &protocol::Device,
+        range: RangeInclusive<jiff::Timestamp>,
+        limit: usize,
+        cursor: Option<Cursor>,
+    ) -> ApiResult<LogPage> {
+        let mut map = self.logs.lock().await;
+        let log = match self.log(&mut map, device, false) {
+            Ok(Some(log)) => log,
+            Ok(None) => {
+                return Ok(LogPage {
+                    events: Vec::new(),
+                    next: None,
+                })
             }
-        } else {
-            Ok(Vec::new())
+            Err(report) => return Err(api::GetLogError::InternalError(format!("{report:#}"))),
+        };
+        match log.get(range, limit, cursor) {
+            Err(report) => Err(api::GetLogError::InternalError(format!("{report:#}"))),
+            Ok(res) => res,
         }
     }
 
     pub(crate) async fn clear_err(&self, device: protocol::Device) -> Result<()> {
-        let mut map = self.0.lock().await;
-        if let Some(log) = map.get_mut(&device) {
-            log.clear()?;
+        self.end_err(&device, EndReason::Cleared).await
+    }
+
+    /// Ends the current error of every device on a node that disconnected
+    pub(crate) async fn node_disconnected(&self, devices: &[protocol::Device]) -> Result<()> {
+        for device in devices {
+            self.end_err(device, EndReason::Disconnected).await?;
         }
         Ok(())
     }
 
+    async fn end_err(&self, device: &protocol::Device, reason: EndReason) -> Result<()> {
+        let mut map = self.logs.lock().await;
+        if let Some(log) = self.log(&mut map, device, false)? {
+            if let Some(ended) = log.end_current(reason)? {
+                self.publish(ErrorEpisode::Resolved(ended));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a tarball of the log dir to `snapshot_dir`. New errors wait
+    /// until it is done.
+    pub(crate) async fn snapshot(
+        &self,
+        log_dir: PathBuf,
+        snapshot_dir: PathBuf,
+    ) -> Result<api::Snapshot, api::SnapshotError> {
+        let map = Arc::clone(&self.logs).lock_owned().await;
+        let written = tokio::task::spawn_blocking(move || {
+            let _map = map;
+            crate::server::snapshot::write(&log_dir, &snapshot_dir)
+        })
+        .await;
+        match written {
+            Ok(written) => written
+                .map_err(|report| api::SnapshotError::InternalError(format!("{report:#}"))),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Every device that reported errors in the last day with the `top`
+    /// errors it reported most
+    pub(crate) async fn summary(&self, top: usize) -> Vec<api::DeviceErrors> {
+        let now = jiff::Timestamp::now();
+        let mut map = self.logs.lock().await;
+        map.iter_mut()
+            .map(|(device, log)| api::DeviceErrors {
+                device: device.clone(),
+                flapping: log.flapping.is_flapping(now),
+                top: log.occurrences.top(top, now),
+            })
+            .filter(|errors| !errors.top.is_empty())
+            .collect()
+    }
+
     pub(crate) async fn list_devices(&self) -> Vec<Device> {
-        let map = self.0.lock().await;
+        let map = self.logs.lock().await;
         map.iter().map(|(key, _)| key.clone()).collect()
     }
 }
 
 /// relative path without extension
-fn base_path(device: &protocol::Device) -> PathBuf {
-    use protocol::reading::tree::{Item, Tree};
-    use protocol::reading::Info;
-
-    let mut parts = Vec::new();
-    let mut current = device
-        .info()
-        .affects_readings
-        .first()
-        .expect("a device has at least one reading it affects") as &dyn Tree;
-    loop {
-        match current.inner() {
-            Item::Leaf(Info { device, .. }) => {
-                parts.push(device.info().name.to_lowercase());
-                break;
+pub(super) fn base_path(device: &protocol::Device) -> PathBuf {
+    PathBuf::from(device.path())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use protocol::large_bedroom::{self, bed};
+
+    use super::*;
+
+    fn test_error(text: &str) -> protocol::Error {
+        protocol::Error::LargeBedroom(large_bedroom::Error::Bed(bed::Error::Setup(
+            bed::SensorError::Sht31(heapless::String::from_str(text).unwrap()),
+        )))
+    }
+
+    fn everything() -> RangeInclusive<jiff::Timestamp> {
+        jiff::Timestamp::MIN..=jiff::Timestamp::MAX
+    }
+
+    #[test]
+    fn every_episode_is_stored() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let mut log = Log::open_or_create(dir.path(), &device).unwrap();
+
+        log.set_err(test_error("a")).unwrap();
+        log.set_err(test_error("b")).unwrap();
+        log.end_current(EndReason::Cleared).unwrap();
+        log.set_err(test_error("c")).unwrap();
+        log.end_current(EndReason::Disconnected).unwrap();
+        // nothing to end
+        log.end_current(EndReason::Cleared).unwrap();
+
+        let page = log.get(everything(), 100, None).unwrap().unwrap();
+        let reasons: Vec<_> = page.events.iter().map(|event| event.end_reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                Some(EndReason::Replaced),
+                Some(EndReason::Cleared),
+                Some(EndReason::Disconnected)
+            ]
+        );
+    }
+
+    #[test]
+    fn episodes_follow_history() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let mut log = Log::open_or_create(dir.path(), &device).unwrap();
+
+        let started = log.set_err(test_error("a")).unwrap();
+        assert!(matches!(started, Some(ErrorEpisode::Started(_))));
+        // repeats of the current error are no new episode
+        assert!(log.set_err(test_error("a")).unwrap().is_none());
+
+        match log.set_err(test_error("b")).unwrap() {
+            Some(ErrorEpisode::Changed { previous, current }) => {
+                assert_eq!(previous.error, test_error("a"));
+                assert_eq!(previous.end_reason, Some(EndReason::Replaced));
+                assert_eq!(current.error, test_error("b"));
+                assert!(current.end.is_none());
             }
-            Item::Node(inner) => {
-                parts.push(current.name().to_lowercase());
-                current = inner;
-                continue;
+            other => panic!("expected a change, got: {other:?}"),
+        }
+
+        let ended = log.end_current(EndReason::Cleared).unwrap().unwrap();
+        assert_eq!(ended.error, test_error("b"));
+        assert_eq!(ended.end_reason, Some(EndReason::Cleared));
+        assert!(log.end_current(EndReason::Cleared).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ongoing_error_ends_after_restart() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let logs = Logs::open(dir.path());
+        logs.set_err(test_error("a")).await.unwrap();
+        drop(logs);
+
+        let logs = Logs::open(dir.path());
+        let (ongoing, _) = logs.subscribe().await;
+        assert_eq!(ongoing.len(), 1);
+        let cleared = jiff::Timestamp::now();
+        logs.clear_err(device.clone()).await.unwrap();
+
+        let page = logs.get(&device, everything(), 10, None).await.unwrap();
+        let [episode] = page.events.as_slice() else {
+            panic!("expected one episode, got: {:?}", page.events);
+        };
+        assert_eq!(episode.error, test_error("a"));
+        assert_eq!(episode.end_reason, Some(EndReason::Cleared));
+        assert!(episode.end.unwrap() >= cleared);
+        let (ongoing, _) = logs.subscribe().await;
+        assert!(ongoing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn history_is_read_from_disk() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let logs = Logs::open(dir.path());
+        // a log that was not there when the logs were opened
+        let mut log = Log::open_or_create(dir.path(), &device).unwrap();
+        log.set_err(test_error("a")).unwrap();
+        log.end_current(EndReason::Cleared).unwrap();
+        drop(log);
+
+        let page = logs.get(&device, everything(), 10, None).await.unwrap();
+        assert_eq!(page.events.len(), 1);
+    }
+
+    #[test]
+    fn pages_cover_everything_once() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let mut log = Log::open_or_create(dir.path(), &device).unwrap();
+        // most of these start in the same second
+        for i in 0..7 {
+            log.set_err(test_error(&i.to_string())).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = log.get(everything(), 2, cursor).unwrap().unwrap();
+            assert!(page.events.len() <= 2);
+            seen.extend(page.events.into_iter().map(|event| event.error));
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
             }
         }
+        let expected: Vec<_> = (0..7).map(|i| test_error(&i.to_string())).collect();
+        assert_eq!(seen, expected);
+
+        assert!(matches!(
+            log.get(everything(), 0, None).unwrap(),
+            Err(api::GetLogError::InvalidLimit { .. })
+        ));
+    }
+
+    #[test]
+    fn episodes_overlapping_the_start_are_included() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let mut log = Log::open_or_create(dir.path(), &device).unwrap();
+        let at = |second| jiff::Timestamp::from_second(second).unwrap();
+        for (start, end, text) in [(100, 200, "a"), (300, 400, "b")] {
+            let line = StoredErrorEvent {
+                end: at(end),
+                error: test_error(text),
+                reason: EndReason::Cleared,
+                episodes: 1,
+            };
+            log.push(at(start), line).unwrap();
+        }
+
+        let errors = |log: &mut Log, range, limit, cursor| {
+            let page: LogPage = log.get(range, limit, cursor).unwrap().unwrap();
+            let errors: Vec<_> = page.events.into_iter().map(|event| event.error).collect();
+            (errors, page.next)
+        };
+        let (seen, _) = errors(&mut log, at(150)..=at(350), 100, None);
+        assert_eq!(seen, [test_error("a"), test_error("b")]);
+        let (seen, _) = errors(&mut log, at(200)..=at(250), 100, None);
+        assert_eq!(seen, [test_error("a")]);
+        let (seen, _) = errors(&mut log, at(201)..=at(250), 100, None);
+        assert!(seen.is_empty());
+
+        let (seen, next) = errors(&mut log, at(150)..=at(350), 1, None);
+        assert_eq!(seen, [test_error("a")]);
+        let (seen, _) = errors(&mut log, at(150)..=at(350), 1, next);
+        assert_eq!(seen, [test_error("b")]);
+    }
+
+    #[test]
+    fn flapping_episodes_are_kept() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let device = test_error("").device();
+        let mut log = Log::open_or_create(dir.path(), &device).unwrap();
+
+        for text in ["a", "b", "a", "b", "a", "b"] {
+            log.set_err(test_error(text)).unwrap();
+        }
+        log.end_current(EndReason::Cleared).unwrap();
+        assert!(log.flapping.is_flapping(jiff::Timestamp::now()));
+
+        // merging is up to the reader, see api::coalesce_flapping
+        let page = log.get(everything(), 100, None).unwrap().unwrap();
+        let episodes: Vec<_> = page.events.iter().map(|event| event.episodes).collect();
+        assert_eq!(episodes, [1; 6]);
+        let coalesced = api::coalesce_flapping(page.events);
+        let episodes: Vec<_> = coalesced.iter().map(|event| event.episodes).collect();
+        assert_eq!(episodes, [1, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn longest_line_fits() {
+        let line = encode_line(&longest_line(), payload_size()).unwrap();
+        assert_eq!(line.len(), payload_size());
+        let decoded = byteseries::Decoder::decode_payload(&mut Decoder, &line).unwrap();
+        assert_eq!(decoded.episodes, u32::MAX);
+        assert!(encode_line(&longest_line(), payload_size() - 1).is_err());
+    }
+
+    #[test]
+    fn lines_without_reason_were_replaced() {
+        #[derive(Serialize)]
+        struct Old {
+            end: jiff::Timestamp,
+            error: protocol::Error,
+        }
+
+        let old = Old {
+            end: jiff::Timestamp::now(),
+            error: test_error("old"),
+        };
+        let line = encode_line(&old, LEGACY_PAYLOAD_SIZE).unwrap();
+
+        let decoded = byteseries::Decoder::decode_payload(&mut Decoder, &line).unwrap();
+        assert_eq!(decoded.reason, EndReason::Replaced);
+        assert_eq!(decoded.error, old.error);
+        assert_eq!(decoded.episodes, 0);
     }
-    parts.into_iter().collect()
 }
@@ -0,0 +1,35 @@
+//! Whether a device keeps starting new error episodes. Every episode is
+//! stored, readers merge them with
+//! [`coalesce_flapping`](crate::api::coalesce_flapping).
+use std::collections::VecDeque;
+
+use crate::api::{FLAPPING_EPISODES, FLAPPING_WINDOW};
+
+#[derive(Debug, Default)]
+pub(super) struct Flapping {
+    /// Starts of the episodes within the window
+    recent: VecDeque<jiff::Timestamp>,
+}
+
+impl Flapping {
+    pub(super) fn episode_started(&mut self, at: jiff::Timestamp) {
+        self.recent.push_back(at);
+        self.forget_before(at);
+    }
+
+    pub(super) fn is_flapping(&mut self, now: jiff::Timestamp) -> bool {
+        self.forget_before(now);
+        self.recent.len() >= FLAPPING_EPISODES
+    }
+
+    fn forget_before(&mut self, now: jiff::Timestamp) {
+        let window = FLAPPING_WINDOW.as_millis() as i64;
+        while self
+            .recent
+            .front()
+            .is_some_and(|start| now.as_millisecond() - start.as_millisecond() > window)
+        {
+            self.recent.pop_front();
+        }
+    }
+}
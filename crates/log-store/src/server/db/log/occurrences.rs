@@ -0,0 +1,152 @@
+//! How often a device reported each error, including repeats of its current
+//! error. Only kept in memory.
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+use crate::api;
+
+/// Reports are counted per minute
+const BUCKET_SECONDS: i64 = 60;
+const HOUR_BUCKETS: i64 = 60;
+const DAY_BUCKETS: i64 = 24 * 60;
+/// Distinct errors tracked per device, the one seen longest ago is dropped
+/// to make room for a new one
+const MAX_ERRORS: usize = 32;
+
+#[derive(Debug)]
+struct Counts {
+    error: protocol::Error,
+    last_seen: jiff::Timestamp,
+    /// Bucket index (minutes since the unix epoch) and number of reports,
+    /// oldest first
+    buckets: VecDeque<(i64, u32)>,
+}
+
+impl Counts {
+    fn reports_since(&self, bucket: i64) -> u32 {
+        self.buckets
+            .iter()
+            .filter(|(b, _)| *b > bucket)
+            .map(|(_, reports)| reports)
+            .sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Occurrences(Vec<Counts>);
+
+impl Occurrences {
+    pub(super) fn record(&mut self, error: &protocol::Error, now: jiff::Timestamp) {
+        let bucket = now.as_second().div_euclid(BUCKET_SECONDS);
+        self.forget_before(bucket);
+
+        let counts = if let Some(i) = self.0.iter().position(|c| c.error == *error) {
+            &mut self.0[i]
+        } else {
+            if self.0.len() >= MAX_ERRORS {
+                let oldest = self
+                    .0
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, counts)| counts.last_seen)
+                    .map(|(i, _)| i)
+                    .expect("len is at least MAX_ERRORS");
+                self.0.swap_remove(oldest);
+            }
+            self.0.push(Counts {
+                error: error.clone(),
+                last_seen: now,
+                buckets: VecDeque::new(),
+            });
+            self.0.last_mut().expect("just pushed")
+        };
+
+        counts.last_seen = now;
+        match counts.buckets.back_mut() {
+            Some((last, reports)) if *last == bucket => *reports += 1,
+            _ => counts.buckets.push_back((bucket, 1)),
+        }
+    }
+
+    /// At most `n` errors, those reported most often in the last day first
+    pub(super) fn top(&mut self, n: usize, now: jiff::Timestamp) -> Vec<api::ErrorCount> {
+        let bucket = now.as_second().div_euclid(BUCKET_SECONDS);
+        self.forget_before(bucket);
+
+        let mut top: Vec<_> = self
+            .0
+            .iter()
+            .map(|counts| api::ErrorCount {
+                error: counts.error.clone(),
+                last_hour: counts.reports_since(bucket - HOUR_BUCKETS),
+                last_day: counts.reports_since(bucket - DAY_BUCKETS),
+                last_seen: counts.last_seen,
+            })
+            .collect();
+        top.sort_by_key(|count| (Reverse(count.last_day), Reverse(count.last_seen)));
+        top.truncate(n);
+        top
+    }
+
+    fn forget_before(&mut self, bucket: i64) {
+        for counts in &mut self.0 {
+            while counts
+                .buckets
+                .front()
+                .is_some_and(|(b, _)| *b <= bucket - DAY_BUCKETS)
+            {
+                counts.buckets.pop_front();
+            }
+        }
+        self.0.retain(|counts| !counts.buckets.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use protocol::large_bedroom::{self, bed};
+
+    use super::*;
+
+    fn test_error(text: &str) -> protocol::Error {
+        protocol::Error::LargeBedroom(large_bedroom::Error::Bed(bed::Error::Running(
+            bed::SensorError::Sht31(heapless::String::from_str(text).unwrap()),
+        )))
+    }
+
+    fn at(minutes: i64) -> jiff::Timestamp {
+        jiff::Timestamp::from_second(minutes * 60).unwrap()
+    }
+
+    #[test]
+    fn counts_over_windows() {
+        let mut occurrences = Occurrences::default();
+        occurrences.record(&test_error("a"), at(0));
+        for minute in [0, 600, 1400, 1410] {
+            occurrences.record(&test_error("b"), at(minute));
+        }
+
+        let top = occurrences.top(10, at(1420));
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].error, test_error("b"));
+        assert_eq!((top[0].last_hour, top[0].last_day), (2, 4));
+        assert_eq!((top[1].last_hour, top[1].last_day), (0, 1));
+
+        // a day later only the recent reports of b remain
+        let top = occurrences.top(10, at(24 * 60 + 700));
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].last_day, 2);
+    }
+
+    #[test]
+    fn ties_go_to_most_recent() {
+        let mut occurrences = Occurrences::default();
+        occurrences.record(&test_error("a"), at(0));
+        occurrences.record(&test_error("b"), at(5));
+
+        let top = occurrences.top(1, at(10));
+        assert_eq!(top[0].error, test_error("b"));
+    }
+}
@@ -1,68 +1,643 @@
+//! Time between readings of the same kind and between the cycles of each
+//! device. Kept as one histogram per hour that is appended to a file when the
+//! hour ends. Every few minutes the hour still going on is written to a file
+//! next to it, so little is lost when the log-store stops. Queries merge the
+//! hours they overlap, an index of the file lets them skip the others.
 use color_eyre::eyre::WrapErr;
 use color_eyre::{Result, Section};
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::iter;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use hdrhistogram::serialization::{Deserializer, Serializer, V2Serializer};
+use hdrhistogram::Histogram;
 use tokio::sync::Mutex;
+use tracing::warn;
 
 use crate::api::{self, Percentile};
 
+/// Length of the windows histograms are kept for, in seconds
+pub(super) const WINDOW: i64 = 60 * 60;
+/// How often the histogram of the unfinished window is written to disk. At
+/// most this much is lost when the log-store stops.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Window start (i64) and length of the serialized histogram (u32)
+const RECORD_HEADER: usize = 8 + 4;
+
+fn empty_histogram() -> Result<Histogram<u64>> {
+    Histogram::new_with_bounds(1, 24 * 60 * 60 * 1000, 2)
+        .wrap_err("Could not create empty histogram")
+}
+
+fn window_start(time: jiff::Timestamp) -> i64 {
+    time.as_second().div_euclid(WINDOW) * WINDOW
+}
+
+fn overlaps(window: i64, range: &RangeInclusive<i64>) -> bool {
+    window + WINDOW > *range.start() && window <= *range.end()
+}
+
+/// What a histogram tracks the intervals of
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Reading {
+        device: protocol::Device,
+        /// Lowercase name of the leaf
+        name: String,
+    },
+    Cycle(protocol::Device),
+}
+
+impl Key {
+    fn reading(reading: &protocol::Reading) -> Self {
+        Self::Reading {
+            device: reading.device(),
+            name: reading.leaf_name().to_lowercase(),
+        }
+    }
+
+    fn path(&self, log_dir: &Path) -> PathBuf {
+        match self {
+            Key::Reading { device, name } => log_dir
+                .join(super::log::base_path(device))
+                .with_extension(format!("{name}.intervals")),
+            Key::Cycle(device) => log_dir
+                .join(super::log::base_path(device))
+                .with_extension("cycle_intervals"),
+        }
+    }
+}
+
+impl From<&api::Timing> for Key {
+    fn from(timing: &api::Timing) -> Self {
+        match timing {
+            api::Timing::Reading(reading) => Key::reading(reading),
+            api::Timing::DeviceCycle(device) => Key::Cycle(device.clone()),
+        }
+    }
+}
+
+/// The readings of a device that arrived since its current cycle started.
+/// A device sends its readings one after the other. A reading that already
+/// arrived this cycle starts the next one.
+#[derive(Debug, Default)]
+struct Cycle {
+    seen: HashSet<String>,
+}
+
+impl Cycle {
+    fn starts_new(&mut self, reading_name: &str) -> bool {
+        let starts_new = self.seen.is_empty() || self.seen.contains(reading_name);
+        if starts_new {
+            self.seen.clear();
+        }
+        self.seen.insert(reading_name.to_owned());
+        starts_new
+    }
+}
+
+/// Where the record of every window in a file is
+#[derive(Debug, Default)]
+struct Index {
+    /// Sorted by window
+    records: Vec<Record>,
+    /// End of the last complete record, the next one is written here
+    end: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    window: i64,
+    /// Where the serialized histogram starts
+    offset: u64,
+    len: u32,
+}
+
+impl Index {
+    /// Reads the record headers, skipping over the histograms. A record cut
+    /// short, for example by a crash while appending, ends the file. It is
+    /// overwritten by the next append.
+    fn build(path: &Path) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not open interval histograms")
+                    .with_note(|| format!("path: {}", path.display()))
+            }
+        };
+        let file_len = file
+            .metadata()
+            .wrap_err("Could not get length of interval histograms")?
+            .len();
+        let mut reader = BufReader::new(file);
+
+        let mut index = Self::default();
+        loop {
+            let mut header = [0u8; RECORD_HEADER];
+            match reader.read_exact(&mut header) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e).wrap_err("Could not read interval histograms"),
+            }
+            let window = i64::from_le_bytes(header[..8].try_into().expect("slice is 8 long"));
+            let len = u32::from_le_bytes(header[8..].try_into().expect("slice is 4 long"));
+            let offset = index.end + RECORD_HEADER as u64;
+            if offset + u64::from(len) > file_len {
+                break;
+            }
+            reader
+                .seek_relative(i64::from(len))
+                .wrap_err("Could not skip interval histogram")?;
+            index.insert(Record {
+                window,
+                offset,
+                len,
+            });
+            index.end = offset + u64::from(len);
+        }
+        if index.end < file_len {
+            warn!("Last interval histogram is incomplete, ignoring it");
+        }
+        Ok(index)
+    }
+
+    fn insert(&mut self, record: Record) {
+        let pos = self.records.partition_point(|r| r.window <= record.window);
+        self.records.insert(pos, record);
+    }
+
+    fn overlapping(&self, range: &RangeInclusive<i64>) -> &[Record] {
+        let first = self
+            .records
+            .partition_point(|r| r.window + WINDOW <= *range.start());
+        let end = self.records.partition_point(|r| r.window <= *range.end());
+        &self.records[first..end.max(first)]
+    }
+
+    /// Writes the record after the last complete one
+    fn append(&mut self, path: &Path, window: i64, histogram: &Histogram<u64>) -> Result<()> {
+        let record = encode_record(window, histogram)?;
+        if let Some(dirs) = path.parent() {
+            fs::create_dir_all(dirs)
+                .wrap_err("Could not create dirs for interval histograms")
+                .with_note(|| format!("dirs: {}", dirs.display()))?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .and_then(|mut file| {
+                file.set_len(self.end)?;
+                file.seek(SeekFrom::Start(self.end))?;
+                file.write_all(&record)
+            })
+            .wrap_err("Could not append histogram to file")
+            .with_note(|| format!("path: {}", path.display()))?;
+
+        let offset = self.end + RECORD_HEADER as u64;
+        let len = (record.len() - RECORD_HEADER) as u32;
+        self.insert(Record {
+            window,
+            offset,
+            len,
+        });
+        self.end = offset + u64::from(len);
+        Ok(())
+    }
+}
+
+fn encode_record(window: i64, histogram: &Histogram<u64>) -> Result<Vec<u8>> {
+    let mut serialized = Vec::new();
+    V2Serializer::new()
+        .serialize(histogram, &mut serialized)
+        .wrap_err("Could not serialize histogram")?;
+    let mut record = Vec::with_capacity(RECORD_HEADER + serialized.len());
+    record.extend_from_slice(&window.to_le_bytes());
+    record.extend_from_slice(&(serialized.len() as u32).to_le_bytes());
+    record.extend_from_slice(&serialized);
+    Ok(record)
+}
+
+/// Reads one record from a file that only holds that one, None if there
+/// is no such file
+fn read_single(path: &Path) -> Result<Option<(i64, Histogram<u64>)>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not read interval histogram")
+                .with_note(|| format!("path: {}", path.display()))
+        }
+    };
+    let Some((header, mut serialized)) = bytes.split_first_chunk::<RECORD_HEADER>() else {
+        warn!("Interval histogram of unfinished window is incomplete, ignoring it");
+        return Ok(None);
+    };
+    let window = i64::from_le_bytes(header[..8].try_into().expect("slice is 8 long"));
+    let histogram = Deserializer::new()
+        .deserialize(&mut serialized)
+        .wrap_err("Could not deserialize interval histogram")
+        .with_note(|| format!("path: {}", path.display()))?;
+    Ok(Some((window, histogram)))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Holds the histogram of the window that did not yet end
+fn unfinished_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".unfinished")
+}
+
 #[derive(Debug)]
-pub(crate) struct Histogram {
-    last_reading: Instant,
-    histogram: hdrhistogram::Histogram<u64>,
+pub(crate) struct Intervals {
+    path: PathBuf,
+    index: Index,
+    /// None until the first reading arrived
+    last_reading: Option<Instant>,
+    last_flush: Instant,
+    /// Start of the window `current` belongs to
+    window: i64,
+    /// Intervals in `window`, appended to the file when the window ends
+    current: Histogram<u64>,
 }
 
-impl Histogram {
-    fn new() -> Result<Self> {
-        Ok(Self {
-            last_reading: Instant::now(),
-            histogram: hdrhistogram::Histogram::new_with_bounds(1, 24 * 60 * 60 * 1000, 2)
-                .wrap_err("Could not create empty histogram")?,
-        })
+impl Intervals {
+    /// Picks up the window a previous run was in when it stopped
+    fn open(path: PathBuf) -> Result<Self> {
+        let mut intervals = Self {
+            index: Index::build(&path)?,
+            path,
+            last_reading: None,
+            last_flush: Instant::now(),
+            window: window_start(jiff::Timestamp::now()),
+            current: empty_histogram()?,
+        };
+
+        let unfinished = unfinished_path(&intervals.path);
+        if let Some((window, histogram)) = read_single(&unfinished)? {
+            if window == intervals.window {
+                intervals.current = histogram;
+            } else {
+                intervals
+                    .index
+                    .append(&intervals.path, window, &histogram)?;
+                remove_if_exists(&unfinished)?;
+            }
+        }
+        Ok(intervals)
     }
+
     fn increment(&mut self) -> Result<()> {
-        let val = self.last_reading.elapsed().as_millis();
-        self.last_reading = Instant::now();
-        self.histogram
-            .record(val as u64)
+        let now = Instant::now();
+        let Some(last_reading) = self.last_reading.replace(now) else {
+            return Ok(());
+        };
+        let val = now.duration_since(last_reading).as_millis() as u64;
+        self.record(jiff::Timestamp::now(), val)?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, now: jiff::Timestamp, interval_ms: u64) -> Result<()> {
+        let window = window_start(now);
+        if window != self.window {
+            self.end_window()?;
+            self.window = window;
+        }
+        self.current
+            .record(interval_ms)
             .wrap_err("Could not record event")
-            .with_note(|| format!("duration was: {val}ms"))
+            .with_note(|| format!("duration was: {interval_ms}ms"))
+    }
+
+    /// Appends the intervals of the window to the file
+    fn end_window(&mut self) -> Result<()> {
+        if !self.current.is_empty() {
+            self.index.append(&self.path, self.window, &self.current)?;
+            self.current.reset();
+        }
+        remove_if_exists(&unfinished_path(&self.path))
+    }
+
+    /// Writes the intervals of the window so far next to the file,
+    /// replacing what was there
+    fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        if self.current.is_empty() {
+            return Ok(());
+        }
+
+        let unfinished = unfinished_path(&self.path);
+        let tmp = with_suffix(&unfinished, "_new");
+        let record = encode_record(self.window, &self.current)?;
+        if let Some(dirs) = self.path.parent() {
+            fs::create_dir_all(dirs)
+                .wrap_err("Could not create dirs for interval histograms")
+                .with_note(|| format!("dirs: {}", dirs.display()))?;
+        }
+        fs::write(&tmp, &record)
+            .wrap_err("Could not write histogram of unfinished window")
+            .with_note(|| format!("path: {}", tmp.display()))?;
+        fs::rename(&tmp, &unfinished)
+            .wrap_err("Could not move file in place")
+            .with_note(|| format!("path: {}", unfinished.display()))
+    }
+
+    /// Calls `on_window` with the start and histogram of every window that
+    /// overlaps `range`. Only those are read from the file.
+    fn for_each_window(
+        &self,
+        range: &RangeInclusive<i64>,
+        mut on_window: impl FnMut(i64, &Histogram<u64>) -> Result<()>,
+    ) -> Result<()> {
+        read_records(
+            &self.path,
+            self.index.overlapping(range),
+            |window, histogram| on_window(window, &histogram),
+        )?;
+        if overlaps(self.window, range) && !self.current.is_empty() {
+            on_window(self.window, &self.current)?;
+        }
+        Ok(())
+    }
+
+    /// All intervals in the windows that overlap `range`
+    fn merged(&self, range: &RangeInclusive<i64>) -> Result<Histogram<u64>> {
+        let mut merged = empty_histogram()?;
+        self.for_each_window(range, |_, histogram| {
+            merged
+                .add(histogram)
+                .wrap_err("Could not merge interval histograms")
+        })?;
+        Ok(merged)
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+            .wrap_err("Could not remove file")
+            .with_note(|| format!("path: {}", path.display())),
+    }
+}
+
+fn read_records(
+    path: &Path,
+    records: &[Record],
+    mut on_record: impl FnMut(i64, Histogram<u64>) -> Result<()>,
+) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut file = File::open(path)
+        .wrap_err("Could not open interval histograms")
+        .with_note(|| format!("path: {}", path.display()))?;
+    let mut deserializer = Deserializer::new();
+    let mut serialized = Vec::new();
+    for record in records {
+        serialized.resize(record.len as usize, 0);
+        file.seek(SeekFrom::Start(record.offset))
+            .and_then(|_| file.read_exact(&mut serialized))
+            .wrap_err("Could not read interval histogram")
+            .with_note(|| format!("path: {}", path.display()))?;
+        let histogram: Histogram<u64> = deserializer
+            .deserialize(&mut serialized.as_slice())
+            .wrap_err("Could not deserialize interval histogram")
+            .with_note(|| format!("path: {}", path.display()))?;
+        on_record(record.window, histogram)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    intervals: HashMap<Key, Intervals>,
+    cycles: HashMap<protocol::Device, Cycle>,
+}
+
+impl Tracked {
+    fn intervals(&mut self, key: Key, log_dir: &Path) -> Result<&mut Intervals> {
+        Ok(match self.intervals.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let intervals = Intervals::open(entry.key().path(log_dir))?;
+                entry.insert(intervals)
+            }
+        })
     }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Stats(pub(crate) Arc<Mutex<HashMap<protocol::Device, Histogram>>>);
+pub(crate) struct Stats {
+    log_dir: PathBuf,
+    tracked: Arc<Mutex<Tracked>>,
+}
 
 impl Stats {
-    pub async fn increment(&self, device: protocol::Device) -> Result<()> {
-        let mut map = self.0.lock().await;
-        if let Some(hist) = map.get_mut(&device) {
-            hist.increment()?;
-        } else {
-            map.insert(device, Histogram::new()?);
+    pub(crate) fn new(log_dir: &Path) -> Self {
+        Self {
+            log_dir: log_dir.to_path_buf(),
+            tracked: Arc::new(Mutex::new(Tracked::default())),
         }
-        Ok(())
     }
 
+    /// Runs `f` on a thread that may block, it can do file IO
+    async fn with_tracked<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Tracked, &Path) -> T + Send + 'static,
+    ) -> T {
+        let mut tracked = Arc::clone(&self.tracked).lock_owned().await;
+        let log_dir = self.log_dir.clone();
+        match tokio::task::spawn_blocking(move || f(&mut tracked, &log_dir)).await {
+            Ok(res) => res,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    pub async fn increment(&self, reading: &protocol::Reading) -> Result<()> {
+        let key = Key::reading(reading);
+        self.with_tracked(move |tracked, log_dir| {
+            let Key::Reading { device, name } = &key else {
+                unreachable!("Key::reading returns a Key::Reading");
+            };
+            let cycle = tracked.cycles.entry(device.clone()).or_default();
+            if cycle.starts_new(name) {
+                tracked
+                    .intervals(Key::Cycle(device.clone()), log_dir)?
+                    .increment()?;
+            }
+            tracked.intervals(key, log_dir)?.increment()
+        })
+        .await
+    }
+
+    /// Percentiles of the intervals in every window that overlaps `range`
     pub(crate) async fn get(
         &self,
-        device: &protocol::Device,
+        timing: &api::Timing,
+        range: RangeInclusive<jiff::Timestamp>,
     ) -> Result<Vec<crate::api::Percentile>, api::GetStatsError> {
-        let mut map = self.0.lock().await;
-        Ok(if let Some(timings) = map.get_mut(device) {
-            timings
-                .histogram
-                .iter_quantiles(1)
-                .map(|it| Percentile {
-                    bucket_ends: it.value_iterated_to(),
-                    percentile: it.percentile(),
-                    count_in_bucket: it.count_at_value(),
-                })
-                .collect()
-        } else {
-            Vec::new()
+        let key = Key::from(timing);
+        let range = range.start().as_second()..=range.end().as_second();
+        let merged = self
+            .with_tracked(move |tracked, log_dir| tracked.intervals(key, log_dir)?.merged(&range))
+            .await
+            .map_err(|report| api::GetStatsError::InternalError(format!("{report:#}")))?;
+
+        Ok(merged
+            .iter_quantiles(1)
+            .map(|it| Percentile {
+                bucket_ends: it.value_iterated_to(),
+                percentile: it.percentile(),
+                count_in_bucket: it.count_at_value(),
+            })
+            .collect())
+    }
+
+    /// Gaps between the cycles of `device` longer then `allowed`, with the
+    /// start of the window they ended in. Only windows overlapping `range`
+    /// are searched. A gap still going on ends now.
+    pub(crate) async fn gaps(
+        &self,
+        device: &protocol::Device,
+        range: RangeInclusive<jiff::Timestamp>,
+        allowed: Duration,
+    ) -> Result<Vec<(jiff::Timestamp, Duration)>> {
+        let key = Key::Cycle(device.clone());
+        let range = range.start().as_second()..=range.end().as_second();
+        let allowed_ms = allowed.as_millis() as u64;
+
+        self.with_tracked(move |tracked, log_dir| {
+            let intervals = tracked.intervals(key, log_dir)?;
+            let mut gaps = Vec::new();
+            intervals.for_each_window(&range, |window, histogram| {
+                let window = jiff::Timestamp::from_second(window)
+                    .expect("was a jiff::Timestamp before it became an i64");
+                for value in histogram.iter_recorded() {
+                    let interval_ms = value.value_iterated_to();
+                    if interval_ms <= allowed_ms {
+                        continue;
+                    }
+                    let gap = (window, Duration::from_millis(interval_ms));
+                    gaps.extend(iter::repeat(gap).take(value.count_at_value() as usize));
+                }
+                Ok(())
+            })?;
+
+            let now = jiff::Timestamp::now();
+            if let Some(last_reading) = intervals.last_reading {
+                let silent_for = last_reading.elapsed();
+                if silent_for > allowed && overlaps(window_start(now), &range) {
+                    let window = jiff::Timestamp::from_second(window_start(now))
+                        .expect("window starts before now");
+                    gaps.push((window, silent_for));
+                }
+            }
+            Ok(gaps)
         })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(second: i64) -> jiff::Timestamp {
+        jiff::Timestamp::from_second(second).unwrap()
+    }
+
+    #[test]
+    fn windows_overlapping_range_are_merged() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.path().join("device.intervals");
+        let mut intervals = Intervals::open(path.clone()).unwrap();
+        intervals.window = 0;
+
+        intervals.record(at(10), 100).unwrap();
+        intervals.record(at(20), 100).unwrap();
+        // starts a new window, appending the first
+        intervals.record(at(WINDOW + 10), 5_000).unwrap();
+        intervals.flush().unwrap();
+        intervals.record(at(WINDOW + 20), 5_000).unwrap();
+        intervals.record(at(3 * WINDOW), 9_000).unwrap();
+        // the unfinished window is only in memory and next to the file
+        intervals.flush().unwrap();
+        assert_eq!(intervals.index.records.len(), 2);
+
+        let count = |intervals: &Intervals, range: RangeInclusive<i64>| {
+            intervals.merged(&range).unwrap().len()
+        };
+        assert_eq!(count(&intervals, 0..=i64::MAX), 5);
+        assert_eq!(count(&intervals, 0..=WINDOW - 1), 2);
+        assert_eq!(count(&intervals, WINDOW + 30..=WINDOW + 40), 2);
+        assert_eq!(count(&intervals, 2 * WINDOW..=2 * WINDOW + 1), 0);
+        assert_eq!(count(&intervals, 3 * WINDOW..=3 * WINDOW), 1);
+
+        // the unfinished window of a previous run ended long ago
+        drop(intervals);
+        let intervals = Intervals::open(path.clone()).unwrap();
+        assert_eq!(intervals.index.records.len(), 3);
+        assert!(!unfinished_path(&path).exists());
+        assert_eq!(count(&intervals, 0..=i64::MAX), 5);
+        assert_eq!(count(&intervals, 3 * WINDOW..=3 * WINDOW), 1);
+    }
+
+    #[test]
+    fn repeated_reading_starts_cycle() {
+        let mut cycle = Cycle::default();
+        let starts: Vec<_> = ["pm1", "pm2_5", "pm10", "pm1", "pm2_5", "pm10", "pm1"]
+            .into_iter()
+            .map(|reading| cycle.starts_new(reading))
+            .collect();
+        assert_eq!(starts, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn incomplete_last_record_is_overwritten() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.path().join("device.intervals");
+        let mut intervals = Intervals::open(path.clone()).unwrap();
+        intervals.window = 0;
+        intervals.record(at(10), 100).unwrap();
+        intervals.record(at(WINDOW), 100).unwrap();
+        intervals.record(at(2 * WINDOW), 100).unwrap();
+        drop(intervals);
+
+        let len = fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut intervals = Intervals::open(path.clone()).unwrap();
+        assert_eq!(intervals.merged(&(0..=i64::MAX)).unwrap().len(), 1);
+        intervals.window = 5 * WINDOW;
+        intervals.record(at(5 * WINDOW), 100).unwrap();
+        intervals.record(at(6 * WINDOW), 100).unwrap();
+
+        let intervals = Intervals::open(path.clone()).unwrap();
+        assert_eq!(intervals.index.records.len(), 2);
+        assert_eq!(intervals.merged(&(0..=i64::MAX)).unwrap().len(), 2);
     }
 }
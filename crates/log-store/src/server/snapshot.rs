@@ -0,0 +1,81 @@
+//! Tarballs with a point in time copy of the log dir and restoring them.
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::{Result, Section};
+
+use super::db::{CurrentError, Log};
+use crate::api;
+
+/// Archives every file in `log_dir` into a new tarball in `snapshot_dir`,
+/// including the current error files. Nothing may write to `log_dir` while
+/// this runs.
+pub(crate) fn write(log_dir: &Path, snapshot_dir: &Path) -> Result<api::Snapshot> {
+    let written = dir_snapshot::write(log_dir, snapshot_dir, "log-store")?;
+    Ok(api::Snapshot {
+        path: written.path,
+        files: written.files,
+        bytes: written.bytes,
+    })
+}
+
+#[derive(Debug)]
+pub struct Restored {
+    pub logs: usize,
+    /// Where the log dir that was replaced was moved to
+    pub previous: Option<PathBuf>,
+}
+
+/// Replaces `log_dir` with the content of a snapshot. It only replaces
+/// `log_dir` if every log in the snapshot opens, which checks its header,
+/// and every current error can be read. The old log dir is kept next to it.
+/// The log-store must not be running.
+pub fn restore(snapshot: &Path, log_dir: &Path) -> Result<Restored> {
+    let restored = dir_snapshot::restore(snapshot, log_dir, |unpacked| {
+        let mut logs = 0;
+        for device in protocol::Device::all() {
+            CurrentError::check(unpacked, &device).with_note(|| format!("device: {device:?}"))?;
+            let opened = Log::open_existing(unpacked, &device)
+                .wrap_err("Log in snapshot is damaged or has an unexpected header")
+                .with_note(|| format!("device: {device:?}"))?;
+            logs += usize::from(opened.is_some());
+        }
+        if logs == 0 {
+            bail!("Snapshot contains no logs, not restoring it");
+        }
+        Ok(logs)
+    })?;
+
+    Ok(Restored {
+        logs: restored.checked,
+        previous: restored.previous,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+
+    use super::*;
+
+    #[test]
+    fn current_errors_are_in_snapshot() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("largebedroom/bed")).unwrap();
+        fs::write(dir.path().join("largebedroom/bed/sht31.current_error"), b"").unwrap();
+
+        let snapshot = write(dir.path(), &dir.path().join("snapshots")).unwrap();
+        assert_eq!(snapshot.files, 1);
+
+        let mut archive = tar::Archive::new(File::open(&snapshot.path).unwrap());
+        let names: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![PathBuf::from("largebedroom/bed/sht31.current_error")]
+        );
+    }
+}
@@ -3,9 +3,11 @@ use std::str::FromStr;
 use std::sync::Once;
 use std::time::Duration;
 
+use data_server::api::ReconnectingClient;
 use data_server::server::AffectorRegistar;
 use futures::FutureExt;
 use futures_concurrency::future::Race;
+use log_store::api::Timing;
 use protocol::large_bedroom::bed;
 use protocol::{large_bedroom, Reading};
 use temp_dir::TempDir;
@@ -35,8 +37,15 @@ async fn data_server(client_addr: impl Into<SocketAddr>, data_port: impl Into<So
 
     let affectors = AffectorRegistar::default();
     let (tx, rx) = mpsc::channel(2000);
+    let clients = server::client::handle(
+        client_addr.into(),
+        tx.clone(),
+        affectors.clone(),
+        None,
+        None,
+    );
     tokio::select! {
-        e = server::client::handle(client_addr.into(), tx.clone(), affectors.clone()) => e.unwrap(),
+        e = clients => e.unwrap(),
         e = server::handle_nodes(data_port.into(), &tx, affectors) => e.unwrap(),
         e = server::spread_updates(rx) => e.unwrap(),
     };
@@ -87,13 +96,22 @@ async fn check_client_get_percentiles(data_store_addr: SocketAddr, data_send: &N
         log_store::api::Client::connect(data_store_addr, "data_store_example".to_owned())
             .await
             .unwrap();
-    let test_device = test_readings(0.0).first().unwrap().device();
-    let list = client.get_percentiles(test_device).await.unwrap();
-
+    let [reading, _] = test_readings(0.0);
+    let range = (jiff::Timestamp::UNIX_EPOCH, jiff::Timestamp::now());
+    let list = client
+        .get_percentiles(Timing::Reading(reading.clone()), range.0, range.1)
+        .await
+        .unwrap();
     assert!(
         !list.is_empty(),
         "list is empty, should contain one reading"
     );
+
+    let list = client
+        .get_percentiles(Timing::DeviceCycle(reading.device()), range.0, range.1)
+        .await
+        .unwrap();
+    assert!(!list.is_empty(), "list is empty, should contain one cycle");
 }
 
 async fn check_client_get_logs(data_store_addr: SocketAddr, data_send: &Notify) {
@@ -104,9 +122,56 @@ async fn check_client_get_logs(data_store_addr: SocketAddr, data_send: &Notify)
         .unwrap();
 
     let test_device = test_readings(0.0).first().unwrap().device();
-    let logs = client.get_logs(test_device).await.unwrap();
+    let page = client
+        .get_logs(
+            test_device.clone(),
+            jiff::Timestamp::UNIX_EPOCH,
+            jiff::Timestamp::now(),
+            10,
+            None,
+        )
+        .await
+        .unwrap();
 
-    assert_eq!(logs.len(), 3);
+    assert_eq!(page.events.len(), 3);
+    assert!(page.next.is_none());
+
+    let availability = client
+        .get_availability(
+            test_device,
+            jiff::Timestamp::now() - jiff::Span::new().minutes(1),
+            jiff::Timestamp::now(),
+        )
+        .await
+        .unwrap();
+    assert!(availability.in_error > 0.0, "{availability:?}");
+    assert!(availability.outages >= 1, "{availability:?}");
+}
+
+async fn check_subscribe(data_store_addr: SocketAddr) {
+    use log_store::api::ErrorEpisode;
+
+    let client = log_store::api::Client::connect(data_store_addr, "log_store_test".to_owned())
+        .await
+        .unwrap();
+    let mut subscribed = client.subscribe().await.unwrap();
+
+    // errors are send as: 1, 1, 2, 1
+    let error = |n| test_error(&format!("log server integration test error: {n}"));
+    match subscribed.next().await.unwrap() {
+        ErrorEpisode::Started(event) => assert_eq!(event.error, error(1)),
+        other => panic!("expected error 1 to start, got: {other:?}"),
+    }
+    for (from, to) in [(1, 2), (2, 1)] {
+        match subscribed.next().await.unwrap() {
+            ErrorEpisode::Changed { previous, current } => {
+                assert_eq!(previous.error, error(from));
+                assert!(previous.end.is_some());
+                assert_eq!(current.error, error(to));
+            }
+            other => panic!("expected change from {from} to {to}, got: {other:?}"),
+        }
+    }
 }
 
 static SETUP_REPORTING: Once = Once::new();
@@ -144,7 +209,14 @@ async fn get_logs() {
         ([127, 0, 0, 1], data_port.port()),
     );
     let run_data_store = sleep(DATA_SERVER_STARTUP).then(|()| {
-        log_store::server::run(data_server_addr, data_store_addr.port(), test_dir.path())
+        log_store::server::run(
+            ReconnectingClient::new(data_server_addr, "ha-log-store".to_string()),
+            data_store_addr.port(),
+            None,
+            None,
+            test_dir.path(),
+            &test_dir.path().join("snapshots"),
+        )
     });
     let send_sensor_value = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP)
         .then(|()| send_sensor_errors(data_port.port(), &errors_send));
@@ -187,7 +259,14 @@ async fn get_percentiles() {
         ([127, 0, 0, 1], data_port.port()),
     );
     let run_data_store = sleep(DATA_SERVER_STARTUP).then(|()| {
-        log_store::server::run(data_server_addr, data_store_addr.port(), test_dir.path())
+        log_store::server::run(
+            ReconnectingClient::new(data_server_addr, "ha-log-store".to_string()),
+            data_store_addr.port(),
+            None,
+            None,
+            test_dir.path(),
+            &test_dir.path().join("snapshots"),
+        )
     });
     let send_sensor_value = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP)
         .then(|()| send_sensor_values(data_port.port(), &data_send));
@@ -205,3 +284,52 @@ async fn get_percentiles() {
 
     res.unwrap();
 }
+
+#[tokio::test]
+async fn subscribe() {
+    const DATA_SERVER_STARTUP: Duration = Duration::from_millis(20);
+    const DATA_STORE_STARTUP: Duration = Duration::from_millis(20);
+    const SUBSCRIBED: Duration = Duration::from_millis(500);
+
+    setup_reporting();
+
+    let test_dir = TempDir::new().unwrap();
+
+    let sub_port = reserve_port::ReservedPort::random().unwrap();
+    let data_port = reserve_port::ReservedPort::random().unwrap();
+    let store_port = reserve_port::ReservedPort::random().unwrap();
+
+    let data_server_addr = SocketAddr::from(([127, 0, 0, 1], sub_port.port()));
+    let data_store_addr = SocketAddr::from(([127, 0, 0, 1], store_port.port()));
+
+    let errors_send = Notify::new();
+    let run_data_server = data_server(
+        ([127, 0, 0, 1], sub_port.port()),
+        ([127, 0, 0, 1], data_port.port()),
+    );
+    let run_data_store = sleep(DATA_SERVER_STARTUP).then(|()| {
+        log_store::server::run(
+            ReconnectingClient::new(data_server_addr, "ha-log-store".to_string()),
+            data_store_addr.port(),
+            None,
+            None,
+            test_dir.path(),
+            &test_dir.path().join("snapshots"),
+        )
+    });
+    let send_sensor_value = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP + SUBSCRIBED)
+        .then(|()| send_sensor_errors(data_port.port(), &errors_send));
+    let run_test = sleep(DATA_SERVER_STARTUP + DATA_STORE_STARTUP)
+        .then(|()| check_subscribe(data_store_addr));
+
+    let res = (
+        run_test.map(Result::Ok),
+        send_sensor_value.map(Result::Ok),
+        run_data_store,
+        run_data_server.map(Result::Ok),
+    )
+        .race()
+        .await;
+
+    res.unwrap();
+}
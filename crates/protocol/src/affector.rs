@@ -64,6 +64,11 @@ impl Affector {
     const ENCODED_SIZE: usize =
         Affector::POSTCARD_MAX_SIZE + cobs_overhead(Affector::POSTCARD_MAX_SIZE);
 
+    #[must_use]
+    pub const fn max_size() -> usize {
+        Self::POSTCARD_MAX_SIZE
+    }
+
     #[cfg(feature = "alloc")]
     #[must_use]
     pub fn encode(&self) -> Vec<u8> {
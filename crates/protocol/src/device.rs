@@ -18,6 +18,34 @@ impl Device {
             Device::SmallBedroom(dev) => dev.info(),
         }
     }
+
+    /// Every device that exists
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn all() -> Vec<Self> {
+        use strum::VariantArray;
+        large_bedroom::Device::all()
+            .into_iter()
+            .map(Self::LargeBedroom)
+            .chain(
+                small_bedroom::Device::VARIANTS
+                    .iter()
+                    .cloned()
+                    .map(Self::SmallBedroom),
+            )
+            .collect()
+    }
+
+    /// Lowercase path of the device, for example `largebedroom/bed/sht31`
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn path(&self) -> String {
+        self.info()
+            .affects_readings
+            .first()
+            .expect("a device has at least one reading it affects")
+            .device_path()
+    }
 }
 
 #[derive(Debug)]
@@ -76,6 +76,18 @@ impl Device {
             Self::Desk(dev) => dev.info(),
         }
     }
+
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn all() -> Vec<Self> {
+        use strum::VariantArray;
+        bed::Device::VARIANTS
+            .iter()
+            .cloned()
+            .map(Self::Bed)
+            .chain(desk::Device::VARIANTS.iter().cloned().map(Self::Desk))
+            .collect()
+    }
 }
 
 #[derive(
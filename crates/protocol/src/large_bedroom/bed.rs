@@ -363,7 +363,18 @@ impl SensorError {
     }
 }
 
-#[derive(Clone, Debug, defmt::Format, Serialize, Deserialize, MaxSize, Eq, PartialEq, Hash)]
+#[derive(
+    strum::VariantArray,
+    Clone,
+    Debug,
+    defmt::Format,
+    Serialize,
+    Deserialize,
+    MaxSize,
+    Eq,
+    PartialEq,
+    Hash,
+)]
 pub enum Device {
     Sht31,
     Bme680,
@@ -51,6 +51,40 @@ impl Reading {
         use tree::Tree;
         self.leaf().device
     }
+    /// Lowercase path through the tree to the device of this reading, for
+    /// example `largebedroom/bed/sht31`
+    #[must_use]
+    pub fn device_path(&self) -> String {
+        use tree::{Item, Tree};
+
+        let mut parts = Vec::new();
+        let mut current = self as &dyn Tree;
+        loop {
+            match current.inner() {
+                Item::Leaf(Info { device, .. }) => {
+                    parts.push(device.info().name.to_lowercase());
+                    return parts.join("/");
+                }
+                Item::Node(inner) => {
+                    parts.push(current.name().to_lowercase());
+                    current = inner;
+                }
+            }
+        }
+    }
+    /// Last part of the path of this reading, for example `Temperature`
+    #[must_use]
+    pub fn leaf_name(&self) -> String {
+        use tree::{Item, Tree};
+
+        let mut current = self as &dyn Tree;
+        loop {
+            match current.inner() {
+                Item::Leaf(_) => return current.name(),
+                Item::Node(inner) => current = inner,
+            }
+        }
+    }
 }
 impl Reading {
     #[must_use]
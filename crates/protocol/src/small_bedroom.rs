@@ -61,7 +61,18 @@ impl Reading {
     }
 }
 
-#[derive(Clone, Debug, defmt::Format, Serialize, Deserialize, MaxSize, Hash, PartialEq, Eq)]
+#[derive(
+    strum::VariantArray,
+    Clone,
+    Debug,
+    defmt::Format,
+    Serialize,
+    Deserialize,
+    MaxSize,
+    Hash,
+    PartialEq,
+    Eq,
+)]
 pub enum Device {
     Gpio,
 }
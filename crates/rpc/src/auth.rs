@@ -0,0 +1,181 @@
+//! Optional challenge-response authentication using a pre-shared key per
+//! client name. The server sends a random nonce after the handshake, the
+//! client answers with an HMAC-SHA256 over it and its name.
+//!
+//! Keys are kept in a text file with a line per client: its name followed by
+//! a 32 byte key as hex, for example from `openssl rand -hex 32`. Empty lines
+//! and lines starting with `#` are ignored:
+//! ```text
+//! # name key
+//! ha-brain 6b1d3ba1c3a9a9f8a1fd1e1c0b2bcf4c2e1ad1de9ecb4cbb8fb0d37e5ca2a1f2
+//! ```
+//! A client keeps its own key in a file with only the key as hex, see
+//! [`Key::load`].
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::{Result, Section};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub type Nonce = [u8; 32];
+pub type Proof = [u8; 32];
+
+/// Separates these proofs from other uses of the key
+const CONTEXT: &[u8] = b"ha-rpc-auth";
+
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl FromStr for Key {
+    type Err = color_eyre::Report;
+
+    fn from_str(text: &str) -> Result<Self> {
+        if text.len() != 64 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("a key must be 64 hex characters");
+        }
+        let mut key = [0u8; 32];
+        for (byte, pair) in key.iter_mut().zip(text.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).expect("checked to be ascii");
+            *byte = u8::from_str_radix(pair, 16).expect("checked to be hex");
+        }
+        Ok(Self(key))
+    }
+}
+
+impl Key {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err("Could not read key file")
+            .with_note(|| format!("path: {}", path.display()))?;
+        text.trim()
+            .parse()
+            .with_note(|| format!("path: {}", path.display()))
+    }
+
+    fn mac(&self, nonce: &Nonce, name: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("any key size works");
+        mac.update(CONTEXT);
+        mac.update(nonce);
+        mac.update(name.as_bytes());
+        mac
+    }
+
+    pub(crate) fn proof(&self, nonce: &Nonce, name: &str) -> Proof {
+        self.mac(nonce, name).finalize().into_bytes().into()
+    }
+
+    pub(crate) fn verify(&self, nonce: &Nonce, name: &str, proof: &Proof) -> bool {
+        self.mac(nonce, name).verify_slice(proof).is_ok()
+    }
+}
+
+/// The key of every client that may connect
+#[derive(Debug, Clone, Default)]
+pub struct Keys(HashMap<String, Key>);
+
+impl Keys {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err("Could not read key file")
+            .with_note(|| format!("path: {}", path.display()))?;
+        text.parse()
+            .with_note(|| format!("path: {}", path.display()))
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Key> {
+        self.0.get(name)
+    }
+}
+
+impl FromStr for Keys {
+    type Err = color_eyre::Report;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for (number, line) in text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+        {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, key) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| eyre!("expected a name and a key"))
+                .with_note(|| format!("line: {number}"))?;
+            let key = key
+                .trim()
+                .parse()
+                .wrap_err("Invalid key")
+                .with_note(|| format!("line: {number}"))?;
+            if keys.insert(name.to_owned(), key).is_some() {
+                return Err(eyre!("Client has more then one key"))
+                    .with_note(|| format!("name: {name}"));
+            }
+        }
+        Ok(Self(keys))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FILE: &str = "
+        # name key
+        ha-brain 6b1d3ba1c3a9a9f8a1fd1e1c0b2bcf4c2e1ad1de9ecb4cbb8fb0d37e5ca2a1f2
+
+        sensor-tui 00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff
+    ";
+
+    #[test]
+    fn proof_is_bound_to_key_nonce_and_name() {
+        let keys: Keys = FILE.parse().unwrap();
+        let brain = keys.get("ha-brain").unwrap();
+        let tui = keys.get("sensor-tui").unwrap();
+        let nonce = [7; 32];
+
+        let proof = brain.proof(&nonce, "ha-brain");
+        assert!(brain.verify(&nonce, "ha-brain", &proof));
+        assert!(!brain.verify(&[8; 32], "ha-brain", &proof));
+        assert!(!brain.verify(&nonce, "sensor-tui", &proof));
+        assert!(!tui.verify(&nonce, "ha-brain", &proof));
+    }
+
+    #[test]
+    fn invalid_key_files_are_rejected() {
+        assert!("ha-brain".parse::<Keys>().is_err());
+        assert!("ha-brain 0011".parse::<Keys>().is_err());
+        let duplicate = format!("{FILE}\nha-brain {}", "ab".repeat(32));
+        assert!(duplicate.parse::<Keys>().is_err());
+    }
+
+    #[test]
+    fn client_key_file_matches_server_entry() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.path().join("key");
+        let hex = "6b1d3ba1c3a9a9f8a1fd1e1c0b2bcf4c2e1ad1de9ecb4cbb8fb0d37e5ca2a1f2";
+        std::fs::write(&path, format!("{hex}\n")).unwrap();
+
+        let key = Key::load(&path).unwrap();
+        let keys: Keys = FILE.parse().unwrap();
+        let nonce = [7; 32];
+        let proof = key.proof(&nonce, "ha-brain");
+        assert!(keys
+            .get("ha-brain")
+            .unwrap()
+            .verify(&nonce, "ha-brain", &proof));
+    }
+}
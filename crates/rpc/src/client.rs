@@ -1,4 +1,5 @@
 use core::fmt;
+use std::collections::HashMap;
 use std::marker::Unpin;
 use std::time::Duration;
 
@@ -7,37 +8,99 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::net::TcpStream;
 use tokio::net::ToSocketAddrs;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::error::Elapsed;
-use tokio::time::timeout_at;
 use tokio::time::Instant;
+use tokio::time::{timeout, timeout_at};
 use tokio_serde::formats::Bincode;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::{debug, warn};
 
+use crate::auth::Key;
+use crate::tls;
 use crate::Request;
 use crate::Response;
+use crate::Transport;
 
-pub struct RpcClient<RpcReq, RpcResp>
-where
-    RpcResp: Serialize,
-{
-    stream: tokio_serde::Framed<
-        Framed<TcpStream, LengthDelimitedCodec>,
-        Response<RpcResp>,
-        Request<RpcReq>,
-        Bincode<Response<RpcResp>, Request<RpcReq>>,
-    >,
+type Stream<RpcReq, RpcResp, SubReq> = tokio_serde::Framed<
+    Framed<Transport, LengthDelimitedCodec>,
+    Response<RpcResp>,
+    Request<RpcReq, SubReq>,
+    Bincode<Response<RpcResp>, Request<RpcReq, SubReq>>,
+>;
+
+/// Calls that wait for the connection task to send them
+const COMMAND_BUFFER: usize = 32;
+/// Updates received but not yet taken by [`Subscription::next`]. A
+/// subscription that falls further behind is ended.
+const UPDATE_BUFFER: usize = 32;
+
+/// A handle to a connection. Clones share the connection, calls from all of
+/// them can be outstanding at the same time. The connection closes once
+/// every handle and [`Subscription`] is dropped.
+pub struct RpcClient<RpcReq, RpcResp, SubReq = ()> {
+    commands: mpsc::Sender<Command<RpcReq, RpcResp, SubReq>>,
 }
 
-impl<T, V: Serialize> fmt::Debug for RpcClient<T, V> {
+impl<T, V, S> fmt::Debug for RpcClient<T, V, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RpcClient").finish()
     }
 }
 
+impl<T, V, S> Clone for RpcClient<T, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+type Updates<RpcResp> = mpsc::Receiver<Result<RpcResp, RpcError>>;
+
+/// Updates for one subscription on a connection. Calls can still be made
+/// on the [`RpcClient`] it came from. Keep reading the updates, once too
+/// many are waiting the subscription is ended with [`RpcError::Lagged`].
+/// Dropping this ends the subscription.
+pub struct Subscription<RpcReq, RpcResp, SubReq = ()> {
+    id: u64,
+    commands: mpsc::Sender<Command<RpcReq, RpcResp, SubReq>>,
+    updates: Updates<RpcResp>,
+    /// No more updates will come
+    ended: bool,
+}
+
+impl<T, V, S> fmt::Debug for Subscription<T, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+enum Command<RpcReq, RpcResp, SubReq> {
+    Rpc {
+        request: RpcReq,
+        answer: oneshot::Sender<Result<RpcResp, RpcError>>,
+    },
+    Subscribe {
+        /// Set to replace an existing subscription
+        id: Option<u64>,
+        request: SubReq,
+        answer: oneshot::Sender<Result<(u64, Updates<RpcResp>), RpcError>>,
+    },
+    Unsubscribe {
+        id: u64,
+    },
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectError {
     #[error("Error while connecting to server: {0}")]
     Io(std::io::Error),
+    #[error("TLS handshake with server failed: {0}")]
+    Tls(std::io::Error),
     #[error("Could not send handshake: {0}")]
     Sending(std::io::Error),
     #[error("Timed out waiting for server")]
@@ -48,6 +111,10 @@ pub enum ConnectError {
     Receiving(std::io::Error),
     #[error("Client was already connected")]
     AlreadyConnected,
+    #[error("Server requires authentication but we have no key")]
+    KeyNeeded,
+    #[error("Server rejected our key")]
+    AuthFailed,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -60,15 +127,57 @@ pub enum RpcError {
     Receiving(std::io::Error),
     #[error("Server closed connection before it awnserd")]
     ConnectionClosed,
+    #[error("Server ended the subscription")]
+    SubscriptionEnded,
+    #[error(
+        "Subscription fell behind and has been ended, updates were not taken \
+        fast enough"
+    )]
+    Lagged,
 }
 
-impl<RpcReq, RpcResp> RpcClient<RpcReq, RpcResp>
+impl<RpcReq, RpcResp, SubReq> RpcClient<RpcReq, RpcResp, SubReq>
 where
-    RpcReq: Unpin + Serialize + fmt::Debug,
-    RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug,
+    RpcReq: Unpin + Serialize + fmt::Debug + Send + 'static,
+    RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + fmt::Debug + Send + 'static,
 {
     pub async fn connect(addr: impl ToSocketAddrs, name: String) -> Result<Self, ConnectError> {
+        Self::connect_inner(addr, name, None, None).await
+    }
+
+    /// Like [`connect`](Self::connect) but proves to the server we are
+    /// `name` if it asks
+    pub async fn connect_with_key(
+        addr: impl ToSocketAddrs,
+        name: String,
+        key: &Key,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_inner(addr, name, Some(key), None).await
+    }
+
+    /// Needed if the server uses TLS, pass a `key` if it also asks clients
+    /// to authenticate
+    pub async fn connect_tls(
+        addr: impl ToSocketAddrs,
+        name: String,
+        tls: &tls::ClientConfig,
+        key: Option<&Key>,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_inner(addr, name, key, Some(tls)).await
+    }
+
+    async fn connect_inner(
+        addr: impl ToSocketAddrs,
+        name: String,
+        key: Option<&Key>,
+        tls: Option<&tls::ClientConfig>,
+    ) -> Result<Self, ConnectError> {
         let stream = TcpStream::connect(addr).await.map_err(ConnectError::Io)?;
+        let stream: Transport = match tls {
+            None => Box::new(stream),
+            Some(tls) => Box::new(tls.connect(stream).await.map_err(ConnectError::Tls)?),
+        };
 
         let length_delimited = Framed::new(
             stream,
@@ -77,103 +186,437 @@ where
                 .new_codec(),
         );
 
-        let mut stream = tokio_serde::Framed::new(length_delimited, Bincode::default());
+        let mut stream: Stream<RpcReq, RpcResp, SubReq> =
+            tokio_serde::Framed::new(length_delimited, Bincode::default());
         stream
-            .send(Request::Handshake { client_name: name })
+            .send(Request::Handshake {
+                client_name: name.clone(),
+            })
             .await
             .map_err(ConnectError::Sending)?;
 
-        match tokio::time::timeout(Duration::from_secs(2), stream.try_next()).await {
-            Ok(Ok(Some(Response::HandshakeOk))) => Ok(Self { stream }),
-            Ok(Ok(Some(Response::AlreadyConnected))) => Err(ConnectError::AlreadyConnected),
-            Ok(Ok(Some(other))) => unreachable!(
+        let mut response = receive_handshake(&mut stream).await?;
+        if let Response::Challenge { nonce } = response {
+            let key = key.ok_or(ConnectError::KeyNeeded)?;
+            let proof = key.proof(&nonce, &name);
+            stream
+                .send(Request::Authenticate { proof })
+                .await
+                .map_err(ConnectError::Sending)?;
+            response = receive_handshake(&mut stream).await?;
+        }
+
+        match response {
+            Response::HandshakeOk => (),
+            Response::AlreadyConnected => return Err(ConnectError::AlreadyConnected),
+            Response::AuthFailed => return Err(ConnectError::AuthFailed),
+            other => unreachable!(
                 "Server should return handshake response or error after sending \
                 first handshake, got impossible response: {other:?}"
             ),
-            Ok(Ok(None)) => Err(ConnectError::Closed),
-            Ok(Err(e)) => Err(ConnectError::Receiving(e)),
-            Err(_) => Err(ConnectError::Timeout),
         }
+
+        let (commands, rx) = mpsc::channel(COMMAND_BUFFER);
+        tokio::task::spawn(drive(stream, rx));
+        Ok(Self { commands })
     }
 
-    pub async fn subscribe(&mut self) -> Result<(), RpcError> {
-        fn send_timeout_err(_: Elapsed) -> RpcError {
-            RpcError::Sending(std::io::Error::new(std::io::ErrorKind::TimedOut, ""))
-        }
-        fn receive_timeout_err(_: Elapsed) -> RpcError {
-            RpcError::Receiving(std::io::Error::new(std::io::ErrorKind::TimedOut, ""))
-        }
+    /// A connection can have any number of subscriptions, calls keep
+    /// working while subscribed
+    pub async fn subscribe(
+        &self,
+        request: SubReq,
+    ) -> Result<Subscription<RpcReq, RpcResp, SubReq>, RpcError> {
+        let (id, updates) = request_subscription(&self.commands, None, request).await?;
+        Ok(Subscription {
+            id,
+            commands: self.commands.clone(),
+            updates,
+            ended: false,
+        })
+    }
+
+    /// The connection failed, calls now return
+    /// [`RpcError::ConnectionClosed`]
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
 
+    pub async fn send_receive(&self, request: RpcReq) -> Result<RpcResp, RpcError> {
         let deadline = Instant::now() + Duration::from_secs(5);
-        let request = Request::Subscribe;
-        timeout_at(deadline, self.stream.send(request))
+        let (answer, rx) = oneshot::channel();
+        let command = Command::Rpc { request, answer };
+        timeout_at(deadline, self.commands.send(command))
             .await
             .map_err(send_timeout_err)?
-            .map_err(RpcError::Sending)?;
+            .map_err(|_| RpcError::ConnectionClosed)?;
 
-        let response = timeout_at(deadline, self.stream.try_next())
+        timeout_at(deadline, rx)
             .await
             .map_err(receive_timeout_err)?
-            .map_err(RpcError::Receiving)?
-            .ok_or(RpcError::ConnectionClosed)?;
-        match response {
-            Response::AlreadyConnected
-            | Response::RpcResponse(_)
-            | Response::Update(_)
-            | Response::TooManyReq { .. }
-            | Response::HandshakeOk => {
-                unreachable!("incorrect response to subscribe: {response:?}")
-            }
-            Response::SubscribeOk => Ok(()),
-        }
+            .map_err(|_| RpcError::ConnectionClosed)?
     }
+}
 
+impl<RpcReq, RpcResp, SubReq> Subscription<RpcReq, RpcResp, SubReq> {
     pub async fn next(&mut self) -> Result<RpcResp, RpcError> {
-        let received = self
-            .stream
-            .try_next()
+        if self.ended {
+            return Err(RpcError::SubscriptionEnded);
+        }
+        let next = self
+            .updates
+            .recv()
             .await
-            .map_err(RpcError::Receiving)?
-            .ok_or(RpcError::ConnectionClosed)?;
-
-        match received {
-            Response::AlreadyConnected
-            | Response::RpcResponse(_)
-            | Response::TooManyReq { .. }
-            | Response::HandshakeOk
-            | Response::SubscribeOk => {
-                unreachable!("incorrect response to get next, are we subscribed?")
-            }
-            Response::Update(v) => Ok(v),
+            .unwrap_or(Err(RpcError::ConnectionClosed));
+        if let Err(RpcError::SubscriptionEnded | RpcError::Lagged) = next {
+            self.ended = true;
         }
+        next
     }
 
-    pub async fn send_receive(&mut self, request: RpcReq) -> Result<RpcResp, RpcError> {
-        fn send_timeout_err(_: Elapsed) -> RpcError {
-            RpcError::Sending(std::io::Error::new(std::io::ErrorKind::TimedOut, ""))
+    /// Replace what this subscribes to. Updates for the previous request
+    /// that have not been taken yet are dropped. If the server does not
+    /// confirm the change in time the subscription is ended.
+    pub async fn change(&mut self, request: SubReq) -> Result<(), RpcError> {
+        if self.ended {
+            return Err(RpcError::SubscriptionEnded);
         }
-        fn receive_timeout_err(_: Elapsed) -> RpcError {
-            RpcError::Receiving(std::io::Error::new(std::io::ErrorKind::TimedOut, ""))
+        match request_subscription(&self.commands, Some(self.id), request).await {
+            Ok((_, updates)) => {
+                self.updates = updates;
+                Ok(())
+            }
+            Err(RpcError::Sending(e) | RpcError::Receiving(e))
+                if e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                // the connection task unsubscribes once it notices the
+                // updates are no longer read or the change is confirmed late
+                self.updates = mpsc::channel(1).1;
+                let _ = self.commands.try_send(Command::Unsubscribe { id: self.id });
+                self.ended = true;
+                Err(RpcError::SubscriptionEnded)
+            }
+            Err(e) => Err(e),
         }
+    }
 
-        let deadline = Instant::now() + Duration::from_secs(5);
-        let request = Request::Rpc(request);
-        timeout_at(deadline, self.stream.send(request))
+    /// Like dropping the subscription but returns once the server has
+    /// been asked to stop sending updates
+    pub async fn unsubscribe(self) -> Result<(), RpcError> {
+        let command = Command::Unsubscribe { id: self.id };
+        timeout(Duration::from_secs(5), self.commands.send(command))
             .await
             .map_err(send_timeout_err)?
-            .map_err(RpcError::Sending)?;
-        match timeout_at(deadline, self.stream.try_next())
-            .await
-            .map_err(receive_timeout_err)?
-            .map_err(RpcError::Receiving)?
-            .ok_or(RpcError::ConnectionClosed)?
-        {
-            Response::AlreadyConnected
-            | Response::Update(_)
-            | Response::TooManyReq { .. }
-            | Response::HandshakeOk
-            | Response::SubscribeOk => unreachable!("only expected after a subscribe"),
-            Response::RpcResponse(v) => Ok(v),
+            .map_err(|_| RpcError::ConnectionClosed)
+    }
+}
+
+async fn request_subscription<RpcReq, RpcResp, SubReq>(
+    commands: &mpsc::Sender<Command<RpcReq, RpcResp, SubReq>>,
+    id: Option<u64>,
+    request: SubReq,
+) -> Result<(u64, Updates<RpcResp>), RpcError> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let (answer, rx) = oneshot::channel();
+    let command = Command::Subscribe {
+        id,
+        request,
+        answer,
+    };
+    timeout_at(deadline, commands.send(command))
+        .await
+        .map_err(send_timeout_err)?
+        .map_err(|_| RpcError::ConnectionClosed)?;
+
+    timeout_at(deadline, rx)
+        .await
+        .map_err(receive_timeout_err)?
+        .map_err(|_| RpcError::ConnectionClosed)?
+}
+
+async fn receive_handshake<RpcReq, RpcResp, SubReq>(
+    stream: &mut Stream<RpcReq, RpcResp, SubReq>,
+) -> Result<Response<RpcResp>, ConnectError>
+where
+    RpcReq: Unpin + Serialize,
+    RpcResp: Unpin + Serialize + DeserializeOwned,
+    SubReq: Unpin + Serialize,
+{
+    match tokio::time::timeout(Duration::from_secs(2), stream.try_next()).await {
+        Ok(Ok(Some(response))) => Ok(response),
+        Ok(Ok(None)) => Err(ConnectError::Closed),
+        Ok(Err(e)) => Err(ConnectError::Receiving(e)),
+        Err(_) => Err(ConnectError::Timeout),
+    }
+}
+
+fn send_timeout_err(_: Elapsed) -> RpcError {
+    RpcError::Sending(std::io::Error::new(std::io::ErrorKind::TimedOut, ""))
+}
+
+fn receive_timeout_err(_: Elapsed) -> RpcError {
+    RpcError::Receiving(std::io::Error::new(std::io::ErrorKind::TimedOut, ""))
+}
+
+/// Owns the connection, sends the calls of every handle and routes the
+/// responses and updates back by their id. Ends when the connection fails
+/// or every handle and subscription is dropped. Calls that are still
+/// waiting then get [`RpcError::ConnectionClosed`], or the reason if the
+/// server sent something it should not have.
+async fn drive<RpcReq, RpcResp, SubReq>(
+    mut stream: Stream<RpcReq, RpcResp, SubReq>,
+    mut commands: mpsc::Receiver<Command<RpcReq, RpcResp, SubReq>>,
+) where
+    RpcReq: Unpin + Serialize + fmt::Debug,
+    RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + fmt::Debug,
+{
+    let mut next_id = 0u64;
+    let mut next_sub_id = 0u64;
+    let mut waiting = HashMap::new();
+    let mut subscribing = HashMap::new();
+    let mut subscriptions: HashMap<u64, mpsc::Sender<_>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                None => return,
+                Some(Command::Rpc { request, answer }) => {
+                    let id = next_id;
+                    next_id += 1;
+                    if let Err(e) = stream.send(Request::Rpc { id, request }).await {
+                        let _ = answer.send(Err(RpcError::Sending(e)));
+                        return;
+                    }
+                    // forget calls that timed out
+                    waiting.retain(|_, answer: &mut oneshot::Sender<_>| !answer.is_closed());
+                    waiting.insert(id, answer);
+                }
+                Some(Command::Subscribe { id, request, answer }) => {
+                    let id = id.unwrap_or_else(|| {
+                        let id = next_sub_id;
+                        next_sub_id += 1;
+                        id
+                    });
+                    if let Err(e) = stream.send(Request::Subscribe { id, request }).await {
+                        let _ = answer.send(Err(RpcError::Sending(e)));
+                        return;
+                    }
+                    subscribing.insert(id, answer);
+                }
+                Some(Command::Unsubscribe { id }) => {
+                    subscriptions.remove(&id);
+                    if let Err(e) = stream.send(Request::Unsubscribe { id }).await {
+                        debug!("Could not send unsubscribe: {e}");
+                        return;
+                    }
+                }
+            },
+            received = stream.try_next() => match received {
+                Ok(Some(Response::RpcResponse { id, response })) => {
+                    if let Some(answer) = waiting.remove(&id) {
+                        // fine if the call timed out
+                        let _ = answer.send(Ok(response));
+                    } else {
+                        debug!("No call waiting for response with id: {id}");
+                    }
+                }
+                Ok(Some(Response::SubscribeOk { id })) => {
+                    // a fresh channel so updates from before a change are dropped
+                    let (tx, rx) = mpsc::channel(UPDATE_BUFFER);
+                    let subscribed = subscribing
+                        .remove(&id)
+                        .is_some_and(|answer| answer.send(Ok((id, rx))).is_ok());
+                    if subscribed {
+                        subscriptions.insert(id, tx);
+                    } else {
+                        // subscribe or change timed out
+                        subscriptions.remove(&id);
+                        if let Err(e) = stream.send(Request::Unsubscribe { id }).await {
+                            debug!("Could not send unsubscribe: {e}");
+                            return;
+                        }
+                    }
+                }
+                Ok(Some(Response::Update { id, update })) => {
+                    let Some(tx) = subscriptions.get(&id) else {
+                        // we unsubscribed, the server had not processed it yet
+                        continue;
+                    };
+                    // waiting for a slow subscription would hold up the
+                    // others and every call
+                    match tx.try_send(Ok(update)) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(_)) => {
+                            warn!("Subscription {id} fell behind, ending it");
+                            let tx = subscriptions.remove(&id).expect("just got it");
+                            end_subscription(tx, RpcError::Lagged);
+                        }
+                        Err(TrySendError::Closed(_)) => {
+                            // the subscription was dropped
+                            subscriptions.remove(&id);
+                        }
+                    }
+                    if let Err(e) = stream.send(Request::Unsubscribe { id }).await {
+                        debug!("Could not send unsubscribe: {e}");
+                        return;
+                    }
+                }
+                Ok(Some(Response::SubscriptionEnded { id })) => {
+                    if let Some(tx) = subscriptions.remove(&id) {
+                        end_subscription(tx, RpcError::SubscriptionEnded);
+                    }
+                }
+                Ok(Some(other)) => {
+                    warn!("Closing connection, server sent a response that is only \
+                        expected during connect: {other:?}");
+                    let error = || {
+                        RpcError::Receiving(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("unexpected response: {other:?}"),
+                        ))
+                    };
+                    for answer in waiting.into_values() {
+                        let _ = answer.send(Err(error()));
+                    }
+                    for answer in subscribing.into_values() {
+                        let _ = answer.send(Err(error()));
+                    }
+                    for tx in subscriptions.into_values() {
+                        end_subscription(tx, error());
+                    }
+                    return;
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    debug!("Connection failed: {e}");
+                    return;
+                }
+            },
         }
     }
 }
+
+/// Tells the subscription why it ended once it has taken the updates that
+/// are still waiting
+fn end_subscription<RpcResp: Send + 'static>(
+    tx: mpsc::Sender<Result<RpcResp, RpcError>>,
+    reason: RpcError,
+) {
+    tokio::task::spawn(async move {
+        let _ = tx.send(Err(reason)).await;
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type ServerSide = tokio_serde::Framed<
+        Framed<TcpStream, LengthDelimitedCodec>,
+        Request<u32, u32>,
+        Response<u32>,
+        Bincode<Request<u32, u32>, Response<u32>>,
+    >;
+
+    /// Accepts one client and completes its handshake, then leaves the
+    /// connection to `script`
+    async fn fake_server<F>(script: impl FnOnce(ServerSide) -> F + Send + 'static) -> SocketAddr
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let framed = Framed::new(stream, LengthDelimitedCodec::new());
+            let mut stream: ServerSide = tokio_serde::Framed::new(framed, Bincode::default());
+            let _handshake = stream.try_next().await.unwrap();
+            stream.send(Response::HandshakeOk).await.unwrap();
+            script(stream).await;
+        });
+        addr
+    }
+
+    async fn connect(addr: SocketAddr) -> RpcClient<u32, u32, u32> {
+        RpcClient::connect(addr, "test".to_owned()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn unexpected_response_closes_connection() {
+        let addr = fake_server(|mut stream| async move {
+            let _call = stream.try_next().await.unwrap();
+            stream.send(Response::HandshakeOk).await.unwrap();
+            // keep the connection open, the client has to close it
+            let _ = stream.try_next().await;
+        })
+        .await;
+
+        let client = connect(addr).await;
+        let res = client.send_receive(1).await;
+        assert!(matches!(res, Err(RpcError::Receiving(_))), "{res:?}");
+        let res = client.send_receive(1).await;
+        assert!(matches!(res, Err(RpcError::ConnectionClosed)), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn slow_subscription_is_ended_without_holding_up_calls() {
+        let addr = fake_server(|mut stream| async move {
+            let Ok(Some(Request::Subscribe { id, .. })) = stream.try_next().await else {
+                panic!("expected subscribe");
+            };
+            stream.send(Response::SubscribeOk { id }).await.unwrap();
+            for update in 0..UPDATE_BUFFER as u32 + 5 {
+                stream.send(Response::Update { id, update }).await.unwrap();
+            }
+            let Ok(Some(Request::Rpc { id, request })) = stream.try_next().await else {
+                panic!("expected a call");
+            };
+            let response = request + 1;
+            stream
+                .send(Response::RpcResponse { id, response })
+                .await
+                .unwrap();
+            while let Ok(Some(_)) = stream.try_next().await {}
+        })
+        .await;
+
+        let client = connect(addr).await;
+        let mut subscription = client.subscribe(0).await.unwrap();
+        assert_eq!(client.send_receive(41).await.unwrap(), 42);
+        for expected in 0..UPDATE_BUFFER as u32 {
+            assert_eq!(subscription.next().await.unwrap(), expected);
+        }
+        assert!(matches!(subscription.next().await, Err(RpcError::Lagged)));
+        assert!(matches!(
+            subscription.next().await,
+            Err(RpcError::SubscriptionEnded)
+        ));
+    }
+
+    #[tokio::test]
+    async fn change_that_times_out_ends_subscription() {
+        let addr = fake_server(|mut stream| async move {
+            let Ok(Some(Request::Subscribe { id, .. })) = stream.try_next().await else {
+                panic!("expected subscribe");
+            };
+            stream.send(Response::SubscribeOk { id }).await.unwrap();
+            // never confirms the change
+            while let Ok(Some(_)) = stream.try_next().await {}
+        })
+        .await;
+
+        let client = connect(addr).await;
+        let mut subscription = client.subscribe(0).await.unwrap();
+        let res = subscription.change(1).await;
+        assert!(matches!(res, Err(RpcError::SubscriptionEnded)), "{res:?}");
+        let res = subscription.next().await;
+        assert!(matches!(res, Err(RpcError::SubscriptionEnded)), "{res:?}");
+    }
+}
@@ -4,18 +4,32 @@ use std::time::Duration;
 
 use futures::{stream, Stream};
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
 
+pub mod auth;
 pub mod client;
 pub mod server;
+pub mod tls;
 
 // 8 MB
 pub(crate) const MAX_PACKAGE_SIZE: usize = 8 * 1024 * 1024;
 
+/// What the framing runs over, plain TCP or TLS
+pub(crate) trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+pub(crate) type Transport = Box<dyn Io>;
+
 pub trait SubscriberHandler: Send + 'static {
     type Update;
+    /// Send by the client to describe what it wants to subscribe to. Use `()`
+    /// if there is nothing to choose.
+    type Request;
+    /// The subscription is confirmed before the returned future is done,
+    /// it runs next to the other requests of the connection.
     #[allow(async_fn_in_trait)]
     fn setup(
         &mut self,
+        request: Self::Request,
     ) -> impl std::future::Future<Output = impl Stream<Item = Self::Update> + Send + 'static>
            + Send
            + 'static;
@@ -30,9 +44,11 @@ pub struct SubscribersUnsupported<Update> {
 
 impl<Update: std::marker::Send + 'static> SubscriberHandler for SubscribersUnsupported<Update> {
     type Update = Update;
+    type Request = ();
 
     fn setup(
         &mut self,
+        _: Self::Request,
     ) -> impl std::future::Future<
         Output = impl futures::prelude::Stream<Item = Self::Update> + Send + 'static,
     > + Send
@@ -42,10 +58,16 @@ impl<Update: std::marker::Send + 'static> SubscriberHandler for SubscribersUnsup
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Request<R> {
+pub enum Request<R, S = ()> {
     Handshake { client_name: String },
-    Subscribe,
-    Rpc(R),
+    /// Answer to [`Response::Challenge`]
+    Authenticate { proof: auth::Proof },
+    /// Starts a subscription or, if the connection already has one with
+    /// this `id`, replaces it. The client picks the `id`.
+    Subscribe { id: u64, request: S },
+    Unsubscribe { id: u64 },
+    /// The response carries the same `id`, the client picks it
+    Rpc { id: u64, request: R },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,9 +76,17 @@ where
     V: Serialize,
 {
     HandshakeOk,
-    SubscribeOk,
+    /// The server needs the client to prove it has the key of the name it
+    /// gave in the handshake
+    Challenge { nonce: auth::Nonce },
+    AuthFailed,
+    SubscribeOk { id: u64 },
     AlreadyConnected,
     TooManyReq { allowed_in: Duration },
-    RpcResponse(V),
-    Update(V),
+    /// Responses can arrive in a different order then their requests
+    RpcResponse { id: u64, response: V },
+    /// For the subscription with this `id`
+    Update { id: u64, update: V },
+    /// The server will send no more updates for this subscription
+    SubscriptionEnded { id: u64 },
 }
@@ -1,94 +1,167 @@
 use std::fmt;
+use std::future;
 use std::marker::Unpin;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU32;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::Duration;
 
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{eyre, Context};
 use color_eyre::Section;
-use futures::{SinkExt, StreamExt, TryStreamExt};
+use futures::stream::{self, FuturesUnordered};
+use futures::{FutureExt, SinkExt, Stream, StreamExt, TryStreamExt};
 use governor::clock::{Clock, DefaultClock};
-use governor::{Quota, RateLimiter};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::pin;
 use tokio_serde::formats::Bincode;
+use tokio_stream::StreamMap;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 use ratelimited_logger::{self as rlog, RateLimitedLogger};
 use tracing::{debug, error, info, instrument};
 
-type Conn<RpcReq, RpcResp> = tokio_serde::Framed<
-    Framed<TcpStream, LengthDelimitedCodec>,
-    crate::Request<RpcReq>,
+type Conn<RpcReq, RpcResp, SubReq> = tokio_serde::Framed<
+    Framed<Transport, LengthDelimitedCodec>,
+    crate::Request<RpcReq, SubReq>,
     crate::Response<RpcResp>,
-    Bincode<crate::Request<RpcReq>, crate::Response<RpcResp>>,
+    Bincode<crate::Request<RpcReq, SubReq>, crate::Response<RpcResp>>,
 >;
 
-pub async fn run<RpcReq, RpcResp, PerfFut>(
+/// With `keys` only clients that prove they have the key for the name they
+/// connect with are served, see [`auth`](crate::auth). With `tls` clients
+/// must connect using TLS, see [`tls`](crate::tls).
+pub async fn run<RpcReq, RpcResp, SubReq, PerfFut>(
     port: u16,
+    keys: Option<Keys>,
+    tls: Option<tls::ServerConfig>,
     perform_request: impl Fn(RpcReq, &str) -> PerfFut + Clone + Send + 'static,
-    sub_handler: Option<impl SubscriberHandler<Update = RpcResp> + Clone + Send + 'static>,
+    sub_handler: Option<
+        impl SubscriberHandler<Update = RpcResp, Request = SubReq> + Clone + Send + 'static,
+    >,
 ) -> color_eyre::Result<()>
 where
     RpcReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
     RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
     PerfFut: Future<Output = RpcResp> + Send + 'static,
 {
     let quota = Quota::with_period(Duration::from_secs(1))
         .unwrap()
         .allow_burst(NonZeroU32::new(5u32).unwrap());
-    let limiter = RateLimiter::keyed(quota);
+    let gate = Arc::new(Gate {
+        keys,
+        tls,
+        limiter: RateLimiter::keyed(quota),
+        logger: Mutex::new(RateLimitedLogger::new()),
+    });
     let listener = TcpListener::bind(format!("0.0.0.0:{port}"))
         .await
         .wrap_err("Could not bind to address")
         .with_note(|| format!("port: {port}"))?;
-    let mut logger = RateLimitedLogger::new();
 
     loop {
         let (socket, source) = match listener.accept().await {
             Err(e) => {
+                let mut logger = gate.logger();
                 rlog::warn!(logger; "client could not connect: {e}");
                 continue;
             }
             Ok(res) => res,
         };
+        tokio::task::spawn(admit_and_handle(
+            socket,
+            source,
+            gate.clone(),
+            perform_request.clone(),
+            sub_handler.clone(),
+        ));
+    }
+}
 
-        let Some((mut conn, name)) =
-            handshake_and_log::<RpcReq, RpcResp>(socket, source, &mut logger).await
-        else {
-            continue;
-        };
+/// State shared by the tasks that admit new connections
+struct Gate {
+    keys: Option<Keys>,
+    tls: Option<tls::ServerConfig>,
+    limiter: DefaultKeyedRateLimiter<(IpAddr, String)>,
+    logger: Mutex<RateLimitedLogger>,
+}
 
-        if let Err(allowed_again) = limiter.check_key(&(source.ip(), name.clone())) {
-            let allowed_in = allowed_again.wait_time_from(DefaultClock::default().now());
-            let _ignore_err = conn.send(crate::Response::TooManyReq { allowed_in });
-            continue;
-        }
+impl Gate {
+    fn logger(&self) -> MutexGuard<'_, RateLimitedLogger> {
+        self.logger.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
 
-        info!("Client {name} connected from {source}");
-        let Ok(()) = conn.send(crate::Response::HandshakeOk).await else {
-            continue;
-        };
+/// Does the TLS handshake, the handshake and, if needed, authentication
+/// before serving the client. Runs in the task of the connection so a slow
+/// client does not hold up others that are connecting.
+async fn admit_and_handle<RpcReq, RpcResp, SubReq, PerfFut>(
+    socket: TcpStream,
+    source: SocketAddr,
+    gate: Arc<Gate>,
+    perform_request: impl Fn(RpcReq, &str) -> PerfFut + Clone + Send + 'static,
+    sub_handler: Option<
+        impl SubscriberHandler<Update = RpcResp, Request = SubReq> + Send + 'static,
+    >,
+) where
+    RpcReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    PerfFut: Future<Output = RpcResp> + Send + 'static,
+{
+    let socket: Transport = match &gate.tls {
+        None => Box::new(socket),
+        Some(tls) => match tls.accept(socket).await {
+            Ok(stream) => Box::new(stream),
+            Err(e) => {
+                let mut logger = gate.logger();
+                rlog::warn!(logger; "TLS handshake with client from {source} failed: {e}");
+                return;
+            }
+        },
+    };
 
-        tokio::task::spawn(handle_client(
-            conn,
-            name,
-            perform_request.clone(),
-            sub_handler.clone(),
-        ));
+    let Some((mut conn, name)) =
+        handshake_and_log::<RpcReq, RpcResp, SubReq>(socket, source, &gate).await
+    else {
+        return;
+    };
+
+    if let Err(allowed_again) = gate.limiter.check_key(&(source.ip(), name.clone())) {
+        let allowed_in = allowed_again.wait_time_from(DefaultClock::default().now());
+        let _ignore_err = conn.send(crate::Response::TooManyReq { allowed_in });
+        return;
     }
+
+    if let Some(keys) = &gate.keys {
+        if let Err(e) = authenticate(&mut conn, &name, keys).await {
+            let _ignore_err = conn.send(crate::Response::AuthFailed).await;
+            let mut logger = gate.logger();
+            rlog::warn!(logger; "client {name} from {source} failed to authenticate: {e}");
+            return;
+        }
+    }
+
+    info!("Client {name} connected from {source}");
+    let Ok(()) = conn.send(crate::Response::HandshakeOk).await else {
+        return;
+    };
+
+    handle_client(conn, name, perform_request, sub_handler).await;
 }
 
-async fn handshake_and_log<RpcReq, RpcResp>(
-    stream: TcpStream,
+async fn handshake_and_log<RpcReq, RpcResp, SubReq>(
+    stream: Transport,
     source: SocketAddr,
-    logger: &mut RateLimitedLogger,
-) -> Option<(Conn<RpcReq, RpcResp>, String)>
+    gate: &Gate,
+) -> Option<(Conn<RpcReq, RpcResp, SubReq>, String)>
 where
     RpcReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
     RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
 {
     let length_delimited = Framed::new(
         stream,
@@ -96,10 +169,16 @@ where
             .max_frame_length(super::MAX_PACKAGE_SIZE)
             .new_codec(),
     );
-    let mut stream: tokio_serde::Framed<_, crate::Request<RpcReq>, crate::Response<RpcResp>, _> =
-        tokio_serde::Framed::new(length_delimited, Bincode::default());
+    let mut stream: tokio_serde::Framed<
+        _,
+        crate::Request<RpcReq, SubReq>,
+        crate::Response<RpcResp>,
+        _,
+    > = tokio_serde::Framed::new(length_delimited, Bincode::default());
 
-    match stream.try_next().await {
+    let received = stream.try_next().await;
+    let mut logger = gate.logger();
+    match received {
         Ok(Some(crate::Request::Handshake { client_name })) => {
             return Some((stream, client_name));
         }
@@ -115,22 +194,97 @@ where
     None
 }
 
+/// Asks the client to prove it has the key of `name`. A client with a name
+/// that has no key gets the same challenge, it just can not pass.
+async fn authenticate<RpcReq, RpcResp, SubReq>(
+    conn: &mut Conn<RpcReq, RpcResp, SubReq>,
+    name: &str,
+    keys: &Keys,
+) -> color_eyre::Result<()>
+where
+    RpcReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+{
+    let nonce: auth::Nonce = rand::random();
+    conn.send(crate::Response::Challenge { nonce })
+        .await
+        .wrap_err("Could not send challenge")?;
+
+    let answer = tokio::time::timeout(Duration::from_secs(2), conn.try_next())
+        .await
+        .map_err(|_| eyre!("Timed out waiting for answer to challenge"))?
+        .wrap_err("Could not receive answer to challenge")?;
+    match answer {
+        Some(crate::Request::Authenticate { proof }) => {
+            if keys
+                .get(name)
+                .is_some_and(|key| key.verify(&nonce, name, &proof))
+            {
+                Ok(())
+            } else {
+                Err(eyre!("Wrong key or unknown name"))
+            }
+        }
+        Some(other) => Err(eyre!("Expected an answer to the challenge, got: {other:?}")),
+        None => Err(eyre!("Connection closed before answering challenge")),
+    }
+}
+
 use core::future::Future;
 
-use crate::SubscriberHandler;
+use crate::auth::{self, Keys};
+use crate::{tls, SubscriberHandler, Transport};
+
+/// Requests of one client that are processed at the same time. No new
+/// requests are read while this many are in flight.
+const MAX_IN_FLIGHT: usize = 16;
+
+/// Yields `None` once after the updates end
+type Updates<RpcResp> = Pin<Box<dyn Stream<Item = Option<RpcResp>> + Send>>;
+
 #[instrument(skip(conn, perform_request, sub_handler))]
-async fn handle_client<RpcReq, RpcResp, PerfFut>(
-    mut conn: Conn<RpcReq, RpcResp>,
+async fn handle_client<RpcReq, RpcResp, SubReq, PerfFut>(
+    mut conn: Conn<RpcReq, RpcResp, SubReq>,
     client_name: String,
     perform_request: impl Fn(RpcReq, &str) -> PerfFut + Clone + Send + 'static,
-    mut sub_handler: Option<impl SubscriberHandler<Update = RpcResp> + Send + 'static>,
+    mut sub_handler: Option<
+        impl SubscriberHandler<Update = RpcResp, Request = SubReq> + Send + 'static,
+    >,
 ) where
     RpcReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
     RpcResp: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
+    SubReq: Unpin + Serialize + DeserializeOwned + fmt::Debug + Send + 'static,
     PerfFut: Future<Output = RpcResp> + Send + 'static,
 {
+    let mut in_flight = FuturesUnordered::new();
+    let mut subscriptions: StreamMap<u64, Updates<RpcResp>> = StreamMap::new();
     loop {
-        let request = match conn.try_next().await {
+        let received = tokio::select! {
+            received = conn.try_next(), if in_flight.len() < MAX_IN_FLIGHT => received,
+            Some((id, response)) = in_flight.next() => {
+                if let Err(e) = conn.send(crate::Response::RpcResponse { id, response }).await {
+                    error!("Error sending response to client: {e:?}");
+                    return;
+                }
+                continue;
+            }
+            Some((id, update)) = subscriptions.next() => {
+                let response = match update {
+                    Some(update) => crate::Response::Update { id, update },
+                    None => {
+                        debug!("Subscription {id} ended");
+                        crate::Response::SubscriptionEnded { id }
+                    }
+                };
+                if let Err(e) = conn.send(response).await {
+                    error!("Error sending update to client: {e:?}");
+                    return;
+                }
+                continue;
+            }
+        };
+        let request = match received {
             Ok(Some(request)) => request,
             Ok(None) => {
                 debug!("Connection ended");
@@ -142,39 +296,35 @@ async fn handle_client<RpcReq, RpcResp, PerfFut>(
             }
         };
         match request {
-            crate::Request::Rpc(rpc_request) => {
-                let response = perform_request(rpc_request, &client_name).await;
-                if let Err(e) = conn.send(crate::Response::RpcResponse(response)).await {
+            crate::Request::Rpc { id, request } => {
+                let response = perform_request(request, &client_name);
+                in_flight.push(response.map(move |response| (id, response)));
+            }
+            crate::Request::Subscribe { id, request } => {
+                let Some(sub_handler) = sub_handler.as_mut() else {
+                    error!("Got subscribe request but no subscribe possible");
+                    return;
+                };
+                // set up while the connection keeps serving other
+                // requests, a slow setup only delays this subscription
+                let updates = stream::once(sub_handler.setup(request))
+                    .flatten()
+                    .map(Some)
+                    .chain(stream::once(future::ready(None)));
+                // replaces the subscription if it already exists
+                subscriptions.insert(id, Box::pin(updates));
+                if let Err(e) = conn.send(crate::Response::SubscribeOk { id }).await {
                     error!("Error sending response to client: {e:?}");
                     return;
                 }
             }
-            crate::Request::Subscribe => {
-                if let Some(mut sub_handler) = sub_handler.take() {
-                    let stream = sub_handler.setup().await;
-                    pin!(stream);
-                    if let Err(e) = conn.send(crate::Response::SubscribeOk).await {
-                        error!("Error sending response to client: {e:?}");
-                        return;
-                    }
-
-                    loop {
-                        let Some(update) = stream.next().await else {
-                            error!("Error subscribe stream should never end");
-                            return;
-                        };
-                        if let Err(e) = conn.send(crate::Response::Update(update)).await {
-                            error!("Error sending response to client: {e:?}");
-                            return;
-                        }
-                    }
-                } else {
-                    error!("Got subscribe request but no subscribe possible");
-                    return;
+            crate::Request::Unsubscribe { id } => {
+                if subscriptions.remove(&id).is_none() {
+                    debug!("Client unsubscribed from unknown or ended subscription: {id}");
                 }
             }
-            crate::Request::Handshake { .. } => {
-                error!("Handshake request only allowed during connect");
+            crate::Request::Handshake { .. } | crate::Request::Authenticate { .. } => {
+                error!("Handshake and authenticate requests only allowed during connect");
                 return;
             }
         };
@@ -0,0 +1,204 @@
+//! Optional TLS for rpc connections. The framing on top of it does not
+//! change, client and server must both use TLS or neither.
+//!
+//! The server needs its certificate (chain) and private key as PEM files.
+//! A client trusts the server either because a private CA signed its
+//! certificate or because it presents one of a list of pinned certificates.
+//! Pinning suits self-signed certificates, for example from:
+//! ```text
+//! openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+//!     -keyout key.pem -out cert.pem -days 3650 -subj "/CN=data-server"
+//! ```
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::{Result, Section};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Time the other side gets to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Only used for SNI, a pinned certificate is accepted whatever its name
+const PINNED_SERVER_NAME: &str = "pinned.invalid";
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .wrap_err("Could not read certificates")
+        .with_note(|| format!("path: {}", path.display()))?;
+    if certificates.is_empty() {
+        return Err(eyre!("No certificates in file"))
+            .with_note(|| format!("path: {}", path.display()));
+    }
+    Ok(certificates)
+}
+
+#[derive(Clone)]
+pub struct ServerConfig(Arc<rustls::ServerConfig>);
+
+impl fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerConfig").finish_non_exhaustive()
+    }
+}
+
+impl ServerConfig {
+    /// `certificates` starts with the certificate of the server, any
+    /// intermediate certificates follow it
+    pub fn load(certificates: &Path, key: &Path) -> Result<Self> {
+        let certificates = load_certificates(certificates)?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .wrap_err("Could not read private key")
+            .with_note(|| format!("path: {}", key.display()))?;
+        let config = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .wrap_err("Could not set up TLS")?
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .wrap_err("Private key does not fit certificate")?;
+        Ok(Self(Arc::new(config)))
+    }
+
+    pub(crate) async fn accept(&self, stream: TcpStream) -> io::Result<impl crate::Io> {
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::clone(&self.0));
+        timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))?
+    }
+}
+
+#[derive(Clone)]
+pub struct ClientConfig {
+    config: Arc<rustls::ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl fmt::Debug for ClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientConfig")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientConfig {
+    /// Trust a server if one of the CA certificates in `ca` signed its
+    /// certificate and that is valid for `server_name`, a DNS name or IP
+    /// address.
+    pub fn with_ca(ca: &Path, server_name: &str) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for certificate in load_certificates(ca)? {
+            roots
+                .add(certificate)
+                .wrap_err("Invalid CA certificate")
+                .with_note(|| format!("path: {}", ca.display()))?;
+        }
+        let server_name = ServerName::try_from(server_name.to_owned())
+            .wrap_err("Not a valid server name")
+            .with_note(|| format!("server name: {server_name}"))?;
+        let config = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .wrap_err("Could not set up TLS")?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    /// Trust a server only if it presents one of the certificates in these
+    /// files. Its name and the validity period of the certificate are not
+    /// checked.
+    pub fn pinned(certificates: &[PathBuf]) -> Result<Self> {
+        let mut pinned = Vec::new();
+        for path in certificates {
+            pinned.extend(load_certificates(path)?);
+        }
+        let provider = provider();
+        let verifier = Pinned {
+            certificates: pinned,
+            algorithms: provider.signature_verification_algorithms,
+        };
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .wrap_err("Could not set up TLS")?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Ok(Self {
+            config: Arc::new(config),
+            server_name: ServerName::try_from(PINNED_SERVER_NAME).expect("valid dns name"),
+        })
+    }
+
+    pub(crate) async fn connect(&self, stream: TcpStream) -> io::Result<impl crate::Io> {
+        let connector = tokio_rustls::TlsConnector::from(Arc::clone(&self.config));
+        timeout(
+            HANDSHAKE_TIMEOUT,
+            connector.connect(self.server_name.clone(), stream),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))?
+    }
+}
+
+#[derive(Debug)]
+struct Pinned {
+    certificates: Vec<CertificateDer<'static>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.certificates.iter().any(|pinned| pinned == end_entity) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,29 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use rpc::client::RpcClient;
+use rpc::SubscribersUnsupported;
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn silent_client_does_not_block_others() {
+    let port = reserve_port::ReservedPort::random().unwrap();
+    tokio::spawn(rpc::server::run(
+        port.port(),
+        None,
+        None,
+        |request: u32, _: &str| async move { request + 1 },
+        None::<SubscribersUnsupported<u32>>,
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // connects but never sends its handshake
+    let _silent = TcpStream::connect((Ipv4Addr::LOCALHOST, port.port()))
+        .await
+        .unwrap();
+    let client: RpcClient<u32, u32> =
+        RpcClient::connect((Ipv4Addr::LOCALHOST, port.port()), "second".to_owned())
+            .await
+            .unwrap();
+    assert_eq!(client.send_receive(1).await.unwrap(), 2);
+}
@@ -0,0 +1,99 @@
+use std::future::Future;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use futures::{stream, Stream};
+use rpc::client::{RpcClient, RpcError};
+use rpc::SubscriberHandler;
+
+/// Subscribers get the three numbers following the one they asked for
+#[derive(Debug, Clone)]
+struct Counter;
+
+/// Setting up a subscription starting here takes a minute
+const SLOW: u32 = 1000;
+
+async fn count_from(start: u32) -> impl Stream<Item = u32> + Send + 'static {
+    if start == SLOW {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    }
+    stream::iter(start + 1..=start + 3)
+}
+
+impl SubscriberHandler for Counter {
+    type Update = u32;
+    type Request = u32;
+
+    fn setup(
+        &mut self,
+        start: Self::Request,
+    ) -> impl Future<Output = impl Stream<Item = Self::Update> + Send + 'static> + Send + 'static
+    {
+        count_from(start)
+    }
+}
+
+/// Answers every request with the request plus one
+async fn connect() -> (reserve_port::ReservedPort, RpcClient<u32, u32, u32>) {
+    let port = reserve_port::ReservedPort::random().unwrap();
+    tokio::spawn(rpc::server::run(
+        port.port(),
+        None,
+        None,
+        |request: u32, _: &str| async move { request + 1 },
+        Some(Counter),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = RpcClient::connect(
+        (Ipv4Addr::LOCALHOST, port.port()),
+        "subscribe_integration_tests".to_owned(),
+    )
+    .await
+    .unwrap();
+    (port, client)
+}
+
+#[tokio::test]
+async fn calls_work_while_subscribed() {
+    let (_port, client) = connect().await;
+
+    let mut first = client.subscribe(0).await.unwrap();
+    let mut second = client.subscribe(10).await.unwrap();
+    assert_eq!(client.send_receive(41).await.unwrap(), 42);
+
+    for expected in 1..=3 {
+        assert_eq!(first.next().await.unwrap(), expected);
+    }
+    assert!(matches!(
+        first.next().await,
+        Err(RpcError::SubscriptionEnded)
+    ));
+    assert_eq!(second.next().await.unwrap(), 11);
+    assert_eq!(client.send_receive(1).await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn slow_setup_does_not_hold_up_the_connection() {
+    let (_port, client) = connect().await;
+
+    let subscribed = tokio::time::timeout(Duration::from_secs(1), client.subscribe(SLOW)).await;
+    let _slow = subscribed
+        .expect("confirmed before the setup is done")
+        .unwrap();
+    let answered = tokio::time::timeout(Duration::from_secs(1), client.send_receive(41)).await;
+    assert_eq!(answered.expect("calls are not held up").unwrap(), 42);
+}
+
+#[tokio::test]
+async fn change_and_unsubscribe() {
+    let (_port, client) = connect().await;
+
+    let mut subscription = client.subscribe(0).await.unwrap();
+    assert_eq!(subscription.next().await.unwrap(), 1);
+    subscription.change(100).await.unwrap();
+    assert_eq!(subscription.next().await.unwrap(), 101);
+
+    subscription.unsubscribe().await.unwrap();
+    assert_eq!(client.send_receive(1).await.unwrap(), 2);
+}
@@ -0,0 +1,140 @@
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use rpc::client::{ConnectError, RpcClient};
+use rpc::tls;
+use rpc::SubscribersUnsupported;
+use temp_dir::TempDir;
+
+struct Files {
+    /// certificate the server presents
+    certificate: PathBuf,
+    key: PathBuf,
+    /// signed the certificate, only set if not self-signed
+    ca: Option<PathBuf>,
+}
+
+fn write(dir: &Path, name: &str, pem: String) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, pem).unwrap();
+    path
+}
+
+fn self_signed(dir: &Path, prefix: &str) -> Files {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    Files {
+        certificate: write(dir, &format!("{prefix}cert.pem"), certified.cert.pem()),
+        key: write(
+            dir,
+            &format!("{prefix}key.pem"),
+            certified.key_pair.serialize_pem(),
+        ),
+        ca: None,
+    }
+}
+
+fn signed_by_ca(dir: &Path) -> Files {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let certificate = CertificateParams::new(vec!["localhost".to_owned()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+    Files {
+        certificate: write(dir, "cert.pem", certificate.pem()),
+        key: write(dir, "key.pem", key.serialize_pem()),
+        ca: Some(write(dir, "ca.pem", ca.pem())),
+    }
+}
+
+/// Answers every request with the request plus one
+async fn start_server(files: &Files) -> reserve_port::ReservedPort {
+    let port = reserve_port::ReservedPort::random().unwrap();
+    let tls = tls::ServerConfig::load(&files.certificate, &files.key).unwrap();
+    tokio::spawn(rpc::server::run::<u32, u32, (), _>(
+        port.port(),
+        None,
+        Some(tls),
+        |request, _| async move { request + 1 },
+        None::<SubscribersUnsupported<u32>>,
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    port
+}
+
+async fn connect(port: u16, tls: &tls::ClientConfig) -> Result<RpcClient<u32, u32>, ConnectError> {
+    RpcClient::connect_tls(
+        (Ipv4Addr::LOCALHOST, port),
+        "tls_integration_tests".to_owned(),
+        tls,
+        None,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn trusts_server_signed_by_ca() {
+    let dir = TempDir::new().unwrap();
+    let files = signed_by_ca(dir.path());
+    let port = start_server(&files).await;
+
+    let ca = files.ca.as_deref().unwrap();
+    let trusting = tls::ClientConfig::with_ca(ca, "localhost").unwrap();
+    let client = connect(port.port(), &trusting).await.unwrap();
+    assert_eq!(client.send_receive(41).await.unwrap(), 42);
+
+    let wrong_name = tls::ClientConfig::with_ca(ca, "data-server").unwrap();
+    let err = connect(port.port(), &wrong_name).await.unwrap_err();
+    assert!(matches!(err, ConnectError::Tls(_)), "got: {err}");
+}
+
+#[tokio::test]
+async fn trusts_only_pinned_certificates() {
+    let dir = TempDir::new().unwrap();
+    let files = self_signed(dir.path(), "");
+    let other = self_signed(dir.path(), "other_");
+    let port = start_server(&files).await;
+
+    let pinned = tls::ClientConfig::pinned(&[other.certificate.clone(), files.certificate]);
+    let client = connect(port.port(), &pinned.unwrap()).await.unwrap();
+    assert_eq!(client.send_receive(1).await.unwrap(), 2);
+
+    let pinned_other = tls::ClientConfig::pinned(&[other.certificate]).unwrap();
+    let err = connect(port.port(), &pinned_other).await.unwrap_err();
+    assert!(matches!(err, ConnectError::Tls(_)), "got: {err}");
+}
+
+#[tokio::test]
+async fn plain_client_is_refused() {
+    let dir = TempDir::new().unwrap();
+    let files = self_signed(dir.path(), "");
+    let port = start_server(&files).await;
+
+    let res = RpcClient::<u32, u32>::connect(
+        (Ipv4Addr::LOCALHOST, port.port()),
+        "tls_integration_tests".to_owned(),
+    )
+    .await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn stalled_tls_handshake_does_not_block_others() {
+    let dir = TempDir::new().unwrap();
+    let files = self_signed(dir.path(), "");
+    let port = start_server(&files).await;
+
+    // connects but never starts the TLS handshake
+    let _stalled = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port.port()))
+        .await
+        .unwrap();
+    let pinned = tls::ClientConfig::pinned(&[files.certificate]).unwrap();
+    let client = connect(port.port(), &pinned).await.unwrap();
+    assert_eq!(client.send_receive(1).await.unwrap(), 2);
+}
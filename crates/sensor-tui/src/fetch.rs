@@ -2,19 +2,22 @@ use std::collections::VecDeque;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::ops::RangeInclusive;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::time::Duration;
 
 use color_eyre::Result;
 use jiff::{Span, Timestamp};
-use log_store::api::{ErrorEvent, Percentile};
+use log_store::api::{AffectorEvent, ErrorEvent, Percentile, Timing};
 use protocol::Reading;
+use tokio::sync::Mutex;
 use tokio::task;
 use tracing::debug;
 
 use crate::{client_name, Fetchable, Update};
 
 const MAX_IN_FLIGHT_REQUESTS: usize = 6;
+/// How far back the affector tab shows who controlled an affector
+const AFFECTOR_HISTORY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
 
 #[derive(Debug, Clone)]
 pub struct Data {
@@ -39,6 +42,8 @@ pub enum Request {
     Data(Data),
     Logs(Logs),
     Hist(Hist),
+    StorageStats,
+    AffectorLog(protocol::Affector),
 }
 
 impl Request {
@@ -62,20 +67,57 @@ impl Request {
     }
 }
 
+/// One connection to the data-store and one to the log-store, shared by
+/// every fetch. Made on first use and again once it failed.
+#[derive(Clone)]
+pub struct Stores {
+    data_store_addr: SocketAddr,
+    log_store_addr: SocketAddr,
+    data_store: Arc<Mutex<Option<data_store::api::Client>>>,
+    log_store: Arc<Mutex<Option<log_store::api::Client>>>,
+}
+
+impl Stores {
+    pub fn new(data_store_addr: SocketAddr, log_store_addr: SocketAddr) -> Self {
+        Self {
+            data_store_addr,
+            log_store_addr,
+            data_store: Arc::default(),
+            log_store: Arc::default(),
+        }
+    }
+
+    pub async fn data_store(&self) -> Result<data_store::api::Client> {
+        let mut connection = self.data_store.lock().await;
+        if let Some(client) = connection.as_ref().filter(|client| !client.is_closed()) {
+            return Ok(client.clone());
+        }
+        let client = data_store::api::Client::connect(self.data_store_addr, client_name()).await?;
+        *connection = Some(client.clone());
+        Ok(client)
+    }
+
+    pub async fn log_store(&self) -> Result<log_store::api::Client> {
+        let mut connection = self.log_store.lock().await;
+        if let Some(client) = connection.as_ref().filter(|client| !client.is_closed()) {
+            return Ok(client.clone());
+        }
+        let client = log_store::api::Client::connect(self.log_store_addr, client_name()).await?;
+        *connection = Some(client.clone());
+        Ok(client)
+    }
+}
+
 pub struct Fetch {
     pub recently_issued: VecDeque<Request>,
     pub tx: tokio::sync::mpsc::Sender<Request>,
 }
 
 impl Fetch {
-    pub fn new(
-        data_store: SocketAddr,
-        log_store: SocketAddr,
-        update_tx: mpsc::Sender<Update>,
-    ) -> Self {
+    pub fn new(stores: Stores, update_tx: mpsc::Sender<Update>) -> Self {
         let (tx, rx) = tokio::sync::mpsc::channel(32);
 
-        task::spawn(handle_requests(data_store, log_store, rx, update_tx));
+        task::spawn(handle_requests(stores, rx, update_tx));
         Self {
             recently_issued: VecDeque::new(),
             tx,
@@ -117,6 +159,16 @@ impl Fetch {
         }
     }
 
+    pub fn request_storage_stats(&mut self) {
+        debug!("Requesting storage stats");
+        self.request(Request::StorageStats);
+    }
+
+    pub fn request_affector_log(&mut self, affector: protocol::Affector) {
+        debug!("Requesting affector log for {affector:?}");
+        self.request(Request::AffectorLog(affector));
+    }
+
     fn history_outdated_not_updating(
         &mut self,
         reading: &Reading,
@@ -197,19 +249,19 @@ impl Fetch {
 }
 
 pub(crate) async fn handle_requests(
-    data_store: SocketAddr,
-    log_store: SocketAddr,
+    stores: Stores,
     mut rx: tokio::sync::mpsc::Receiver<Request>,
     tx: mpsc::Sender<Update>,
 ) {
     let mut inflight_request = VecDeque::new();
     while let Some(request) = rx.recv().await {
-        let data_store = data_store.clone();
+        let stores = stores.clone();
         let tx = tx.clone();
+        let failed = request.clone();
         let handle = match request {
             Request::Data(Data { reading, range }) => tokio::spawn(get_wrap_send(
-                get_data(data_store, reading.clone(), range),
-                |res| match res {
+                get_data(stores, reading.clone(), range),
+                move |res| match res {
                     Ok(data) => Update::Fetched {
                         reading,
                         thing: Fetchable::Data {
@@ -217,12 +269,15 @@ pub(crate) async fn handle_requests(
                             data: data.1,
                         },
                     },
-                    Err(err) => Update::FetchError(err),
+                    Err(error) => Update::FetchError {
+                        request: failed,
+                        error,
+                    },
                 },
                 tx,
             )),
             Request::Logs(Logs { reading, range }) => tokio::spawn(get_wrap_send(
-                get_logs(log_store, reading.clone()),
+                get_logs(stores, reading.clone(), range.clone()),
                 move |res| match res {
                     Ok(logs) => Update::Fetched {
                         reading,
@@ -231,18 +286,50 @@ pub(crate) async fn handle_requests(
                             start_at: *range.start(),
                         },
                     },
-                    Err(err) => Update::FetchError(err),
+                    Err(error) => Update::FetchError {
+                        request: failed,
+                        error,
+                    },
                 },
                 tx,
             )),
             Request::Hist(Hist { reading, range }) => tokio::spawn(get_wrap_send(
-                get_percentiles(log_store, reading.clone()),
+                get_percentiles(stores, reading.clone(), range.clone()),
                 move |res| match res {
-                    Ok(percentiles) => Update::Fetched {
+                    Ok((percentiles, cycle_percentiles)) => Update::Fetched {
                         reading,
-                        thing: Fetchable::Hist { percentiles, range },
+                        thing: Fetchable::Hist {
+                            percentiles,
+                            cycle_percentiles,
+                            range,
+                        },
+                    },
+                    Err(error) => Update::FetchError {
+                        request: failed,
+                        error,
+                    },
+                },
+                tx,
+            )),
+            Request::StorageStats => tokio::spawn(get_wrap_send(
+                get_storage_stats(stores),
+                move |res| match res {
+                    Ok(stats) => Update::StorageStats(stats),
+                    Err(error) => Update::FetchError {
+                        request: failed,
+                        error,
+                    },
+                },
+                tx,
+            )),
+            Request::AffectorLog(affector) => tokio::spawn(get_wrap_send(
+                get_affector_log(stores, affector),
+                move |res| match res {
+                    Ok(events) => Update::AffectorLog { affector, events },
+                    Err(error) => Update::FetchError {
+                        request: failed,
+                        error,
                     },
-                    Err(err) => Update::FetchError(err),
                 },
                 tx,
             )),
@@ -266,28 +353,114 @@ pub async fn get_wrap_send<T>(
 }
 
 pub async fn get_data(
-    data_store: SocketAddr,
+    stores: Stores,
     reading: Reading,
     range: RangeInclusive<Timestamp>,
 ) -> Result<(Vec<Timestamp>, Vec<f32>)> {
-    let mut api = data_store::api::Client::connect(data_store, client_name()).await?;
+    let mut api = stores.data_store().await?;
 
+    let span_ms = range.end().as_millisecond() - range.start().as_millisecond();
+    let bucket = bucket_for(Duration::from_millis(span_ms.unsigned_abs()));
     let history = api
-        .get_data(*range.start(), *range.end(), reading, 300)
+        .get_data_aligned(*range.start(), *range.end(), reading, bucket)
         .await?;
     Ok(history)
 }
 
-pub async fn get_logs(log_store: SocketAddr, reading: Reading) -> Result<Vec<ErrorEvent>> {
-    let mut api = log_store::api::Client::connect(log_store, client_name()).await?;
+/// The smallest round bucket duration that splits `range` into at most 300
+/// points. Buckets are aligned so panning keeps the plot stable.
+fn bucket_for(range: Duration) -> Duration {
+    const MAX_POINTS: u32 = 300;
+    const LADDER: [u64; 13] = [
+        1,
+        5,
+        10,
+        30,
+        60,
+        5 * 60,
+        15 * 60,
+        30 * 60,
+        60 * 60,
+        3 * 60 * 60,
+        6 * 60 * 60,
+        12 * 60 * 60,
+        24 * 60 * 60,
+    ];
 
-    let history = api.get_logs(reading.device()).await?;
-    Ok(history)
+    let needed = range / MAX_POINTS;
+    LADDER
+        .into_iter()
+        .map(Duration::from_secs)
+        .find(|bucket| *bucket >= needed)
+        .unwrap_or_else(|| Duration::from_secs(needed.as_secs().next_multiple_of(24 * 60 * 60)))
 }
 
-pub async fn get_percentiles(log_store: SocketAddr, reading: Reading) -> Result<Vec<Percentile>> {
-    let mut api = log_store::api::Client::connect(log_store, client_name()).await?;
+pub async fn get_storage_stats(stores: Stores) -> Result<Vec<data_store::api::SeriesStorage>> {
+    let mut api = stores.data_store().await?;
+    Ok(api.storage_stats().await?)
+}
+
+pub async fn get_logs(
+    stores: Stores,
+    reading: Reading,
+    range: RangeInclusive<Timestamp>,
+) -> Result<Vec<ErrorEvent>> {
+    const PAGE: usize = 500;
+    let mut api = stores.log_store().await?;
 
-    let percentile = api.get_percentiles(reading.device()).await?;
-    Ok(percentile)
+    let mut history = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = api
+            .get_logs(reading.device(), *range.start(), *range.end(), PAGE, cursor)
+            .await?;
+        history.extend(page.events);
+        cursor = page.next;
+        if cursor.is_none() {
+            return Ok(log_store::api::coalesce_flapping(history));
+        }
+    }
+}
+
+/// Who controlled affectors like `affector` recently, oldest first
+pub async fn get_affector_log(
+    stores: Stores,
+    affector: protocol::Affector,
+) -> Result<Vec<AffectorEvent>> {
+    const PAGE: usize = 500;
+    let mut api = stores.log_store().await?;
+
+    let end = Timestamp::now();
+    let start = end - Span::try_from(AFFECTOR_HISTORY).unwrap();
+    let mut history = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = api
+            .get_affector_log(affector, start, end, PAGE, cursor)
+            .await?;
+        history.extend(page.events);
+        cursor = page.next;
+        if cursor.is_none() {
+            return Ok(history);
+        }
+    }
+}
+
+/// Percentiles of the time between readings like `reading` and between the
+/// cycles of its device
+pub async fn get_percentiles(
+    stores: Stores,
+    reading: Reading,
+    range: RangeInclusive<Timestamp>,
+) -> Result<(Vec<Percentile>, Vec<Percentile>)> {
+    let mut api = stores.log_store().await?;
+
+    let device = reading.device();
+    let per_reading = api
+        .get_percentiles(Timing::Reading(reading), *range.start(), *range.end())
+        .await?;
+    let per_cycle = api
+        .get_percentiles(Timing::DeviceCycle(device), *range.start(), *range.end())
+        .await?;
+    Ok((per_reading, per_cycle))
 }
@@ -6,7 +6,7 @@ use std::thread;
 use clap::Parser;
 use color_eyre::eyre::{Report, WrapErr};
 use color_eyre::Result;
-use data_server::api::{Client, SubMessage};
+use data_server::api::{ConnectOptions, SubMessage};
 
 mod fetch;
 mod populate;
@@ -14,7 +14,7 @@ mod time;
 mod tui;
 
 pub(crate) use fetch::Fetch;
-use log_store::api::{ErrorEvent, Percentile};
+use log_store::api::{AffectorEvent, ErrorEvent, Percentile};
 use protocol::Reading;
 use tokio::task;
 
@@ -34,6 +34,7 @@ enum Fetchable {
     },
     Hist {
         percentiles: Vec<Percentile>,
+        cycle_percentiles: Vec<Percentile>,
         range: RangeInclusive<jiff::Timestamp>,
     },
 }
@@ -44,7 +45,10 @@ enum Update {
         reading: Reading,
         thing: Fetchable,
     },
-    FetchError(color_eyre::Report),
+    FetchError {
+        request: fetch::Request,
+        error: color_eyre::Report,
+    },
     SensorReading(protocol::Reading),
     SensorError(Box<protocol::Error>),
     SubscribeError(color_eyre::Report),
@@ -53,15 +57,22 @@ enum Update {
         affector: protocol::Affector,
         controlled_by: String,
     },
+    StorageStats(Vec<data_store::api::SeriesStorage>),
+    AffectorLog {
+        affector: protocol::Affector,
+        events: Vec<AffectorEvent>,
+    },
 }
 
-async fn receive_data(data_server: SocketAddr, tx: mpsc::Sender<Update>) {
-    let client = match Client::connect(data_server, client_name()).await {
+async fn receive_data(
+    data_server: SocketAddr,
+    options: ConnectOptions,
+    tx: mpsc::Sender<Update>,
+) {
+    let client = match options.connect(data_server, client_name()).await {
         Ok(client) => client,
         Err(err) => {
-            let _ignore_panicked_ui = tx.send(Update::SubscribeError(
-                Report::new(err).wrap_err("Could not connect to data server"),
-            ));
+            let _ignore_panicked_ui = tx.send(Update::SubscribeError(err));
             return;
         }
     };
@@ -80,28 +91,28 @@ async fn receive_data(data_server: SocketAddr, tx: mpsc::Sender<Update>) {
         let res = subbed
             .next()
             .await
-            .wrap_err("Error getting next reading from server")
-            .map(|msg| match msg {
-                SubMessage::Reading(reading) => Update::SensorReading(reading),
-                SubMessage::ErrorReport(error) => Update::SensorError(error),
-                SubMessage::AffectorControlled {
-                    affector,
-                    controlled_by,
-                } => Update::AffectorControlled {
-                    affector,
-                    controlled_by,
-                },
-            });
-
-        match res {
-            Ok(msg) => {
-                tx.send(msg).unwrap();
-            }
+            .wrap_err("Error getting next reading from server");
+
+        let msg = match res {
+            Ok(msg) => msg,
             Err(err) => {
                 tx.send(Update::SubscribeError(err)).unwrap();
                 break;
             }
-        }
+        };
+        let update = match msg {
+            SubMessage::Reading(reading) => Update::SensorReading(reading),
+            SubMessage::ErrorReport(error) => Update::SensorError(error),
+            SubMessage::AffectorControlled {
+                affector,
+                controlled_by,
+            } => Update::AffectorControlled {
+                affector,
+                controlled_by,
+            },
+            SubMessage::NodeDisconnected { .. } => continue,
+        };
+        tx.send(update).unwrap();
     }
 }
 
@@ -114,6 +125,9 @@ struct Cli {
     #[arg(short, long, default_value_t = SocketAddr::from(([192,168,1,43], 1235)))]
     data_server: SocketAddr,
 
+    #[command(flatten)]
+    data_server_options: ConnectOptions,
+
     /// server where we can fetch historical sensor data
     #[arg(short='s', long, default_value_t = SocketAddr::from(([192,168,1,43], 1236)))]
     data_store: SocketAddr,
@@ -129,6 +143,7 @@ async fn main() -> Result<()> {
 
     let Cli {
         data_server,
+        data_server_options,
         data_store,
         log_store,
     } = Cli::parse();
@@ -136,13 +151,14 @@ async fn main() -> Result<()> {
     let (tx1, rx1) = mpsc::channel();
     let (tx2, rx2) = mpsc::channel();
 
-    let fetcher = Fetch::new(data_store, log_store, tx1.clone());
+    let stores = fetch::Stores::new(data_store, log_store);
+    let fetcher = Fetch::new(stores.clone(), tx1.clone());
 
     let tx1_clone1 = tx1.clone();
     let tx1_clone2 = tx1.clone();
-    task::spawn(receive_data(data_server, tx1_clone1));
+    task::spawn(receive_data(data_server, data_server_options, tx1_clone1));
     thread::spawn(move || tui::run(rx1, tx2, fetcher));
-    thread::spawn(move || populate::tree(data_store, log_store, tx1_clone2));
+    task::spawn(populate::tree(stores, tx1_clone2));
 
     loop {
         let UserIntent::Shutdown = rx2.recv()?;
@@ -1,28 +1,16 @@
-use std::net::SocketAddr;
 use std::sync::mpsc;
 
 use color_eyre::Result;
 use tracing::warn;
 
-use crate::{client_name, Update};
+use crate::fetch::Stores;
+use crate::Update;
 
-pub fn tree(
-    data_store_addr: SocketAddr,
-    log_store_addr: SocketAddr,
-    tx: mpsc::Sender<Update>,
-) {
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_io()
-        .enable_time()
-        .build()
-        .unwrap();
-
-    let (res1, res2) = rt.block_on(async {
-        tokio::join!(
-            list_from_store(data_store_addr, tx.clone()),
-            list_from_logs(log_store_addr, tx)
-        )
-    });
+pub async fn tree(stores: Stores, tx: mpsc::Sender<Update>) {
+    let (res1, res2) = tokio::join!(
+        list_from_store(&stores, tx.clone()),
+        list_from_logs(&stores, tx)
+    );
 
     if let Err(e) = res1 {
         warn!("Could not populate readings list from store: {e}")
@@ -33,22 +21,16 @@ pub fn tree(
     }
 }
 
-async fn list_from_store(
-    data_store_addr: SocketAddr,
-    tx: mpsc::Sender<Update>,
-) -> Result<()> {
-    let mut client = data_store::api::Client::connect(data_store_addr, client_name()).await?;
+async fn list_from_store(stores: &Stores, tx: mpsc::Sender<Update>) -> Result<()> {
+    let mut client = stores.data_store().await?;
     let list = client.list_data().await?;
     tracing::debug!("list: {list:?}");
     tx.send(Update::ReadingList(list)).unwrap();
     Ok(())
 }
 
-async fn list_from_logs(
-    log_store_addr: SocketAddr,
-    tx: mpsc::Sender<Update>,
-) -> Result<()> {
-    let mut client = log_store::api::Client::connect(log_store_addr, client_name()).await?;
+async fn list_from_logs(stores: &Stores, tx: mpsc::Sender<Update>) -> Result<()> {
+    let mut client = stores.log_store().await?;
     let list = client.list_devices().await?;
     tracing::debug!("list: {list:?}");
     tx.send(Update::DeviceList(list)).unwrap();
@@ -19,26 +19,32 @@ use crate::{Fetch, Update, UserIntent};
 mod affectors;
 mod readings;
 mod render;
+mod storage;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 enum ActiveTab {
     #[default]
     Readings,
     Affectors,
+    Storage,
 }
 
 impl ActiveTab {
-    fn swap(self) -> Self {
-        match self {
-            Self::Readings => Self::Affectors,
-            Self::Affectors => Self::Readings,
-        }
+    const ALL: [Self; 3] = [Self::Readings, Self::Affectors, Self::Storage];
+
+    fn next(self) -> Self {
+        Self::ALL[(self.number() + 1) % Self::ALL.len()]
+    }
+
+    fn prev(self) -> Self {
+        Self::ALL[(self.number() + Self::ALL.len() - 1) % Self::ALL.len()]
     }
 
     fn number(&self) -> usize {
         match self {
             ActiveTab::Readings => 0,
             ActiveTab::Affectors => 1,
+            ActiveTab::Storage => 2,
         }
     }
 }
@@ -82,6 +88,7 @@ struct App {
     active_tab: ActiveTab,
     readings_tab: readings::Tab,
     affectors_tab: affectors::Tab,
+    storage_tab: storage::Tab,
 }
 
 impl App {
@@ -99,7 +106,14 @@ impl App {
                         self.readings_tab
                             .render(&mut fetcher, frame, layout, &self.theme)
                     }
-                    ActiveTab::Affectors => self.affectors_tab.render(frame, layout, &self.theme),
+                    ActiveTab::Affectors => {
+                        self.affectors_tab
+                            .render(&mut fetcher, frame, layout, &self.theme)
+                    }
+                    ActiveTab::Storage => {
+                        self.storage_tab
+                            .render(&mut fetcher, frame, layout, &self.theme)
+                    }
                 }
             })?;
 
@@ -109,10 +123,10 @@ impl App {
                     if key.kind == KeyEventKind::Press {
                         match key.code {
                             KeyCode::Left => {
-                                self.active_tab = self.active_tab.swap();
+                                self.active_tab = self.active_tab.prev();
                             }
                             KeyCode::Right => {
-                                self.active_tab = self.active_tab.swap();
+                                self.active_tab = self.active_tab.next();
                             }
                             KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                                 break Ok(());
@@ -123,6 +137,7 @@ impl App {
                         let res = match self.active_tab {
                             ActiveTab::Readings => self.readings_tab.handle_key(key),
                             ActiveTab::Affectors => self.affectors_tab.handle_key(key),
+                            ActiveTab::Storage => self.storage_tab.handle_key(key),
                         };
                         if let Some(unhandled_key) = res {
                             match unhandled_key.code {
@@ -139,6 +154,7 @@ impl App {
             };
 
             self.affectors_tab.process_update(&update);
+            self.storage_tab.process_update(&update);
             self.readings_tab.process_update(update);
         }
     }
@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 
 use crossterm::event::{KeyCode, KeyEvent};
+use log_store::api::AffectorEvent;
 use protocol::{affector, Affector, Device};
 use ratatui::layout::{Constraint, Layout, Rect};
 use ratatui::Frame;
 use tui_tree_widget::{TreeItem, TreeState};
 
-use crate::Update;
+use crate::fetch::Request;
+use crate::{Fetch, Update};
 
 use super::Theme;
 use protocol::affector::tree::Item;
@@ -21,6 +23,16 @@ struct AffectorState {
     affector: Affector,
     selected_control: usize,
     info: affector::Info,
+    history: History,
+}
+
+/// Who controlled the affector, from the log-store
+enum History {
+    NotRequested,
+    Fetching,
+    /// Oldest first
+    Fetched(Vec<AffectorEvent>),
+    Failed(String),
 }
 
 #[derive(Default)]
@@ -32,7 +44,7 @@ pub struct Tab {
 }
 
 impl Tab {
-    pub fn render(&mut self, frame: &mut Frame, layout: Rect, theme: &Theme) {
+    pub fn render(&mut self, fetcher: &mut Fetch, frame: &mut Frame, layout: Rect, theme: &Theme) {
         let [main, footer] =
             Layout::vertical([Constraint::Fill(1), Constraint::Max(1)]).areas(layout);
         let [left, right] =
@@ -47,10 +59,20 @@ impl Tab {
             .and_then(|key| self.data.get_mut(key));
 
         if let Some(ref mut data) = data {
-            let [top, bottom] =
-                Layout::vertical([Constraint::Fill(1), Constraint::Fill(1)]).areas(right);
+            if let History::NotRequested = data.history {
+                fetcher.request_affector_log(data.affector);
+                data.history = History::Fetching;
+            }
+
+            let [top, middle, bottom] = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+            ])
+            .areas(right);
             render::details(frame, &data.info, top);
-            render::controls(frame, data, bottom);
+            render::controls(frame, data, middle);
+            render::history(frame, &data.history, bottom);
         };
         render::footer(frame, footer, data, theme)
     }
@@ -72,6 +94,11 @@ impl Tab {
             KeyCode::Enter => {
                 self.tree_state.toggle_selected();
             }
+            KeyCode::Char('r') => {
+                if let Some(state) = data {
+                    state.history = History::NotRequested;
+                }
+            }
             _ => {
                 if let Some(state) = data {
                     return handle_key::handle(key, state);
@@ -85,10 +112,40 @@ impl Tab {
         let devices = match update {
             Update::ReadingList(_)
             | Update::Fetched { .. }
-            | Update::FetchError(_)
-            | Update::SubscribeError(_) => return,
-            Update::AffectorControlled { affector, .. } => {
+            | Update::SubscribeError(_)
+            | Update::StorageStats(_) => return,
+            Update::FetchError {
+                request: Request::AffectorLog(affector),
+                error,
+            } => {
+                if let Some(state) = self.data.get_mut(&tree_key(affector)) {
+                    state.history = History::Failed(error.to_string());
+                }
+                return;
+            }
+            Update::FetchError { .. } => return,
+            Update::AffectorLog { affector, events } => {
+                if let Some(state) = self.data.get_mut(&tree_key(affector)) {
+                    state.history = History::Fetched(events.clone());
+                }
+                return;
+            }
+            Update::AffectorControlled {
+                affector,
+                controlled_by,
+            } => {
                 self.update_tree(affector);
+                if let Some(AffectorState {
+                    history: History::Fetched(events),
+                    ..
+                }) = self.data.get_mut(&tree_key(affector))
+                {
+                    events.push(AffectorEvent {
+                        at: jiff::Timestamp::now(),
+                        affector: *affector,
+                        controlled_by: controlled_by.clone(),
+                    });
+                }
                 return;
             }
             Update::SensorReading(r) => &vec![r.device()],
@@ -135,12 +192,17 @@ impl Tab {
                 Item::Leaf(info) => {
                     let text = tree_node.name();
                     add_leaf(text, tree, key);
+                    let history = match self.data.remove(&key) {
+                        Some(existing) => existing.history,
+                        None => History::NotRequested,
+                    };
                     self.data.insert(
                         key,
                         AffectorState {
                             affector: affector.clone(),
                             info,
                             selected_control: 0,
+                            history,
                         },
                     );
                     return;
@@ -3,14 +3,14 @@ use protocol::affector;
 use ratatui::layout::{Alignment, Constraint, Layout, Rect};
 use ratatui::style::{Modifier, Style, Stylize};
 use ratatui::text::Text;
-use ratatui::widgets::{self, Block, Borders, Gauge};
+use ratatui::widgets::{self, Block, Borders, Gauge, Row, Table};
 use ratatui::Frame;
 use tui_tree_widget::Tree;
 use tui_tree_widget::{TreeItem, TreeState};
 
 use crate::tui::Theme;
 
-use super::{AffectorState, TreeKey};
+use super::{AffectorState, History, TreeKey};
 
 pub(super) fn tree(
     frame: &mut Frame,
@@ -75,6 +75,61 @@ pub(crate) fn controls(frame: &mut Frame, data: &mut &mut AffectorState, bottom:
     }
 }
 
+pub(super) fn history(frame: &mut Frame, history: &History, bottom: Rect) {
+    let block = Block::bordered().title("Controlled by (last week)");
+    let events = match history {
+        History::Fetched(events) if !events.is_empty() => events,
+        History::Fetched(_) => return centered(frame, block, "Never controlled", bottom),
+        History::NotRequested | History::Fetching => {
+            return centered(frame, block, "Fetching", bottom)
+        }
+        History::Failed(error) => {
+            let text = format!("Could not fetch: {error}, press r to retry");
+            return centered(frame, block, &text, bottom);
+        }
+    };
+
+    let header = Row::new(["At", "Value", "By"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    let rows = events.iter().rev().map(|event| {
+        Row::new([
+            event
+                .at
+                .to_zoned(jiff::tz::TimeZone::system())
+                .strftime("%m-%d %H:%M:%S")
+                .to_string(),
+            value(&event.affector),
+            event.controlled_by.clone(),
+        ])
+    });
+    let widths = [
+        Constraint::Length(14),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+    ];
+    frame.render_widget(Table::new(rows, widths).header(header).block(block), bottom);
+}
+
+fn centered(frame: &mut Frame, block: Block, text: &str, layout: Rect) {
+    frame.render_widget(
+        widgets::Paragraph::new(text)
+            .alignment(Alignment::Center)
+            .block(block),
+        layout,
+    )
+}
+
+/// The affector without the path to it, for example: `RgbLed { red: 0, .. }`
+fn value(affector: &protocol::Affector) -> String {
+    use affector::tree::{Item, Tree};
+
+    let mut current = affector as &dyn Tree;
+    while let Item::Node(inner) = current.inner() {
+        current = inner;
+    }
+    format!("{current:?}")
+}
+
 #[tracing::instrument(skip(frame, layout))]
 fn render_slider(
     frame: &mut Frame,
@@ -130,6 +185,7 @@ pub(crate) fn footer(
             ..
         }) => {
             footer.push("u/d: select prev/next");
+            footer.push("r: refresh history");
             match affector.controls()[*selected_control].value {
             C::Trigger => footer.push("enter: trigger affector"),
             C::SetNum { .. } => footer.push("f/b increase/decrease"),
@@ -57,7 +57,7 @@ impl Tab {
             plot_buf,
         } = self;
 
-        let (chart, percentiles, details, logs) = {
+        let (chart, timing, details, logs) = {
             let data = ui_state
                 .tree_state
                 .selected()
@@ -77,13 +77,16 @@ impl Tab {
                 );
                 (
                     data.chart(plot_buf),
-                    data.percentiles(),
+                    render::Timing {
+                        reading: data.percentiles(),
+                        cycle: data.cycle_percentiles(),
+                    },
                     Some(data.details()),
                     Some(data.logs()),
                 )
             } else {
                 plot_buf.clear();
-                (None, Vec::new(), None, None)
+                (None, render::Timing::default(), None, None)
             }
         };
 
@@ -93,7 +96,7 @@ impl Tab {
         let have_details = details.is_some();
         render::readings_and_details(frame, top, ui_state, readings, details);
         if have_details {
-            render::graph_hist_logs(frame, bottom, ui_state, &percentiles, logs, chart, theme);
+            render::graph_hist_logs(frame, bottom, ui_state, &timing, logs, chart, theme);
         }
         render::footer(frame, footer, ui_state, theme);
     }
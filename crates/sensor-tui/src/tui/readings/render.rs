@@ -79,11 +79,18 @@ pub fn footer(frame: &mut Frame, layout: Rect, app: &mut UiState, theme: &Theme)
     frame.render_widget(footer, layout)
 }
 
+/// Percentiles of the intervals between readings
+#[derive(Default)]
+pub struct Timing {
+    pub reading: Vec<Percentile>,
+    pub cycle: Vec<Percentile>,
+}
+
 pub fn graph_hist_logs(
     frame: &mut Frame,
     layout: Rect,
     app: &mut UiState,
-    percentiles: &[Percentile],
+    timing: &Timing,
     logs: Option<Vec<ErrorEvent>>,
     chart: Option<ChartParts>,
     theme: &Theme,
@@ -97,7 +104,7 @@ pub fn graph_hist_logs(
     if logs.as_ref().is_some_and(|logs| !logs.is_empty()) && app.show_logs {
         constraints[1] = Constraint::Fill(10);
     }
-    if !percentiles.is_empty() && app.show_histogram {
+    if !timing.reading.is_empty() && app.show_histogram {
         let idx = 1 + app.show_logs as usize;
         constraints[idx] = Constraint::Fill(10);
     }
@@ -132,23 +139,29 @@ pub fn graph_hist_logs(
     }
 
     if app.show_histogram {
-        if percentiles.is_empty() {
+        if timing.reading.is_empty() {
             centered_text(
                 "No histogram as there is no timing information",
                 frame,
                 layout.next().unwrap(),
                 theme,
             )
+        } else if timing.cycle.is_empty() {
+            render_histogram(frame, layout.next().unwrap(), "Histogram", &timing.reading);
         } else {
-            render_histogram(frame, layout.next().unwrap(), percentiles);
+            let [reading, cycle] =
+                Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)])
+                    .areas(layout.next().unwrap());
+            render_histogram(frame, reading, "Between readings", &timing.reading);
+            render_histogram(frame, cycle, "Between device cycles", &timing.cycle);
         }
     }
 }
 
-fn render_histogram(frame: &mut Frame, lower: Rect, percentiles: &[Percentile]) {
+fn render_histogram(frame: &mut Frame, lower: Rect, title: &str, percentiles: &[Percentile]) {
     let histogram = histogram_bars(&percentiles);
     let barchart = BarChart::default()
-        .block(Block::bordered().title("Histogram"))
+        .block(Block::bordered().title(title))
         .data(BarGroup::default().bars(&histogram))
         .bar_width(12);
     frame.render_widget(barchart, lower)
@@ -1,4 +1,4 @@
-use log_store::api::ErrorEvent;
+use log_store::api::{EndReason, ErrorEvent};
 use ratatui::layout::{Constraint, Flex, Rect};
 use ratatui::style::{Color, Modifier, Style, Stylize};
 use ratatui::text::Text;
@@ -36,7 +36,7 @@ pub fn render_table(
         .add_modifier(Modifier::REVERSED)
         .fg(Color::White);
 
-    let header = ["Error", "Started at", "Cleared at"]
+    let header = ["Error", "Started at", "Ended at", "Ended by"]
         .into_iter()
         .map(Cell::from)
         .collect::<Row>()
@@ -45,14 +45,20 @@ pub fn render_table(
 
     let longest_error_msg = logs
         .iter()
-        .map(|ErrorEvent { error, .. }| error.to_string().chars().count() as u16)
+        .map(|event| describe(event).chars().count() as u16)
         .max()
         .unwrap_or_default();
     let rows = logs
         .into_iter()
         .rev()
         .enumerate()
-        .map(|(i, ErrorEvent { start, end, error })| {
+        .map(|(i, event)| {
+            let ErrorEvent {
+                start,
+                end,
+                end_reason,
+                ..
+            } = event;
             let color = match i % 2 {
                 0 => Color::Gray,
                 _ => Color::White,
@@ -62,10 +68,17 @@ pub fn render_table(
             } else {
                 "ongoing".to_owned()
             };
+            let end_reason = match end_reason {
+                Some(EndReason::Replaced) => "new error",
+                Some(EndReason::Cleared) => "reading",
+                Some(EndReason::Disconnected) => "disconnect",
+                None => "",
+            };
             let item = [
-                format!("{error}"),
+                describe(&event),
                 format!("{}", start.strftime("%I:%M:%S")),
                 end,
+                end_reason.to_owned(),
             ];
             item.into_iter()
                 .map(|content| Text::from(content))
@@ -82,6 +95,7 @@ pub fn render_table(
             Constraint::Max(longest_error_msg),
             Constraint::Max(10),
             Constraint::Max(10),
+            Constraint::Max(10),
         ],
     )
     .header(header)
@@ -99,3 +113,13 @@ pub fn render_table(
 
     frame.render_stateful_widget(table, layout, table_state);
 }
+
+/// The error, followed by the number of episodes if it summarises a device
+/// that was flapping
+fn describe(event: &ErrorEvent) -> String {
+    if event.episodes > 1 {
+        format!("{} (x{})", event.error, event.episodes)
+    } else {
+        event.error.to_string()
+    }
+}
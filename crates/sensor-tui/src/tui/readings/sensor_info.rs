@@ -23,6 +23,8 @@ pub struct SensorInfo {
     pub reading: Reading,
     timing: Histogram<u64>,
     pub percentiles_from_store: Vec<Percentile>,
+    /// Time between the cycles of the device, only known by the store
+    pub cycle_percentiles_from_store: Vec<Percentile>,
     recent_history: Vec<(jiff::Timestamp, f32)>,
     pub histogram_range: Option<RangeInclusive<jiff::Timestamp>>,
     pub history_from_store: Vec<(jiff::Timestamp, f32)>,
@@ -81,7 +83,7 @@ impl SensorInfo {
         }
     }
 
-    pub fn percentiles(&self) -> Vec<api::Percentile> {
+    fn store_hist_outdated(&self) -> bool {
         let older_then_15s = |range: &RangeInclusive<jiff::Timestamp>| {
             jiff::Timestamp::now()
                 .since(*range.end())
@@ -90,15 +92,27 @@ impl SensorInfo {
                 > 15
         };
 
-        if self.histogram_range.is_none()
-            || self.histogram_range.as_ref().is_some_and(older_then_15s)
-        {
+        self.histogram_range.is_none() || self.histogram_range.as_ref().is_some_and(older_then_15s)
+    }
+
+    /// Time between readings of this kind
+    pub fn percentiles(&self) -> Vec<api::Percentile> {
+        if self.store_hist_outdated() {
             self.fallback_local_hist()
         } else {
             self.percentiles_from_store.clone()
         }
     }
 
+    /// Time between the cycles of the device that sends this reading
+    pub fn cycle_percentiles(&self) -> Vec<api::Percentile> {
+        if self.store_hist_outdated() {
+            Vec::new()
+        } else {
+            self.cycle_percentiles_from_store.clone()
+        }
+    }
+
     pub fn fallback_local_hist(&self) -> Vec<log_store::api::Percentile> {
         self.timing
             .iter_quantiles(1)
@@ -307,6 +321,7 @@ impl Readings {
                         reading: broken.clone(),
                         timing: Histogram::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap(),
                         percentiles_from_store: Vec::new(),
+                        cycle_percentiles_from_store: Vec::new(),
                         histogram_range: None,
 
                         recent_history: Vec::new(),
@@ -343,6 +358,7 @@ impl Readings {
                     reading,
                     timing: Histogram::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap(),
                     percentiles_from_store: Vec::new(),
+                    cycle_percentiles_from_store: Vec::new(),
                     histogram_range: None,
 
                     recent_history: history,
@@ -370,6 +386,7 @@ impl Readings {
                 reading,
                 timing: Histogram::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap(),
                 percentiles_from_store: Vec::new(),
+                cycle_percentiles_from_store: Vec::new(),
                 histogram_range: None,
 
                 recent_history: Vec::new(),
@@ -455,8 +472,13 @@ impl Readings {
                 sensorinfo.logs_from_store = logs;
                 sensorinfo.logs_from_store_hist = Some(start_at);
             }
-            Fetchable::Hist { percentiles, range } => {
+            Fetchable::Hist {
+                percentiles,
+                cycle_percentiles,
+                range,
+            } => {
                 sensorinfo.percentiles_from_store = percentiles;
+                sensorinfo.cycle_percentiles_from_store = cycle_percentiles;
                 sensorinfo.histogram_range = Some(range);
             }
         }
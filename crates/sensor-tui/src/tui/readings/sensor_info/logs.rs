@@ -14,7 +14,9 @@ impl Logs {
             current: Some(ErrorEvent {
                 start: jiff::Timestamp::now(),
                 end: None,
+                end_reason: None,
                 error: error.clone(),
+                episodes: 1,
             }),
             history: Vec::new(),
         }
@@ -36,14 +38,18 @@ impl Logs {
             self.history.push(api::ErrorEvent {
                 start,
                 end: Some(jiff::Timestamp::now()),
+                end_reason: Some(api::EndReason::Replaced),
                 error,
+                episodes: 1,
             })
         }
 
         self.current = Some(ErrorEvent {
             start: jiff::Timestamp::now(),
             end: None,
+            end_reason: None,
             error: new_error.clone(),
+            episodes: 1,
         })
     }
 
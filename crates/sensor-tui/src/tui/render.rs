@@ -9,7 +9,7 @@ pub(super) fn render_tab(frame: &mut Frame, app: &App) -> Rect {
         .flex(Flex::Legacy)
         .areas(frame.area());
 
-    let tabs = Tabs::new(vec!["Readings", "Affectors"])
+    let tabs = Tabs::new(vec!["Readings", "Affectors", "Storage"])
         .style(app.theme.bars)
         .select(app.active_tab.number())
         .divider("|")
@@ -0,0 +1,198 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use data_store::api::SeriesStorage;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+
+use crate::fetch::Request;
+use crate::{Fetch, Update};
+
+use super::Theme;
+
+#[derive(Default)]
+pub struct Tab {
+    stats: Vec<SeriesStorage>,
+    table_state: TableState,
+    /// Cleared to request new stats on the next render
+    requested: bool,
+    /// A request was send and no answer came in yet
+    fetching: bool,
+    fetched_at: Option<jiff::Timestamp>,
+}
+
+impl Tab {
+    pub fn render(&mut self, fetcher: &mut Fetch, frame: &mut Frame, layout: Rect, theme: &Theme) {
+        if !self.requested && !self.fetching {
+            fetcher.request_storage_stats();
+            self.requested = true;
+            self.fetching = true;
+        }
+
+        let [main, footer] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Max(1)]).areas(layout);
+        let [top, bottom] =
+            Layout::vertical([Constraint::Fill(2), Constraint::Fill(1)]).areas(main);
+
+        self.render_table(frame, top);
+        if let Some(selected) = self.table_state.selected().and_then(|i| self.stats.get(i)) {
+            render_details(frame, bottom, selected);
+        }
+        self.render_footer(frame, footer, theme);
+    }
+
+    fn render_table(&mut self, frame: &mut Frame, layout: Rect) {
+        let header = Row::new(["series", "on disk", "lines", "first", "last", "line", "lines/h"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = self.stats.iter().map(|series| {
+            let on_disk: u64 = series.files.iter().map(|file| file.bytes).sum();
+            Row::new([
+                series.path.display().to_string(),
+                fmt_bytes(on_disk),
+                series.lines.to_string(),
+                fmt_time(series.first),
+                fmt_time(series.last),
+                format!("{} B", series.payload_bytes),
+                format!("{:.0}", series.lines_per_hour),
+            ])
+        });
+
+        let widths = [
+            Constraint::Fill(3),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(16),
+            Constraint::Length(16),
+            Constraint::Length(6),
+            Constraint::Length(8),
+        ];
+        let title = match self.fetched_at {
+            Some(at) => format!(
+                "Storage (as of {})",
+                at.to_zoned(jiff::tz::TimeZone::system()).strftime("%H:%M:%S")
+            ),
+            None if self.fetching => "Storage (fetching)".to_string(),
+            None => "Storage (could not fetch, press r to retry)".to_string(),
+        };
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
+            .highlight_symbol(">>");
+        frame.render_stateful_widget(table, layout, &mut self.table_state);
+    }
+
+    fn render_footer(&self, frame: &mut Frame, layout: Rect, theme: &Theme) {
+        let footer = ["ESC or q: quit", "r: refresh", "up/down: select series"].join("  ");
+        let footer = Text::raw(footer)
+            .alignment(Alignment::Center)
+            .style(theme.bars);
+        frame.render_widget(footer, layout)
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<KeyEvent> {
+        match key.code {
+            KeyCode::Down => {
+                let last = self.stats.len().saturating_sub(1);
+                let next = self.table_state.selected().map_or(0, |i| (i + 1).min(last));
+                self.table_state.select(Some(next));
+            }
+            KeyCode::Up => {
+                let prev = self.table_state.selected().map_or(0, |i| i.saturating_sub(1));
+                self.table_state.select(Some(prev));
+            }
+            KeyCode::Char('r') => self.requested = false,
+            _ => return Some(key),
+        }
+        None
+    }
+
+    pub fn process_update(&mut self, update: &Update) {
+        let stats = match update {
+            Update::StorageStats(stats) => stats,
+            Update::FetchError {
+                request: Request::StorageStats,
+                ..
+            } => {
+                self.fetching = false;
+                return;
+            }
+            _ => return,
+        };
+        self.stats = stats.clone();
+        self.fetching = false;
+        self.fetched_at = Some(jiff::Timestamp::now());
+        if self.table_state.selected().is_none() && !self.stats.is_empty() {
+            self.table_state.select(Some(0));
+        }
+    }
+}
+
+fn render_details(frame: &mut Frame, layout: Rect, series: &SeriesStorage) {
+    let [files, fields] =
+        Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(layout);
+
+    let lines: Vec<_> = series
+        .files
+        .iter()
+        .map(|file| Line::raw(format!("{:>10}  {}", fmt_bytes(file.bytes), file.name)))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Files")),
+        files,
+    );
+
+    // values would be f32 without the bitspec encoding
+    let uncompressed = series.fields.len() * 32;
+    let bits: usize = series.fields.iter().map(|field| field.bits as usize).sum();
+    let mut lines: Vec<_> = series
+        .fields
+        .iter()
+        .map(|field| Line::raw(format!("{:>3} bits  {}", field.bits, reading_name(&field.reading))))
+        .collect();
+    lines.push(Line::raw(format!(
+        "{bits} of {uncompressed} bits per line ({:.0}% of f32)",
+        100.0 * bits as f32 / uncompressed.max(1) as f32
+    )));
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Encoding")),
+        fields,
+    );
+}
+
+fn reading_name(reading: &protocol::Reading) -> String {
+    use protocol::reading::tree::{Item, Tree};
+
+    let mut current = reading as &dyn Tree;
+    loop {
+        match current.inner() {
+            Item::Leaf(_) => return current.name(),
+            Item::Node(inner) => current = inner,
+        }
+    }
+}
+
+fn fmt_time(time: Option<jiff::Timestamp>) -> String {
+    time.map(|time| {
+        time.to_zoned(jiff::tz::TimeZone::system())
+            .strftime("%Y-%m-%d %H:%M")
+            .to_string()
+    })
+    .unwrap_or_else(|| "-".to_string())
+}
+
+fn fmt_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
@@ -1,7 +1,7 @@
 use color_eyre::eyre::{eyre, Context};
 use color_eyre::Result;
 use data_server::api::SubMessage;
-use data_server::api::{ReconnectingClient, ReconnectingSubscribedClient};
+use data_server::api::{ConnectOptions, ReconnectingClient, ReconnectingSubscribedClient};
 use gethostname::gethostname;
 use protocol::reading::tree::Tree;
 use protocol::Reading;
@@ -25,6 +25,9 @@ struct Cli {
     #[arg(long, default_value_t = SocketAddr::from(([127,0,0,1], 1235)))]
     server: SocketAddr,
 
+    #[command(flatten)]
+    server_options: ConnectOptions,
+
     /// print json format: {"msg": "reading value"}
     #[arg(short, long)]
     json: bool,
@@ -72,7 +75,8 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     setup_tracing(cli.debug)?;
 
-    let mut client = ReconnectingClient::new(cli.server, name()).subscribe();
+    let client = ReconnectingClient::new(cli.server, name());
+    let mut client = cli.server_options.apply(client)?.subscribe();
 
     let reading = match cache::load_from_file(&cli.reading).await {
         Ok(Some(reading)) => reading,